@@ -0,0 +1,38 @@
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, Criterion};
+use http_resources::{normalize_path, percent_decode, HttpRequest, HttpResponse, HttpResponseOptions, MimeRegistry};
+
+// What every static file response goes through: the request head, the path, the content type and the response head.
+
+fn header_serialization(c: &mut Criterion) {
+    let response = HttpResponse::builder()
+        .content_type("text/html; charset=utf-8")
+        .header(HttpResponseOptions::CacheControl, "max-age=3600")
+        .header(HttpResponseOptions::ETag, "\"5f3a-18c2b7e4d10\"")
+        .header(HttpResponseOptions::LastModified, "Tue, 13 Oct 2026 08:12:44 GMT")
+        .header(HttpResponseOptions::AcceptRanges, "bytes")
+        .body("<!DOCTYPE html><title>home</title>")
+        .build();
+    c.bench_function("response head", |b| b.iter(|| black_box(&response).get_header()));
+    c.bench_function("response bytes", |b| b.iter(|| black_box(&response).to_bytes()));
+
+    let raw = b"GET /assets/css/site.css?v=3 HTTP/1.1\r\nHost: example.com\r\nUser-Agent: Mozilla/5.0 (X11; Linux x86_64)\r\n\
+        Accept: text/css,*/*;q=0.1\r\nAccept-Encoding: gzip, deflate, br\r\nConnection: keep-alive\r\n\r\n";
+    c.bench_function("request parse", |b| b.iter(|| HttpRequest::parse(&mut black_box(&raw[..])).unwrap()));
+}
+
+fn mime_lookup(c: &mut Criterion) {
+    let registry = MimeRegistry::new();
+    c.bench_function("mime known", |b| b.iter(|| registry.lookup(black_box(Some("woff2"))).len()));
+    c.bench_function("mime upper case", |b| b.iter(|| registry.lookup(black_box(Some("JPEG"))).len()));
+    c.bench_function("mime unknown", |b| b.iter(|| registry.lookup(black_box(Some("unknown"))).len()));
+}
+
+fn path_sanitization(c: &mut Criterion) {
+    c.bench_function("path plain", |b| b.iter(|| normalize_path(&percent_decode(black_box("/assets/css/site.css")).unwrap())));
+    c.bench_function("path escaped", |b| b.iter(|| normalize_path(&percent_decode(black_box("/docs/my%20page/caf%C3%A9/./index.html")).unwrap())));
+    c.bench_function("path traversal", |b| b.iter(|| normalize_path(&percent_decode(black_box("/a/%2e%2e/%2e%2e/settings.cfg")).unwrap())));
+}
+
+criterion_group!(benches, header_serialization, mime_lookup, path_sanitization);
+criterion_main!(benches);
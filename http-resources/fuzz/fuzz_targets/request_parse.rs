@@ -0,0 +1,27 @@
+// Feeds arbitrary bytes to the request parser: cargo +nightly fuzz run request_parse (from http-resources/).
+#![no_main]
+
+use std::io::BufReader;
+use http_resources::{HttpRequest, HttpRequestLimits};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the buffer size, so lines also get split across reads the way they do off a socket.
+    let Some((&capacity, raw)) = data.split_first() else {
+        return;
+    };
+    let limits = HttpRequestLimits { max_body_size: 4096, max_request_line: 1024, max_header_size: 1024, max_header_bytes: 4096, max_headers: 32 };
+    let mut reader = BufReader::with_capacity(capacity as usize + 1, raw);
+    let parsed = HttpRequest::parse_with_limits(&mut reader, &limits);
+
+    // Errors are typed by construction; a request has to be one the rest of the server can rely on.
+    if let Ok(request) = parsed {
+        assert!(request.get_target().starts_with('/') || request.get_target() == "*");
+        assert!(request.get_target().bytes().all(|b| b.is_ascii_graphic()));
+        assert!(request.get_body().len() <= limits.max_body_size);
+        assert!(request.get_headers().len() <= limits.max_headers);
+        // The body is exactly what the request announced, never a read into whatever follows it.
+        let announced = request.get_header("Content-Length").map_or(0, |length| length.parse().unwrap());
+        assert_eq!(request.get_body().len(), announced);
+    }
+});
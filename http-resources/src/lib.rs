@@ -1,14 +1,43 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::hash::{Hash};
-use std::io::Write;
-use std::net::TcpStream;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
-#[derive(Hash)]
-#[derive(Eq, PartialEq)]
+#[derive(Clone)]
 pub enum HttpResponseOptions {
     ContentType,
     ContentLength,
+    ContentEncoding,
+    ContentRange,
+    ContentDisposition,
+    AcceptRanges,
+    Allow,
+    CacheControl,
+    Connection,
+    Date,
+    ETag,
+    Expires,
+    KeepAlive,
+    LastModified,
+    Location,
+    RequestId,
+    RetryAfter,
+    Server,
+    SetCookie,
+    TransferEncoding,
+    Vary,
+    WwwAuthenticate,
+    Custom(String),
 }
 
 impl HttpResponseOptions {
@@ -16,12 +45,80 @@ impl HttpResponseOptions {
         match self {
             HttpResponseOptions::ContentType => "Content-Type",
             HttpResponseOptions::ContentLength => "Content-Length",
+            HttpResponseOptions::ContentEncoding => "Content-Encoding",
+            HttpResponseOptions::ContentRange => "Content-Range",
+            HttpResponseOptions::ContentDisposition => "Content-Disposition",
+            HttpResponseOptions::AcceptRanges => "Accept-Ranges",
+            HttpResponseOptions::Allow => "Allow",
+            HttpResponseOptions::CacheControl => "Cache-Control",
+            HttpResponseOptions::Connection => "Connection",
+            HttpResponseOptions::Date => "Date",
+            HttpResponseOptions::ETag => "ETag",
+            HttpResponseOptions::Expires => "Expires",
+            HttpResponseOptions::KeepAlive => "Keep-Alive",
+            HttpResponseOptions::LastModified => "Last-Modified",
+            HttpResponseOptions::Location => "Location",
+            HttpResponseOptions::RequestId => "X-Request-Id",
+            HttpResponseOptions::RetryAfter => "Retry-After",
+            HttpResponseOptions::Server => "Server",
+            HttpResponseOptions::SetCookie => "Set-Cookie",
+            HttpResponseOptions::TransferEncoding => "Transfer-Encoding",
+            HttpResponseOptions::Vary => "Vary",
+            HttpResponseOptions::WwwAuthenticate => "WWW-Authenticate",
+            HttpResponseOptions::Custom(name) => name.as_str(),
         }
     }
+
+    pub fn from_name(name: &str) -> HttpResponseOptions {
+        match name.to_ascii_lowercase().as_str() {
+            "content-type" => HttpResponseOptions::ContentType,
+            "content-length" => HttpResponseOptions::ContentLength,
+            "content-encoding" => HttpResponseOptions::ContentEncoding,
+            "content-range" => HttpResponseOptions::ContentRange,
+            "content-disposition" => HttpResponseOptions::ContentDisposition,
+            "accept-ranges" => HttpResponseOptions::AcceptRanges,
+            "allow" => HttpResponseOptions::Allow,
+            "cache-control" => HttpResponseOptions::CacheControl,
+            "connection" => HttpResponseOptions::Connection,
+            "date" => HttpResponseOptions::Date,
+            "etag" => HttpResponseOptions::ETag,
+            "expires" => HttpResponseOptions::Expires,
+            "keep-alive" => HttpResponseOptions::KeepAlive,
+            "last-modified" => HttpResponseOptions::LastModified,
+            "location" => HttpResponseOptions::Location,
+            "x-request-id" => HttpResponseOptions::RequestId,
+            "retry-after" => HttpResponseOptions::RetryAfter,
+            "server" => HttpResponseOptions::Server,
+            "set-cookie" => HttpResponseOptions::SetCookie,
+            "transfer-encoding" => HttpResponseOptions::TransferEncoding,
+            "vary" => HttpResponseOptions::Vary,
+            "www-authenticate" => HttpResponseOptions::WwwAuthenticate,
+            _ => HttpResponseOptions::Custom(name.to_string()),
+        }
+    }
+
+    pub fn is_repeatable(&self) -> bool {
+        matches!(self, HttpResponseOptions::SetCookie | HttpResponseOptions::WwwAuthenticate)
+    }
+}
+
+impl PartialEq for HttpResponseOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.get_name().eq_ignore_ascii_case(other.get_name())
+    }
+}
+
+impl Eq for HttpResponseOptions {}
+
+impl Hash for HttpResponseOptions {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.get_name().to_ascii_lowercase().hash(state);
+    }
 }
 
 #[derive(Debug)]
 #[derive(PartialEq)]
+#[derive(Clone, Copy)]
 pub enum HttpProtocols {
     ZeroNine,
     One,
@@ -29,93 +126,3478 @@ pub enum HttpProtocols {
     Two
 }
 
-impl HttpProtocols {
-    pub fn get_name(&self) -> &str {
-        match self {
+impl fmt::Display for HttpProtocols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
             HttpProtocols::ZeroNine => "HTTP/0.9",
             HttpProtocols::One => "HTTP/1.0",
             HttpProtocols::OneOne => "HTTP/1.1",
             HttpProtocols::Two => "HTTP/2.0",
+        })
+    }
+}
+
+impl FromStr for HttpProtocols {
+    type Err = HttpError;
+
+    fn from_str(name: &str) -> Result<HttpProtocols, HttpError> {
+        match name {
+            "HTTP/0.9" => Ok(HttpProtocols::ZeroNine),
+            "HTTP/1.0" => Ok(HttpProtocols::One),
+            "HTTP/1.1" => Ok(HttpProtocols::OneOne),
+            "HTTP/2.0" | "HTTP/2" => Ok(HttpProtocols::Two),
+            _ => Err(HttpError::UnsupportedVersion),
+        }
+    }
+}
+
+impl HttpProtocols {
+    // The version to answer a request of this version with; HTTP/2 is never spoken over this framing.
+    pub fn response_version(&self) -> HttpProtocols {
+        match self {
+            HttpProtocols::ZeroNine => HttpProtocols::ZeroNine,
+            HttpProtocols::One => HttpProtocols::One,
+            HttpProtocols::OneOne | HttpProtocols::Two => HttpProtocols::OneOne,
+        }
+    }
+
+    pub fn supports_chunked(&self) -> bool {
+        matches!(self, HttpProtocols::OneOne | HttpProtocols::Two)
+    }
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone, Copy)]
+pub enum HttpMethod {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+}
+
+impl HttpMethod {
+    pub const ALL: [HttpMethod; 7] = [HttpMethod::Get, HttpMethod::Head, HttpMethod::Post, HttpMethod::Put, HttpMethod::Delete, HttpMethod::Options, HttpMethod::Patch];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Patch => "PATCH",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HttpMethod> {
+        match name {
+            "GET" => Some(HttpMethod::Get),
+            "HEAD" => Some(HttpMethod::Head),
+            "POST" => Some(HttpMethod::Post),
+            "PUT" => Some(HttpMethod::Put),
+            "DELETE" => Some(HttpMethod::Delete),
+            "OPTIONS" => Some(HttpMethod::Options),
+            "PATCH" => Some(HttpMethod::Patch),
+            _ => None,
         }
     }
 }
 
 #[derive(Debug)]
 #[derive(PartialEq)]
+#[derive(Clone)]
 pub enum HttpResponseStatusCode {
     OK,
+    Created,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
     NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    PayloadTooLarge,
+    UriTooLong,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    MisdirectedRequest,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    HttpVersionNotSupported,
+    Custom(u16, String),
 }
 
 impl HttpResponseStatusCode {
-    pub fn get_header(&self) -> &str {
+    pub fn get_reason(&self) -> &str {
         match self {
-            HttpResponseStatusCode::OK => "200 Ok",
-            HttpResponseStatusCode::NotFound => "404 Not Found",
-            HttpResponseStatusCode::InternalServerError => "500 Internal Server Error",
+            HttpResponseStatusCode::OK => "OK",
+            HttpResponseStatusCode::Created => "Created",
+            HttpResponseStatusCode::NoContent => "No Content",
+            HttpResponseStatusCode::PartialContent => "Partial Content",
+            HttpResponseStatusCode::MovedPermanently => "Moved Permanently",
+            HttpResponseStatusCode::Found => "Found",
+            HttpResponseStatusCode::NotModified => "Not Modified",
+            HttpResponseStatusCode::TemporaryRedirect => "Temporary Redirect",
+            HttpResponseStatusCode::PermanentRedirect => "Permanent Redirect",
+            HttpResponseStatusCode::BadRequest => "Bad Request",
+            HttpResponseStatusCode::Unauthorized => "Unauthorized",
+            HttpResponseStatusCode::Forbidden => "Forbidden",
+            HttpResponseStatusCode::NotFound => "Not Found",
+            HttpResponseStatusCode::MethodNotAllowed => "Method Not Allowed",
+            HttpResponseStatusCode::NotAcceptable => "Not Acceptable",
+            HttpResponseStatusCode::RequestTimeout => "Request Timeout",
+            HttpResponseStatusCode::PayloadTooLarge => "Payload Too Large",
+            HttpResponseStatusCode::UriTooLong => "URI Too Long",
+            HttpResponseStatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpResponseStatusCode::ExpectationFailed => "Expectation Failed",
+            HttpResponseStatusCode::MisdirectedRequest => "Misdirected Request",
+            HttpResponseStatusCode::TooManyRequests => "Too Many Requests",
+            HttpResponseStatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            HttpResponseStatusCode::InternalServerError => "Internal Server Error",
+            HttpResponseStatusCode::NotImplemented => "Not Implemented",
+            HttpResponseStatusCode::BadGateway => "Bad Gateway",
+            HttpResponseStatusCode::ServiceUnavailable => "Service Unavailable",
+            HttpResponseStatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+            HttpResponseStatusCode::Custom(_, reason) => reason,
         }
     }
+
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            HttpResponseStatusCode::OK => 200,
+            HttpResponseStatusCode::Created => 201,
+            HttpResponseStatusCode::NoContent => 204,
+            HttpResponseStatusCode::PartialContent => 206,
+            HttpResponseStatusCode::MovedPermanently => 301,
+            HttpResponseStatusCode::Found => 302,
+            HttpResponseStatusCode::NotModified => 304,
+            HttpResponseStatusCode::TemporaryRedirect => 307,
+            HttpResponseStatusCode::PermanentRedirect => 308,
+            HttpResponseStatusCode::BadRequest => 400,
+            HttpResponseStatusCode::Unauthorized => 401,
+            HttpResponseStatusCode::Forbidden => 403,
+            HttpResponseStatusCode::NotFound => 404,
+            HttpResponseStatusCode::MethodNotAllowed => 405,
+            HttpResponseStatusCode::NotAcceptable => 406,
+            HttpResponseStatusCode::RequestTimeout => 408,
+            HttpResponseStatusCode::PayloadTooLarge => 413,
+            HttpResponseStatusCode::UriTooLong => 414,
+            HttpResponseStatusCode::RangeNotSatisfiable => 416,
+            HttpResponseStatusCode::ExpectationFailed => 417,
+            HttpResponseStatusCode::MisdirectedRequest => 421,
+            HttpResponseStatusCode::TooManyRequests => 429,
+            HttpResponseStatusCode::RequestHeaderFieldsTooLarge => 431,
+            HttpResponseStatusCode::InternalServerError => 500,
+            HttpResponseStatusCode::NotImplemented => 501,
+            HttpResponseStatusCode::BadGateway => 502,
+            HttpResponseStatusCode::ServiceUnavailable => 503,
+            HttpResponseStatusCode::HttpVersionNotSupported => 505,
+            HttpResponseStatusCode::Custom(code, _) => *code,
+        }
+    }
+
+    // Informational responses, 204 and 304 end with their head (RFC 9110, section 6.4.1).
+    pub fn allows_body(&self) -> bool {
+        let code = self.as_u16();
+        code >= 200 && code != 204 && code != 304
+    }
+
+    // Only codes with a variant of their own; anything else needs `Custom`.
+    pub fn from_u16(code: u16) -> Option<HttpResponseStatusCode> {
+        const KNOWN: [HttpResponseStatusCode; 28] = [
+            HttpResponseStatusCode::OK, HttpResponseStatusCode::Created, HttpResponseStatusCode::NoContent,
+            HttpResponseStatusCode::PartialContent, HttpResponseStatusCode::MovedPermanently, HttpResponseStatusCode::Found,
+            HttpResponseStatusCode::NotModified, HttpResponseStatusCode::TemporaryRedirect, HttpResponseStatusCode::PermanentRedirect,
+            HttpResponseStatusCode::BadRequest, HttpResponseStatusCode::Unauthorized, HttpResponseStatusCode::Forbidden,
+            HttpResponseStatusCode::NotFound, HttpResponseStatusCode::MethodNotAllowed, HttpResponseStatusCode::NotAcceptable, HttpResponseStatusCode::RequestTimeout,
+            HttpResponseStatusCode::PayloadTooLarge, HttpResponseStatusCode::UriTooLong, HttpResponseStatusCode::RangeNotSatisfiable,
+            HttpResponseStatusCode::ExpectationFailed, HttpResponseStatusCode::MisdirectedRequest, HttpResponseStatusCode::TooManyRequests, HttpResponseStatusCode::RequestHeaderFieldsTooLarge,
+            HttpResponseStatusCode::InternalServerError, HttpResponseStatusCode::NotImplemented, HttpResponseStatusCode::BadGateway,
+            HttpResponseStatusCode::ServiceUnavailable, HttpResponseStatusCode::HttpVersionNotSupported,
+        ];
+        KNOWN.into_iter().find(|status| status.as_u16() == code)
+    }
+
+    // Known codes get their own variant whatever the reason phrase says, so parsed responses compare as expected.
+    fn from_parts(code: u16, reason: &str) -> HttpResponseStatusCode {
+        Self::from_u16(code).unwrap_or_else(|| HttpResponseStatusCode::Custom(code, reason.to_string()))
+    }
+}
+
+impl fmt::Display for HttpResponseStatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.as_u16(), self.get_reason())
+    }
+}
+
+// Accepts a bare code such as "404" as well as a full status like "404 Not Found".
+impl FromStr for HttpResponseStatusCode {
+    type Err = HttpError;
+
+    fn from_str(status: &str) -> Result<HttpResponseStatusCode, HttpError> {
+        let (code, reason) = status.trim().split_once(' ').unwrap_or((status.trim(), ""));
+        let code = code.parse().ok().filter(|code| (100..1000).contains(code)).ok_or(HttpError::ParseError)?;
+        Ok(Self::from_parts(code, reason.trim()))
+    }
 }
 
+// Everything that can go wrong reading or building a message.
 #[derive(Debug)]
 #[derive(PartialEq)]
-pub struct HttpResponse {
+pub enum HttpError {
+    ConnectionClosed,
+    ReadFailed,
+    ParseError,
+    // The head or the body ends before it should.
+    Incomplete,
+    InvalidHeaderName(String),
+    InvalidHeaderValue(String),
+    UnsupportedVersion,
+    // A Transfer-Encoding other than chunked, which the body cannot be read without.
+    UnsupportedTransferEncoding,
+    BodyTooLarge,
+    UriTooLong,
+    HeadersTooLarge,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpError::ConnectionClosed => write!(f, "the connection was closed"),
+            HttpError::ReadFailed => write!(f, "reading the message failed"),
+            HttpError::ParseError => write!(f, "the message is malformed"),
+            HttpError::Incomplete => write!(f, "the message ends early"),
+            HttpError::InvalidHeaderName(name) => write!(f, "\"{}\" is not a valid header name", name.escape_debug()),
+            HttpError::InvalidHeaderValue(name) => write!(f, "the value of the {} header contains control characters", name.escape_debug()),
+            HttpError::UnsupportedVersion => write!(f, "the HTTP version is not supported"),
+            HttpError::UnsupportedTransferEncoding => write!(f, "the transfer coding is not supported"),
+            HttpError::BodyTooLarge => write!(f, "the body is too large"),
+            HttpError::UriTooLong => write!(f, "the request target is too long"),
+            HttpError::HeadersTooLarge => write!(f, "the headers are too large"),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub enum JsonError {
+    // The body is not declared as application/json (or a +json type).
+    UnsupportedMediaType,
+    Invalid(serde_json::Error),
+}
+
+#[cfg(feature = "json")]
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonError::UnsupportedMediaType => write!(f, "the body is not JSON"),
+            JsonError::Invalid(err) => write!(f, "the JSON body is invalid: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl std::error::Error for JsonError {}
+
+#[cfg(feature = "templates")]
+#[derive(Debug)]
+pub enum TemplateError {
+    NotFound(String),
+    Syntax { name: String, line: usize, message: String },
+    Read(PathBuf, io::Error),
+    // The context could not be turned into values, such as a map with non-string keys.
+    Context(serde_json::Error),
+    // Partials that include each other without end.
+    TooDeep(String),
+}
+
+#[cfg(feature = "templates")]
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::NotFound(name) => write!(f, "there is no template named \"{}\"", name),
+            TemplateError::Syntax { name, line, message } => write!(f, "{}:{}: {}", name, line, message),
+            TemplateError::Read(path, err) => write!(f, "unable to read {}: {}", path.display(), err),
+            TemplateError::Context(err) => write!(f, "invalid template context: {}", err),
+            TemplateError::TooDeep(name) => write!(f, "partials are nested too deeply in \"{}\"", name),
+        }
+    }
+}
+
+#[cfg(feature = "templates")]
+impl std::error::Error for TemplateError {}
+
+// Header names are RFC 9110 tokens.
+pub fn check_header_name(name: &str) -> Result<(), HttpError> {
+    let valid = !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if valid { Ok(()) } else { Err(HttpError::InvalidHeaderName(name.to_string())) }
+}
+
+// Line breaks in a value would let it end the header early and smuggle in others.
+pub fn check_header_value(name: &str, value: &str) -> Result<(), HttpError> {
+    let valid = value.chars().all(|c| c == '\t' || !c.is_control());
+    if valid { Ok(()) } else { Err(HttpError::InvalidHeaderValue(name.to_string())) }
+}
+
+// How many empty lines may come before a request line.
+const MAX_EMPTY_LINES: usize = 4;
+
+// The origin form "/path?query", the absolute form "http://host/path" and "*", which only OPTIONS may ask about. All of
+// them are visible ASCII; anything else has to be percent-encoded.
+// Reads a chunked body, handing its data to `sink` as it arrives, and returns the trailer fields. Chunk sizes are plain
+// hex numbers, optionally followed by extensions, which are ignored. How large the body may grow is up to `sink`.
+pub fn read_chunked<R: BufRead, E: From<HttpError>>(reader: &mut R, limits: &HttpRequestLimits, mut sink: impl FnMut(&[u8]) -> Result<(), E>) -> Result<Vec<(String, String)>, E> {
+    loop {
+        let line = HttpRequest::read_line(reader, limits.max_header_size)?;
+        let size = line.split(';').next().unwrap_or_default().trim_end_matches([' ', '\t']);
+        if size.is_empty() || size.len() > 16 || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(HttpError::ParseError.into());
+        }
+        let mut remaining = u64::from_str_radix(size, 16).map_err(|_| HttpError::ParseError)?;
+        if remaining == 0 {
+            break;
+        }
+        while remaining > 0 {
+            let available = reader.fill_buf().ok().filter(|data| !data.is_empty()).ok_or(HttpError::ReadFailed)?;
+            let take = available.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+            sink(&available[..take])?;
+            reader.consume(take);
+            remaining -= take as u64;
+        }
+        let mut end = [0; 2];
+        reader.read_exact(&mut end).map_err(|_| HttpError::ReadFailed)?;
+        if &end != b"\r\n" {
+            return Err(HttpError::ParseError.into());
+        }
+    }
+
+    // Trailers are held to the same limits as the head.
+    let mut trailers = Vec::new();
+    let mut trailer_bytes = 0;
+    loop {
+        let line = HttpRequest::read_line(reader, limits.max_header_size)?;
+        if line.is_empty() {
+            return Ok(trailers);
+        }
+        trailer_bytes += line.len();
+        if trailer_bytes > limits.max_header_bytes || trailers.len() >= limits.max_headers {
+            return Err(HttpError::HeadersTooLarge.into());
+        }
+        let (name, value) = line.split_once(':').ok_or(HttpError::ParseError)?;
+        trailers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+fn is_request_target(method: HttpMethod, target: &str) -> bool {
+    let visible = !target.is_empty() && target.bytes().all(|b| b.is_ascii_graphic());
+    let absolute = ["http://", "https://"].iter().any(|scheme| target.len() >= scheme.len() && target[..scheme.len()].eq_ignore_ascii_case(scheme));
+    visible && (target.starts_with('/') || absolute || (target == "*" && method == HttpMethod::Options))
+}
+
+// Only a well-formed version this server does not speak is unsupported; anything else is not a request line at all.
+fn parse_version(version: &str) -> Result<HttpProtocols, HttpError> {
+    let well_formed = match version.strip_prefix("HTTP/").map(str::as_bytes) {
+        Some([major]) => major.is_ascii_digit(),
+        Some([major, b'.', minor]) => major.is_ascii_digit() && minor.is_ascii_digit(),
+        _ => false,
+    };
+    if !well_formed {
+        return Err(HttpError::ParseError);
+    }
+    version.parse()
+}
+
+// Where the body ends has to be beyond doubt, or a proxy in front could read it differently and take the rest for a
+// request of its own. Only a single Content-Length made of digits, or chunked alone on HTTP/1.1, is accepted.
+fn check_framing(protocol: HttpProtocols, headers: &[(String, String)]) -> Result<(), HttpError> {
+    let headers = Headers { fields: headers };
+    let lengths = headers.get_all("Content-Length");
+    let codings = headers.get_list("Transfer-Encoding");
+    if lengths.len() > 1 || lengths.iter().any(|length| length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit())) {
+        return Err(HttpError::ParseError);
+    }
+    if codings.is_empty() {
+        return Ok(());
+    }
+    if !lengths.is_empty() || protocol != HttpProtocols::OneOne {
+        return Err(HttpError::ParseError);
+    }
+    match codings.as_slice() {
+        [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(()),
+        _ => Err(HttpError::UnsupportedTransferEncoding),
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct HttpRequestLimits {
+    pub max_body_size: usize,
+    pub max_request_line: usize,
+    pub max_header_size: usize,
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+}
+
+impl Default for HttpRequestLimits {
+    fn default() -> Self {
+        HttpRequestLimits {
+            max_body_size: 1024 * 1024,
+            max_request_line: 8 * 1024,
+            max_header_size: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_headers: 100,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct HttpRequest {
+    method: HttpMethod,
+    target: String,
+    path: String,
+    query: Option<String>,
     protocol: HttpProtocols,
-    status: HttpResponseStatusCode,
-    options: HashMap<HttpResponseOptions, &'static str>,
-    payload: Vec<u8>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    params: HashMap<String, String>,
+    client_ip: Option<IpAddr>,
+    peer_ip: Option<IpAddr>,
+    secure: bool,
 }
 
-impl HttpResponse {
-    pub const SEPARATOR: &'static str = "\r\n";
+impl HttpRequest {
+    pub fn parse<R: BufRead>(reader: &mut R) -> Result<HttpRequest, HttpError> {
+        Self::parse_with_limits(reader, &HttpRequestLimits::default())
+    }
 
-    pub fn new(protocol: HttpProtocols) -> HttpResponse {
-        if protocol != HttpProtocols::OneOne {
-            println!("Warning! You are using the \"{}\" HTTP protocol, which is not directly supported by this library. Only proceed if you know what you are doing!", protocol.get_name());
+    pub fn parse_with_limits<R: BufRead>(reader: &mut R, limits: &HttpRequestLimits) -> Result<HttpRequest, HttpError> {
+        let mut request = Self::parse_head(reader, limits)?;
+        request.read_body(reader, limits)?;
+        Ok(request)
+    }
+
+    pub fn parse_head<R: BufRead>(reader: &mut R, limits: &HttpRequestLimits) -> Result<HttpRequest, HttpError> {
+        // Clients may send a stray line ending after a body, so a few empty lines are skipped; a stream of them is not.
+        let mut skipped = 0;
+        let request_line = loop {
+            match Self::read_line(reader, limits.max_request_line) {
+                Ok(line) if line.is_empty() && skipped < MAX_EMPTY_LINES => skipped += 1,
+                Err(HttpError::ReadFailed) => return Err(HttpError::ConnectionClosed),
+                Err(HttpError::HeadersTooLarge) => return Err(HttpError::UriTooLong),
+                result => break result?,
+            }
+        };
+        let mut parts = request_line.split(' ');
+        let method = parts.next().and_then(HttpMethod::from_name).ok_or(HttpError::ParseError)?;
+        let target = parts.next().filter(|target| is_request_target(method, target)).ok_or(HttpError::ParseError)?;
+        let protocol = match parts.next() {
+            Some(version) => parse_version(version)?,
+            None => HttpProtocols::ZeroNine,
+        };
+        // HTTP/0.9 only knows simple GET requests, and nothing follows the version.
+        if protocol == HttpProtocols::ZeroNine && method != HttpMethod::Get || parts.next().is_some() {
+            return Err(HttpError::ParseError);
         }
-        HttpResponse {
-            protocol,
-            status: HttpResponseStatusCode::OK,
-            options: HashMap::new(),
-            payload: Vec::new(),
+
+        let mut headers: Vec<(String, String)> = Vec::new();
+        let mut header_bytes: usize = 0;
+        if protocol != HttpProtocols::ZeroNine {
+            loop {
+                let line = Self::read_line(reader, limits.max_header_size)?;
+                if line.is_empty() {
+                    break;
+                }
+                header_bytes += line.len();
+                if headers.len() >= limits.max_headers || header_bytes > limits.max_header_bytes {
+                    return Err(HttpError::HeadersTooLarge);
+                }
+                // Folded continuation lines are obsolete, and whitespace before the colon is left in the name so that
+                // it gets rejected; both have been used to make proxies and servers disagree about a header.
+                if line.starts_with([' ', '\t']) {
+                    return Err(HttpError::ParseError);
+                }
+                let (name, value) = line.split_once(':').ok_or(HttpError::ParseError)?;
+                headers.push((name.to_string(), value.trim().to_string()));
+            }
         }
+
+        // An absolute target names the host itself, which then takes the place of the Host header.
+        let origin;
+        let target = match target.split_once("://") {
+            Some((_, rest)) if !target.starts_with('/') => {
+                let end = rest.find(['/', '?']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(HttpError::ParseError);
+                }
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Host"));
+                headers.push(("Host".to_string(), rest[..end].to_string()));
+                origin = if rest[end..].starts_with('/') { rest[end..].to_string() } else { format!("/{}", &rest[end..]) };
+                &origin
+            },
+            _ => target,
+        };
+        check_framing(protocol, &headers)?;
+        Self::from_parts(method, target, protocol, headers, Vec::new())
     }
 
-    pub fn set_status(&mut self, new_status: HttpResponseStatusCode) {
-        self.status = new_status;
+    // Builds a request from an already decoded head, as HTTP/2 delivers it.
+    pub fn from_parts(method: HttpMethod, target: &str, protocol: HttpProtocols, headers: Vec<(String, String)>, body: Vec<u8>) -> Result<HttpRequest, HttpError> {
+        let (raw_path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, None),
+        };
+        let path = percent_decode(raw_path).map_err(|_| HttpError::ParseError)?;
+        for (name, value) in &headers {
+            check_header_name(name)?;
+            check_header_value(name, value)?;
+        }
+        Ok(HttpRequest { method, target: target.to_string(), path, query, protocol, headers, body, params: HashMap::new(), client_ip: None, peer_ip: None, secure: false })
     }
 
-    pub fn append_option(&mut self, option: HttpResponseOptions, payload: &'static str) {
-        self.options.insert(option, payload);
+    pub fn read_body<R: BufRead>(&mut self, reader: &mut R, limits: &HttpRequestLimits) -> Result<(), HttpError> {
+        if self.headers().contains("Transfer-Encoding") {
+            if !self.is_chunked() {
+                return Err(HttpError::UnsupportedTransferEncoding);
+            }
+            let mut body = Vec::new();
+            read_chunked(reader, limits, |data| {
+                if data.len() > limits.max_body_size - body.len() {
+                    return Err(HttpError::BodyTooLarge);
+                }
+                body.extend_from_slice(data);
+                Ok(())
+            })?;
+            self.body = body;
+            // The body is whole now, so it is passed on as if it had been sent with its length.
+            self.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Transfer-Encoding"));
+            self.headers.push(("Content-Length".to_string(), self.body.len().to_string()));
+            return Ok(());
+        }
+        if let Some(length) = self.get_header("Content-Length") {
+            let length: usize = length.parse().map_err(|_| HttpError::ParseError)?;
+            if length > limits.max_body_size {
+                return Err(HttpError::BodyTooLarge);
+            }
+            self.body = vec![0; length];
+            reader.read_exact(&mut self.body).map_err(|_| HttpError::ReadFailed)?;
+        }
+        Ok(())
     }
 
-    pub fn append_payload(&mut self, payload: Vec<u8>) {
-        self.payload = payload
+    // Reads at most `max` bytes plus the line ending, so an endless line cannot exhaust memory.
+    fn read_line<R: BufRead>(reader: &mut R, max: usize) -> Result<String, HttpError> {
+        let mut line = String::new();
+        let read = Read::take(&mut *reader, max as u64 + 2).read_line(&mut line).map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidData => HttpError::ParseError,
+            _ => HttpError::ReadFailed,
+        })?;
+        if read == 0 {
+            return Err(HttpError::ReadFailed);
+        }
+        if !line.ends_with('\n') {
+            return Err(if read > max { HttpError::HeadersTooLarge } else { HttpError::ReadFailed });
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
     }
 
-    pub fn send(&self, stream: &mut TcpStream) {
-        let out: String = self.get_header();
-        stream.write_all(out.as_bytes()).unwrap_or(());
-        stream.write(&self.payload).unwrap_or(0);
+    pub fn get_method(&self) -> HttpMethod {
+        self.method
     }
 
-    pub fn get_header(&self) -> String {
-        let mut out: String = String::new();
-        out.push_str(self.protocol.get_name());
-        out.push_str(" ");
-        out.push_str(self.status.get_header());
-        out.push_str(Self::SEPARATOR);
-        for (key, value) in &self.options {
-            out.push_str(key.get_name());
-            out.push_str(": ");
-            out.push_str(value);
-            out.push_str(Self::SEPARATOR);
+    pub fn get_target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    pub fn query_params(&self) -> HashMap<String, Vec<String>> {
+        parse_urlencoded(self.query.as_deref().unwrap_or(""))
+    }
+
+    // The lowercased MIME type of the body, without parameters.
+    pub fn get_media_type(&self) -> Option<String> {
+        self.get_header("Content-Type").map(|value| value.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+    }
+
+    // Empty unless the body is application/x-www-form-urlencoded.
+    pub fn form_params(&self) -> HashMap<String, Vec<String>> {
+        if self.get_media_type().as_deref() != Some("application/x-www-form-urlencoded") {
+            return HashMap::new();
+        }
+        parse_urlencoded(&String::from_utf8_lossy(&self.body))
+    }
+
+    pub fn get_form_param(&self, name: &str) -> Option<String> {
+        self.form_params().remove(name)?.into_iter().next()
+    }
+
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, JsonError> {
+        let media_type = self.get_media_type();
+        if !media_type.as_deref().is_some_and(|media_type| media_type == "application/json" || media_type.ends_with("+json")) {
+            return Err(JsonError::UnsupportedMediaType);
+        }
+        serde_json::from_slice(&self.body).map_err(JsonError::Invalid)
+    }
+
+    // Fails with `ParseError` when the body is not multipart/form-data or has no boundary.
+    pub fn multipart(&self, limits: &MultipartLimits) -> Result<MultipartForm, HttpError> {
+        if self.get_media_type().as_deref() != Some("multipart/form-data") {
+            return Err(HttpError::ParseError);
+        }
+        let boundary = self.get_header("Content-Type")
+            .and_then(|value| value.split_once(';'))
+            .and_then(|(_, params)| header_params(params).into_iter().find(|(key, _)| key == "boundary"))
+            .map(|(_, boundary)| boundary)
+            .filter(|boundary| !boundary.is_empty())
+            .ok_or(HttpError::ParseError)?;
+        MultipartForm::parse(self.body.as_slice(), &boundary, limits)
+    }
+
+    pub fn cookies(&self) -> HashMap<String, String> {
+        self.headers().get_all("Cookie").into_iter().flat_map(Cookie::parse_pairs).collect()
+    }
+
+    pub fn get_cookie(&self, name: &str) -> Option<String> {
+        self.cookies().remove(name)
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|value| value.as_str())
+    }
+
+    pub fn get_protocol(&self) -> &HttpProtocols {
+        &self.protocol
+    }
+
+    pub fn get_headers(&self) -> &Vec<(String, String)> {
+        &self.headers
+    }
+
+    pub fn headers(&self) -> Headers<'_> {
+        Headers { fields: &self.headers }
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers().get(name)
+    }
+
+    // Whether the body is sent in chunks, the only transfer coding `read_body` understands.
+    pub fn is_chunked(&self) -> bool {
+        matches!(self.headers().get_list("Transfer-Encoding").as_slice(), [coding] if coding.eq_ignore_ascii_case("chunked"))
+    }
+
+    // None when the header is missing or not a number; `read_body` refuses the latter.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers().get("Content-Length").and_then(|length| length.trim().parse().ok())
+    }
+
+    // The name or address in the Host header, without the port or the brackets around an IPv6 address.
+    pub fn host(&self) -> Option<&str> {
+        let host = self.headers().get("Host")?.trim();
+        if let Some(address) = host.strip_prefix('[') {
+            return Some(address.split_once(']').map_or(address, |(address, _)| address));
+        }
+        Some(host.rsplit_once(':').map_or(host, |(name, _)| name))
+    }
+
+    // Whether Accept-Encoding allows `coding`, by name or through "*", with a weight above zero. Without the
+    // header, any coding is acceptable (RFC 9110, section 12.5.3).
+    pub fn accepts_encoding(&self, coding: &str) -> bool {
+        if !self.headers().contains("Accept-Encoding") {
+            return true;
+        }
+        let accepted = self.headers().get_list("Accept-Encoding");
+        let weight = |name: &str| accepted.iter()
+            .map(|entry| entry.split(';').map(str::trim))
+            .find_map(|mut parts| parts.next().filter(|found| found.eq_ignore_ascii_case(name)).map(|_| parts))
+            .map(|mut params| params.find_map(|param| param.strip_prefix("q=")).map_or(1.0, |q| q.parse::<f32>().unwrap_or(0.0)));
+        weight(coding).or_else(|| weight("*")).is_some_and(|weight| weight > 0.0)
+    }
+
+    pub fn accepts_gzip(&self) -> bool {
+        self.accepts_encoding("gzip")
+    }
+
+    // Replaces every existing value of the header, so later lookups see only this one.
+    pub fn set_header(&mut self, name: &str, value: impl Into<String>) {
+        self.headers.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.into()));
+    }
+
+    // The address of the client, as the server worked it out from the connection and any trusted proxies in front
+    // of it. Requests that were not received from a socket have none.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    pub fn set_client_ip(&mut self, ip: Option<IpAddr>) {
+        self.client_ip = ip;
+    }
+
+    // The other end of the connection, which is the last proxy rather than the client when there is one.
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        self.peer_ip
+    }
+
+    pub fn set_peer_ip(&mut self, ip: Option<IpAddr>) {
+        self.peer_ip = ip;
+    }
+
+    // "https" for requests that arrived over TLS, "http" otherwise.
+    pub fn scheme(&self) -> &'static str {
+        if self.secure { "https" } else { "http" }
+    }
+
+    pub fn set_secure(&mut self, secure: bool) {
+        self.secure = secure;
+    }
+
+    pub fn get_body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    pub fn wants_keep_alive(&self) -> bool {
+        let connection = self.get_header("Connection").map(|s| s.to_ascii_lowercase());
+        match connection.as_deref() {
+            Some(value) if value.contains("close") => false,
+            Some(value) if value.contains("keep-alive") => true,
+            _ => self.protocol == HttpProtocols::OneOne,
         }
-        out.push_str(Self::SEPARATOR);
-        out
     }
 }
 
-#[cfg(test)]
-mod tests {  }
+// The header fields of a request, looked up by name in any case. A field sent more than once keeps all its values,
+// in the order they arrived.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+pub struct Headers<'a> {
+    fields: &'a [(String, String)],
+}
+
+impl<'a> Headers<'a> {
+    // The first value of the field.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&'a str> {
+        self.iter().filter(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value).collect()
+    }
+
+    // The elements of a comma-separated list field such as Accept, however the client split them across lines.
+    pub fn get_list(&self, name: &str) -> Vec<&'a str> {
+        self.get_all(name).into_iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct MultipartLimits {
+    pub max_parts: usize,
+    pub max_field_size: usize,
+    pub max_file_size: u64,
+    // File parts up to this size stay in memory; larger ones are written to `temp_dir` as they arrive.
+    pub memory_threshold: usize,
+    pub temp_dir: PathBuf,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        MultipartLimits {
+            max_parts: 100,
+            max_field_size: 64 * 1024,
+            max_file_size: 100 * 1024 * 1024,
+            memory_threshold: 256 * 1024,
+            temp_dir: env::temp_dir(),
+        }
+    }
+}
+
+// An upload on disk. It is deleted when dropped unless it has been persisted.
+#[derive(Debug)]
+pub struct TempFile {
+    path: Option<PathBuf>,
+    length: u64,
+}
+
+impl TempFile {
+    fn create(dir: &Path) -> io::Result<(TempFile, File)> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.subsec_nanos());
+        let path = dir.join(format!("upload-{}-{nanos:x}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        Ok((TempFile { path: Some(path), length: 0 }, file))
+    }
+
+    pub fn get_path(&self) -> &Path {
+        self.path.as_deref().unwrap_or(Path::new(""))
+    }
+
+    pub fn get_length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn open(&self) -> io::Result<File> {
+        File::open(self.get_path())
+    }
+
+    // Moves the file to `destination`, copying when that is on another file system.
+    pub fn persist(mut self, destination: &Path) -> io::Result<()> {
+        let Some(path) = self.path.take() else {
+            return Ok(());
+        };
+        if fs::rename(&path, destination).is_err() {
+            fs::copy(&path, destination)?;
+            fs::remove_file(&path).unwrap_or(());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            fs::remove_file(path).unwrap_or(());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PartBody {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+#[derive(Debug)]
+pub struct FormPart {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    body: PartBody,
+}
+
+impl FormPart {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    // As sent by the client, so it may contain a path and must not be trusted as one.
+    pub fn get_filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn get_content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn get_body(&self) -> &PartBody {
+        &self.body
+    }
+
+    pub fn into_body(self) -> PartBody {
+        self.body
+    }
+
+    pub fn get_length(&self) -> u64 {
+        match &self.body {
+            PartBody::Memory(data) => data.len() as u64,
+            PartBody::File(file) => file.get_length(),
+        }
+    }
+
+    // `None` for parts on disk and for ones that are not UTF-8.
+    pub fn get_text(&self) -> Option<&str> {
+        match &self.body {
+            PartBody::Memory(data) => std::str::from_utf8(data).ok(),
+            PartBody::File(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MultipartForm {
+    parts: Vec<FormPart>,
+}
+
+impl MultipartForm {
+    // Reads a multipart/form-data body. Fields are kept in memory; files are spilled to disk past the threshold.
+    pub fn parse<R: Read>(reader: R, boundary: &str, limits: &MultipartLimits) -> Result<MultipartForm, HttpError> {
+        // The leading line break lets the first delimiter be found like every other one.
+        let mut scanner = PartScanner { reader, buffer: b"\r\n".to_vec() };
+        let delimiter = format!("\r\n--{boundary}").into_bytes();
+        scanner.copy_until(&delimiter, |_| Ok(()))?;
+
+        let mut parts = Vec::new();
+        loop {
+            // "--" right after a delimiter closes the body; otherwise the rest of the line is padding.
+            while scanner.buffer.len() < 2 && scanner.fill()? {}
+            if scanner.buffer.starts_with(b"--") {
+                break;
+            }
+            scanner.read_line(1024)?;
+            if parts.len() >= limits.max_parts {
+                return Err(HttpError::BodyTooLarge);
+            }
+
+            let mut headers: Vec<(String, String)> = Vec::new();
+            loop {
+                let line = scanner.read_line(8 * 1024)?;
+                if line.is_empty() {
+                    break;
+                }
+                if headers.len() >= 16 {
+                    return Err(HttpError::HeadersTooLarge);
+                }
+                let (name, value) = line.split_once(':').ok_or(HttpError::ParseError)?;
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+            let header = |name: &str| headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+            let disposition = header_params(header("content-disposition").ok_or(HttpError::ParseError)?);
+            let param = |name: &str| disposition.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone());
+            let name = param("name").ok_or(HttpError::ParseError)?;
+            let filename = param("filename");
+            let content_type = header("content-type").map(|value| value.to_string());
+
+            let body = match filename {
+                Some(_) => scanner.read_file(&delimiter, limits)?,
+                None => {
+                    let mut data = Vec::new();
+                    scanner.copy_until(&delimiter, |chunk| {
+                        if data.len() + chunk.len() > limits.max_field_size {
+                            return Err(HttpError::BodyTooLarge);
+                        }
+                        data.extend_from_slice(chunk);
+                        Ok(())
+                    })?;
+                    PartBody::Memory(data)
+                },
+            };
+            parts.push(FormPart { name, filename, content_type, body });
+        }
+        Ok(MultipartForm { parts })
+    }
+
+    pub fn get_parts(&self) -> &[FormPart] {
+        &self.parts
+    }
+
+    pub fn into_parts(self) -> Vec<FormPart> {
+        self.parts
+    }
+
+    // The first plain field of that name.
+    pub fn get_field(&self, name: &str) -> Option<&str> {
+        self.parts.iter().find(|part| part.name == name && part.filename.is_none()).and_then(|part| part.get_text())
+    }
+
+    pub fn get_file(&self, name: &str) -> Option<&FormPart> {
+        self.parts.iter().find(|part| part.name == name && part.filename.is_some())
+    }
+}
+
+// Buffers just enough of the body to find the next delimiter, so parts never have to fit in memory whole.
+struct PartScanner<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> PartScanner<R> {
+    // `false` once the reader is exhausted.
+    fn fill(&mut self) -> Result<bool, HttpError> {
+        let mut chunk = [0; 8 * 1024];
+        let read = self.reader.read(&mut chunk).map_err(|_| HttpError::ReadFailed)?;
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+
+    fn read_line(&mut self, max: usize) -> Result<String, HttpError> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8(line).map_err(|_| HttpError::ParseError)?;
+                return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+            }
+            if self.buffer.len() > max {
+                return Err(HttpError::HeadersTooLarge);
+            }
+            if !self.fill()? {
+                return Err(HttpError::Incomplete);
+            }
+        }
+    }
+
+    // Hands everything before the delimiter to `sink` as it arrives, then consumes the delimiter.
+    fn copy_until(&mut self, delimiter: &[u8], mut sink: impl FnMut(&[u8]) -> Result<(), HttpError>) -> Result<(), HttpError> {
+        loop {
+            if let Some(at) = self.buffer.windows(delimiter.len()).position(|window| window == delimiter) {
+                sink(&self.buffer[..at])?;
+                self.buffer.drain(..at + delimiter.len());
+                return Ok(());
+            }
+            // The tail could be the start of a delimiter, so it waits for more data.
+            let complete = self.buffer.len().saturating_sub(delimiter.len() - 1);
+            sink(&self.buffer[..complete])?;
+            self.buffer.drain(..complete);
+            if !self.fill()? {
+                return Err(HttpError::Incomplete);
+            }
+        }
+    }
+
+    fn read_file(&mut self, delimiter: &[u8], limits: &MultipartLimits) -> Result<PartBody, HttpError> {
+        let mut memory: Vec<u8> = Vec::new();
+        let mut spilled: Option<(TempFile, File)> = None;
+        let mut length: u64 = 0;
+        self.copy_until(delimiter, |chunk| {
+            length += chunk.len() as u64;
+            if length > limits.max_file_size {
+                return Err(HttpError::BodyTooLarge);
+            }
+            if spilled.is_none() && memory.len() + chunk.len() > limits.memory_threshold {
+                let (temp, mut file) = TempFile::create(&limits.temp_dir).map_err(|_| HttpError::ReadFailed)?;
+                file.write_all(&memory).map_err(|_| HttpError::ReadFailed)?;
+                memory = Vec::new();
+                spilled = Some((temp, file));
+            }
+            match &mut spilled {
+                Some((_, file)) => file.write_all(chunk).map_err(|_| HttpError::ReadFailed),
+                None => {
+                    memory.extend_from_slice(chunk);
+                    Ok(())
+                },
+            }
+        })?;
+
+        Ok(match spilled {
+            Some((mut temp, mut file)) => {
+                file.flush().map_err(|_| HttpError::ReadFailed)?;
+                temp.length = length;
+                PartBody::File(temp)
+            },
+            None => PartBody::Memory(memory),
+        })
+    }
+}
+
+// The `key=value` parameters of a header such as Content-Type or Content-Disposition, with keys lowercased and
+// quoted values unescaped. Whatever precedes the first parameter is skipped.
+fn header_params(input: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = input;
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.rsplit(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut end = quoted.len();
+                let mut chars = quoted.char_indices();
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, escaped)| escaped)),
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        },
+                        c => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            },
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            },
+        };
+        params.push((key, value));
+        rest = remaining;
+    }
+    params
+}
+
+#[derive(Debug)]
+pub struct FileBody {
+    file: File,
+    offset: u64,
+    length: u64,
+}
+
+impl FileBody {
+    pub fn get_file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn get_offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_length(&self) -> u64 {
+        self.length
+    }
+
+    // Copies through a fixed-size buffer, so memory use does not grow with the file size.
+    pub fn copy_to<W: Write + ?Sized>(&self, stream: &mut W) -> io::Result<u64> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.offset))?;
+        io::copy(&mut file.take(self.length), stream)
+    }
+}
+
+// Open files cannot be compared, so two file bodies are equal when they cover the same byte range.
+impl PartialEq for FileBody {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset && self.length == other.length
+    }
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct HttpResponse {
+    protocol: HttpProtocols,
+    status: HttpResponseStatusCode,
+    options: HashMap<HttpResponseOptions, Vec<String>>,
+    payload: Vec<u8>,
+    file_body: Option<FileBody>,
+    events: Option<EventStream>,
+    body_suppressed: bool,
+}
+
+impl HttpResponse {
+    pub const SEPARATOR: &'static str = "\r\n";
+
+    pub fn new(protocol: HttpProtocols) -> HttpResponse {
+        if protocol != HttpProtocols::OneOne {
+            log::warn!("You are using the \"{}\" HTTP protocol, which is not directly supported by this library. Only proceed if you know what you are doing!", protocol);
+        }
+        HttpResponse {
+            protocol,
+            status: HttpResponseStatusCode::OK,
+            options: HashMap::new(),
+            payload: Vec::new(),
+            file_body: None,
+            events: None,
+            body_suppressed: false,
+        }
+    }
+
+    pub fn builder() -> HttpResponseBuilder {
+        HttpResponseBuilder { response: HttpResponse::new(HttpProtocols::OneOne) }
+    }
+
+    pub fn ok_html(body: impl Into<Vec<u8>>) -> HttpResponse {
+        HttpResponse::builder().content_type("text/html; charset=utf-8").body(body).build()
+    }
+
+    pub fn ok_text(body: impl Into<Vec<u8>>) -> HttpResponse {
+        HttpResponse::builder().content_type("text/plain; charset=utf-8").body(body).build()
+    }
+
+    // Values that cannot be serialized, such as maps with non-string keys, give an empty 500.
+    #[cfg(feature = "json")]
+    pub fn json(value: &impl serde::Serialize) -> HttpResponse {
+        match serde_json::to_vec(value) {
+            Ok(body) => HttpResponse::builder().content_type("application/json").body(body).build(),
+            Err(err) => {
+                log::error!("Failed to serialize a JSON response: {}", err);
+                HttpResponse::builder().status(HttpResponseStatusCode::InternalServerError).body("").build()
+            },
+        }
+    }
+
+    // An HTML page from the templates installed with `Templates::install`. A missing template or one that fails to
+    // render gives an empty 500.
+    #[cfg(feature = "templates")]
+    pub fn render(name: &str, context: &impl serde::Serialize) -> HttpResponse {
+        let rendered = Templates::installed()
+            .ok_or_else(|| TemplateError::NotFound(name.to_string()))
+            .and_then(|templates| templates.render(name, context));
+        match rendered {
+            Ok(body) => HttpResponse::ok_html(body),
+            Err(err) => {
+                log::error!("Failed to render a template: {}", err);
+                HttpResponse::builder().status(HttpResponseStatusCode::InternalServerError).body("").build()
+            },
+        }
+    }
+
+    // A text/event-stream response that stays open for the events pushed through the returned sender, until every
+    // clone of it is dropped or the client goes away.
+    pub fn event_stream() -> (HttpResponse, EventSender) {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_BUFFER);
+        let mut response = HttpResponse::builder()
+            .content_type("text/event-stream")
+            .header(HttpResponseOptions::CacheControl, "no-cache")
+            .build();
+        response.events = Some(EventStream { receiver });
+        (response, EventSender { sender })
+    }
+
+    pub fn no_content() -> HttpResponse {
+        HttpResponse::builder().status(HttpResponseStatusCode::NoContent).build()
+    }
+
+    pub fn not_found() -> HttpResponse {
+        HttpResponse::builder().status(HttpResponseStatusCode::NotFound).content_type("text/plain; charset=utf-8").body("404 Not Found").build()
+    }
+
+    pub fn redirect(status: HttpResponseStatusCode, location: impl Into<String>) -> HttpResponse {
+        HttpResponse::builder()
+            .status(status)
+            .header(HttpResponseOptions::Location, location)
+            .header(HttpResponseOptions::ContentLength, "0")
+            .build()
+    }
+
+    pub fn set_protocol(&mut self, protocol: HttpProtocols) {
+        self.protocol = protocol;
+    }
+
+    pub fn get_protocol(&self) -> &HttpProtocols {
+        &self.protocol
+    }
+
+    pub fn set_status(&mut self, new_status: HttpResponseStatusCode) {
+        self.status = new_status;
+    }
+
+    pub fn get_status(&self) -> &HttpResponseStatusCode {
+        &self.status
+    }
+
+    pub fn append_option(&mut self, option: HttpResponseOptions, payload: impl Into<String>) {
+        self.options.insert(option, vec![payload.into()]);
+    }
+
+    // Like `append_option`, but refuses a value that would break the header apart when sent.
+    pub fn try_append_option(&mut self, option: HttpResponseOptions, payload: impl Into<String>) -> Result<(), HttpError> {
+        let payload = payload.into();
+        check_header_name(option.get_name())?;
+        check_header_value(option.get_name(), &payload)?;
+        self.append_option(option, payload);
+        Ok(())
+    }
+
+    pub fn add_option(&mut self, option: HttpResponseOptions, payload: impl Into<String>) {
+        if option.is_repeatable() {
+            self.options.entry(option).or_default().push(payload.into());
+        } else {
+            self.append_option(option, payload);
+        }
+    }
+
+    // The type and its charset in one header, such as "text/csv; charset=utf-8"; no charset or an empty one leaves it
+    // out.
+    pub fn set_content_type(&mut self, media_type: &str, charset: Option<&str>) {
+        self.append_option(HttpResponseOptions::ContentType, with_charset(media_type, charset));
+    }
+
+    pub fn add_vary(&mut self, field: &str) {
+        let vary = match self.get_option(&HttpResponseOptions::Vary) {
+            Some(existing) if existing.split(',').any(|value| value.trim().eq_ignore_ascii_case(field)) => return,
+            Some(existing) => format!("{existing}, {field}"),
+            None => field.to_string(),
+        };
+        self.append_option(HttpResponseOptions::Vary, vary);
+    }
+
+    pub fn set_cookie(&mut self, cookie: &Cookie) {
+        self.add_option(HttpResponseOptions::SetCookie, cookie.to_header_value());
+    }
+
+    pub fn get_option(&self, option: &HttpResponseOptions) -> Option<&str> {
+        self.options.get(option).and_then(|values| values.first()).map(|s| s.as_str())
+    }
+
+    pub fn get_headers(&self) -> Vec<(&str, &str)> {
+        self.options.iter()
+            .flat_map(|(option, values)| values.iter().map(move |value| (option.get_name(), value.as_str())))
+            .collect()
+    }
+
+    pub fn get_option_values(&self, option: &HttpResponseOptions) -> &[String] {
+        self.options.get(option).map_or(&[], |values| values.as_slice())
+    }
+
+    pub fn remove_option(&mut self, option: &HttpResponseOptions) -> Option<Vec<String>> {
+        self.options.remove(option)
+    }
+
+    pub fn append_payload(&mut self, payload: Vec<u8>) {
+        self.payload = payload
+    }
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    // Streams `length` bytes of `file` starting at `offset` instead of the payload when the response is sent.
+    pub fn set_file_body(&mut self, file: File, offset: u64, length: u64) {
+        self.file_body = Some(FileBody { file, offset, length });
+    }
+
+    pub fn get_file_body(&self) -> Option<&FileBody> {
+        self.file_body.as_ref()
+    }
+
+    pub fn is_event_stream(&self) -> bool {
+        self.events.is_some()
+    }
+
+    pub fn take_event_stream(&mut self) -> Option<EventStream> {
+        self.events.take()
+    }
+
+    pub fn get_body_length(&self) -> usize {
+        self.file_body.as_ref().map_or(self.payload.len(), |body| body.length as usize)
+    }
+
+    pub fn set_body_suppressed(&mut self, suppressed: bool) {
+        self.body_suppressed = suppressed;
+    }
+
+    pub fn is_body_suppressed(&self) -> bool {
+        self.body_suppressed
+    }
+
+    // Whether `send` writes the body: not for HEAD, and never for a status that cannot have one.
+    pub fn has_body(&self) -> bool {
+        !self.body_suppressed && self.status.allows_body()
+    }
+
+    // The response exactly as `send` would write it. A file body that cannot be read ends early.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.get_body_length() + 256);
+        self.send(&mut out).unwrap_or(0);
+        out
+    }
+
+    // Reads a complete response, as written by `to_bytes` or received from another server. A chunked body is
+    // decoded and given a Content-Length instead; without either header, the body is everything after the head.
+    pub fn parse(bytes: &[u8]) -> Result<HttpResponse, HttpError> {
+        let head_end = bytes.windows(4).position(|window| window == b"\r\n\r\n").ok_or(HttpError::Incomplete)?;
+        let head = std::str::from_utf8(&bytes[..head_end]).map_err(|_| HttpError::ParseError)?;
+        let body = &bytes[head_end + 4..];
+
+        let mut lines = head.split(Self::SEPARATOR);
+        let (version, status) = lines.next().and_then(|line| line.split_once(' ')).ok_or(HttpError::ParseError)?;
+        let protocol = version.parse::<HttpProtocols>()
+            .ok()
+            .filter(|protocol| *protocol != HttpProtocols::ZeroNine)
+            .ok_or(HttpError::UnsupportedVersion)?;
+        let status: HttpResponseStatusCode = status.parse()?;
+
+        let mut response = HttpResponse {
+            protocol,
+            status,
+            options: HashMap::new(),
+            payload: Vec::new(),
+            file_body: None,
+            events: None,
+            body_suppressed: false,
+        };
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or(HttpError::ParseError)?;
+            check_header_name(name.trim())?;
+            check_header_value(name.trim(), value)?;
+            response.options.entry(HttpResponseOptions::from_name(name.trim())).or_default().push(value.trim().to_string());
+        }
+
+        let chunked = response.get_option(&HttpResponseOptions::TransferEncoding).is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+        response.payload = if chunked {
+            let payload = decode_chunked(body)?;
+            response.options.remove(&HttpResponseOptions::TransferEncoding);
+            response.append_option(HttpResponseOptions::ContentLength, payload.len().to_string());
+            payload
+        } else {
+            match response.get_option(&HttpResponseOptions::ContentLength) {
+                Some(length) => {
+                    let length: usize = length.parse().map_err(|_| HttpError::ParseError)?;
+                    body.get(..length).ok_or(HttpError::Incomplete)?.to_vec()
+                },
+                None => body.to_vec(),
+            }
+        };
+        Ok(response)
+    }
+
+    // Returns the number of bytes written, including the header. The body is framed by a Content-Length that
+    // matches it, whatever the response claimed before.
+    pub fn send<W: Write + ?Sized>(&self, stream: &mut W) -> io::Result<usize> {
+        let header = self.get_framed_header();
+        stream.write_all(header.as_bytes())?;
+        let body = if !self.has_body() {
+            0
+        } else {
+            match &self.file_body {
+                Some(body) => body.copy_to(stream)? as usize,
+                None => {
+                    stream.write_all(&self.payload)?;
+                    self.payload.len()
+                },
+            }
+        };
+        Ok(header.len() + body)
+    }
+
+    // Older clients cannot decode chunks, so their body is written as-is and ends when the connection closes.
+    // A status without a body gets no framing at all, and whatever is written is dropped.
+    pub fn send_chunked<'a, W: Write>(&mut self, stream: &'a mut W) -> io::Result<ChunkedResponse<'a>> {
+        let allowed = self.status.allows_body();
+        let framed = allowed && self.protocol.supports_chunked();
+        self.options.remove(&HttpResponseOptions::ContentLength);
+        if framed {
+            self.append_option(HttpResponseOptions::TransferEncoding, "chunked");
+        } else if allowed {
+            self.append_option(HttpResponseOptions::Connection, "close");
+        }
+        stream.write_all(self.get_header().as_bytes())?;
+        Ok(ChunkedResponse { stream, suppressed: self.body_suppressed || !allowed, framed })
+    }
+
+    // The head with the framing headers as given, for callers that frame the body themselves. Only a status that
+    // cannot have a body loses them.
+    pub fn get_header(&self) -> String {
+        self.head(None)
+    }
+
+    // The head as `send` writes it: Content-Length is the length of the body that follows. HEAD responses keep the
+    // length they announce, since no body follows to measure.
+    pub fn get_framed_header(&self) -> String {
+        self.head(self.has_body().then(|| self.get_body_length()))
+    }
+
+    // HTTP/0.9 responses have no status line or headers at all.
+    fn head(&self, content_length: Option<usize>) -> String {
+        if self.protocol == HttpProtocols::ZeroNine {
+            return String::new();
+        }
+        let mut out: String = String::new();
+        out.push_str(&format!("{} {}{}", self.protocol, self.status, Self::SEPARATOR));
+        // Every response carries the time it was sent, unless it was given one already.
+        if !self.options.contains_key(&HttpResponseOptions::Date) {
+            out.push_str("Date: ");
+            out.push_str(&http_date_now());
+            out.push_str(Self::SEPARATOR);
+        }
+        for (key, values) in &self.options {
+            let framing = matches!(key, HttpResponseOptions::ContentLength | HttpResponseOptions::TransferEncoding);
+            if framing && (content_length.is_some() || !self.status.allows_body()) {
+                continue;
+            }
+            for value in values {
+                out.push_str(key.get_name());
+                out.push_str(": ");
+                out.push_str(value);
+                out.push_str(Self::SEPARATOR);
+            }
+        }
+        if let Some(length) = content_length {
+            out.push_str(&format!("Content-Length: {length}{}", Self::SEPARATOR));
+        }
+        out.push_str(Self::SEPARATOR);
+        out
+    }
+}
+
+// The current time as an IMF-fixdate, for the Date header. It only changes once a second, so each thread formats it
+// at most that often.
+pub fn http_date_now() -> String {
+    thread_local! {
+        static CACHED: RefCell<(u64, String)> = const { RefCell::new((0, String::new())) };
+    }
+    let now = SystemTime::now();
+    let second = now.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    CACHED.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        if cached.0 != second || cached.1.is_empty() {
+            *cached = (second, httpdate::fmt_http_date(now));
+        }
+        cached.1.clone()
+    })
+}
+
+// Trailers after the last chunk are skipped.
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, HttpError> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n").ok_or(HttpError::Incomplete)?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()
+            .map(|line| line.split(';').next().unwrap_or("").trim())
+            .and_then(|size| usize::from_str_radix(size, 16).ok())
+            .ok_or(HttpError::ParseError)?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        let chunk = body.get(..size).ok_or(HttpError::Incomplete)?;
+        out.extend_from_slice(chunk);
+        body = body.get(size..).and_then(|rest| rest.strip_prefix(b"\r\n")).ok_or(HttpError::Incomplete)?;
+    }
+}
+
+pub struct HttpResponseBuilder {
+    response: HttpResponse,
+}
+
+impl HttpResponseBuilder {
+    pub fn status(mut self, status: HttpResponseStatusCode) -> Self {
+        self.response.set_status(status);
+        self
+    }
+
+    pub fn header(mut self, option: HttpResponseOptions, value: impl Into<String>) -> Self {
+        self.response.add_option(option, value);
+        self
+    }
+
+    pub fn content_type(self, content_type: impl Into<String>) -> Self {
+        self.header(HttpResponseOptions::ContentType, content_type)
+    }
+
+    pub fn cookie(mut self, cookie: &Cookie) -> Self {
+        self.response.set_cookie(cookie);
+        self
+    }
+
+    // Also sets Content-Length to match the body.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        let body = body.into();
+        self.response.append_option(HttpResponseOptions::ContentLength, body.len().to_string());
+        self.response.append_payload(body);
+        self
+    }
+
+    pub fn build(self) -> HttpResponse {
+        self.response
+    }
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone, Copy)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<i64>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Cookie {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn parse_pairs(header: &str) -> Vec<(String, String)> {
+        header.split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().trim_matches('"').to_string()))
+            .filter(|(name, _)| !name.is_empty())
+            .collect()
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Cookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Cookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn max_age(mut self, seconds: i64) -> Cookie {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn expires(mut self, time: SystemTime) -> Cookie {
+        self.expires = Some(time);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    // Browsers drop `SameSite=None` cookies that are not also `Secure`, so that combination forces it on.
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.secure |= same_site == SameSite::None;
+        self.same_site = Some(same_site);
+        self
+    }
+
+    // Expires the cookie immediately, which makes browsers delete it.
+    pub fn removal(name: impl Into<String>) -> Cookie {
+        Cookie::new(name, "").max_age(0).expires(SystemTime::UNIX_EPOCH)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn to_header_value(&self) -> String {
+        let mut out = format!("{}={}", self.name, self.value);
+        if let Some(path) = &self.path {
+            out.push_str(&format!("; Path={path}"));
+        }
+        if let Some(domain) = &self.domain {
+            out.push_str(&format!("; Domain={domain}"));
+        }
+        if let Some(max_age) = self.max_age {
+            out.push_str(&format!("; Max-Age={max_age}"));
+        }
+        if let Some(expires) = self.expires {
+            out.push_str(&format!("; Expires={}", httpdate::fmt_http_date(expires)));
+        }
+        if self.secure {
+            out.push_str("; Secure");
+        }
+        if self.http_only {
+            out.push_str("; HttpOnly");
+        }
+        if let Some(same_site) = self.same_site {
+            out.push_str(&format!("; SameSite={}", same_site.get_name()));
+        }
+        out
+    }
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum HttpRangeError {
+    Invalid,
+    Unsatisfiable,
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone, Copy)]
+pub struct ByteRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+// More ranges than this in one request are not worth a part each, and the Range header is ignored instead.
+const MAX_RANGES: usize = 16;
+
+impl ByteRange {
+    pub fn parse(header: &str, length: usize) -> Result<ByteRange, HttpRangeError> {
+        let spec = header.trim().strip_prefix("bytes=").ok_or(HttpRangeError::Invalid)?.trim();
+        if spec.contains(',') {
+            return Err(HttpRangeError::Invalid);
+        }
+        Self::parse_spec(spec, length)
+    }
+
+    // "bytes=0-99, 200-299". Ranges that start past the end are left out, and overlapping or adjacent ones are merged
+    // and put in order, so that a request cannot have the same bytes sent over and over.
+    pub fn parse_all(header: &str, length: usize) -> Result<Vec<ByteRange>, HttpRangeError> {
+        let specs: Vec<&str> = header.trim().strip_prefix("bytes=").ok_or(HttpRangeError::Invalid)?
+            .split(',')
+            .map(str::trim)
+            .filter(|spec| !spec.is_empty())
+            .collect();
+        if specs.is_empty() || specs.len() > MAX_RANGES {
+            return Err(HttpRangeError::Invalid);
+        }
+
+        let mut ranges = Vec::with_capacity(specs.len());
+        for spec in specs {
+            match Self::parse_spec(spec, length) {
+                Ok(range) => ranges.push(range),
+                Err(HttpRangeError::Unsatisfiable) => {},
+                Err(err) => return Err(err),
+            }
+        }
+        ranges.sort_unstable_by_key(|range| range.start);
+        let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end + 1 => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        if merged.is_empty() {
+            return Err(HttpRangeError::Unsatisfiable);
+        }
+        Ok(merged)
+    }
+
+    fn parse_spec(spec: &str, length: usize) -> Result<ByteRange, HttpRangeError> {
+        let (start, end) = spec.split_once('-').ok_or(HttpRangeError::Invalid)?;
+        let (start, end) = (start.trim(), end.trim());
+
+        let range = if start.is_empty() {
+            let suffix: usize = end.parse().map_err(|_| HttpRangeError::Invalid)?;
+            if suffix == 0 || length == 0 {
+                return Err(HttpRangeError::Unsatisfiable);
+            }
+            ByteRange { start: length.saturating_sub(suffix), end: length - 1 }
+        } else {
+            let start: usize = start.parse().map_err(|_| HttpRangeError::Invalid)?;
+            let end: usize = match end {
+                "" => usize::MAX,
+                end => end.parse().map_err(|_| HttpRangeError::Invalid)?,
+            };
+            if end < start {
+                return Err(HttpRangeError::Invalid);
+            }
+            if start >= length {
+                return Err(HttpRangeError::Unsatisfiable);
+            }
+            ByteRange { start, end: end.min(length - 1) }
+        };
+
+        Ok(range)
+    }
+
+    pub fn get_length(&self) -> usize {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, length: usize) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, length)
+    }
+}
+
+// The multipart/byteranges body for several ranges of something `length` bytes long, each part with its type and its
+// own Content-Range.
+pub fn multipart_byteranges(parts: &[(ByteRange, &[u8])], length: usize, content_type: &str, boundary: &str) -> Vec<u8> {
+    let mut body = Vec::with_capacity(parts.iter().map(|(_, data)| data.len() + 128).sum());
+    for (range, data) in parts {
+        body.extend_from_slice(format!("\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n", range.content_range(length)).as_bytes());
+        body.extend_from_slice(data);
+    }
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    body
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum HttpPathError {
+    Malformed,
+    Traversal,
+}
+
+pub fn percent_decode(input: &str) -> Result<String, HttpPathError> {
+    let bytes = input.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).ok_or(HttpPathError::Malformed)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| HttpPathError::Malformed)?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| HttpPathError::Malformed)?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| HttpPathError::Malformed)
+}
+
+pub fn percent_encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(byte as char),
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+pub fn decode_query_component(input: &str) -> Result<String, HttpPathError> {
+    percent_decode(&input.replace('+', " "))
+}
+
+// Query strings and form bodies share this format. Badly escaped components are kept as they are.
+pub fn parse_urlencoded(input: &str) -> HashMap<String, Vec<String>> {
+    let mut params: HashMap<String, Vec<String>> = HashMap::new();
+    for pair in input.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let key = decode_query_component(key).unwrap_or_else(|_| key.to_string());
+        let value = decode_query_component(value).unwrap_or_else(|_| value.to_string());
+        params.entry(key).or_default().push(value);
+    }
+    params
+}
+
+pub fn normalize_path(decoded: &str) -> Result<String, HttpPathError> {
+    if decoded.contains(['\0', '\\']) {
+        return Err(HttpPathError::Traversal);
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => {
+                segments.pop().ok_or(HttpPathError::Traversal)?;
+            },
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut out = format!("/{}", segments.join("/"));
+    if decoded.ends_with('/') && !segments.is_empty() {
+        out.push('/');
+    }
+    Ok(out)
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct MimeRegistry {
+    types: HashMap<String, String>,
+    charsets: HashMap<String, String>,
+    default_charset: String,
+}
+
+impl MimeRegistry {
+    pub const FALLBACK: &'static str = "application/octet-stream";
+
+    const DEFAULTS: &'static [(&'static str, &'static str)] = &[
+        ("html", "text/html"),
+        ("htm", "text/html"),
+        ("css", "text/css"),
+        ("js", "application/javascript"),
+        ("mjs", "application/javascript"),
+        ("json", "application/json"),
+        ("map", "application/json"),
+        ("webmanifest", "application/manifest+json"),
+        ("xml", "application/xml"),
+        ("txt", "text/plain"),
+        ("csv", "text/csv"),
+        ("md", "text/markdown"),
+        ("png", "image/png"),
+        ("jpg", "image/jpeg"),
+        ("jpeg", "image/jpeg"),
+        ("gif", "image/gif"),
+        ("webp", "image/webp"),
+        ("avif", "image/avif"),
+        ("svg", "image/svg+xml"),
+        ("ico", "image/x-icon"),
+        ("bmp", "image/bmp"),
+        ("tif", "image/tiff"),
+        ("tiff", "image/tiff"),
+        ("woff", "font/woff"),
+        ("woff2", "font/woff2"),
+        ("ttf", "font/ttf"),
+        ("otf", "font/otf"),
+        ("eot", "application/vnd.ms-fontobject"),
+        ("mp4", "video/mp4"),
+        ("webm", "video/webm"),
+        ("ogv", "video/ogg"),
+        ("mov", "video/quicktime"),
+        ("mp3", "audio/mpeg"),
+        ("ogg", "audio/ogg"),
+        ("wav", "audio/wav"),
+        ("flac", "audio/flac"),
+        ("m4a", "audio/mp4"),
+        ("aac", "audio/aac"),
+        ("pdf", "application/pdf"),
+        ("zip", "application/zip"),
+        ("gz", "application/gzip"),
+        ("tar", "application/x-tar"),
+        ("7z", "application/x-7z-compressed"),
+        ("wasm", "application/wasm"),
+        ("rtf", "application/rtf"),
+        ("epub", "application/epub+zip"),
+    ];
+
+    pub fn new() -> MimeRegistry {
+        let mut registry = MimeRegistry { types: HashMap::new(), charsets: HashMap::new(), default_charset: "utf-8".to_string() };
+        for (extension, mime) in Self::DEFAULTS {
+            registry.insert(extension, mime);
+        }
+        registry
+    }
+
+    pub fn insert(&mut self, extension: &str, mime: &str) {
+        self.types.insert(extension.trim_start_matches('.').to_ascii_lowercase(), mime.to_string());
+    }
+
+    pub fn get(&self, extension: &str) -> Option<&str> {
+        self.types.get(&extension.to_ascii_lowercase()).map(|s| s.as_str())
+    }
+
+    pub fn lookup(&self, extension: Option<&str>) -> &str {
+        extension.and_then(|extension| self.get(extension)).unwrap_or(Self::FALLBACK)
+    }
+
+    // An empty charset sends the type without one.
+    pub fn set_charset(&mut self, extension: &str, charset: &str) {
+        self.charsets.insert(extension.trim_start_matches('.').to_ascii_lowercase(), charset.to_string());
+    }
+
+    pub fn set_default_charset(&mut self, charset: &str) {
+        self.default_charset = charset.to_string();
+    }
+
+    // The Content-Type for files with this extension. A charset set for the extension is always added; otherwise text
+    // types get the default one. A type that names its own parameters is left as it is.
+    pub fn content_type(&self, extension: Option<&str>) -> String {
+        let media_type = self.lookup(extension);
+        if media_type.contains(';') {
+            return media_type.to_string();
+        }
+        let charset = match extension.and_then(|extension| self.charsets.get(&extension.to_ascii_lowercase())) {
+            Some(charset) => charset,
+            None if is_text(media_type) => &self.default_charset,
+            None => "",
+        };
+        with_charset(media_type, Some(charset))
+    }
+}
+
+// Types that are read as characters, so their charset matters.
+pub fn is_text(media_type: &str) -> bool {
+    let media_type = media_type.split(';').next().unwrap_or("").trim();
+    media_type.starts_with("text/") || media_type == "application/javascript"
+}
+
+fn with_charset(media_type: &str, charset: Option<&str>) -> String {
+    match charset.filter(|charset| !charset.is_empty()) {
+        Some(charset) => format!("{media_type}; charset={charset}"),
+        None => media_type.to_string(),
+    }
+}
+
+impl Default for MimeRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct ChunkedResponse<'a> {
+    stream: &'a mut dyn Write,
+    suppressed: bool,
+    framed: bool,
+}
+
+impl ChunkedResponse<'_> {
+    pub fn write_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.suppressed || data.is_empty() {
+            return Ok(());
+        }
+        if !self.framed {
+            return self.stream.write_all(data);
+        }
+        self.stream.write_all(format!("{:X}{}", data.len(), HttpResponse::SEPARATOR).as_bytes())?;
+        self.stream.write_all(data)?;
+        self.stream.write_all(HttpResponse::SEPARATOR.as_bytes())
+    }
+
+    // Pushes what was written so far to the client, for bodies that are produced slowly.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        if !self.suppressed && self.framed {
+            self.stream.write_all(format!("0{0}{0}", HttpResponse::SEPARATOR).as_bytes())?;
+        }
+        self.stream.flush()
+    }
+}
+
+// Frames a sender may queue before `send` waits for the client to catch up.
+const EVENT_BUFFER: usize = 64;
+
+// One server-sent event. Data with several lines becomes one "data:" field per line, which the client joins again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+    data: String,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event { event: None, id: None, retry: None, data: data.into() }
+    }
+
+    // Line breaks would end the field early, so they are removed from the name and ID.
+    pub fn event(mut self, name: impl Into<String>) -> Event {
+        self.event = Some(name.into().replace(['\r', '\n'], ""));
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into().replace(['\r', '\n', '\0'], ""));
+        self
+    }
+
+    // How long the client waits before reconnecting after the stream ends.
+    pub fn retry(mut self, delay: Duration) -> Event {
+        self.retry = Some(delay);
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {event}\n"));
+        }
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {id}\n"));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split('\n') {
+            out.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+        out.push('\n');
+        out.into_bytes()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventStreamClosed;
+
+impl fmt::Display for EventStreamClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the event stream was closed")
+    }
+}
+
+impl std::error::Error for EventStreamClosed {}
+
+// The handler's end of an event stream. It can be cloned and moved to other threads; sending fails once the client
+// has gone away, which is the cue to stop producing events.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl EventSender {
+    pub fn send(&self, event: &Event) -> Result<(), EventStreamClosed> {
+        self.sender.send(event.to_bytes()).map_err(|_| EventStreamClosed)
+    }
+
+    pub fn send_data(&self, data: impl Into<String>) -> Result<(), EventStreamClosed> {
+        self.send(&Event::new(data))
+    }
+
+    // Comments are ignored by clients. Each line of `text` becomes its own comment.
+    pub fn comment(&self, text: &str) -> Result<(), EventStreamClosed> {
+        let mut frame: String = text.lines().map(|line| format!(": {line}\n")).collect();
+        frame.push('\n');
+        self.sender.send(frame.into_bytes()).map_err(|_| EventStreamClosed)
+    }
+}
+
+// The server's end of an event stream, taken from the response before it is sent. Dropping it closes the stream.
+#[derive(Debug)]
+pub struct EventStream {
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl EventStream {
+    // The next encoded frame, or `Disconnected` once every sender is gone and the queue is empty.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Vec<u8>, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+}
+
+// Two responses are equal regardless of the stream behind them.
+impl PartialEq for EventStream {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "templates")]
+static INSTALLED_TEMPLATES: std::sync::RwLock<Option<std::sync::Arc<Templates>>> = std::sync::RwLock::new(None);
+
+#[cfg(feature = "templates")]
+const MAX_PARTIAL_DEPTH: usize = 32;
+
+// Named templates in a small Handlebars-like syntax:
+//   {{ user.name }}                 a value from the context, HTML-escaped ({{{ raw }}} is not escaped)
+//   {{#if admin}} .. {{else}} .. {{/if}}, and {{#unless ..}}
+//   {{#each items}} {{@index}}: {{ name }} {{else}} none {{/each}}, where names are looked up on the item first,
+//                                   {{ this }} is the item itself and objects give {{@key}}
+//   {{> header}}                    another template, with the same context
+//   {{! a comment }}
+#[cfg(feature = "templates")]
+#[derive(Debug, Default)]
+pub struct Templates {
+    templates: HashMap<String, Vec<TemplateNode>>,
+}
+
+#[cfg(feature = "templates")]
+impl Templates {
+    pub fn new() -> Templates {
+        Templates::default()
+    }
+
+    // Every file below `dir`, named by its path relative to it without the extension, so "errors/404.html" is
+    // "errors/404". Hidden files are skipped.
+    pub fn load_dir(dir: &Path) -> Result<Templates, TemplateError> {
+        let mut templates = Templates::new();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            let entries = fs::read_dir(&current).map_err(|err| TemplateError::Read(current.clone(), err))?;
+            for entry in entries {
+                let path = entry.map_err(|err| TemplateError::Read(current.clone(), err))?.path();
+                if path.file_name().and_then(|name| name.to_str()).is_none_or(|name| name.starts_with('.')) {
+                    continue;
+                }
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let source = fs::read_to_string(&path).map_err(|err| TemplateError::Read(path.clone(), err))?;
+                let relative = path.strip_prefix(dir).unwrap_or(&path).with_extension("");
+                let name = relative.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+                templates.add(&name, &source)?;
+            }
+        }
+        Ok(templates)
+    }
+
+    // Syntax errors are reported here rather than when the template is rendered.
+    pub fn add(&mut self, name: &str, source: &str) -> Result<(), TemplateError> {
+        let mut parser = TemplateParser { name, source, pos: 0 };
+        let (nodes, _) = parser.parse_block(None)?;
+        self.templates.insert(name.to_string(), nodes);
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.templates.contains_key(name)
+    }
+
+    pub fn get_names(&self) -> Vec<&str> {
+        self.templates.keys().map(|name| name.as_str()).collect()
+    }
+
+    pub fn render(&self, name: &str, context: &impl serde::Serialize) -> Result<String, TemplateError> {
+        let context = serde_json::to_value(context).map_err(TemplateError::Context)?;
+        let nodes = self.templates.get(name).ok_or_else(|| TemplateError::NotFound(name.to_string()))?;
+        let mut out = String::new();
+        let mut scopes = vec![TemplateScope { value: &context, index: None, key: None, last: false }];
+        self.render_nodes(nodes, &mut scopes, &mut out, 0)?;
+        Ok(out)
+    }
+
+    // Makes these the templates used by `HttpResponse::render`, replacing any installed before.
+    pub fn install(self) {
+        *INSTALLED_TEMPLATES.write().unwrap_or_else(|e| e.into_inner()) = Some(std::sync::Arc::new(self));
+    }
+
+    pub fn installed() -> Option<std::sync::Arc<Templates>> {
+        INSTALLED_TEMPLATES.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn render_nodes<'a>(&self, nodes: &[TemplateNode], scopes: &mut Vec<TemplateScope<'a>>, out: &mut String, depth: usize) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                TemplateNode::Text(text) => out.push_str(text),
+                TemplateNode::Value { path, escape } => {
+                    let text = lookup(scopes, path).map(value_text).unwrap_or_default();
+                    if *escape {
+                        out.push_str(&escape_html(&text));
+                    } else {
+                        out.push_str(&text);
+                    }
+                },
+                TemplateNode::If { path, negate, then, otherwise } => {
+                    let branch = if lookup(scopes, path).is_some_and(is_truthy) != *negate { then } else { otherwise };
+                    self.render_nodes(branch, scopes, out, depth)?;
+                },
+                TemplateNode::Each { path, body, otherwise } => {
+                    let value = lookup(scopes, path);
+                    let items: Vec<(Option<&str>, &serde_json::Value)> = match value.as_deref() {
+                        Some(serde_json::Value::Array(items)) => items.iter().map(|item| (None, item)).collect(),
+                        Some(serde_json::Value::Object(map)) => map.iter().map(|(key, item)| (Some(key.as_str()), item)).collect(),
+                        _ => Vec::new(),
+                    };
+                    if items.is_empty() {
+                        self.render_nodes(otherwise, scopes, out, depth)?;
+                    }
+                    for (index, (key, item)) in items.iter().enumerate() {
+                        let mut inner: Vec<TemplateScope> = scopes.clone();
+                        inner.push(TemplateScope { value: item, index: Some(index), key: *key, last: index + 1 == items.len() });
+                        self.render_nodes(body, &mut inner, out, depth)?;
+                    }
+                },
+                TemplateNode::Partial(name) => {
+                    if depth >= MAX_PARTIAL_DEPTH {
+                        return Err(TemplateError::TooDeep(name.clone()));
+                    }
+                    let nodes = self.templates.get(name).ok_or_else(|| TemplateError::NotFound(name.clone()))?;
+                    self.render_nodes(nodes, scopes, out, depth + 1)?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "templates")]
+#[derive(Debug)]
+enum TemplateNode {
+    Text(String),
+    Value { path: String, escape: bool },
+    If { path: String, negate: bool, then: Vec<TemplateNode>, otherwise: Vec<TemplateNode> },
+    Each { path: String, body: Vec<TemplateNode>, otherwise: Vec<TemplateNode> },
+    Partial(String),
+}
+
+#[cfg(feature = "templates")]
+#[derive(Clone)]
+struct TemplateScope<'a> {
+    value: &'a serde_json::Value,
+    index: Option<usize>,
+    key: Option<&'a str>,
+    last: bool,
+}
+
+#[cfg(feature = "templates")]
+struct TemplateParser<'a> {
+    name: &'a str,
+    source: &'a str,
+    pos: usize,
+}
+
+#[cfg(feature = "templates")]
+impl TemplateParser<'_> {
+    fn error(&self, at: usize, message: impl Into<String>) -> TemplateError {
+        let line = self.source[..at].matches('\n').count() + 1;
+        TemplateError::Syntax { name: self.name.to_string(), line, message: message.into() }
+    }
+
+    // Parses until the end of the source or, inside a block, its closing tag. The second list is what follows an
+    // {{else}}.
+    fn parse_block(&mut self, open: Option<(&str, usize)>) -> Result<(Vec<TemplateNode>, Vec<TemplateNode>), TemplateError> {
+        let mut nodes = Vec::new();
+        let mut otherwise = Vec::new();
+        let mut in_else = false;
+        loop {
+            let rest = &self.source[self.pos..];
+            let Some(start) = rest.find("{{") else {
+                if let Some((block, at)) = open {
+                    return Err(self.error(at, format!("{{{{#{block}}}}} is never closed")));
+                }
+                if !rest.is_empty() {
+                    nodes.push(TemplateNode::Text(rest.to_string()));
+                }
+                self.pos = self.source.len();
+                return Ok((nodes, otherwise));
+            };
+            let target = if in_else { &mut otherwise } else { &mut nodes };
+            if start > 0 {
+                target.push(TemplateNode::Text(rest[..start].to_string()));
+            }
+
+            let at = self.pos + start;
+            let raw = rest[start..].starts_with("{{{");
+            let (open_len, close) = if raw { (3, "}}}") } else { (2, "}}") };
+            let body_start = at + open_len;
+            let end = self.source[body_start..].find(close).ok_or_else(|| self.error(at, "unclosed tag"))?;
+            let tag = self.source[body_start..body_start + end].trim();
+            self.pos = body_start + end + close.len();
+
+            if raw {
+                target.push(TemplateNode::Value { path: tag.to_string(), escape: false });
+            } else if tag.starts_with('!') {
+                continue;
+            } else if let Some(name) = tag.strip_prefix('>') {
+                target.push(TemplateNode::Partial(name.trim().to_string()));
+            } else if let Some(block) = tag.strip_prefix('#') {
+                let (kind, path) = block.split_once(char::is_whitespace).map_or((block, ""), |(kind, path)| (kind, path.trim()));
+                if path.is_empty() {
+                    return Err(self.error(at, format!("{{{{#{kind}}}}} needs a value")));
+                }
+                let (body, alternative) = self.parse_block(Some((kind, at)))?;
+                let node = match kind {
+                    "if" | "unless" => TemplateNode::If { path: path.to_string(), negate: kind == "unless", then: body, otherwise: alternative },
+                    "each" => TemplateNode::Each { path: path.to_string(), body, otherwise: alternative },
+                    _ => return Err(self.error(at, format!("unknown block {{{{#{kind}}}}}"))),
+                };
+                target.push(node);
+            } else if let Some(block) = tag.strip_prefix('/') {
+                return match open {
+                    Some((open, _)) if open == block.trim() => Ok((nodes, otherwise)),
+                    Some((open, _)) => Err(self.error(at, format!("{{{{/{}}}}} closes {{{{#{open}}}}}", block.trim()))),
+                    None => Err(self.error(at, format!("{{{{/{}}}}} without an opening block", block.trim()))),
+                };
+            } else if tag == "else" {
+                if open.is_none() || in_else {
+                    return Err(self.error(at, "{{else}} outside of a block"));
+                }
+                in_else = true;
+            } else if tag.is_empty() {
+                return Err(self.error(at, "empty tag"));
+            } else {
+                target.push(TemplateNode::Value { path: tag.to_string(), escape: true });
+            }
+        }
+    }
+}
+
+// "this" is the innermost item, "@index", "@key", "@first" and "@last" describe it, and any other name is looked up
+// from the innermost scope outwards.
+#[cfg(feature = "templates")]
+fn lookup<'a>(scopes: &[TemplateScope<'a>], path: &str) -> Option<std::borrow::Cow<'a, serde_json::Value>> {
+    use std::borrow::Cow;
+    let item = scopes.iter().rev().find(|scope| scope.index.is_some());
+    match path {
+        "@index" => return item.and_then(|item| item.index).map(|index| Cow::Owned(index.into())),
+        "@first" => return item.map(|item| Cow::Owned((item.index == Some(0)).into())),
+        "@last" => return item.map(|item| Cow::Owned(item.last.into())),
+        "@key" => return item.and_then(|item| item.key).map(|key| Cow::Owned(key.into())),
+        _ => {},
+    }
+    let mut parts = path.split('.');
+    let first = parts.next()?;
+    let mut value = if first == "this" {
+        scopes.last()?.value
+    } else {
+        scopes.iter().rev().find_map(|scope| scope.value.get(first))?
+    };
+    for part in parts {
+        value = match value {
+            serde_json::Value::Array(items) => items.get(part.parse::<usize>().ok()?)?,
+            _ => value.get(part)?,
+        };
+    }
+    Some(Cow::Borrowed(value))
+}
+
+#[cfg(feature = "templates")]
+fn value_text(value: std::borrow::Cow<serde_json::Value>) -> String {
+    match value.as_ref() {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(feature = "templates")]
+fn is_truthy(value: std::borrow::Cow<serde_json::Value>) -> bool {
+    match value.as_ref() {
+        serde_json::Value::Null => false,
+        serde_json::Value::Bool(value) => *value,
+        serde_json::Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+        serde_json::Value::String(text) => !text.is_empty(),
+        serde_json::Value::Array(items) => !items.is_empty(),
+        serde_json::Value::Object(_) => true,
+    }
+}
+
+#[cfg(feature = "templates")]
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// Answers the requests a route matches. Closures taking the request are handlers too, so routes can be given either.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse;
+}
+
+impl<F: Fn(&HttpRequest) -> HttpResponse + Send + Sync> Handler for F {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        self(request)
+    }
+}
+
+enum RouteSegment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+struct Route {
+    method: HttpMethod,
+    segments: Vec<RouteSegment>,
+    handler: Box<dyn Handler>,
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut params: HashMap<String, String> = HashMap::new();
+        let mut parts = path.trim_start_matches('/').split('/').filter(|part| !part.is_empty()).peekable();
+        for segment in &self.segments {
+            match segment {
+                RouteSegment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                },
+                RouteSegment::Param(name) => {
+                    params.insert(name.clone(), parts.next()?.to_string());
+                },
+                RouteSegment::Wildcard(name) => {
+                    params.insert(name.clone(), parts.by_ref().collect::<Vec<&str>>().join("/"));
+                },
+            }
+        }
+        if parts.peek().is_some() {
+            return None;
+        }
+        Some(params)
+    }
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router { routes: Vec::new() }
+    }
+
+    pub fn route<F>(&mut self, method: HttpMethod, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.handler(method, pattern, handler)
+    }
+
+    // Like `route`, for handlers of any kind. Closures are better given to `route`, which works out their types.
+    pub fn handler<H: Handler + 'static>(&mut self, method: HttpMethod, pattern: &str, handler: H) -> &mut Router {
+        let segments = pattern.split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| match segment.chars().next() {
+                Some(':') => RouteSegment::Param(segment[1..].to_string()),
+                Some('*') => RouteSegment::Wildcard(segment[1..].to_string()),
+                _ => RouteSegment::Literal(segment.to_string()),
+            })
+            .collect();
+        self.routes.push(Route { method, segments, handler: Box::new(handler) });
+        self
+    }
+
+    pub fn get<F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static>(&mut self, pattern: &str, handler: F) -> &mut Router {
+        self.route(HttpMethod::Get, pattern, handler)
+    }
+
+    pub fn post<F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static>(&mut self, pattern: &str, handler: F) -> &mut Router {
+        self.route(HttpMethod::Post, pattern, handler)
+    }
+
+    pub fn put<F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static>(&mut self, pattern: &str, handler: F) -> &mut Router {
+        self.route(HttpMethod::Put, pattern, handler)
+    }
+
+    pub fn delete<F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static>(&mut self, pattern: &str, handler: F) -> &mut Router {
+        self.route(HttpMethod::Delete, pattern, handler)
+    }
+
+    pub fn patch<F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static>(&mut self, pattern: &str, handler: F) -> &mut Router {
+        self.route(HttpMethod::Patch, pattern, handler)
+    }
+
+    // The methods the routes for `path` answer to, with HEAD wherever there is GET and OPTIONS wherever there is
+    // anything. Empty when no pattern matches.
+    pub fn allowed_methods(&self, path: &str) -> Vec<HttpMethod> {
+        let mut allowed: Vec<HttpMethod> = Vec::new();
+        for route in self.routes.iter().filter(|route| route.matches(path).is_some()) {
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+        if allowed.contains(&HttpMethod::Get) && !allowed.contains(&HttpMethod::Head) {
+            allowed.push(HttpMethod::Head);
+        }
+        if !allowed.is_empty() && !allowed.contains(&HttpMethod::Options) {
+            allowed.push(HttpMethod::Options);
+        }
+        allowed
+    }
+
+    // Every method some route answers to, for "OPTIONS *".
+    pub fn methods(&self) -> Vec<HttpMethod> {
+        let mut methods: Vec<HttpMethod> = Vec::new();
+        for route in &self.routes {
+            if !methods.contains(&route.method) {
+                methods.push(route.method);
+            }
+        }
+        methods
+    }
+
+    // Returns `None` when no pattern matches, so the caller can fall back to serving static files.
+    pub fn handle(&self, request: &mut HttpRequest) -> Option<HttpResponse> {
+        for route in &self.routes {
+            let Some(params) = route.matches(&request.path) else {
+                continue;
+            };
+            let head_as_get = request.method == HttpMethod::Head && route.method == HttpMethod::Get;
+            if route.method != request.method && !head_as_get {
+                continue;
+            }
+
+            request.params = params;
+            let mut response = route.handler.handle(request);
+            let framed = response.is_event_stream() || response.get_option(&HttpResponseOptions::TransferEncoding).is_some();
+            if response.get_option(&HttpResponseOptions::ContentLength).is_none() && !framed {
+                response.append_option(HttpResponseOptions::ContentLength, response.get_payload().len().to_string());
+            }
+            if head_as_get {
+                response.set_body_suppressed(true);
+            }
+            return Some(response);
+        }
+
+        let allowed = self.allowed_methods(&request.path);
+        if allowed.is_empty() {
+            return None;
+        }
+        let mut response = HttpResponse::new(HttpProtocols::OneOne);
+        response.append_option(HttpResponseOptions::Allow, allowed.iter().map(|method| method.get_name()).collect::<Vec<&str>>().join(", "));
+        // Without a route of its own, OPTIONS is answered with what the others allow.
+        if request.method == HttpMethod::Options {
+            response.set_status(HttpResponseStatusCode::NoContent);
+        } else {
+            response.set_status(HttpResponseStatusCode::MethodNotAllowed);
+            response.append_option(HttpResponseOptions::ContentLength, "0");
+        }
+        Some(response)
+    }
+}
+
+// An in-memory connection for tests: reads come from the bytes it was created with and writes are collected, so a
+// request can be parsed from it and the response sent back to it without a socket.
+#[derive(Debug)]
+#[derive(Default)]
+pub struct MockStream {
+    input: io::Cursor<Vec<u8>>,
+    output: Vec<u8>,
+    write_limit: Option<usize>,
+}
+
+impl MockStream {
+    pub fn new(input: impl Into<Vec<u8>>) -> MockStream {
+        MockStream { input: io::Cursor::new(input.into()), output: Vec::new(), write_limit: None }
+    }
+
+    // Writes fail with `BrokenPipe` past this many bytes, like a client that went away mid-response.
+    pub fn with_write_limit(mut self, limit: usize) -> MockStream {
+        self.write_limit = Some(limit);
+        self
+    }
+
+    pub fn get_written(&self) -> &[u8] {
+        &self.output
+    }
+
+    // Everything written so far, leaving the stream empty for the next response.
+    pub fn take_written(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    // Whether every input byte has been consumed.
+    pub fn is_drained(&self) -> bool {
+        self.input.position() as usize >= self.input.get_ref().len()
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl BufRead for MockStream {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.input.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.input.consume(amount);
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.write_limit.map_or(buf.len(), |limit| limit.saturating_sub(self.output.len()).min(buf.len()));
+        if room == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.output.extend_from_slice(&buf[..room]);
+        Ok(room)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn resolve(target: &str) -> Result<String, HttpPathError> {
+        normalize_path(&percent_decode(target)?)
+    }
+
+    #[test]
+    fn normalize_path_resolves_dot_segments() {
+        assert_eq!(resolve("/a/./b/../c"), Ok("/a/c".to_string()));
+        assert_eq!(resolve("//a//b/"), Ok("/a/b/".to_string()));
+        assert_eq!(resolve("/"), Ok("/".to_string()));
+    }
+
+    #[test]
+    fn normalize_path_rejects_traversal() {
+        assert_eq!(resolve("/../settings.cfg"), Err(HttpPathError::Traversal));
+        assert_eq!(resolve("/a/../../settings.cfg"), Err(HttpPathError::Traversal));
+        assert_eq!(resolve("/%2e%2e/settings.cfg"), Err(HttpPathError::Traversal));
+        assert_eq!(resolve("/%2E%2E%2Fsettings.cfg"), Err(HttpPathError::Traversal));
+        assert_eq!(resolve("/a/%2e%2e/%2e%2e/settings.cfg"), Err(HttpPathError::Traversal));
+        assert_eq!(resolve("/..%5csettings.cfg"), Err(HttpPathError::Traversal));
+        assert_eq!(resolve("/home.html%00.png"), Err(HttpPathError::Traversal));
+    }
+
+    #[test]
+    fn normalize_path_rejects_malformed_escapes() {
+        assert_eq!(resolve("/%2"), Err(HttpPathError::Malformed));
+        assert_eq!(resolve("/%zz"), Err(HttpPathError::Malformed));
+        assert_eq!(resolve("/%c3"), Err(HttpPathError::Malformed));
+    }
+
+    #[test]
+    fn several_ranges_are_merged_and_framed() {
+        assert_eq!(ByteRange::parse_all("bytes=0-1, 5-", 8), Ok(vec![ByteRange { start: 0, end: 1 }, ByteRange { start: 5, end: 7 }]));
+        assert_eq!(ByteRange::parse_all("bytes=4-5,0-2,2-3,-1", 8), Ok(vec![ByteRange { start: 0, end: 5 }, ByteRange { start: 7, end: 7 }]));
+        assert_eq!(ByteRange::parse_all("bytes=0-0,20-30", 8), Ok(vec![ByteRange { start: 0, end: 0 }]));
+        assert_eq!(ByteRange::parse_all("bytes=20-30,40-", 8), Err(HttpRangeError::Unsatisfiable));
+        assert_eq!(ByteRange::parse_all("bytes=0-1,x", 8), Err(HttpRangeError::Invalid));
+        assert_eq!(ByteRange::parse_all(&format!("bytes={}", vec!["0-0"; 17].join(",")), 8), Err(HttpRangeError::Invalid));
+        assert_eq!(ByteRange::parse("bytes=0-1,5-", 8), Err(HttpRangeError::Invalid));
+
+        let content = b"abcdefgh";
+        let parts = [(ByteRange { start: 0, end: 1 }, &content[0..2]), (ByteRange { start: 5, end: 7 }, &content[5..8])];
+        let body = multipart_byteranges(&parts, content.len(), "text/plain", "XYZ");
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "\r\n--XYZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/8\r\n\r\nab\
+             \r\n--XYZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 5-7/8\r\n\r\nfgh\
+             \r\n--XYZ--\r\n",
+        );
+    }
+
+    #[test]
+    fn percent_decode_handles_utf8() {
+        assert_eq!(percent_decode("/my%20page/caf%C3%A9"), Ok("/my page/café".to_string()));
+        assert_eq!(percent_decode("/a+b"), Ok("/a+b".to_string()));
+        assert_eq!(percent_decode("/%ff%fe"), Err(HttpPathError::Malformed));
+    }
+
+    #[test]
+    fn normalize_path_does_not_double_decode() {
+        assert_eq!(resolve("/%252e%252e/settings.cfg"), Ok("/%2e%2e/settings.cfg".to_string()));
+    }
+
+    #[test]
+    fn query_params_split_from_path() {
+        let mut raw: &[u8] = b"GET /search%20page?q=a+b&tag=x&tag=y%21 HTTP/1.1\r\n\r\n";
+        let request = HttpRequest::parse(&mut raw).unwrap();
+        assert_eq!(request.get_path(), "/search page");
+        let params = request.query_params();
+        assert_eq!(params["q"], vec!["a b".to_string()]);
+        assert_eq!(params["tag"], vec!["x".to_string(), "y!".to_string()]);
+    }
+
+    fn request(raw: &str) -> HttpRequest {
+        HttpRequest::parse(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn router_extracts_params_and_wildcards() {
+        let mut router = Router::new();
+        router.get("/users/:id", |req| {
+            let mut response = HttpResponse::new(HttpProtocols::OneOne);
+            response.append_payload(req.get_param("id").unwrap_or_default().as_bytes().to_vec());
+            response
+        });
+        router.get("/files/*rest", |req| {
+            let mut response = HttpResponse::new(HttpProtocols::OneOne);
+            response.append_payload(req.get_param("rest").unwrap_or_default().as_bytes().to_vec());
+            response
+        });
+
+        let response = router.handle(&mut request("GET /users/42 HTTP/1.1\r\n\r\n")).unwrap();
+        assert_eq!(response.get_payload(), b"42");
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentLength), Some("2"));
+
+        let response = router.handle(&mut request("GET /files/a/b.txt HTTP/1.1\r\n\r\n")).unwrap();
+        assert_eq!(response.get_payload(), b"a/b.txt");
+
+        assert!(router.handle(&mut request("GET /users/42/posts HTTP/1.1\r\n\r\n")).is_none());
+        assert!(router.handle(&mut request("GET /users HTTP/1.1\r\n\r\n")).is_none());
+    }
+
+    #[test]
+    fn router_takes_handlers_alongside_closures() {
+        struct Greeting {
+            greeting: &'static str,
+        }
+
+        impl Handler for Greeting {
+            fn handle(&self, request: &HttpRequest) -> HttpResponse {
+                HttpResponse::ok_text(format!("{} {}", self.greeting, request.get_param("name").unwrap_or_default()))
+            }
+        }
+
+        let mut router = Router::new();
+        router.handler(HttpMethod::Get, "/hello/:name", Greeting { greeting: "hello" });
+        router.get("/bye", |_| HttpResponse::ok_text("bye"));
+
+        let response = router.handle(&mut request("GET /hello/world HTTP/1.1\r\n\r\n")).unwrap();
+        assert_eq!(response.get_payload(), b"hello world");
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentLength), Some("11"));
+        assert_eq!(router.handle(&mut request("GET /bye HTTP/1.1\r\n\r\n")).unwrap().get_payload(), b"bye");
+        assert_eq!(Greeting { greeting: "hi" }.handle(&request("GET / HTTP/1.1\r\n\r\n")).get_payload(), b"hi ");
+    }
+
+    #[test]
+    fn router_rejects_unregistered_methods() {
+        let mut router = Router::new();
+        router.get("/api/health", |_| HttpResponse::new(HttpProtocols::OneOne));
+
+        let response = router.handle(&mut request("HEAD /api/health HTTP/1.1\r\n\r\n")).unwrap();
+        assert!(response.is_body_suppressed());
+
+        let response = router.handle(&mut request("POST /api/health HTTP/1.1\r\n\r\n")).unwrap();
+        assert_eq!(response.get_status().as_u16(), 405);
+        assert_eq!(response.get_option(&HttpResponseOptions::Allow), Some("GET, HEAD, OPTIONS"));
+
+        router.post("/api/items/:id", |_| HttpResponse::new(HttpProtocols::OneOne));
+        router.get("/api/items/:id", |_| HttpResponse::new(HttpProtocols::OneOne));
+        let response = router.handle(&mut request("OPTIONS /api/items/7 HTTP/1.1\r\n\r\n")).unwrap();
+        assert_eq!(response.get_status().as_u16(), 204);
+        assert_eq!(response.get_option(&HttpResponseOptions::Allow), Some("POST, GET, HEAD, OPTIONS"));
+        assert!(router.handle(&mut request("OPTIONS /api/other HTTP/1.1\r\n\r\n")).is_none());
+        assert_eq!(router.methods(), vec![HttpMethod::Get, HttpMethod::Post]);
+    }
+
+    #[test]
+    fn cookies_parse_from_request_headers() {
+        let request = request("GET / HTTP/1.1\r\nCookie: session=abc123; theme=\"dark\"\r\nCookie: lang=en\r\n\r\n");
+        let cookies = request.cookies();
+        assert_eq!(cookies.get("session").map(String::as_str), Some("abc123"));
+        assert_eq!(cookies.get("theme").map(String::as_str), Some("dark"));
+        assert_eq!(request.get_cookie("lang"), Some("en".to_string()));
+        assert_eq!(request.get_cookie("missing"), None);
+    }
+
+    #[test]
+    fn form_bodies_are_parsed() {
+        let headers = vec![("Content-Type".to_string(), "application/x-www-form-urlencoded; charset=UTF-8".to_string())];
+        let request = HttpRequest::from_parts(HttpMethod::Post, "/", HttpProtocols::OneOne, headers, b"name=J%C3%BCrgen+M&tag=a&tag=b".to_vec()).unwrap();
+        assert_eq!(request.get_form_param("name"), Some("Jürgen M".to_string()));
+        assert_eq!(request.form_params().get("tag"), Some(&vec!["a".to_string(), "b".to_string()]));
+        assert!(request.multipart(&MultipartLimits::default()).is_err());
+
+        let body = "preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a;b.txt\"\r\nContent-Type: text/plain\r\n\r\n\
+            line one\r\n--XY not yet\r\n--XyZ--\r\n";
+        let headers = vec![("Content-Type".to_string(), "multipart/form-data; boundary=\"XyZ\"".to_string())];
+        let request = HttpRequest::from_parts(HttpMethod::Post, "/", HttpProtocols::OneOne, headers, body.as_bytes().to_vec()).unwrap();
+        assert!(request.form_params().is_empty());
+
+        let form = request.multipart(&MultipartLimits::default()).unwrap();
+        assert_eq!(form.get_parts().len(), 2);
+        assert_eq!(form.get_field("title"), Some("Hello"));
+        let file = form.get_file("upload").unwrap();
+        assert_eq!(file.get_filename(), Some("a;b.txt"));
+        assert_eq!(file.get_content_type(), Some("text/plain"));
+        assert_eq!(file.get_text(), Some("line one\r\n--XY not yet"));
+
+        // Past the threshold the file goes to disk, and is removed again with the form.
+        let limits = MultipartLimits { memory_threshold: 4, ..MultipartLimits::default() };
+        let form = request.multipart(&limits).unwrap();
+        let file = form.get_file("upload").unwrap();
+        let PartBody::File(temp) = file.get_body() else {
+            panic!("expected the part on disk");
+        };
+        let path = temp.get_path().to_path_buf();
+        assert_eq!(fs::read(&path).unwrap(), b"line one\r\n--XY not yet");
+        assert_eq!(file.get_length(), 22);
+        drop(form);
+        assert!(!path.exists());
+
+        let limits = MultipartLimits { max_file_size: 10, ..MultipartLimits::default() };
+        assert_eq!(request.multipart(&limits).unwrap_err(), HttpError::BodyTooLarge);
+        let limits = MultipartLimits { max_parts: 1, ..MultipartLimits::default() };
+        assert_eq!(request.multipart(&limits).unwrap_err(), HttpError::BodyTooLarge);
+        let truncated = MultipartForm::parse(&body.as_bytes()[..body.len() - 8], "XyZ", &MultipartLimits::default());
+        assert_eq!(truncated.unwrap_err(), HttpError::Incomplete);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_bodies_round_trip() {
+        let value = serde_json::json!({ "name": "widget", "tags": ["a", "b"], "count": 3 });
+        let response = HttpResponse::json(&value);
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentType), Some("application/json"));
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentLength), Some(response.get_payload().len().to_string().as_str()));
+
+        let headers = vec![("Content-Type".to_string(), "application/vnd.api+json; charset=utf-8".to_string())];
+        let request = HttpRequest::from_parts(HttpMethod::Post, "/", HttpProtocols::OneOne, headers, response.get_payload().to_vec()).unwrap();
+        assert_eq!(request.json::<serde_json::Value>().unwrap(), value);
+        assert!(matches!(request.json::<Vec<u8>>(), Err(JsonError::Invalid(_))));
+
+        let headers = vec![("Content-Type".to_string(), "text/plain".to_string())];
+        let request = HttpRequest::from_parts(HttpMethod::Post, "/", HttpProtocols::OneOne, headers, b"{}".to_vec()).unwrap();
+        assert!(matches!(request.json::<serde_json::Value>(), Err(JsonError::UnsupportedMediaType)));
+
+        let unserializable: HashMap<Vec<u8>, u8> = HashMap::from([(vec![1], 1)]);
+        assert_eq!(HttpResponse::json(&unserializable).get_status(), &HttpResponseStatusCode::InternalServerError);
+    }
+
+    #[cfg(feature = "templates")]
+    #[test]
+    fn templates_render_values_blocks_and_partials() {
+        let mut templates = Templates::new();
+        templates.add("header", "<h1>{{ title }}</h1>").unwrap();
+        templates.add("page", concat!(
+            "{{> header}}{{! ignored }}",
+            "{{#if user.admin}}admin{{else}}guest{{/if}} {{ user.name }} {{{ user.name }}}\n",
+            "{{#each items}}{{@index}}:{{ name }}/{{ title }}{{#unless @last}}, {{/unless}}{{else}}none{{/each}}\n",
+            "{{#each tags}}{{@key}}={{ this }};{{/each}}{{ missing.value }}{{ items.1.name }}",
+        )).unwrap();
+
+        let context = serde_json::json!({
+            "title": "Shop",
+            "user": { "name": "<Ann>", "admin": false },
+            "items": [{ "name": "a" }, { "name": "b" }],
+            "tags": { "x": 1 },
+        });
+        assert_eq!(templates.render("page", &context).unwrap(), "<h1>Shop</h1>guest &lt;Ann&gt; <Ann>\n0:a/Shop, 1:b/Shop\nx=1;b");
+        let empty = serde_json::json!({ "items": [] });
+        assert_eq!(templates.render("page", &empty).unwrap(), "<h1></h1>guest  \nnone\n");
+        assert!(matches!(templates.render("other", &empty), Err(TemplateError::NotFound(_))));
+
+        assert!(matches!(templates.add("broken", "a\n{{#if x}}b{{/each}}"), Err(TemplateError::Syntax { line: 2, .. })));
+        assert!(matches!(templates.add("broken", "{{#each x}}"), Err(TemplateError::Syntax { line: 1, .. })));
+        templates.add("loop", "{{> loop}}").unwrap();
+        assert!(matches!(templates.render("loop", &empty), Err(TemplateError::TooDeep(_))));
+
+        templates.install();
+        let response = HttpResponse::render("header", &serde_json::json!({ "title": "Hi" }));
+        assert_eq!(response.get_payload(), b"<h1>Hi</h1>");
+        assert_eq!(HttpResponse::render("nope", &empty).get_status(), &HttpResponseStatusCode::InternalServerError);
+    }
+
+    #[test]
+    fn event_streams_carry_frames() {
+        let event = Event::new("first\r\nsecond").event("update\n").id("7").retry(Duration::from_secs(3));
+        assert_eq!(event.to_bytes(), b"event: update\nid: 7\nretry: 3000\ndata: first\ndata: second\n\n");
+
+        let mut router = Router::new();
+        router.get("/events", |_| {
+            let (response, sender) = HttpResponse::event_stream();
+            sender.send_data("hello").unwrap();
+            sender.comment("ping").unwrap();
+            response
+        });
+        let mut response = router.handle(&mut request("GET /events HTTP/1.1\r\n\r\n")).unwrap();
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentType), Some("text/event-stream"));
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentLength), None);
+
+        let events = response.take_event_stream().unwrap();
+        assert!(!response.is_event_stream());
+        assert_eq!(events.recv_timeout(Duration::ZERO).unwrap(), b"data: hello\n\n");
+        assert_eq!(events.recv_timeout(Duration::ZERO).unwrap(), b": ping\n\n");
+        assert_eq!(events.recv_timeout(Duration::ZERO), Err(RecvTimeoutError::Disconnected));
+
+        let (mut response, sender) = HttpResponse::event_stream();
+        drop(response.take_event_stream());
+        assert_eq!(sender.send_data("late"), Err(EventStreamClosed));
+    }
+
+    #[test]
+    fn set_header_replaces_existing_values() {
+        let mut request = request("GET / HTTP/1.1\r\nX-Request-Id: a\r\nx-request-id: b\r\n\r\n");
+        request.set_header("X-Request-Id", "c");
+        assert_eq!(request.get_header("x-request-id"), Some("c"));
+        assert_eq!(request.get_headers().len(), 1);
+        assert_eq!(HttpResponseOptions::from_name("x-request-id"), HttpResponseOptions::RequestId);
+    }
+
+    #[test]
+    fn headers_are_looked_up_in_any_case() {
+        let request = request("GET / HTTP/1.1\r\nHost: [::1]:8080\r\nAccept-Encoding: br;q=1, gzip;q=0\r\nCONTENT-TYPE: text/plain\r\nAccept: text/html\r\naccept: application/json, */*;q=0.1\r\n\r\n");
+        let headers = request.headers();
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+        assert_eq!(headers.get_all("Accept"), ["text/html", "application/json, */*;q=0.1"]);
+        assert_eq!(headers.get_list("ACCEPT"), ["text/html", "application/json", "*/*;q=0.1"]);
+        assert!(headers.contains("host") && !headers.contains("Cookie"));
+        assert_eq!(headers.len(), 5);
+        assert_eq!(request.host(), Some("::1"));
+        assert_eq!(request.content_length(), None);
+        assert!(request.accepts_encoding("br") && !request.accepts_gzip() && !request.accepts_encoding("deflate"));
+
+        let request = self::request("POST / HTTP/1.1\r\nHost: Example.com:80\r\nContent-Length: 2\r\nAccept-Encoding: *\r\n\r\nhi");
+        assert_eq!(request.host(), Some("Example.com"));
+        assert_eq!(request.content_length(), Some(2));
+        assert!(request.accepts_gzip());
+        assert!(self::request("GET / HTTP/1.0\r\n\r\n").accepts_gzip());
+    }
+
+    #[test]
+    fn responses_round_trip_through_bytes() {
+        let mut response = HttpResponse::builder()
+            .status(HttpResponseStatusCode::NotFound)
+            .content_type("text/plain")
+            .header(HttpResponseOptions::Date, "Tue, 13 Oct 2026 08:12:44 GMT")
+            .body("missing")
+            .build();
+        response.set_cookie(&Cookie::new("a", "1"));
+        response.set_cookie(&Cookie::new("b", "2"));
+        let bytes = response.to_bytes();
+        assert!(bytes.ends_with(b"\r\n\r\nmissing"));
+        assert_eq!(HttpResponse::parse(&bytes), Ok(response));
+
+        let parsed = HttpResponse::parse(b"HTTP/1.1 299 Fine\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2;x=y\r\nde\r\n0\r\n\r\n").unwrap();
+        assert_eq!(parsed.get_status(), &HttpResponseStatusCode::Custom(299, "Fine".to_string()));
+        assert_eq!(parsed.get_payload(), b"abcde");
+        assert_eq!(parsed.get_option(&HttpResponseOptions::ContentLength), Some("5"));
+        assert_eq!(parsed.get_option(&HttpResponseOptions::TransferEncoding), None);
+
+        assert_eq!(HttpResponse::parse(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort"), Err(HttpError::Incomplete));
+        assert_eq!(HttpResponse::parse(b"HTTP/1.1 200 OK\r\n"), Err(HttpError::Incomplete));
+        assert_eq!(HttpResponse::parse(b"SPDY/3 200 OK\r\n\r\n"), Err(HttpError::UnsupportedVersion));
+        assert_eq!(HttpResponse::parse(b"HTTP/1.1 abc OK\r\n\r\n"), Err(HttpError::ParseError));
+    }
+
+    #[test]
+    fn set_cookie_serializes_attributes() {
+        let cookie = Cookie::new("session", "abc123").path("/").max_age(3600).http_only(true).same_site(SameSite::None);
+        assert_eq!(cookie.to_header_value(), "session=abc123; Path=/; Max-Age=3600; Secure; HttpOnly; SameSite=None");
+
+        let mut response = HttpResponse::new(HttpProtocols::OneOne);
+        response.set_cookie(&cookie);
+        response.set_cookie(&Cookie::removal("old"));
+        assert_eq!(response.get_option_values(&HttpResponseOptions::SetCookie).len(), 2);
+        assert!(response.get_header().contains("Set-Cookie: old=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"));
+    }
+
+    #[test]
+    fn request_head_limits_are_enforced() {
+        let limits = HttpRequestLimits { max_request_line: 32, max_header_size: 16, max_header_bytes: 32, max_headers: 3, ..HttpRequestLimits::default() };
+        let parse = |raw: &str| HttpRequest::parse_with_limits(&mut raw.as_bytes(), &limits).err();
+
+        assert_eq!(parse("GET /short HTTP/1.1\r\nA: 1\r\n\r\n"), None);
+        assert_eq!(parse(&format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(64))), Some(HttpError::UriTooLong));
+        assert_eq!(parse(&format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "b".repeat(32))), Some(HttpError::HeadersTooLarge));
+        assert_eq!(parse("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n"), Some(HttpError::HeadersTooLarge));
+        assert_eq!(parse("GET / HTTP/1.1\r\nA: 1234567890\r\nB: 1234567890\r\nC: 1234567890\r\n\r\n"), Some(HttpError::HeadersTooLarge));
+    }
+
+    #[test]
+    fn chunked_bodies_are_decoded() {
+        let mut rest = "POST /a HTTP/1.1\r\nTransfer-Encoding: Chunked\r\n\r\n5;ext=1\r\nhello\r\n1\r\n!\r\n0\r\nX-Trailer: 1\r\n\r\nGET /b HTTP/1.1\r\n\r\n".as_bytes();
+        let request = HttpRequest::parse(&mut rest).unwrap();
+        assert_eq!(request.get_body(), b"hello!");
+        assert_eq!(request.get_header("Transfer-Encoding"), None);
+        assert_eq!(request.content_length(), Some(6));
+        // What follows the last chunk and the trailers is the next request, not part of this one.
+        assert_eq!(HttpRequest::parse(&mut rest).unwrap().get_path(), "/b");
+
+        let limits = HttpRequestLimits { max_body_size: 8, ..HttpRequestLimits::default() };
+        let parse = |raw: &str| HttpRequest::parse_with_limits(&mut raw.as_bytes(), &limits).err();
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"), None);
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"), Some(HttpError::BodyTooLarge));
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffffff\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+5\r\nhello\r\n0\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhello\r\n0\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel"), Some(HttpError::ReadFailed));
+    }
+
+    #[test]
+    fn chunked_bodies_are_streamed_with_their_trailers() {
+        let limits = HttpRequestLimits { max_header_bytes: 32, max_headers: 2, ..HttpRequestLimits::default() };
+        let read = |raw: &str| {
+            let mut body = Vec::new();
+            read_chunked(&mut raw.as_bytes(), &limits, |data| -> Result<(), HttpError> {
+                body.extend_from_slice(data);
+                Ok(())
+            }).map(|trailers| (body, trailers))
+        };
+        let (body, trailers) = read("3\r\nabc\r\n2;name=value\r\nde\r\n0\r\nDigest: sha-256=x\r\nX-Count:  2 \r\n\r\n").unwrap();
+        assert_eq!(body, b"abcde");
+        assert_eq!(trailers, [("Digest".to_string(), "sha-256=x".to_string()), ("X-Count".to_string(), "2".to_string())]);
+
+        for size in ["+5", " 5", "5 x", "0x5", "-1", ""] {
+            assert_eq!(read(&format!("{size}\r\nhello\r\n0\r\n\r\n")).err(), Some(HttpError::ParseError), "{size:?}");
+        }
+        assert_eq!(read("0\r\nX-Long: 0123456789012345678901234567890123456789\r\n\r\n").err(), Some(HttpError::HeadersTooLarge));
+        assert_eq!(read("0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n").err(), Some(HttpError::HeadersTooLarge));
+        assert_eq!(read("0\r\nnot a field\r\n\r\n").err(), Some(HttpError::ParseError));
+
+        // What the sink refuses ends the body there.
+        let refused = read_chunked(&mut "5\r\nhello\r\n0\r\n\r\n".as_bytes(), &limits, |_| Err(HttpError::BodyTooLarge));
+        assert_eq!(refused.err(), Some(HttpError::BodyTooLarge));
+    }
+
+    #[test]
+    fn other_transfer_codings_are_refused() {
+        let parse = |raw: &str| HttpRequest::parse(&mut raw.as_bytes()).err();
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n"), Some(HttpError::UnsupportedTransferEncoding));
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n"), Some(HttpError::UnsupportedTransferEncoding));
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"), Some(HttpError::UnsupportedTransferEncoding));
+    }
+
+    #[test]
+    fn ambiguous_framing_is_rejected() {
+        let parse = |raw: &str| HttpRequest::parse(&mut raw.as_bytes()).err();
+        assert_eq!(parse("POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /s.txt"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n0\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nContent-Length: 0\r\nContent-Length: 31\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nContent-Length: 2\r\ncontent-length: 2\r\n\r\nhi"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nContent-Length: 2, 2\r\n\r\nhi"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nContent-Length: +2\r\n\r\nhi"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.1\r\nContent-Length:\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"), Some(HttpError::ParseError));
+        // Refused from the head alone, before anything is read of a body that cannot be delimited.
+        assert_eq!(HttpRequest::parse_head(&mut "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n".as_bytes(), &HttpRequestLimits::default()).err(), Some(HttpError::UnsupportedTransferEncoding));
+
+        assert_eq!(parse("POST / HTTP/1.1\r\nContent-Length: 02\r\n\r\nhi"), None);
+        assert_eq!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked,\r\n\r\n0\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn protocols_and_statuses_display_and_parse() {
+        assert_eq!(HttpProtocols::OneOne.to_string(), "HTTP/1.1");
+        assert_eq!("HTTP/1.0".parse(), Ok(HttpProtocols::One));
+        assert_eq!("HTTP/3".parse::<HttpProtocols>(), Err(HttpError::UnsupportedVersion));
+
+        assert_eq!(HttpResponseStatusCode::NotFound.to_string(), "404 Not Found");
+        assert_eq!("404".parse(), Ok(HttpResponseStatusCode::NotFound));
+        assert_eq!("404 Gone Fishing".parse(), Ok(HttpResponseStatusCode::NotFound));
+        assert_eq!("418 I'm a teapot".parse(), Ok(HttpResponseStatusCode::Custom(418, "I'm a teapot".to_string())));
+        assert_eq!("4O4".parse::<HttpResponseStatusCode>(), Err(HttpError::ParseError));
+
+        assert_eq!(HttpResponseStatusCode::from_u16(503), Some(HttpResponseStatusCode::ServiceUnavailable));
+        assert_eq!(HttpResponseStatusCode::from_u16(418), None);
+        assert_eq!(HttpResponseStatusCode::from_u16(301).map(|status| status.as_u16()), Some(301));
+    }
+
+    #[test]
+    fn invalid_messages_are_reported() {
+        let parse = |raw: &str| HttpRequest::parse(&mut raw.as_bytes()).err();
+        assert_eq!(parse("GET / HTTP/3.0\r\n\r\n"), Some(HttpError::UnsupportedVersion));
+        assert_eq!(parse("GET / FTP\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET / HTTP/1.1\r\nBad Name: 1\r\n\r\n"), Some(HttpError::InvalidHeaderName("Bad Name".to_string())));
+        assert_eq!(parse("GET / HTTP/1.1\r\nA: 1\x002\r\n\r\n"), Some(HttpError::InvalidHeaderValue("A".to_string())));
+        assert_eq!(parse("GET\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET  HTTP/1.1\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET / HTTP/1.1 extra\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET / HTTP/abc\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET index.html HTTP/1.1\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET * HTTP/1.1\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET /caf\u{e9} HTTP/1.1\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET / HTTP/1.1\r\nA: 1\r\n  folded\r\n\r\n"), Some(HttpError::ParseError));
+        assert_eq!(parse("GET / HTTP/1.1\r\nHost : a\r\n\r\n"), Some(HttpError::InvalidHeaderName("Host ".to_string())));
+        assert_eq!(HttpRequest::parse(&mut &b"GET /\xff HTTP/1.1\r\n\r\n"[..]).err(), Some(HttpError::ParseError));
+        assert_eq!(parse("\r\n\r\n\r\n\r\n\r\n\r\n"), Some(HttpError::ParseError));
+
+        let mut response = HttpResponse::builder().build();
+        assert_eq!(response.try_append_option(HttpResponseOptions::Location, "/a\r\nSet-Cookie: x=1"), Err(HttpError::InvalidHeaderValue("Location".to_string())));
+        assert_eq!(response.get_option(&HttpResponseOptions::Location), None);
+        assert!(response.try_append_option(HttpResponseOptions::Location, "/a\tb").is_ok());
+        assert_eq!(HttpError::InvalidHeaderName("a b".to_string()).to_string(), "\"a b\" is not a valid header name");
+    }
+
+    #[test]
+    fn builder_sets_status_headers_and_length() {
+        let response = HttpResponse::builder()
+            .status(HttpResponseStatusCode::Created)
+            .content_type("application/json")
+            .header(HttpResponseOptions::from_name("X-Trace"), "abc")
+            .body("{}")
+            .build();
+        assert_eq!(response.get_status().as_u16(), 201);
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentType), Some("application/json"));
+        assert_eq!(response.get_option(&HttpResponseOptions::from_name("x-trace")), Some("abc"));
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentLength), Some("2"));
+        assert_eq!(response.get_payload(), b"{}");
+
+        let redirect = HttpResponse::redirect(HttpResponseStatusCode::Found, "/new");
+        assert_eq!(redirect.get_status().as_u16(), 302);
+        assert_eq!(redirect.get_option(&HttpResponseOptions::Location), Some("/new"));
+        assert_eq!(HttpResponse::not_found().get_status().as_u16(), 404);
+    }
+
+    #[test]
+    fn text_types_carry_a_charset() {
+        let mut registry = MimeRegistry::new();
+        assert_eq!(registry.content_type(Some("html")), "text/html; charset=utf-8");
+        assert_eq!(registry.content_type(Some("js")), "application/javascript; charset=utf-8");
+        assert_eq!(registry.content_type(Some("png")), "image/png");
+        assert_eq!(registry.content_type(None), "application/octet-stream");
+
+        registry.set_charset("csv", "iso-8859-1");
+        registry.set_charset("json", "utf-8");
+        registry.set_charset("md", "");
+        registry.insert("txt", "text/plain; charset=us-ascii");
+        assert_eq!(registry.content_type(Some("CSV")), "text/csv; charset=iso-8859-1");
+        assert_eq!(registry.content_type(Some("json")), "application/json; charset=utf-8");
+        assert_eq!(registry.content_type(Some("md")), "text/markdown");
+        assert_eq!(registry.content_type(Some("txt")), "text/plain; charset=us-ascii");
+        registry.set_default_charset("");
+        assert_eq!(registry.content_type(Some("css")), "text/css");
+
+        let mut response = HttpResponse::ok_text("hi");
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentType), Some("text/plain; charset=utf-8"));
+        response.set_content_type("text/csv", Some("utf-16"));
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentType), Some("text/csv; charset=utf-16"));
+        response.set_content_type("image/png", None);
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentType), Some("image/png"));
+    }
+
+    #[test]
+    fn send_writes_header_and_body() {
+        let response = HttpResponse::ok_text("hello");
+        let mut out = Vec::new();
+        let written = response.send(&mut out).unwrap();
+        assert_eq!(written, out.len());
+        assert!(out.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with(b"\r\n\r\nhello"));
+        let sent = HttpResponse::parse(&out).unwrap();
+        let date = httpdate::parse_http_date(sent.get_option(&HttpResponseOptions::Date).unwrap()).unwrap();
+        assert!(SystemTime::now().duration_since(date).unwrap() < Duration::from_secs(5));
+
+        let mut full = [0u8; 8];
+        let err = response.send(&mut &mut full[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn send_frames_the_body_by_its_status() {
+        // A stale or doubled length is replaced by that of the body, and a chunked claim by a length.
+        let mut response = HttpResponse::ok_text("hello");
+        response.append_option(HttpResponseOptions::ContentLength, "99");
+        response.append_option(HttpResponseOptions::TransferEncoding, "chunked");
+        let sent = String::from_utf8(response.to_bytes()).unwrap();
+        assert_eq!(sent.matches("Content-Length").count(), 1, "{sent}");
+        assert!(sent.contains("Content-Length: 5\r\n") && !sent.contains("Transfer-Encoding") && sent.ends_with("\r\n\r\nhello"), "{sent}");
+
+        // HEAD announces the length of the body it leaves out.
+        let mut head = HttpResponse::ok_text("hello");
+        head.set_body_suppressed(true);
+        let sent = String::from_utf8(head.to_bytes()).unwrap();
+        assert!(sent.contains("Content-Length: 5\r\n") && sent.ends_with("\r\n\r\n"), "{sent}");
+
+        for status in [HttpResponseStatusCode::NoContent, HttpResponseStatusCode::NotModified, HttpResponseStatusCode::Custom(103, "Early Hints".to_string())] {
+            let mut response = HttpResponse::ok_text("hello");
+            response.set_status(status.clone());
+            assert!(!response.has_body());
+            let sent = String::from_utf8(response.to_bytes()).unwrap();
+            assert!(sent.ends_with("\r\n\r\n") && !sent.contains("Content-Length") && !sent.contains("hello"), "{sent}");
+
+            let mut out = Vec::new();
+            let mut body = response.send_chunked(&mut out).unwrap();
+            body.write_chunk(b"hello").unwrap();
+            body.finish().unwrap();
+            let sent = String::from_utf8(out).unwrap();
+            assert!(sent.ends_with("\r\n\r\n") && !sent.contains("Transfer-Encoding") && !sent.contains("Connection"), "{sent}");
+        }
+        assert!(HttpResponseStatusCode::OK.allows_body() && HttpResponseStatusCode::NotFound.allows_body());
+    }
+
+    #[test]
+    fn mock_stream_serves_pipelined_requests() {
+        let mut router = Router::new();
+        router.get("/users/:id", |req| HttpResponse::ok_text(format!("user {}", req.get_param("id").unwrap_or_default())));
+
+        let mut stream = MockStream::new("GET /users/7 HTTP/1.1\r\nHost: a\r\n\r\nHEAD /users/8 HTTP/1.1\r\nHost: a\r\n\r\n");
+        let mut request = HttpRequest::parse(&mut stream).unwrap();
+        router.handle(&mut request).unwrap().send(&mut stream).unwrap();
+        let first = String::from_utf8(stream.take_written()).unwrap();
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(first.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(first.ends_with("\r\n\r\nuser 7"));
+
+        let mut request = HttpRequest::parse(&mut stream).unwrap();
+        assert!(stream.is_drained());
+        router.handle(&mut request).unwrap().send(&mut stream).unwrap();
+        let second = String::from_utf8(stream.take_written()).unwrap();
+        assert!(second.contains("Content-Length: 6\r\n"));
+        assert!(second.ends_with("\r\n\r\n"));
+        assert_eq!(HttpRequest::parse(&mut stream).unwrap_err(), HttpError::ConnectionClosed);
+
+        let mut closed = MockStream::default().with_write_limit(10);
+        let err = HttpResponse::ok_text("hello").send(&mut closed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(closed.get_written(), b"HTTP/1.1 2");
+    }
+
+    #[test]
+    fn request_targets_take_every_allowed_form() {
+        let leading = request("\r\nGET /a?b=http://c HTTP/1.1\r\n\r\n");
+        assert_eq!(leading.get_path(), "/a");
+        assert_eq!(leading.get_query(), Some("b=http://c"));
+
+        let absolute = request("GET http://example.com/a?b HTTP/1.1\r\nHost: other\r\n\r\n");
+        assert_eq!(absolute.get_path(), "/a");
+        assert_eq!(absolute.get_query(), Some("b"));
+        assert_eq!(absolute.get_header("Host"), Some("example.com"));
+        assert_eq!(request("GET https://example.com HTTP/1.1\r\n\r\n").get_path(), "/");
+        assert!(HttpRequest::parse(&mut "GET http:///a HTTP/1.1\r\n\r\n".as_bytes()).is_err());
+
+        assert_eq!(request("OPTIONS * HTTP/1.1\r\n\r\n").get_path(), "*");
+    }
+
+    // Mutates well-formed requests at random, with a fixed seed so that failures can be reproduced. The parser has to
+    // answer every one of them with a request or an error, and never panic.
+    #[test]
+    fn mangled_requests_never_panic() {
+        let seeds: [&[u8]; 4] = [
+            b"GET /index.html?a=1 HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n",
+            b"POST /form HTTP/1.0\r\nContent-Length: 5\r\nContent-Type: text/plain\r\n\r\nhello",
+            b"OPTIONS * HTTP/1.1\r\n\r\n",
+            b"GET http://example.com/%7Ea HTTP/1.1\r\n\r\n",
+        ];
+        const SPECIAL: &[u8] = b" :/?*%\t\r\n";
+        let limits = HttpRequestLimits { max_body_size: 16, max_request_line: 64, max_header_size: 64, max_header_bytes: 128, max_headers: 4 };
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut random = |below: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below as u64) as usize
+        };
+        for round in 0..20_000 {
+            let mut raw = seeds[round % seeds.len()].to_vec();
+            for _ in 0..1 + random(4) {
+                let at = random(raw.len() + 1);
+                match random(4) {
+                    0 if at < raw.len() => raw[at] = random(256) as u8,
+                    1 if at < raw.len() => { raw.remove(at); },
+                    2 => raw.insert(at, SPECIAL[random(SPECIAL.len())]),
+                    _ => raw.truncate(at),
+                }
+            }
+            if let Ok(request) = HttpRequest::parse_with_limits(&mut &raw[..], &limits) {
+                assert!(request.get_target().starts_with('/') || request.get_target() == "*", "{:?}", String::from_utf8_lossy(&raw));
+            }
+        }
+    }
+
+    proptest! {
+        // Whatever arrives ends in a request or a typed error. A request never takes more of the stream than its own
+        // head and body, whatever the bytes after it are.
+        #[test]
+        fn arbitrary_bytes_parse_or_fail(raw in prop::collection::vec(any::<u8>(), 0..512)) {
+            let mut rest = &raw[..];
+            if let Ok(request) = HttpRequest::parse(&mut rest) {
+                prop_assert!(request.get_target().starts_with('/') || request.get_target() == "*");
+                prop_assert!(request.get_body().len() <= HttpRequestLimits::default().max_body_size);
+            }
+        }
+
+        #[test]
+        fn well_formed_requests_are_read_exactly(
+            method in prop::sample::select(vec!["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"]),
+            path in "(/[a-zA-Z0-9._~-]{0,12}){1,4}",
+            query in prop::option::of("[a-z0-9=&]{0,16}"),
+            headers in prop::collection::vec(("X-[A-Za-z0-9-]{1,15}", "[ -~]{0,32}"), 0..8),
+            body in prop::collection::vec(any::<u8>(), 0..64),
+            next in prop::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let target = match &query {
+                Some(query) => format!("{path}?{query}"),
+                None => path.clone(),
+            };
+            let mut raw = format!("{method} {target} HTTP/1.1\r\nContent-Length: {}\r\n", body.len()).into_bytes();
+            for (name, value) in &headers {
+                raw.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+            }
+            raw.extend_from_slice(b"\r\n");
+            raw.extend_from_slice(&body);
+            raw.extend_from_slice(&next);
+
+            let mut rest = &raw[..];
+            let request = HttpRequest::parse(&mut rest).unwrap();
+            prop_assert_eq!(request.get_method().get_name(), method);
+            prop_assert_eq!(request.get_path(), path.as_str());
+            prop_assert_eq!(request.get_query(), query.as_deref());
+            for (name, value) in &headers {
+                prop_assert!(request.get_headers().iter().any(|(found, found_value)| found == name && found_value == value.trim()));
+            }
+            prop_assert_eq!(request.get_body(), &body[..]);
+            prop_assert_eq!(rest, &next[..]);
+        }
+    }
+
+    #[test]
+    fn older_protocols_are_answered_in_kind() {
+        let simple = request("GET /index.html\r\n");
+        assert_eq!(simple.get_protocol(), &HttpProtocols::ZeroNine);
+        assert!(!simple.wants_keep_alive());
+        assert!(HttpRequest::parse(&mut "POST /form\r\n".as_bytes()).is_err());
+
+        let mut response = HttpResponse::ok_text("hi");
+        response.set_protocol(simple.get_protocol().response_version());
+        let mut out = Vec::new();
+        response.send(&mut out).unwrap();
+        assert_eq!(out, b"hi");
+
+        let mut response = HttpResponse::new(HttpProtocols::OneOne);
+        response.set_protocol(HttpProtocols::One);
+        let mut out = Vec::new();
+        let mut chunked = response.send_chunked(&mut out).unwrap();
+        chunked.write_chunk(b"abc").unwrap();
+        chunked.finish().unwrap();
+        assert!(out.starts_with(b"HTTP/1.0 200 OK\r\n"));
+        assert!(out.ends_with(b"\r\n\r\nabc"));
+        assert!(response.get_option(&HttpResponseOptions::TransferEncoding).is_none());
+    }
+
+    // A soak test for leaks in the request and response path, such as the header values that were once leaked per
+    // request. It takes a while, so it only runs when asked for: cargo test -p http-resources -- --ignored
+    #[test]
+    #[ignore]
+    #[cfg(target_os = "linux")]
+    fn memory_stays_flat_across_many_requests() {
+        let mut router = Router::new();
+        router.get("/items/:id", |req| {
+            HttpResponse::builder()
+                .content_type("text/plain")
+                .header(HttpResponseOptions::CacheControl, "no-cache")
+                .body(format!("item {}", req.get_param("id").unwrap_or_default()))
+                .build()
+        });
+        let serve = |index: usize| {
+            let raw = format!("GET /items/{index}?page=2 HTTP/1.1\r\nHost: example.com\r\nCookie: session={index}\r\n\r\n");
+            let response = router.handle(&mut request(&raw)).unwrap();
+            let mut out = Vec::new();
+            response.send(&mut out).unwrap();
+            assert!(out.ends_with(format!("item {index}").as_bytes()));
+        };
+
+        // Let the allocator settle before measuring.
+        (0..10_000).for_each(serve);
+        let before = resident_bytes();
+        (0..100_000).for_each(serve);
+        let grown = resident_bytes().saturating_sub(before);
+        assert!(grown < 2 * 1024 * 1024, "resident memory grew by {grown} bytes over 100000 requests");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn resident_bytes() -> usize {
+        // The second field of statm is the resident set in pages, which are 4 KiB on the platforms this runs on.
+        let statm = fs::read_to_string("/proc/self/statm").unwrap();
+        statm.split_whitespace().nth(1).and_then(|pages| pages.parse::<usize>().ok()).unwrap() * 4096
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage: load_test [OPTIONS] <url>
+
+Opens keep-alive connections to a running server and requests <url> (http://host:port/path) on each of them as
+fast as it answers, then reports throughput and latency.
+
+Options:
+  --connections <count>  Concurrent connections (default 16)
+  --duration <seconds>   How long to keep sending requests (default 10)
+  --header <name: value> Send an extra request header; may be repeated
+  --timeout <seconds>    Socket timeout for a single read or write (default 5)
+  --min-rps <count>      Exit with an error if fewer requests per second were answered
+  --help                 Print this message and exit";
+
+struct Options {
+    host: String,
+    address: String,
+    path: String,
+    connections: usize,
+    duration: Duration,
+    timeout: Duration,
+    headers: Vec<String>,
+    min_rps: Option<f64>,
+}
+
+// What one connection saw. Latencies are kept whole, so percentiles are exact.
+#[derive(Default)]
+struct Results {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    bytes: u64,
+    errors: usize,
+    reconnects: usize,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        },
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        },
+    };
+
+    let options = Arc::new(options);
+    println!("Running for {}s on {} connections against http://{}{}...", options.duration.as_secs(), options.connections, options.address, options.path);
+    let started = Instant::now();
+    let workers: Vec<_> = (0..options.connections).map(|_| {
+        let options = Arc::clone(&options);
+        thread::spawn(move || run(&options))
+    }).collect();
+
+    let mut total = Results::default();
+    for worker in workers {
+        let results = worker.join().expect("Load thread panicked");
+        total.latencies.extend(results.latencies);
+        for (status, count) in results.statuses {
+            *total.statuses.entry(status).or_insert(0) += count;
+        }
+        total.bytes += results.bytes;
+        total.errors += results.errors;
+        total.reconnects += results.reconnects;
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let rps = total.latencies.len() as f64 / elapsed;
+    report(&mut total, elapsed);
+    match options.min_rps {
+        Some(min) if rps < min => {
+            eprintln!("Throughput of {rps:.0} requests/s is below the required {min:.0}.");
+            ExitCode::FAILURE
+        },
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut url = None;
+    let mut options = Options {
+        host: String::new(),
+        address: String::new(),
+        path: String::new(),
+        connections: 16,
+        duration: Duration::from_secs(10),
+        timeout: Duration::from_secs(5),
+        headers: Vec::new(),
+        min_rps: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("{flag} needs a value"));
+        match arg.as_str() {
+            "--connections" => options.connections = positive(&value("--connections")?, "--connections")? as usize,
+            "--duration" => options.duration = Duration::from_secs(positive(&value("--duration")?, "--duration")?),
+            "--timeout" => options.timeout = Duration::from_secs(positive(&value("--timeout")?, "--timeout")?),
+            "--header" => options.headers.push(value("--header")?),
+            "--min-rps" => options.min_rps = Some(f64::from_str(&value("--min-rps")?).map_err(|_| "--min-rps needs a number".to_string())?),
+            "--help" | "-h" => return Ok(None),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {arg}")),
+            _ => url = Some(arg),
+        }
+    }
+
+    let url = url.ok_or("No URL given")?;
+    let rest = url.strip_prefix("http://").ok_or("Only http:// URLs are supported")?;
+    let (authority, path) = rest.find('/').map_or((rest, "/"), |index| (&rest[..index], &rest[index..]));
+    options.host = authority.to_string();
+    options.address = if authority.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+    options.path = path.to_string();
+    Ok(Some(options))
+}
+
+fn positive(value: &str, flag: &str) -> Result<u64, String> {
+    u64::from_str(value).ok().filter(|value| *value > 0).ok_or_else(|| format!("{flag} needs a positive number"))
+}
+
+fn run(options: &Options) -> Results {
+    let mut results = Results::default();
+    let mut request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n", options.path, options.host);
+    for header in &options.headers {
+        request.push_str(header);
+        request.push_str("\r\n");
+    }
+    request.push_str("\r\n");
+
+    let deadline = Instant::now() + options.duration;
+    let mut connection: Option<BufReader<TcpStream>> = None;
+    while Instant::now() < deadline {
+        let mut reader = match connection.take() {
+            Some(reader) => reader,
+            None => match connect(options) {
+                Ok(stream) => BufReader::new(stream),
+                Err(_) => {
+                    results.errors += 1;
+                    // A server that refuses connections should not be hammered in a tight loop.
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                },
+            },
+        };
+
+        let sent = Instant::now();
+        match exchange(&mut reader, request.as_bytes()) {
+            Ok((status, bytes, keep_alive)) => {
+                results.latencies.push(sent.elapsed());
+                *results.statuses.entry(status).or_insert(0) += 1;
+                results.bytes += bytes;
+                if keep_alive {
+                    connection = Some(reader);
+                } else {
+                    results.reconnects += 1;
+                }
+            },
+            Err(_) => results.errors += 1,
+        }
+    }
+    results
+}
+
+fn connect(options: &Options) -> io::Result<TcpStream> {
+    let address = options.address.to_socket_addrs()?.next().ok_or(io::ErrorKind::NotFound)?;
+    let stream = TcpStream::connect_timeout(&address, options.timeout)?;
+    stream.set_read_timeout(Some(options.timeout))?;
+    stream.set_write_timeout(Some(options.timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+// Sends one request and reads the whole response. Returns its status, the bytes received and whether the server
+// keeps the connection open.
+fn exchange(reader: &mut BufReader<TcpStream>, request: &[u8]) -> io::Result<(u16, u64, bool)> {
+    reader.get_mut().write_all(request)?;
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line.split(' ').nth(1).and_then(|status| status.parse::<u16>().ok()).ok_or(io::ErrorKind::InvalidData)?;
+    let mut bytes = line.len() as u64;
+    let mut length = None;
+    let mut chunked = false;
+    let mut keep_alive = line.starts_with("HTTP/1.1");
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        bytes += line.len() as u64;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(io::ErrorKind::InvalidData.into());
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = Some(value.parse::<u64>().map_err(|_| io::ErrorKind::InvalidData)?),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "connection" => keep_alive = !value.eq_ignore_ascii_case("close"),
+            _ => {},
+        }
+    }
+
+    bytes += if chunked {
+        read_chunked(reader)?
+    } else if let Some(length) = length {
+        skip(reader, length)?
+    } else {
+        // The body runs until the server closes the connection.
+        keep_alive = false;
+        io::copy(reader, &mut io::sink())?
+    };
+    Ok((status, bytes, keep_alive))
+}
+
+fn read_chunked(reader: &mut BufReader<TcpStream>) -> io::Result<u64> {
+    let mut bytes = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = line.trim_end().split(';').next().and_then(|size| u64::from_str_radix(size.trim(), 16).ok()).ok_or(io::ErrorKind::InvalidData)?;
+        bytes += line.len() as u64;
+        if size == 0 {
+            // Trailers, if any, up to the empty line.
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                    return Ok(bytes + line.len() as u64);
+                }
+                bytes += line.len() as u64;
+            }
+        }
+        // The chunk and its line ending.
+        bytes += skip(reader, size + 2)?;
+    }
+}
+
+fn skip(reader: &mut BufReader<TcpStream>, length: u64) -> io::Result<u64> {
+    let skipped = io::copy(&mut reader.take(length), &mut io::sink())?;
+    if skipped < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(skipped)
+}
+
+fn report(results: &mut Results, elapsed: f64) {
+    let requests = results.latencies.len();
+    println!("requests: {requests} in {elapsed:.2}s");
+    println!("throughput: {:.0} requests/s, {:.2} MB/s", requests as f64 / elapsed, results.bytes as f64 / elapsed / 1_000_000.0);
+
+    results.latencies.sort_unstable();
+    if let (Some(min), Some(max)) = (results.latencies.first(), results.latencies.last()) {
+        let percentile = |quantile: f64| results.latencies[((requests - 1) as f64 * quantile).round() as usize];
+        println!(
+            "latency: min {}, p50 {}, p90 {}, p99 {}, max {}",
+            format_duration(*min),
+            format_duration(percentile(0.5)),
+            format_duration(percentile(0.9)),
+            format_duration(percentile(0.99)),
+            format_duration(*max),
+        );
+    }
+    let statuses: Vec<String> = results.statuses.iter().map(|(status, count)| format!("{status}={count}")).collect();
+    println!("responses: {}", statuses.join(", "));
+    println!("errors: {}", results.errors);
+    println!("reconnects: {}", results.reconnects);
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
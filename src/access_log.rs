@@ -0,0 +1,185 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use http_resources::HttpRequest;
+use log::error;
+
+pub const COMMON_LOG_FORMAT: &str = "%h - - %t \"%r\" %s %b";
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+#[derive(PartialEq)]
+#[derive(Clone, Copy)]
+pub enum Rotation {
+    Never,
+    Daily,
+}
+
+pub struct AccessLogEntry<'a> {
+    pub time: SystemTime,
+    pub client: Option<IpAddr>,
+    pub request: Option<&'a HttpRequest>,
+    pub status: u16,
+    pub bytes: usize,
+    pub duration: Duration,
+    pub request_id: Option<String>,
+}
+
+pub struct AccessLogger {
+    format: String,
+    sender: mpsc::Sender<String>,
+}
+
+impl AccessLogger {
+    pub fn open(path: &Path, format: &str, max_size: u64, rotation: Rotation) -> Option<AccessLogger> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).ok()?;
+        }
+        let writer = LogWriter::open(path.to_path_buf(), max_size, rotation)
+            .map_err(|err| error!("Unable to open access log {}: {}", path.display(), err))
+            .ok()?;
+
+        let (sender, receiver) = mpsc::channel::<String>();
+        thread::spawn(move || writer.run(receiver));
+
+        Some(AccessLogger { format: format.to_string(), sender })
+    }
+
+    pub fn log(&self, entry: &AccessLogEntry) {
+        self.sender.send(format_entry(&self.format, entry)).unwrap_or(());
+    }
+}
+
+struct LogWriter {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    max_size: u64,
+    rotation: Rotation,
+    day: u64,
+}
+
+impl LogWriter {
+    fn open(path: PathBuf, max_size: u64, rotation: Rotation) -> std::io::Result<LogWriter> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(LogWriter { path, writer: BufWriter::new(file), size, max_size, rotation, day: unix_day(SystemTime::now()) })
+    }
+
+    fn run(mut self, receiver: mpsc::Receiver<String>) {
+        while let Ok(line) = receiver.recv() {
+            self.write_line(&line);
+            while let Ok(line) = receiver.try_recv() {
+                self.write_line(&line);
+            }
+            self.writer.flush().unwrap_or(());
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.should_rotate() {
+            self.rotate().unwrap_or_else(|err| error!("Unable to rotate access log {}: {}", self.path.display(), err));
+        }
+        if self.writer.write_all(line.as_bytes()).and_then(|_| self.writer.write_all(b"\n")).is_ok() {
+            self.size += line.len() as u64 + 1;
+        }
+    }
+
+    fn should_rotate(&self) -> bool {
+        (self.max_size > 0 && self.size >= self.max_size)
+            || (self.rotation == Rotation::Daily && unix_day(SystemTime::now()) != self.day)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        let (year, month, day, hour, minute, second) = civil_time(SystemTime::now());
+        let stamp = format!("{year:04}{month:02}{day:02}-{hour:02}{minute:02}{second:02}");
+        let mut rotated = PathBuf::from(format!("{}.{stamp}", self.path.display()));
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = PathBuf::from(format!("{}.{stamp}.{n}", self.path.display()));
+            n += 1;
+        }
+        fs::rename(&self.path, rotated)?;
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        self.day = unix_day(SystemTime::now());
+        Ok(())
+    }
+}
+
+fn format_entry(format: &str, entry: &AccessLogEntry) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => out.push_str(&entry.client.map_or_else(|| "-".to_string(), |ip| ip.to_string())),
+            Some('t') => out.push_str(&clf_time(entry.time)),
+            Some('r') => out.push_str(&entry.request.map_or_else(|| "-".to_string(), |request| {
+                format!("{} {} {}", request.get_method().get_name(), request.get_target(), request.get_protocol())
+            })),
+            Some('m') => out.push_str(entry.request.map_or("-", |request| request.get_method().get_name())),
+            Some('U') => out.push_str(entry.request.map_or("-", |request| request.get_path())),
+            Some('q') => out.push_str(&entry.request.and_then(|request| request.get_query()).map_or_else(String::new, |query| format!("?{query}"))),
+            Some('H') => out.push_str(&entry.request.map_or_else(|| "-".to_string(), |request| request.get_protocol().to_string())),
+            Some('s') => out.push_str(&entry.status.to_string()),
+            Some('b') => out.push_str(&if entry.bytes == 0 { "-".to_string() } else { entry.bytes.to_string() }),
+            Some('B') => out.push_str(&entry.bytes.to_string()),
+            Some('D') => out.push_str(&entry.duration.as_micros().to_string()),
+            Some('T') => out.push_str(&format!("{:.3}", entry.duration.as_secs_f64())),
+            Some('L') => out.push_str(entry.request_id.as_deref().unwrap_or("-")),
+            Some('{') => {
+                let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                match chars.next() {
+                    Some('i') => out.push_str(entry.request.and_then(|request| request.get_header(&name)).unwrap_or("-")),
+                    Some(other) => out.push_str(&format!("%{{{name}}}{other}")),
+                    None => out.push_str(&format!("%{{{name}}}")),
+                }
+            },
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            },
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+fn clf_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil_time(time);
+    format!("[{day:02}/{}/{year:04}:{hour:02}:{minute:02}:{second:02} +0000]", MONTHS[month as usize - 1])
+}
+
+fn unix_day(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() / 86400)
+}
+
+pub(crate) fn civil_time(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let (hour, minute, second) = ((secs % 86400) / 3600, (secs % 3600) / 60, secs % 60);
+
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, hour, minute, second)
+}
@@ -0,0 +1,249 @@
+use std::fmt::{Display, Write as _};
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::time::Duration;
+use http_resources::{HttpMethod, HttpRequest, HttpRequestLimits, HttpResponse, HttpResponseOptions, HttpResponseStatusCode};
+use log::debug;
+use crate::{conf, shutdown, Config, FILE_CACHE, STATE};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Requests are answered on this thread rather than by the pool, so health checks and scrapes still get through when
+// every worker is busy. One request per connection is plenty for that.
+pub fn serve(listener: TcpListener) {
+    for stream in listener.incoming() {
+        if shutdown::is_requested() {
+            break;
+        }
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap_or(());
+        stream.set_write_timeout(Some(TIMEOUT)).unwrap_or(());
+
+        let mut response = match HttpRequest::parse_head(&mut BufReader::new(&stream), &HttpRequestLimits::default()) {
+            Ok(request) => respond(&request),
+            Err(_) => HttpResponse::builder().status(HttpResponseStatusCode::BadRequest).body("").build(),
+        };
+        response.append_option(HttpResponseOptions::Connection, "close");
+        if let Err(err) = response.send(&mut stream) {
+            debug!("Failed to answer an admin request: {}", err);
+        }
+    }
+}
+
+fn respond(request: &HttpRequest) -> HttpResponse {
+    if !matches!(request.get_method(), HttpMethod::Get | HttpMethod::Head) {
+        return HttpResponse::builder()
+            .status(HttpResponseStatusCode::MethodNotAllowed)
+            .header(HttpResponseOptions::Allow, "GET, HEAD")
+            .body("")
+            .build();
+    }
+
+    let mut response = match request.get_path() {
+        // Liveness: the process is up and answering.
+        "/healthz" | "/healthz/live" => HttpResponse::ok_text("ok\n"),
+        "/healthz/ready" => match readiness() {
+            Ok(()) => HttpResponse::ok_text("ready\n"),
+            Err(reason) => HttpResponse::builder()
+                .status(HttpResponseStatusCode::ServiceUnavailable)
+                .content_type("text/plain; charset=utf-8")
+                .body(format!("{reason}\n"))
+                .build(),
+        },
+        "/metrics" => HttpResponse::builder().content_type("text/plain; version=0.0.4").body(metrics()).build(),
+        "/config" => HttpResponse::ok_text(describe_config(&conf())),
+        "/connections" => HttpResponse::ok_text(connections()),
+        _ => HttpResponse::not_found(),
+    };
+    response.set_body_suppressed(request.get_method() == HttpMethod::Head);
+    response
+}
+
+// Readiness: new connections would actually be served.
+fn readiness() -> Result<(), &'static str> {
+    if shutdown::is_requested() {
+        return Err("shutting down");
+    }
+    if STATE.is_queue_full() {
+        return Err("job queue full");
+    }
+    if STATE.is_connections_full() {
+        return Err("connection limit reached");
+    }
+    Ok(())
+}
+
+fn metrics() -> String {
+    let mut out = String::new();
+    STATE.metrics.write_prometheus(&mut out);
+
+    gauge(&mut out, "webserver_connections_active", "Open client connections.", STATE.connections.get_total());
+    if let Some(pool) = STATE.get_pool() {
+        gauge(&mut out, "webserver_pool_workers", "Worker threads in the pool.", pool.get_size());
+        gauge(&mut out, "webserver_pool_busy_workers", "Workers currently running a job.", pool.get_busy_workers());
+        gauge(&mut out, "webserver_pool_queued_jobs", "Connections waiting for a worker.", pool.get_queued_jobs());
+    }
+    counter(&mut out, "webserver_queue_rejections_total", "Connections turned away because the job queue was full.", STATE.get_queue_rejections());
+    counter(&mut out, "webserver_connection_rejections_total", "Connections turned away because max-connections were open.", STATE.get_connection_rejections());
+    counter(&mut out, "webserver_per_ip_rejections_total", "Connections turned away because max-connections-per-ip were open from one address.", STATE.get_per_ip_rejections());
+
+    let (hits, misses) = (FILE_CACHE.get_hits(), FILE_CACHE.get_misses());
+    counter(&mut out, "webserver_file_cache_hits_total", "Files served from the in-memory cache.", hits);
+    counter(&mut out, "webserver_file_cache_misses_total", "Files read from disk.", misses);
+    let ratio = if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 };
+    gauge(&mut out, "webserver_file_cache_hit_ratio", "Share of file lookups answered from the cache.", ratio);
+
+    gauge(&mut out, "webserver_uptime_seconds", "Seconds since the server started.", STATE.get_uptime().as_secs());
+    out
+}
+
+fn connections() -> String {
+    let mut out = String::new();
+    for info in STATE.connections.list() {
+        writeln!(out, "{}", info.describe()).unwrap_or(());
+    }
+    writeln!(out, "total: {}", STATE.connections.get_total()).unwrap_or(());
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl Display) {
+    writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}").unwrap_or(());
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: impl Display) {
+    writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}").unwrap_or(());
+}
+
+// The running config in the legacy format. Auth tokens and password hashes are left out.
+fn describe_config(config: &Config) -> String {
+    let mut out = String::new();
+    let mut line = |key: &str, value: &dyn Display| writeln!(out, "{key} = {value}").unwrap_or(());
+
+    let http = config.http_addresses().join(", ");
+    line("listen", &http);
+    if !config.ssl.is_empty() {
+        #[cfg(feature = "tls")]
+        line("listen-tls", &config.tls_addresses().join(", "));
+        line("ssl-cert", &config.ssl);
+        line("ssl-only", &config.ssl_only);
+    }
+    if !config.acme_domains.is_empty() {
+        line("acme-domains", &config.acme_domains.join(", "));
+        line("acme-directory", &config.acme_directory);
+        line("acme-renew-days", &config.acme_renew_days);
+    }
+    if !config.allowed_hosts.is_empty() {
+        line("allowed-hosts", &config.allowed_hosts.join(", "));
+    }
+    if !config.trusted_proxies.is_empty() {
+        line("trusted-proxies", &config.trusted_proxies.iter().map(|proxy| proxy.describe()).collect::<Vec<_>>().join(", "));
+    }
+    line("proxy-protocol", &config.proxy_protocol);
+    if !config.admin_listen.is_empty() {
+        line("admin-listen", &config.admin_listen);
+    }
+    line("num-threads", &config.threads);
+    line("io-backend", &config.io_backend);
+    line("queue-limit", &config.queue_limit);
+    line("queue-full", &if config.queue_full_wait { "wait" } else { "reject" });
+    line("max-connections", &config.max_connections);
+    line("connections-full", &if config.connections_full_wait { "wait" } else { "reject" });
+    line("root-dir", &config.root_dir.display());
+    line("home-name", &config.home_name);
+    line("keep-alive-timeout", &config.keep_alive_timeout);
+    line("keep-alive-max", &config.keep_alive_max);
+    line("read-timeout", &config.read_timeout);
+    line("write-timeout", &config.write_timeout);
+    line("header-timeout", &config.header_timeout);
+    line("max-connections-per-ip", &config.max_connections_per_ip);
+    line("max-body-size", &config.max_body_size);
+    line("max-upload-size", &config.max_upload_size);
+    line("rate-limit", &config.rate_limit);
+    line("rate-limit-burst", &config.rate_limit_burst);
+    line("bandwidth-limit", &config.bandwidth_limit);
+    line("bandwidth-limit-total", &config.bandwidth_limit_total);
+    line("compression", &config.compression);
+    line("precompressed", &config.precompressed);
+    line("default-charset", &config.default_charset);
+    for (extension, charset) in &config.charsets {
+        line(&format!("charset.{extension}"), charset);
+    }
+    line("file-cache-size", &config.file_cache_size);
+    if !config.preload.is_empty() {
+        line("preload", &config.preload.join(", "));
+    }
+    line("dev-mode", &config.dev_mode);
+    line("autoindex", &config.autoindex);
+    line("html-extension", &config.html_extension);
+    if !config.spa_fallback.is_empty() {
+        line("spa-fallback", &config.spa_fallback);
+    }
+    if !config.favicon.as_os_str().is_empty() {
+        line("favicon", &config.favicon.display());
+    }
+    if !config.well_known_dir.as_os_str().is_empty() {
+        line("well-known-dir", &config.well_known_dir.display());
+    }
+    if !config.templates_dir.as_os_str().is_empty() {
+        line("templates-dir", &config.templates_dir.display());
+    }
+    line("deny", &config.deny.patterns.join(", "));
+    if !config.deny.exceptions.is_empty() {
+        line("deny-except", &config.deny.exceptions.join(", "));
+    }
+    line("deny-status", &config.deny.status);
+    line("security-headers", &config.security_headers);
+    line("server-header", &config.server_header);
+    for (name, value) in &config.default_headers {
+        line(&format!("header.{name}"), value);
+    }
+    line("log-level", &config.log_level.as_str().to_ascii_lowercase());
+    if !config.access_log.is_empty() {
+        line("access-log", &config.access_log);
+    }
+    if config.cors.is_enabled() {
+        line("cors-origins", &config.cors.origins.join(", "));
+    }
+    for (code, page) in &config.error_pages {
+        line(&format!("error.{code}"), &page.display());
+    }
+    for (prefix, upstream) in &config.proxies {
+        line(&format!("proxy.{prefix}"), &upstream.get_url());
+    }
+    for (prefix, dir) in &config.mounts {
+        line(&format!("mount.{prefix}"), &dir.display());
+    }
+    for (prefix, dir) in &config.uploads {
+        line(&format!("upload.{prefix}"), &dir.display());
+    }
+    for (prefix, dir) in &config.cgi {
+        line(&format!("cgi.{prefix}"), &dir.display());
+    }
+    if !config.cgi_extensions.is_empty() {
+        line("cgi-extensions", &config.cgi_extensions.join(", "));
+    }
+    line("cgi-timeout", &config.cgi_timeout);
+    line("sse-keep-alive", &config.sse_keep_alive);
+    for (extension, address) in &config.fastcgi {
+        line(&format!("fastcgi.{extension}"), &address.describe());
+    }
+    for rule in &config.cache_rules {
+        line(&format!("cache.{}", rule.get_name()), &rule.describe());
+    }
+    for rule in &config.redirects {
+        line(&format!("redirect.{}", rule.get_prefix()), &rule.describe());
+    }
+    for rule in &config.auth {
+        line(&format!("auth.{}", rule.get_prefix()), &rule.describe());
+    }
+
+    for host in &config.hosts {
+        writeln!(out, "\n[host \"{}\"]\nroot = {}\nhome-name = {}", host.names.join(", "), host.root_dir.display(), host.home_name).unwrap_or(());
+        if !host.ssl_cert.is_empty() {
+            writeln!(out, "ssl-cert = {}", host.ssl_cert).unwrap_or(());
+        }
+    }
+    out
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http_resources::HttpRequest;
+use log::warn;
+use sha1::{Digest, Sha1};
+
+const REALM: &str = "Restricted";
+
+#[derive(PartialEq)]
+pub enum AuthScheme {
+    Basic(Vec<(String, String)>),
+    Bearer(String),
+}
+
+#[derive(PartialEq)]
+pub struct AuthRule {
+    prefix: String,
+    scheme: AuthScheme,
+}
+
+impl AuthRule {
+    pub fn parse(prefix: &str, value: &str) -> Option<AuthRule> {
+        let scheme = match value.split_once(':')? {
+            ("basic", path) => AuthScheme::Basic(load_htpasswd(path)?),
+            ("bearer", token) if !token.is_empty() => AuthScheme::Bearer(token.to_string()),
+            _ => return None,
+        };
+        Some(AuthRule { prefix: prefix.trim_end_matches('/').to_string(), scheme })
+    }
+
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    // Leaves out the token and password hashes, so the rule can be shown on the admin endpoint.
+    pub fn describe(&self) -> String {
+        match &self.scheme {
+            AuthScheme::Basic(users) => format!("basic ({} users)", users.len()),
+            AuthScheme::Bearer(_) => "bearer (token hidden)".to_string(),
+        }
+    }
+
+    pub fn challenge(&self) -> String {
+        match self.scheme {
+            AuthScheme::Basic(_) => format!("Basic realm=\"{REALM}\", charset=\"UTF-8\""),
+            AuthScheme::Bearer(_) => format!("Bearer realm=\"{REALM}\""),
+        }
+    }
+
+    pub fn authorize(&self, request: &HttpRequest) -> bool {
+        let Some((scheme, credentials)) = request.get_header("Authorization").and_then(|value| value.split_once(' ')) else {
+            return false;
+        };
+        match &self.scheme {
+            AuthScheme::Basic(users) if scheme.eq_ignore_ascii_case("basic") => {
+                let Some(decoded) = STANDARD.decode(credentials.trim()).ok().and_then(|bytes| String::from_utf8(bytes).ok()) else {
+                    return false;
+                };
+                let Some((user, password)) = decoded.split_once(':') else {
+                    return false;
+                };
+                users.iter().any(|(name, hash)| name == user && verify_password(password, hash))
+            },
+            AuthScheme::Bearer(token) if scheme.eq_ignore_ascii_case("bearer") => constant_time_eq(credentials.trim().as_bytes(), token.as_bytes()),
+            _ => false,
+        }
+    }
+}
+
+pub fn find<'a>(rules: &'a [AuthRule], path: &str) -> Option<&'a AuthRule> {
+    rules.iter()
+        .filter(|rule| path.strip_prefix(rule.prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')))
+        .max_by_key(|rule| rule.prefix.len())
+}
+
+fn load_htpasswd(path: &str) -> Option<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path).map_err(|err| warn!("Unable to read htpasswd file {}: {}", path, err)).ok()?;
+    let mut users = Vec::new();
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        match line.split_once(':') {
+            Some((user, hash)) if is_supported_hash(hash) => users.push((user.to_string(), hash.to_string())),
+            Some((user, _)) => warn!("Skipping \"{}\" in {}: only bcrypt and {{SHA}} hashes are supported.", user, path),
+            None => warn!("Skipping invalid line in {}: {}", path, line),
+        }
+    }
+    Some(users)
+}
+
+fn is_supported_hash(hash: &str) -> bool {
+    hash.starts_with("{SHA}") || ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    match hash.strip_prefix("{SHA}") {
+        Some(expected) => constant_time_eq(STANDARD.encode(Sha1::digest(password.as_bytes())).as_bytes(), expected.as_bytes()),
+        None => bcrypt::verify(password, hash).unwrap_or(false),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use http_resources::{HttpResponseOptions, HttpResponseStatusCode};
+    use crate::ConnectionError;
+    use super::*;
+
+    fn request(path: &str, authorization: Option<&str>) -> HttpRequest {
+        let authorization = authorization.map(|value| format!("Authorization: {value}\r\n")).unwrap_or_default();
+        HttpRequest::parse(&mut format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n{authorization}\r\n").as_bytes()).unwrap()
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        format!("Basic {}", STANDARD.encode(format!("{user}:{password}")))
+    }
+
+    fn rule(prefix: &str, users: &[(&str, &str)]) -> AuthRule {
+        let users = users.iter().map(|(user, hash)| (user.to_string(), hash.to_string())).collect();
+        AuthRule { prefix: prefix.to_string(), scheme: AuthScheme::Basic(users) }
+    }
+
+    #[test]
+    fn checks_basic_credentials_against_either_hash() {
+        let sha = format!("{{SHA}}{}", STANDARD.encode(Sha1::digest(b"open:sesame")));
+        let bcrypt = bcrypt::hash("hunter2", 4).unwrap();
+        let rule = rule("/private", &[("ali", &sha), ("bob", &bcrypt)]);
+
+        // Only the first colon separates the user from the password.
+        assert!(rule.authorize(&request("/private", Some(&basic("ali", "open:sesame")))));
+        assert!(rule.authorize(&request("/private", Some(&basic("bob", "hunter2")))));
+        // The scheme is case-insensitive.
+        assert!(rule.authorize(&request("/private", Some(&basic("bob", "hunter2").replace("Basic", "bASIC")))));
+        assert!(!rule.authorize(&request("/private", Some(&basic("ali", "open:sesame!")))));
+        assert!(!rule.authorize(&request("/private", Some(&basic("ali", "hunter2")))));
+        assert!(!rule.authorize(&request("/private", Some(&basic("carol", "hunter2")))));
+        assert!(!rule.authorize(&request("/private", Some(&basic("bob", "")))));
+    }
+
+    #[test]
+    fn refuses_malformed_credentials() {
+        let rule = rule("/private", &[("ali", &format!("{{SHA}}{}", STANDARD.encode(Sha1::digest(b"pw"))))]);
+        let no_colon = format!("Basic {}", STANDARD.encode("alipw"));
+        let not_utf8 = format!("Basic {}", STANDARD.encode(b"ali:\xff"));
+        for authorization in ["Basic", "Basic !!!not base64!!!", "Basic YWxpOnB3=", &no_colon, &not_utf8, "Bearer YWxpOnB3", "YWxpOnB3"] {
+            assert!(!rule.authorize(&request("/private", Some(authorization))), "{authorization}");
+        }
+        assert!(!rule.authorize(&request("/private", None)));
+        assert!(rule.authorize(&request("/private", Some("Basic YWxpOnB3"))));
+    }
+
+    #[test]
+    fn the_longest_matching_prefix_applies() {
+        let rules = [rule("/docs", &[]), rule("/docs/internal", &[]), rule("", &[])];
+        assert_eq!(find(&rules, "/docs/internal/plan.txt").map(AuthRule::get_prefix), Some("/docs/internal"));
+        assert_eq!(find(&rules, "/docs/internal").map(AuthRule::get_prefix), Some("/docs/internal"));
+        assert_eq!(find(&rules, "/docs/guide.txt").map(AuthRule::get_prefix), Some("/docs"));
+        // Prefixes only match whole segments.
+        assert_eq!(find(&rules, "/docs-public/index.html").map(AuthRule::get_prefix), Some(""));
+        assert!(find(&rules[..2], "/docsx").is_none());
+        assert!(find(&rules[..2], "/").is_none());
+    }
+
+    #[test]
+    fn unauthorized_requests_are_challenged() {
+        let rule = rule("/private", &[]);
+        assert_eq!(rule.challenge(), "Basic realm=\"Restricted\", charset=\"UTF-8\"");
+        assert!(!rule.authorize(&request("/private", None)));
+
+        let response = ConnectionError::Unauthorized(rule.challenge()).into_response(Path::new("/nonexistent"), None);
+        assert_eq!(response.get_status(), &HttpResponseStatusCode::Unauthorized);
+        assert_eq!(response.get_option(&HttpResponseOptions::WwwAuthenticate), Some("Basic realm=\"Restricted\", charset=\"UTF-8\""));
+    }
+}
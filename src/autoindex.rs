@@ -0,0 +1,106 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use http_resources::percent_encode;
+use crate::deny::DenyRules;
+use crate::negotiation;
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+// Entries the deny rules hide are left out, so the listing does not advertise them.
+fn read_entries(dir: &Path, deny: &DenyRules) -> io::Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !deny.hides(&entry.file_name().to_string_lossy()))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some(Entry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            })
+        })
+        .collect();
+
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+pub fn render_html(url_path: &str, dir: &Path, deny: &DenyRules) -> io::Result<String> {
+    let base = if url_path.ends_with('/') { url_path.to_string() } else { format!("{url_path}/") };
+    let title = escape_html(&base);
+
+    let mut out = String::new();
+    out.push_str(&format!("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Index of {title}</title></head><body>"));
+    out.push_str(&format!("<h1>Index of {title}</h1><table><tr><th>Name</th><th>Size</th><th>Last Modified</th></tr>"));
+
+    if base != "/" {
+        out.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>");
+    }
+
+    for entry in read_entries(dir, deny)? {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let href = format!("{}{}{suffix}", percent_encode(&base), percent_encode(&entry.name));
+        let size = if entry.is_dir { "-".to_string() } else { entry.size.to_string() };
+        let modified = entry.modified.map_or_else(String::new, httpdate::fmt_http_date);
+        out.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}{suffix}</a></td><td>{size}</td><td>{modified}</td></tr>",
+            escape_html(&href),
+            escape_html(&entry.name),
+        ));
+    }
+
+    out.push_str("</table></body></html>");
+    Ok(out)
+}
+
+// The same entries for scripts: an array of {"name", "size", "mtime", "is_dir"}, where mtime is in Unix seconds
+// and size is null for directories.
+pub fn render_json(dir: &Path, deny: &DenyRules) -> io::Result<String> {
+    let entries: Vec<String> = read_entries(dir, deny)?.iter()
+        .map(|entry| {
+            let size = if entry.is_dir { "null".to_string() } else { entry.size.to_string() };
+            let mtime = entry.modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or_else(|| "null".to_string(), |mtime| mtime.as_secs().to_string());
+            format!("{{\"name\":\"{}\",\"size\":{size},\"mtime\":{mtime},\"is_dir\":{}}}", escape_json(&entry.name), entry.is_dir)
+        })
+        .collect();
+    Ok(format!("[{}]", entries.join(",")))
+}
+
+// JSON only when the client prefers it to HTML, so browsers sending */* still get the page.
+pub fn wants_json(accept: &str) -> bool {
+    negotiation::quality(accept, "application/json") > negotiation::quality(accept, "text/html")
+}
+
+fn escape_json(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn escape_html(input: &str) -> String {
+    input.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
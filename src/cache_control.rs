@@ -0,0 +1,51 @@
+use crate::deny::glob_matches;
+
+#[derive(PartialEq)]
+pub struct CacheRule {
+    name: String,
+    // Without a path pattern the rule's name picks files by extension ("html") or media type ("image").
+    pattern: Option<String>,
+    directives: String,
+}
+
+impl CacheRule {
+    // "/assets/* max-age=31536000, immutable" or just the directives, such as "no-cache".
+    pub fn parse(name: &str, value: &str) -> Option<CacheRule> {
+        let value = value.trim();
+        let (pattern, directives) = if value.starts_with('/') {
+            let (pattern, directives) = value.split_once(char::is_whitespace)?;
+            (Some(pattern.to_string()), directives.trim())
+        } else {
+            (None, value)
+        };
+        if name.is_empty() || directives.is_empty() || http_resources::check_header_value("Cache-Control", directives).is_err() {
+            return None;
+        }
+        Some(CacheRule { name: name.to_string(), pattern, directives: directives.to_string() })
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_directives(&self) -> &str {
+        &self.directives
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.pattern {
+            Some(pattern) => format!("{} {}", pattern, self.directives),
+            None => self.directives.clone(),
+        }
+    }
+}
+
+// Path rules come first, the longest pattern winning, then a rule for the extension, then one for the media type.
+pub fn find<'a>(rules: &'a [CacheRule], path: &str, extension: Option<&str>, content_type: &str) -> Option<&'a CacheRule> {
+    let media_type = content_type.split('/').next().unwrap_or_default();
+    rules.iter()
+        .filter(|rule| rule.pattern.as_deref().is_some_and(|pattern| glob_matches(pattern, path)))
+        .max_by_key(|rule| rule.pattern.as_ref().map_or(0, |pattern| pattern.len()))
+        .or_else(|| rules.iter().find(|rule| rule.pattern.is_none() && extension.is_some_and(|extension| rule.name.eq_ignore_ascii_case(extension))))
+        .or_else(|| rules.iter().find(|rule| rule.pattern.is_none() && rule.name.eq_ignore_ascii_case(media_type)))
+}
@@ -0,0 +1,478 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use http_resources::{check_header_name, check_header_value, normalize_path, Handler, HttpMethod, HttpProtocols, HttpRequest, HttpResponse, HttpResponseOptions, HttpResponseStatusCode};
+use log::warn;
+use crate::proxy::{ProxyError, Relay, Relayed};
+use crate::{conf, error_for_site, Config, ConnectionError};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// Framing is ours to decide, so these are dropped from a script's headers.
+const FRAMING: [&str; 4] = ["connection", "keep-alive", "transfer-encoding", "content-length"];
+
+// Runs the scripts in a directory like "cgi.*" does, for routes of their own: the request's path names the script,
+// and whatever follows it becomes PATH_INFO. The whole output is collected before it is answered with.
+pub struct Cgi {
+    dir: PathBuf,
+}
+
+impl Cgi {
+    pub fn new(dir: impl Into<PathBuf>) -> Cgi {
+        Cgi { dir: dir.into() }
+    }
+}
+
+impl Handler for Cgi {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        let config = conf();
+        let path = normalize_path(request.get_path()).unwrap_or_default();
+        let timeout = Duration::from_secs(config.cgi_timeout.max(1));
+        let Some(route) = in_dir(&self.dir, config.site_for(request.host()).root_dir, &path, timeout) else {
+            return error_for_site(ConnectionError::SourceNotFound, Some(request));
+        };
+        route.handle(request)
+    }
+}
+
+pub struct CgiRoute {
+    script: PathBuf,
+    script_name: String,
+    path_info: String,
+    document_root: PathBuf,
+    timeout: Duration,
+}
+
+impl CgiRoute {
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl Handler for CgiRoute {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        fetch(self, request).unwrap_or_else(|_| error_for_site(ConnectionError::BadGateway, Some(request)))
+    }
+}
+
+impl Relay for CgiRoute {
+    fn relay(&self, request: &HttpRequest, client: &mut dyn Write, keep_alive: bool, decorate: &mut dyn FnMut(&mut HttpResponse)) -> Result<Relayed, ProxyError> {
+        run(self, request, client, keep_alive, decorate)
+    }
+}
+
+// Scripts live either in a directory mapped to a prefix ("cgi./cgi-bin") or anywhere in the site with one of the
+// "cgi-extensions". Whatever follows the script in the path becomes PATH_INFO.
+pub fn route(config: &Config, root: &Path, path: &str) -> Option<CgiRoute> {
+    let mapped = config.cgi.iter()
+        .filter_map(|(prefix, dir)| {
+            let prefix = prefix.trim_end_matches('/');
+            let rest = path.strip_prefix(prefix).filter(|rest| rest.starts_with('/'))?;
+            Some((prefix, dir, rest))
+        })
+        .max_by_key(|(prefix, _, _)| prefix.len())
+        .and_then(|(prefix, dir, rest)| {
+            let (script, end) = find_script(dir, rest, |_| true)?;
+            Some((script, format!("{prefix}{}", &rest[..end]), &rest[end..]))
+        });
+    let timeout = Duration::from_secs(config.cgi_timeout.max(1));
+    match mapped {
+        Some((script, script_name, path_info)) => Some(CgiRoute { script, script_name, path_info: path_info.to_string(), document_root: root.to_path_buf(), timeout }),
+        None => find_in_site(root, path, &config.cgi_extensions, timeout),
+    }
+}
+
+// A script anywhere in the site, recognized by its extension.
+pub fn find_in_site(root: &Path, path: &str, extensions: &[String], timeout: Duration) -> Option<CgiRoute> {
+    let is_script = |file: &Path| file.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(extension)));
+    if extensions.is_empty() {
+        return None;
+    }
+    let (script, end) = find_script(root, path, is_script)?;
+    Some(CgiRoute {
+        script,
+        script_name: path[..end].to_string(),
+        path_info: path[end..].to_string(),
+        document_root: root.to_path_buf(),
+        timeout,
+    })
+}
+
+// A script in `dir` itself, named by the start of `path`, for the `Cgi` handler.
+pub fn in_dir(dir: &Path, root: &Path, path: &str, timeout: Duration) -> Option<CgiRoute> {
+    let (script, end) = find_script(dir, path, |_| true)?;
+    Some(CgiRoute {
+        script,
+        script_name: path[..end].to_string(),
+        path_info: path[end..].to_string(),
+        document_root: root.to_path_buf(),
+        timeout,
+    })
+}
+
+// Walks `rest` one segment at a time until it names a file, which must stay inside `base`. Returns the file and
+// where in `rest` its path ends.
+fn find_script(base: &Path, rest: &str, accept: impl Fn(&Path) -> bool) -> Option<(PathBuf, usize)> {
+    let base = fs::canonicalize(base).ok()?;
+    let ends = rest.match_indices('/').map(|(index, _)| index).filter(|index| *index > 0).chain([rest.len()]);
+    for end in ends {
+        let candidate = base.join(rest[..end].trim_start_matches('/'));
+        if candidate.is_file() {
+            let resolved = fs::canonicalize(&candidate).ok().filter(|resolved| resolved.starts_with(&base))?;
+            return accept(&resolved).then_some((resolved, end));
+        }
+        if !candidate.is_dir() {
+            return None;
+        }
+    }
+    None
+}
+
+pub fn run<W: Write + ?Sized>(route: &CgiRoute, request: &HttpRequest, client: &mut W, keep_alive: bool, decorate: impl FnOnce(&mut HttpResponse)) -> Result<Relayed, ProxyError> {
+    let mut script = Script::start(route, request)?;
+    // A script that was stopped must not look like it finished, so the response is cut off instead.
+    relay(&mut script.stdout, request, client, keep_alive, decorate, || !script.timed_out.load(Ordering::Relaxed))
+}
+
+// Collects the whole output, for connections that cannot pass HTTP/1.1 framing through.
+pub fn fetch(route: &CgiRoute, request: &HttpRequest) -> Result<HttpResponse, ProxyError> {
+    let mut script = Script::start(route, request)?;
+    let response = collect(&mut script.stdout, request)?;
+    if script.timed_out.load(Ordering::Relaxed) {
+        return Err(ProxyError::BadGateway);
+    }
+    Ok(response)
+}
+
+// Streams CGI-style output (headers, a blank line, the body) as it is produced, chunked where the client
+// understands it. `completed` is asked once the output ends, before the response is marked as complete.
+pub fn relay<R: BufRead, W: Write + ?Sized>(output: &mut R, request: &HttpRequest, mut client: &mut W, keep_alive: bool, decorate: impl FnOnce(&mut HttpResponse), completed: impl FnOnce() -> bool) -> Result<Relayed, ProxyError> {
+    let mut response = read_head(output, request)?;
+    // Without chunked framing, only closing the connection can mark the end of the body.
+    let keep_alive = keep_alive && response.get_protocol().supports_chunked();
+    response.append_option(HttpResponseOptions::Connection, if keep_alive { "keep-alive" } else { "close" });
+    response.set_body_suppressed(request.get_method() == HttpMethod::Head);
+    decorate(&mut response);
+
+    let mut body = response.send_chunked(&mut client).map_err(|_| ProxyError::Aborted)?;
+    let mut bytes = 0;
+    loop {
+        let chunk = output.fill_buf().map_err(|_| ProxyError::Aborted)?;
+        if chunk.is_empty() {
+            break;
+        }
+        let length = chunk.len();
+        body.write_chunk(chunk).map_err(|_| ProxyError::Aborted)?;
+        output.consume(length);
+        bytes += length;
+    }
+    if !completed() {
+        return Err(ProxyError::Aborted);
+    }
+    body.finish().map_err(|_| ProxyError::Aborted)?;
+    Ok(Relayed { status: response.get_status().as_u16(), bytes, keep_alive })
+}
+
+pub fn collect<R: BufRead>(output: &mut R, request: &HttpRequest) -> Result<HttpResponse, ProxyError> {
+    let mut response = read_head(output, request)?;
+    let mut payload = Vec::new();
+    output.read_to_end(&mut payload).map_err(|_| ProxyError::BadGateway)?;
+    response.append_option(HttpResponseOptions::ContentLength, payload.len().to_string());
+    response.append_payload(payload);
+    Ok(response)
+}
+
+struct Script {
+    stdout: BufReader<ChildStdout>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl Script {
+    // The body is written to stdin and stderr is logged on threads of their own, so a script that reads or
+    // complains slowly cannot block its output.
+    fn start(route: &CgiRoute, request: &HttpRequest) -> Result<Script, ProxyError> {
+        let mut command = Command::new(&route.script);
+        // A group of its own lets a timeout stop whatever the script started, which would otherwise keep stdout open.
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = command
+            .env_clear()
+            .envs(environment(route, request))
+            .current_dir(route.script.parent().unwrap_or(Path::new(".")))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                warn!("Unable to run the CGI script {}: {}", route.script.display(), err);
+                ProxyError::BadGateway
+            })?;
+
+        let (Some(mut stdin), Some(stdout), Some(stderr)) = (child.stdin.take(), child.stdout.take(), child.stderr.take()) else {
+            child.kill().unwrap_or(());
+            return Err(ProxyError::BadGateway);
+        };
+        let body = request.get_body().to_vec();
+        thread::spawn(move || stdin.write_all(&body).unwrap_or(()));
+        let name = route.script_name.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                warn!("{}: {}", name, line);
+            }
+        });
+
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let (flag, name, timeout) = (timed_out.clone(), route.script_name.clone(), route.timeout);
+        thread::spawn(move || watch(child, timeout, &name, &flag));
+        Ok(Script { stdout: BufReader::new(stdout), timed_out })
+    }
+}
+
+fn watch(mut child: Child, timeout: Duration, name: &str, timed_out: &AtomicBool) {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        match child.try_wait() {
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            _ => return,
+        }
+    }
+    warn!("The CGI script {} ran longer than {} seconds and was stopped.", name, timeout.as_secs());
+    timed_out.store(true, Ordering::Relaxed);
+    // SAFETY: only signals the process group created for this script.
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    child.kill().unwrap_or(());
+    child.wait().map(|_| ()).unwrap_or(());
+}
+
+// The meta-variables of RFC 3875, plus the request headers as HTTP_*. Authorization stays with the server, and
+// Proxy is dropped so it cannot turn into HTTP_PROXY ("httpoxy").
+pub fn environment(route: &CgiRoute, request: &HttpRequest) -> Vec<(String, String)> {
+    let host = request.get_header("Host").unwrap_or("localhost");
+    let scheme = request.scheme();
+    let default_port = if scheme == "https" { "443" } else { "80" };
+    let port = host.rsplit_once(':').map(|(_, port)| port).filter(|port| port.bytes().all(|byte| byte.is_ascii_digit()));
+
+    let mut vars: Vec<(String, String)> = vec![
+        ("GATEWAY_INTERFACE".to_string(), "CGI/1.1".to_string()),
+        ("SERVER_SOFTWARE".to_string(), format!("backend_web_server/{}", env!("CARGO_PKG_VERSION"))),
+        ("SERVER_NAME".to_string(), request.host().unwrap_or("localhost").to_string()),
+        ("SERVER_PORT".to_string(), port.unwrap_or(default_port).to_string()),
+        ("SERVER_PROTOCOL".to_string(), request.get_protocol().to_string()),
+        ("REQUEST_METHOD".to_string(), request.get_method().get_name().to_string()),
+        ("REQUEST_URI".to_string(), request.get_target().to_string()),
+        ("REQUEST_SCHEME".to_string(), scheme.to_string()),
+        ("SCRIPT_NAME".to_string(), route.script_name.clone()),
+        ("SCRIPT_FILENAME".to_string(), route.script.display().to_string()),
+        ("DOCUMENT_ROOT".to_string(), route.document_root.display().to_string()),
+        ("QUERY_STRING".to_string(), request.get_query().unwrap_or("").to_string()),
+        // PHP's CGI binary refuses to run without it.
+        ("REDIRECT_STATUS".to_string(), "200".to_string()),
+        ("PATH".to_string(), env::var("PATH").unwrap_or_default()),
+    ];
+    if !route.path_info.is_empty() {
+        vars.push(("PATH_INFO".to_string(), route.path_info.clone()));
+        vars.push(("PATH_TRANSLATED".to_string(), route.document_root.join(route.path_info.trim_start_matches('/')).display().to_string()));
+    }
+    if let Some(client) = request.client_ip().or(request.peer_ip()) {
+        vars.push(("REMOTE_ADDR".to_string(), client.to_string()));
+    }
+    if scheme == "https" {
+        vars.push(("HTTPS".to_string(), "on".to_string()));
+    }
+    if !request.get_body().is_empty() || request.headers().contains("Content-Length") {
+        vars.push(("CONTENT_LENGTH".to_string(), request.get_body().len().to_string()));
+    }
+    if let Some(content_type) = request.get_header("Content-Type") {
+        vars.push(("CONTENT_TYPE".to_string(), content_type.to_string()));
+    }
+
+    for (name, value) in request.get_headers() {
+        let lower = name.to_ascii_lowercase();
+        if matches!(lower.as_str(), "content-type" | "content-length" | "authorization" | "proxy") {
+            continue;
+        }
+        let key = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        match vars.iter_mut().find(|(existing, _)| *existing == key) {
+            Some(entry) => entry.1 = format!("{}, {}", entry.1, value),
+            None => vars.push((key, value.clone())),
+        }
+    }
+    vars
+}
+
+// CGI output starts with header lines like an HTTP response, without the status line; "Status" sets it instead.
+fn read_head<R: BufRead>(stdout: &mut R, request: &HttpRequest) -> Result<HttpResponse, ProxyError> {
+    let mut response = HttpResponse::new(HttpProtocols::OneOne);
+    response.set_protocol(request.get_protocol().response_version());
+    let mut status = None;
+    let (mut location, mut content_type) = (false, false);
+    loop {
+        let line = read_line(stdout).ok_or(ProxyError::BadGateway)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or(ProxyError::BadGateway)?;
+        let (name, value) = (name.trim(), value.trim());
+        let lower = name.to_ascii_lowercase();
+        if lower == "status" {
+            status = Some(value.parse::<HttpResponseStatusCode>().map_err(|_| ProxyError::BadGateway)?);
+            continue;
+        }
+        if FRAMING.contains(&lower.as_str()) {
+            continue;
+        }
+        check_header_name(name).and_then(|_| check_header_value(name, value)).map_err(|_| ProxyError::BadGateway)?;
+        location |= lower == "location";
+        content_type |= lower == "content-type";
+        response.add_option(HttpResponseOptions::from_name(name), value);
+    }
+
+    if status.is_none() && !location && !content_type {
+        warn!("A CGI script answered without a Content-Type, Location or Status header.");
+        return Err(ProxyError::BadGateway);
+    }
+    response.set_status(status.unwrap_or(if location { HttpResponseStatusCode::Found } else { HttpResponseStatusCode::OK }));
+    Ok(response)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Option<String> {
+    let mut line = String::new();
+    match reader.by_ref().take(8 * 1024).read_line(&mut line) {
+        Ok(_) if line.ends_with('\n') => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::net::{IpAddr, Ipv4Addr};
+    use super::*;
+
+    fn request(head: &str, body: &str) -> HttpRequest {
+        HttpRequest::parse(&mut format!("{head}\r\n\r\n{body}").as_bytes()).unwrap()
+    }
+
+    fn route(script: PathBuf, script_name: &str, path_info: &str) -> CgiRoute {
+        CgiRoute { script, script_name: script_name.to_string(), path_info: path_info.to_string(), document_root: PathBuf::from("/srv/site"), timeout: Duration::from_secs(5) }
+    }
+
+    fn lookup<'a>(vars: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        vars.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn builds_the_environment_from_the_request() {
+        let mut request = request(concat!(
+            "POST /cgi-bin/env.sh/extra/path?a=1 HTTP/1.1\r\nHost: site.example:8443\r\nContent-Type: text/plain\r\nContent-Length: 7\r\n",
+            "Authorization: Basic YTpi\r\nProxy: http://evil.example\r\nX-Custom: one\r\nX-Custom: two",
+        ), "payload");
+        request.set_secure(true);
+        request.set_peer_ip(Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 2))));
+        let vars = environment(&route(PathBuf::from("/srv/cgi-bin/env.sh"), "/cgi-bin/env.sh", "/extra/path"), &request);
+
+        for (name, value) in [
+            ("REQUEST_METHOD", "POST"), ("REQUEST_URI", "/cgi-bin/env.sh/extra/path?a=1"), ("QUERY_STRING", "a=1"),
+            ("SCRIPT_NAME", "/cgi-bin/env.sh"), ("SCRIPT_FILENAME", "/srv/cgi-bin/env.sh"), ("PATH_INFO", "/extra/path"),
+            ("PATH_TRANSLATED", "/srv/site/extra/path"), ("SERVER_NAME", "site.example"), ("SERVER_PORT", "8443"),
+            ("SERVER_PROTOCOL", "HTTP/1.1"), ("REQUEST_SCHEME", "https"), ("HTTPS", "on"), ("REMOTE_ADDR", "198.51.100.2"),
+            ("CONTENT_LENGTH", "7"), ("CONTENT_TYPE", "text/plain"), ("HTTP_HOST", "site.example:8443"), ("HTTP_X_CUSTOM", "one, two"),
+        ] {
+            assert_eq!(lookup(&vars, name), Some(value), "{name}: {vars:?}");
+        }
+        // httpoxy: a Proxy header must never become HTTP_PROXY, which many clients read as their proxy.
+        for hidden in ["HTTP_PROXY", "HTTP_AUTHORIZATION", "HTTP_CONTENT_TYPE", "HTTP_CONTENT_LENGTH"] {
+            assert_eq!(lookup(&vars, hidden), None, "{hidden}: {vars:?}");
+        }
+    }
+
+    #[test]
+    fn plain_http_gets_its_default_port_and_no_https() {
+        let vars = environment(&route(PathBuf::from("/srv/cgi-bin/env.sh"), "/cgi-bin/env.sh", ""), &request("GET /cgi-bin/env.sh HTTP/1.1\r\nHost: site.example", ""));
+        assert_eq!(lookup(&vars, "SERVER_PORT"), Some("80"));
+        assert_eq!(lookup(&vars, "REQUEST_SCHEME"), Some("http"));
+        for missing in ["HTTPS", "PATH_INFO", "REMOTE_ADDR", "CONTENT_LENGTH"] {
+            assert_eq!(lookup(&vars, missing), None, "{missing}: {vars:?}");
+        }
+    }
+
+    #[test]
+    fn reads_the_status_and_headers_from_the_output() {
+        let get = request("GET /cgi-bin/env.sh HTTP/1.1\r\nHost: site.example", "");
+        let collected = |output: &str| collect(&mut Cursor::new(output.to_string()), &get).ok();
+
+        let response = collected("Status: 201 Created\r\nContent-Type: text/plain\nContent-Length: 999\r\nConnection: close\r\nX-Script: yes\r\n\r\nmade").unwrap();
+        assert_eq!(response.get_status().as_u16(), 201);
+        assert_eq!(response.get_option(&HttpResponseOptions::ContentLength), Some("4"));
+        assert_eq!(response.get_option(&HttpResponseOptions::Connection), None);
+        assert_eq!(response.get_option(&HttpResponseOptions::Custom("X-Script".to_string())), Some("yes"));
+        assert_eq!(response.get_payload(), b"made");
+
+        let redirect = collected("Location: /elsewhere\r\n\r\n").unwrap();
+        assert_eq!(redirect.get_status().as_u16(), 302);
+        assert_eq!(collected("Content-Type: text/plain\r\n\r\n").unwrap().get_status().as_u16(), 200);
+        // Output that is not a CGI response is a bad gateway.
+        for invalid in ["X-Script: yes\r\n\r\nbody", "Status: teapot\r\n\r\n", "not a header\r\n\r\n", "Content-Type: text/plain\r\n", "Content-Type: text/\x01plain\r\n\r\n"] {
+            assert!(collected(invalid).is_none(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn relays_the_body_chunked() {
+        let get = request("GET /cgi-bin/env.sh HTTP/1.1\r\nHost: site.example", "");
+        let mut client = Vec::new();
+        let Ok(relayed) = relay(&mut Cursor::new("Content-Type: text/plain\r\n\r\nhello"), &get, &mut client, true, |_| {}, || true) else {
+            panic!("The output was not relayed");
+        };
+        let client = String::from_utf8(client).unwrap();
+        assert_eq!((relayed.status, relayed.bytes, relayed.keep_alive), (200, 5, true));
+        assert!(client.contains("\r\nTransfer-Encoding: chunked\r\n") && client.contains("\r\nConnection: keep-alive\r\n"), "{client}");
+        assert!(client.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"), "{client}");
+
+        // A script that was stopped must not look like it finished.
+        let result = relay(&mut Cursor::new("Content-Type: text/plain\r\n\r\nhel"), &get, &mut Vec::new(), true, |_| {}, || false);
+        assert!(matches!(result, Err(ProxyError::Aborted)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_scripts_with_the_request_on_stdin() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("backend-web-server-cgi-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("env.sh");
+        fs::write(&script, "#!/bin/sh\nprintf 'Status: 202 Accepted\\r\\nContent-Type: text/plain\\r\\nX-Script: yes\\r\\n\\r\\n'\nenv\nprintf 'body='\ncat\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let request = request("POST /env.sh/extra?a=1 HTTP/1.1\r\nHost: site.example\r\nProxy: http://evil.example\r\nContent-Length: 7", "payload");
+        let route = in_dir(&dir, Path::new("/srv/site"), "/env.sh/extra", Duration::from_secs(5)).unwrap();
+        let missing = in_dir(&dir, Path::new("/srv/site"), "/none.sh", Duration::from_secs(5));
+        let escaped = in_dir(&dir, Path::new("/srv/site"), "/../env.sh", Duration::from_secs(5));
+        let response = fetch(&route, &request).ok();
+        fs::remove_dir_all(&dir).unwrap_or(());
+
+        assert!(missing.is_none() && escaped.is_none());
+        let response = response.expect("The script did not answer");
+        let output = String::from_utf8_lossy(response.get_payload()).to_string();
+        assert_eq!(response.get_status().as_u16(), 202);
+        assert_eq!(response.get_option(&HttpResponseOptions::Custom("X-Script".to_string())), Some("yes"));
+        for line in ["REQUEST_METHOD=POST", "SCRIPT_NAME=/env.sh", "PATH_INFO=/extra", "QUERY_STRING=a=1", "CONTENT_LENGTH=7", "GATEWAY_INTERFACE=CGI/1.1"] {
+            assert!(output.lines().any(|output| output == line), "{line}: {output}");
+        }
+        assert!(!output.contains("HTTP_PROXY="), "{output}");
+        assert!(output.ends_with("body=payload"), "{output}");
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use log::LevelFilter;
+use crate::config::Config;
+use crate::logging;
+
+pub const USAGE: &str = "\
+Usage: backend_web_server [OPTIONS]
+
+Options:
+  --config <path>      Read the configuration from <path>, either .toml or the legacy .cfg format
+  --port <port>        Listen on <port>, replacing \"port\" and any \"listen\" lines
+  --root <dir>         Serve files from <dir>
+  --threads <count>    Number of worker threads
+  --log-level <level>  off, error, warn, info, debug or trace
+  --daemon             Run without the stdin console or prompts, as with \"interactive = false\"
+  --validate-config    Check the configuration and exit
+  --version            Print the version and exit
+  --help               Print this message and exit";
+
+// Values given on the command line win over the config file, and keep winning across reloads.
+#[derive(Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub port: Option<String>,
+    pub root: Option<String>,
+    pub threads: Option<usize>,
+    pub log_level: Option<LevelFilter>,
+    pub daemon: bool,
+    pub validate_config: bool,
+    pub version: bool,
+    pub help: bool,
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut out = Args::default();
+        while let Some(arg) = args.next() {
+            // Both "--flag value" and "--flag=value" are accepted.
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || inline.clone().or_else(|| args.next()).ok_or_else(|| format!("{flag} needs a value"));
+
+            match flag.as_str() {
+                "--config" => out.config = Some(PathBuf::from(value()?)),
+                "--port" => out.port = Some(value()?),
+                "--root" => out.root = Some(value()?),
+                "--threads" => out.threads = Some(usize::from_str(&value()?).ok().filter(|threads| *threads > 0)
+                    .ok_or_else(|| "--threads needs a positive number".to_string())?),
+                "--log-level" => out.log_level = Some(logging::parse_level(&value()?)
+                    .ok_or_else(|| "--log-level needs one of off, error, warn, info, debug or trace".to_string())?),
+                "--daemon" => out.daemon = true,
+                "--validate-config" => out.validate_config = true,
+                "--version" | "-V" => out.version = true,
+                "--help" | "-h" => out.help = true,
+                _ => return Err(format!("Unknown option: {flag}")),
+            }
+        }
+        Ok(out)
+    }
+
+    // Puts the values given over `config`, returning the keys they replaced.
+    pub fn apply(&self, config: &mut Config) -> Vec<&'static str> {
+        let mut replaced = Vec::new();
+        if let Some(port) = &self.port {
+            config.port = port.clone();
+            config.listen.clear();
+            replaced.push("port");
+        }
+        if self.daemon {
+            config.interactive = false;
+            replaced.push("interactive");
+        }
+        if let Some(threads) = self.threads {
+            config.threads = threads;
+            replaced.push("num-threads");
+        }
+        if let Some(level) = self.log_level {
+            config.log_level = level;
+            replaced.push("log-level");
+        }
+        if let Some(root) = &self.root {
+            config.root_dir = PathBuf::from(root);
+            replaced.push("root-dir");
+        }
+        replaced
+    }
+
+    // Without --config, settings.toml is preferred when it exists, then the legacy settings.cfg.
+    pub fn get_config_path(&self) -> PathBuf {
+        match &self.config {
+            Some(path) => path.clone(),
+            None if Path::new("settings.toml").exists() => PathBuf::from("settings.toml"),
+            None => PathBuf::from("settings.cfg"),
+        }
+    }
+}
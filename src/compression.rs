@@ -0,0 +1,111 @@
+use std::io::Write;
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone, Copy)]
+pub enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub const PREFERENCE: &'static [Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        Encoding::Gzip,
+        Encoding::Deflate,
+    ];
+
+    pub fn get_name(&self) -> &str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                let mut out = Vec::new();
+                let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                encoder.write_all(data).ok()?;
+                drop(encoder);
+                Some(out)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).ok()?;
+                encoder.finish().ok()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).ok()?;
+                encoder.finish().ok()
+            }
+        }
+    }
+}
+
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let accepted = parse_accept_encoding(accept_encoding);
+    Encoding::PREFERENCE.iter()
+        .map(|encoding| (*encoding, quality_of(&accepted, encoding.get_name())))
+        .filter(|(_, quality)| *quality > 0.0)
+        .fold(None, |best: Option<(Encoding, f32)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(candidate),
+        })
+        .map(|(encoding, _)| encoding)
+}
+
+// Picks among encodings that are already available, such as precompressed files, by the client's weights. Earlier
+// names win ties.
+pub fn choose<'a>(accept_encoding: &str, available: &[&'a str]) -> Option<&'a str> {
+    let accepted = parse_accept_encoding(accept_encoding);
+    available.iter()
+        .map(|name| (*name, quality_of(&accepted, name)))
+        .filter(|(_, quality)| *quality > 0.0)
+        .fold(None, |best: Option<(&str, f32)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(candidate),
+        })
+        .map(|(name, _)| name)
+}
+
+fn parse_accept_encoding(accept_encoding: &str) -> Vec<(String, f32)> {
+    accept_encoding.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(|s| s.trim());
+            let name = parts.next().filter(|s| !s.is_empty())?.to_ascii_lowercase();
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(1.0, |q| q.parse::<f32>().unwrap_or(0.0));
+            Some((name, quality))
+        })
+        .collect()
+}
+
+fn quality_of(accepted: &[(String, f32)], name: &str) -> f32 {
+    accepted.iter()
+        .find(|(accepted_name, _)| accepted_name == name)
+        .or_else(|| accepted.iter().find(|(accepted_name, _)| accepted_name == "*"))
+        .map_or(0.0, |(_, quality)| *quality)
+}
+
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    mime.starts_with("text/") || matches!(mime,
+        "application/javascript"
+        | "application/json"
+        | "application/xml"
+        | "application/wasm"
+        | "image/svg+xml"
+    )
+}
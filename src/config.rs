@@ -0,0 +1,922 @@
+use std::collections::HashMap;
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use http_resources::{MimeRegistry, Templates};
+use log::{warn, LevelFilter};
+use crate::access_log::{Rotation, COMMON_LOG_FORMAT};
+use crate::auth::AuthRule;
+use crate::cache_control::CacheRule;
+use crate::cors::CorsPolicy;
+use crate::deny::DenyRules;
+use crate::fastcgi::FastCgiAddress;
+use crate::forwarded::TrustedProxy;
+use crate::proxy::Upstream;
+use crate::redirect::RedirectRule;
+use crate::{listener, logging, rate_limit, settings};
+
+const SECURITY_HEADERS: [(&str, &str); 5] = [
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "SAMEORIGIN"),
+    ("Strict-Transport-Security", "max-age=31536000; includeSubDomains"),
+    ("Content-Security-Policy", "default-src 'self'"),
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+];
+
+// The server's settings, as read from settings.toml or settings.cfg.
+pub struct Config {
+    pub ip: String,
+    pub port: String,
+    pub listen: Vec<String>,
+    pub listen_tls: Vec<String>,
+    pub admin_listen: String,
+    pub threads: usize,
+    pub io_backend: String,
+    pub queue_limit: usize,
+    pub queue_full_wait: bool,
+    pub max_connections: usize,
+    pub connections_full_wait: bool,
+    pub home_name: String,
+    pub root_dir: PathBuf,
+    pub hosts: Vec<VirtualHost>,
+    pub mounts: Vec<(String, PathBuf)>,
+    pub allowed_hosts: Vec<String>,
+    pub trusted_proxies: Vec<TrustedProxy>,
+    pub proxy_protocol: bool,
+    pub error_pages: Vec<(u16, PathBuf)>,
+    pub proxies: Vec<(String, Upstream)>,
+    pub uploads: Vec<(String, PathBuf)>,
+    pub cgi: Vec<(String, PathBuf)>,
+    pub cgi_extensions: Vec<String>,
+    pub cgi_timeout: u64,
+    pub fastcgi: Vec<(String, FastCgiAddress)>,
+    pub sse_keep_alive: u64,
+    pub auth: Vec<AuthRule>,
+    pub redirects: Vec<RedirectRule>,
+    pub cache_rules: Vec<CacheRule>,
+    pub html_extension: String,
+    pub deny: DenyRules,
+    pub spa_fallback: String,
+    pub favicon: PathBuf,
+    pub well_known_dir: PathBuf,
+    pub templates_dir: PathBuf,
+    pub rate_limit: f64,
+    pub rate_limit_burst: f64,
+    pub bandwidth_limit: u64,
+    pub bandwidth_limit_total: u64,
+    pub cors: CorsPolicy,
+    pub security_headers: bool,
+    pub security_header_values: Vec<(String, String)>,
+    pub server_header: String,
+    pub default_headers: Vec<(String, String)>,
+    pub ssl: String,
+    pub ssl_key: String,
+    pub ssl_port: String,
+    pub ssl_only: bool,
+    pub acme_domains: Vec<String>,
+    pub acme_email: String,
+    pub acme_directory: String,
+    pub acme_dir: PathBuf,
+    pub acme_renew_days: u64,
+    pub keep_alive_timeout: u64,
+    pub read_timeout: u64,
+    pub write_timeout: u64,
+    pub header_timeout: u64,
+    pub max_connections_per_ip: usize,
+    pub keep_alive_max: usize,
+    pub max_body_size: usize,
+    pub max_upload_size: u64,
+    pub max_request_line: usize,
+    pub max_header_size: usize,
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    pub compression: bool,
+    pub compression_min_size: usize,
+    pub precompressed: bool,
+    pub file_cache_size: usize,
+    pub preload: Vec<String>,
+    pub stream_threshold: u64,
+    pub dev_mode: bool,
+    pub mime_types: Vec<(String, String)>,
+    pub charsets: Vec<(String, String)>,
+    pub default_charset: String,
+    pub mime: MimeRegistry,
+    pub autoindex: bool,
+    pub access_log: String,
+    pub access_log_format: String,
+    pub access_log_max_size: u64,
+    pub access_log_rotation: Rotation,
+    pub log_level: LevelFilter,
+    pub error_log: String,
+    pub shutdown_timeout: u64,
+    pub interactive: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ip: "127.0.0.1".to_string(),
+            port: "8080".to_string(),
+            listen: Vec::new(),
+            listen_tls: Vec::new(),
+            admin_listen: "".to_string(),
+            home_name: "home".to_string(),
+            root_dir: PathBuf::from("website"),
+            hosts: Vec::new(),
+            mounts: Vec::new(),
+            allowed_hosts: Vec::new(),
+            trusted_proxies: Vec::new(),
+            proxy_protocol: false,
+            error_pages: vec![(404, PathBuf::from("__errors__/404.html")), (500, PathBuf::from("__errors__/500.html"))],
+            proxies: Vec::new(),
+            uploads: Vec::new(),
+            cgi: Vec::new(),
+            cgi_extensions: Vec::new(),
+            cgi_timeout: 30,
+            fastcgi: Vec::new(),
+            sse_keep_alive: 15,
+            auth: Vec::new(),
+            redirects: Vec::new(),
+            cache_rules: Vec::new(),
+            html_extension: "keep".to_string(),
+            deny: DenyRules::default(),
+            spa_fallback: String::new(),
+            favicon: PathBuf::new(),
+            well_known_dir: PathBuf::new(),
+            templates_dir: PathBuf::new(),
+            rate_limit: 0.0,
+            rate_limit_burst: 20.0,
+            bandwidth_limit: 0,
+            bandwidth_limit_total: 0,
+            cors: CorsPolicy::default(),
+            security_headers: false,
+            security_header_values: SECURITY_HEADERS.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            server_header: "backend_web_server".to_string(),
+            default_headers: Vec::new(),
+            ssl: "".to_string(),
+            ssl_key: "".to_string(),
+            ssl_port: "".to_string(),
+            ssl_only: false,
+            acme_domains: Vec::new(),
+            acme_email: String::new(),
+            acme_directory: "https://acme-v02.api.letsencrypt.org/directory".to_string(),
+            acme_dir: PathBuf::from("acme"),
+            acme_renew_days: 30,
+            threads: 20,
+            io_backend: "threads".to_string(),
+            queue_limit: 256,
+            queue_full_wait: false,
+            max_connections: 0,
+            connections_full_wait: false,
+            keep_alive_timeout: 5,
+            read_timeout: 10,
+            write_timeout: 10,
+            header_timeout: 10,
+            max_connections_per_ip: 64,
+            keep_alive_max: 100,
+            max_body_size: 1024 * 1024,
+            max_upload_size: 100 * 1024 * 1024,
+            max_request_line: 8 * 1024,
+            max_header_size: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_headers: 100,
+            compression: true,
+            compression_min_size: 1024,
+            precompressed: false,
+            file_cache_size: 64 * 1024 * 1024,
+            preload: Vec::new(),
+            stream_threshold: 1024 * 1024,
+            dev_mode: false,
+            mime_types: Vec::new(),
+            charsets: Vec::new(),
+            default_charset: "utf-8".to_string(),
+            mime: MimeRegistry::new(),
+            autoindex: false,
+            access_log: "".to_string(),
+            access_log_format: COMMON_LOG_FORMAT.to_string(),
+            access_log_max_size: 0,
+            access_log_rotation: Rotation::Never,
+            log_level: LevelFilter::Info,
+            error_log: "".to_string(),
+            shutdown_timeout: 10,
+            interactive: true,
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub struct VirtualHost {
+    pub names: Vec<String>,
+    pub root_dir: PathBuf,
+    pub home_name: String,
+    pub ssl_cert: String,
+    pub ssl_key: String,
+}
+
+pub struct Site<'a> {
+    pub root_dir: &'a Path,
+    pub home_name: &'a str,
+    // The URL prefix of a mounted site, which its paths are relative to; empty for the host's own root.
+    pub prefix: &'a str,
+}
+
+impl Config {
+    // Reads a config file: TOML by its extension, the legacy format otherwise.
+    pub fn from_file(path: &Path) -> Result<Config, Vec<String>> {
+        Config::load(path, |_| Vec::new())
+    }
+
+    // Like `from_file`, but `overrides` gets to change the settings before they are checked. It returns the keys it
+    // replaced, which problems are then no longer blamed on a line of the file for.
+    pub fn load(path: &Path, overrides: impl FnOnce(&mut Config) -> Vec<&'static str>) -> Result<Config, Vec<String>> {
+        let mut layers = Layers::new(Config::default());
+        layers.read_file(path);
+        for key in overrides(&mut layers.config) {
+            layers.lines.remove(key);
+        }
+        layers.finish()
+    }
+
+    // Layers settings in the legacy format on top of these ones. Settings given again replace what is there, except
+    // those that can be given several times, such as "listen", "mime.*" or [host] sections, which add to it.
+    pub fn merge(self, source: &str) -> Result<Config, Vec<String>> {
+        let mut layers = Layers::new(self);
+        layers.read("config", settings::read_legacy(source));
+        layers.finish()
+    }
+
+    // Any "listen" line replaces the ip/port pair.
+    pub fn http_addresses(&self) -> Vec<String> {
+        if self.listen.is_empty() {
+            return vec![listener::join_host_port(&self.ip, &self.port)];
+        }
+        self.listen.clone()
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls_addresses(&self) -> Vec<String> {
+        if !self.listen_tls.is_empty() {
+            return self.listen_tls.clone();
+        }
+        if self.ssl_port.is_empty() {
+            return Vec::new();
+        }
+        vec![listener::join_host_port(&self.ip, &self.ssl_port)]
+    }
+
+    // `host` is the name from the Host header, without its port.
+    pub fn site_for(&self, host: Option<&str>) -> Site<'_> {
+        let host = host.map(|host| host.to_ascii_lowercase());
+        let matched = host.as_deref().and_then(|host| self.hosts.iter().find(|vhost| {
+            vhost.names.iter().any(|name| host_matches(name, host))
+        }));
+
+        match matched {
+            Some(vhost) => Site { root_dir: &vhost.root_dir, home_name: &vhost.home_name, prefix: "" },
+            None => Site { root_dir: &self.root_dir, home_name: &self.home_name, prefix: "" },
+        }
+    }
+
+    // The site mounted at the longest prefix `path` lies below, whatever the host. Like directories, mounted sites
+    // are entered at their index page.
+    pub fn mount_for(&self, path: &str) -> Option<Site<'_>> {
+        self.mounts.iter()
+            .filter(|(prefix, _)| path.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, root_dir)| Site { root_dir, home_name: "index", prefix })
+    }
+}
+
+// Settings in the legacy format, over the defaults.
+impl FromStr for Config {
+    type Err = Vec<String>;
+
+    fn from_str(source: &str) -> Result<Config, Vec<String>> {
+        Config::default().merge(source)
+    }
+}
+
+// "*.example.com" matches any subdomain, but not example.com itself.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+// Settings are read in layers, each on top of the ones before. Every invalid value is collected, so a broken config
+// can be fixed in one go rather than one error at a time.
+struct Layers {
+    config: Config,
+    errors: Vec<String>,
+    // Where each top-level setting was last set, as "file:line", to point check_config's problems at.
+    lines: HashMap<String, String>,
+    // The last source read, for problems no line is to blame for.
+    origin: String,
+}
+
+impl Layers {
+    fn new(config: Config) -> Layers {
+        Layers { config, errors: Vec::new(), lines: HashMap::new(), origin: String::new() }
+    }
+
+    fn read_file(&mut self, path: &Path) {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                return self.errors.push(format!("Unable to open configuration file {}: {}", path.display(), err));
+            }
+        };
+        let entries = if path.extension().is_some_and(|extension| extension == "toml") {
+            match settings::read_toml(&source) {
+                Ok(entries) => entries,
+                Err(err) => return self.errors.push(format!("Unable to parse {}: {}", path.display(), err)),
+            }
+        } else {
+            settings::read_legacy(&source)
+        };
+        self.read(&path.display().to_string(), entries);
+    }
+
+    // `origin` names the source in messages.
+    fn read(&mut self, origin: &str, entries: Vec<settings::Entry>) {
+        self.origin = origin.to_string();
+        let mut suppress_warning: bool = false;
+        let mut current_host: Option<VirtualHost> = None;
+
+        for entry in entries {
+            let (line, key, value) = match entry {
+                settings::Entry::Section { host, .. } => {
+                    self.config.hosts.extend(current_host.take());
+                    current_host = host.map(|name| VirtualHost {
+                        names: vec![name.to_ascii_lowercase()],
+                        root_dir: PathBuf::new(),
+                        home_name: self.config.home_name.clone(),
+                        ssl_cert: String::new(),
+                        ssl_key: String::new(),
+                    });
+                    continue;
+                },
+                settings::Entry::Invalid { line, message } => {
+                    if !suppress_warning {
+                        warn!("{}:{}: {}", origin, line, message);
+                        warn!("Continuing, but this line will be skipped.");
+                        warn!("To ignore these warnings add \"suppress-warnings = true\" at the top of the config file.");
+                    }
+                    continue;
+                },
+                settings::Entry::Setting { line, key, value } => (line, key, value),
+            };
+            let (key, value) = (key.as_str(), value.as_str());
+
+            // Both report the value against this entry's line and move on to the next entry.
+            macro_rules! invalid {
+                ($($arg:tt)+) => {{
+                    self.errors.push(format!("{}:{}: {}", origin, line, format!($($arg)+)));
+                    continue;
+                }};
+            }
+            macro_rules! parse {
+                ($ty:ty) => {
+                    match <$ty>::from_str(value.trim_matches('\"')) {
+                        Ok(parsed) => parsed,
+                        Err(_) => invalid!("\"{}\" must be {}, not \"{}\"", key, describe_type(stringify!($ty)), value.trim_matches('\"')),
+                    }
+                };
+            }
+
+            if let Some(host) = current_host.as_mut() {
+                match key {
+                    "root-dir" => host.root_dir = PathBuf::from(value.trim_matches('\"')),
+                    "home-name" => host.home_name = value.trim_matches('\"').to_string(),
+                    "ssl-cert" => host.ssl_cert = value.trim_matches('\"').to_string(),
+                    "ssl-key" => host.ssl_key = value.trim_matches('\"').to_string(),
+                    "aliases" => host.names.extend(value.trim_matches('\"').split(',').map(|s| s.trim().to_ascii_lowercase()).filter(|s| !s.is_empty())),
+                    _ => if !suppress_warning {
+                        warn!("{}:{}: \"{}\" is not supported inside a [host] section and will be skipped.", origin, line, key);
+                    },
+                }
+                continue;
+            }
+
+            self.lines.insert(key.to_string(), format!("{origin}:{line}"));
+            match key {
+                "ip" => self.config.ip = value.trim_matches('\"').to_string(),
+                "port" => self.config.port = value.trim_matches('\"').to_string(),
+                "listen" => self.config.listen.extend(value.trim_matches('\"').split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty())),
+                "admin-listen" => self.config.admin_listen = value.trim_matches('\"').to_string(),
+                "listen-tls" => self.config.listen_tls.extend(value.trim_matches('\"').split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty())),
+                "num-threads" => self.config.threads = parse!(usize),
+                "io-backend" => self.config.io_backend = match value.trim_matches('\"') {
+                    backend @ ("threads" | "async") => backend.to_string(),
+                    _ => invalid!("\"io-backend\" must be threads or async, not \"{}\"", value.trim_matches('\"')),
+                },
+                "queue-limit" => self.config.queue_limit = parse!(usize),
+                "queue-full" => self.config.queue_full_wait = match value.trim_matches('\"') {
+                    "reject" => false,
+                    "wait" => true,
+                    _ => invalid!("\"queue-full\" must be reject or wait, not \"{}\"", value.trim_matches('\"')),
+                },
+                "max-connections" => self.config.max_connections = parse!(usize),
+                "connections-full" => self.config.connections_full_wait = match value.trim_matches('\"') {
+                    "reject" => false,
+                    "wait" => true,
+                    _ => invalid!("\"connections-full\" must be reject or wait, not \"{}\"", value.trim_matches('\"')),
+                },
+                "suppress-warnings" => suppress_warning = parse!(bool),
+                "home-name" => self.config.home_name = value.trim_matches('\"').to_string(),
+                "access-log" => self.config.access_log = value.trim_matches('\"').to_string(),
+                "access-log-format" => self.config.access_log_format = value.trim_matches('\"').to_string(),
+                "access-log-max-size" => self.config.access_log_max_size = parse!(u64),
+                "access-log-rotate" => self.config.access_log_rotation = match value.trim_matches('\"') {
+                    "daily" => Rotation::Daily,
+                    "never" => Rotation::Never,
+                    _ => invalid!("\"access-log-rotate\" must be never or daily, not \"{}\"", value.trim_matches('\"')),
+                },
+                "log-level" => self.config.log_level = match logging::parse_level(value.trim_matches('\"')) {
+                    Some(level) => level,
+                    None => invalid!("\"log-level\" must be off, error, warn, info, debug or trace, not \"{}\"", value.trim_matches('\"')),
+                },
+                "error-log" => self.config.error_log = value.trim_matches('\"').to_string(),
+                "shutdown-timeout" => self.config.shutdown_timeout = parse!(u64),
+                "interactive" => self.config.interactive = parse!(bool),
+                "autoindex" => self.config.autoindex = parse!(bool),
+                "deny" => self.config.deny.patterns = value.trim_matches('\"').split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                "deny-except" => self.config.deny.exceptions = value.trim_matches('\"').split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                "deny-status" => self.config.deny.status = parse!(u16),
+                "html-extension" => self.config.html_extension = match value.trim_matches('\"') {
+                    mode @ ("keep" | "strip" | "add") => mode.to_string(),
+                    _ => invalid!("\"html-extension\" must be keep, strip or add, not \"{}\"", value.trim_matches('\"')),
+                },
+                "spa-fallback" => self.config.spa_fallback = value.trim_matches('\"').to_string(),
+                "templates-dir" => self.config.templates_dir = PathBuf::from(value.trim_matches('\"')),
+                "favicon" => self.config.favicon = PathBuf::from(value.trim_matches('\"')),
+                "well-known-dir" => self.config.well_known_dir = PathBuf::from(value.trim_matches('\"')),
+                "root-dir" => self.config.root_dir = PathBuf::from(value.trim_matches('\"')),
+                "ssl-cert" => self.config.ssl = value.trim_matches('\"').to_string(),
+                "ssl-key" => self.config.ssl_key = value.trim_matches('\"').to_string(),
+                "ssl-port" => self.config.ssl_port = value.trim_matches('\"').to_string(),
+                "ssl-only" => self.config.ssl_only = parse!(bool),
+                "acme-domains" => self.config.acme_domains = value.trim_matches('\"').split(',').map(|s| s.trim().to_ascii_lowercase()).filter(|s| !s.is_empty()).collect(),
+                "acme-email" => self.config.acme_email = value.trim_matches('\"').to_string(),
+                "acme-directory" => self.config.acme_directory = value.trim_matches('\"').to_string(),
+                "acme-dir" => self.config.acme_dir = PathBuf::from(value.trim_matches('\"')),
+                "acme-renew-days" => self.config.acme_renew_days = parse!(u64),
+                "keep-alive-timeout" => self.config.keep_alive_timeout = parse!(u64),
+                "read-timeout" => self.config.read_timeout = parse!(u64),
+                "write-timeout" => self.config.write_timeout = parse!(u64),
+                "header-timeout" => self.config.header_timeout = parse!(u64),
+                "max-connections-per-ip" => self.config.max_connections_per_ip = parse!(usize),
+                "keep-alive-max" => self.config.keep_alive_max = parse!(usize),
+                "max-body-size" => self.config.max_body_size = parse!(usize),
+                "max-upload-size" => self.config.max_upload_size = parse!(u64),
+                "cgi-extensions" => self.config.cgi_extensions = value.trim_matches('\"').split(',').map(|s| s.trim().trim_start_matches('.').to_string()).filter(|s| !s.is_empty()).collect(),
+                "cgi-timeout" => self.config.cgi_timeout = parse!(u64),
+                "sse-keep-alive" => self.config.sse_keep_alive = parse!(u64),
+                "max-request-line" => self.config.max_request_line = parse!(usize),
+                "max-header-size" => self.config.max_header_size = parse!(usize),
+                "max-header-bytes" => self.config.max_header_bytes = parse!(usize),
+                "max-headers" => self.config.max_headers = parse!(usize),
+                "compression" => self.config.compression = parse!(bool),
+                "rate-limit" => self.config.rate_limit = parse!(f64),
+                "rate-limit-burst" => self.config.rate_limit_burst = parse!(f64),
+                "bandwidth-limit" => self.config.bandwidth_limit = parse!(u64),
+                "bandwidth-limit-total" => self.config.bandwidth_limit_total = parse!(u64),
+                "allowed-hosts" => self.config.allowed_hosts = value.trim_matches('\"').split(',').map(|s| s.trim().to_ascii_lowercase()).filter(|s| !s.is_empty()).collect(),
+                "trusted-proxies" => {
+                    let mut proxies = Vec::new();
+                    for entry in value.trim_matches('\"').split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                        match TrustedProxy::parse(entry) {
+                            Some(proxy) => proxies.push(proxy),
+                            None => invalid!("\"trusted-proxies\" must list IP addresses or networks like 10.0.0.0/8, not \"{}\"", entry),
+                        }
+                    }
+                    self.config.trusted_proxies = proxies;
+                },
+                "proxy-protocol" => self.config.proxy_protocol = parse!(bool),
+                "cors-origins" => self.config.cors.origins = value.trim_matches('\"').split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                "cors-methods" => self.config.cors.methods = value.trim_matches('\"').to_string(),
+                "cors-headers" => self.config.cors.headers = value.trim_matches('\"').to_string(),
+                "cors-max-age" => self.config.cors.max_age = parse!(u64),
+                "cors-credentials" => self.config.cors.credentials = parse!(bool),
+                "security-headers" => self.config.security_headers = parse!(bool),
+                "server-header" => self.config.server_header = value.trim_matches('\"').to_string(),
+                "file-cache-size" => self.config.file_cache_size = parse!(usize),
+                "preload" => self.config.preload = value.trim_matches('\"').split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+                "stream-threshold" => self.config.stream_threshold = parse!(u64),
+                "compression-min-size" => self.config.compression_min_size = parse!(usize),
+                "precompressed" => self.config.precompressed = parse!(bool),
+                "dev-mode" => self.config.dev_mode = parse!(bool),
+                "default-charset" => self.config.default_charset = value.trim_matches('\"').to_string(),
+                _ => {
+                    if let Some(extension) = key.strip_prefix("mime.") {
+                        self.config.mime_types.push((extension.to_string(), value.trim_matches('\"').to_string()));
+                    } else if let Some(extension) = key.strip_prefix("charset.") {
+                        self.config.charsets.push((extension.to_string(), value.trim_matches('\"').to_string()));
+                    } else if let Some(code) = key.strip_prefix("error.") {
+                        match u16::from_str(code).ok().filter(|code| (400..600).contains(code)) {
+                            Some(code) => {
+                                let page = PathBuf::from(value.trim_matches('\"'));
+                                match self.config.error_pages.iter_mut().find(|(status, _)| *status == code) {
+                                    Some(entry) => entry.1 = page,
+                                    None => self.config.error_pages.push((code, page)),
+                                }
+                            },
+                            None => invalid!("\"{}\" is not an error status code between 400 and 599", code),
+                        }
+                    } else if let Some(name) = key.strip_prefix("security.") {
+                        let value = value.trim_matches('\"').to_string();
+                        if let Err(err) = http_resources::check_header_name(name).and_then(|_| http_resources::check_header_value(name, &value)) {
+                            invalid!("\"{}\": {}", key, err);
+                        }
+                        match self.config.security_header_values.iter_mut().find(|(header, _)| header.eq_ignore_ascii_case(name)) {
+                            Some(entry) => entry.1 = value,
+                            None => self.config.security_header_values.push((name.to_string(), value)),
+                        }
+                    } else if let Some(name) = key.strip_prefix("header.") {
+                        let value = value.trim_matches('\"').to_string();
+                        if let Err(err) = http_resources::check_header_name(name).and_then(|_| http_resources::check_header_value(name, &value)) {
+                            invalid!("\"{}\": {}", key, err);
+                        }
+                        match self.config.default_headers.iter_mut().find(|(header, _)| header.eq_ignore_ascii_case(name)) {
+                            Some(entry) => entry.1 = value,
+                            None => self.config.default_headers.push((name.to_string(), value)),
+                        }
+                    } else if let Some(prefix) = key.strip_prefix("auth.") {
+                        match AuthRule::parse(prefix, value.trim_matches('\"')) {
+                            Some(rule) => self.config.auth.push(rule),
+                            None => invalid!("Invalid auth rule for \"{}\": {} (expected basic:<htpasswd file> or bearer:<token>)", key, value),
+                        }
+                    } else if let Some(name) = key.strip_prefix("cache.") {
+                        match CacheRule::parse(name, value.trim_matches('\"')) {
+                            Some(rule) => self.config.cache_rules.push(rule),
+                            None => invalid!("Invalid Cache-Control rule for \"{}\": {} (expected [/path/pattern] <directives>)", key, value),
+                        }
+                    } else if let Some(prefix) = key.strip_prefix("redirect.") {
+                        match RedirectRule::parse(prefix, value.trim_matches('\"')) {
+                            Some(rule) => self.config.redirects.push(rule),
+                            None => invalid!("Invalid redirect for \"{}\": {} (expected <location> or <location> [301|302|307|308])", key, value),
+                        }
+                    } else if let Some(prefix) = key.strip_prefix("proxy.") {
+                        match Upstream::parse(value.trim_matches('\"')) {
+                            Some(upstream) => self.config.proxies.push((prefix.to_string(), upstream)),
+                            None => invalid!("Invalid upstream for \"{}\": {} (expected http://host:port)", key, value),
+                        }
+                    } else if let Some(prefix) = key.strip_prefix("mount.") {
+                        self.config.mounts.push((prefix.trim_end_matches('/').to_string(), PathBuf::from(value.trim_matches('\"'))));
+                    } else if let Some(prefix) = key.strip_prefix("upload.") {
+                        self.config.uploads.push((prefix.to_string(), PathBuf::from(value.trim_matches('\"'))));
+                    } else if let Some(prefix) = key.strip_prefix("cgi.") {
+                        self.config.cgi.push((prefix.to_string(), PathBuf::from(value.trim_matches('\"'))));
+                    } else if let Some(extension) = key.strip_prefix("fastcgi.") {
+                        match FastCgiAddress::parse(value.trim_matches('\"')) {
+                            Some(address) => self.config.fastcgi.push((extension.trim_start_matches('.').to_string(), address)),
+                            None => invalid!("Invalid FastCGI server for \"{}\": {} (expected host:port or unix:<socket path>)", key, value),
+                        }
+                    } else if !suppress_warning {
+                        warn!("{}:{}: Unknown setting \"{}\" will be ignored.", origin, line, key);
+                    }
+                }
+            }
+        }
+
+        self.config.hosts.extend(current_host.take());
+    }
+
+    fn finish(mut self) -> Result<Config, Vec<String>> {
+        let out = &mut self.config;
+        for (extension, mime) in &out.mime_types {
+            out.mime.insert(extension, mime);
+        }
+        out.mime.set_default_charset(&out.default_charset);
+        for (extension, charset) in &out.charsets {
+            out.mime.set_charset(extension, charset);
+        }
+
+        out.root_dir = resolve_root_dir(&out.root_dir);
+        for (_, root_dir) in &mut out.mounts {
+            *root_dir = resolve_root_dir(root_dir);
+        }
+        for host in &mut out.hosts {
+            if host.root_dir.as_os_str().is_empty() {
+                warn!("[host \"{}\"] has no root-dir; it will serve the default website.", host.names[0]);
+                host.root_dir = out.root_dir.clone();
+            } else {
+                host.root_dir = resolve_root_dir(&host.root_dir);
+            }
+        }
+
+        for (key, message) in check_config(&self.config) {
+            match self.lines.get(key) {
+                Some(line) => self.errors.push(format!("{}: {}", line, message)),
+                None if self.origin.is_empty() => self.errors.push(message),
+                None => self.errors.push(format!("{}: {}", self.origin, message)),
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(self.config)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+fn describe_type(name: &str) -> &'static str {
+    match name {
+        "bool" => "true or false",
+        "f64" => "a number",
+        _ => "a whole number",
+    }
+}
+
+// Settings that parse fine on their own but cannot work, keyed by the setting to blame.
+fn check_config(config: &Config) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    // Port 0 lets the system pick a free port, which is logged once bound.
+    let valid_port = |port: &str| u16::from_str(port).is_ok();
+    let valid_address = |address: &str| address.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && valid_port(port));
+
+    if config.listen.is_empty() && !valid_port(&config.port) {
+        problems.push(("port", format!("\"port\" must be between 0 and 65535, not {}", config.port)));
+    }
+    for address in config.listen.iter().filter(|address| !valid_address(address)) {
+        problems.push(("listen", format!("\"listen\" needs a host and a port between 0 and 65535, not {}", address)));
+    }
+    for address in config.listen_tls.iter().filter(|address| !valid_address(address)) {
+        problems.push(("listen-tls", format!("\"listen-tls\" needs a host and a port between 0 and 65535, not {}", address)));
+    }
+    if !config.admin_listen.is_empty() && !valid_address(&config.admin_listen) {
+        problems.push(("admin-listen", format!("\"admin-listen\" needs a host and a port between 0 and 65535, not {}", config.admin_listen)));
+    }
+    if config.threads == 0 {
+        problems.push(("num-threads", "\"num-threads\" must be at least 1".to_string()));
+    }
+
+    let tls_requested = config.ssl_only || !config.listen_tls.is_empty();
+    if config.ssl.is_empty() {
+        if tls_requested {
+            let key = if config.ssl_only { "ssl-only" } else { "listen-tls" };
+            problems.push((key, format!("\"{}\" enables HTTPS, but no \"ssl-cert\" is set", key)));
+        }
+    } else {
+        if config.ssl_key.is_empty() {
+            problems.push(("ssl-cert", "\"ssl-cert\" is set, but \"ssl-key\" is not".to_string()));
+        }
+        // With ACME, both are written before the listener starts.
+        let issued = cfg!(feature = "acme") && !config.acme_domains.is_empty();
+        if !issued && !Path::new(&config.ssl).is_file() {
+            problems.push(("ssl-cert", format!("The certificate {} does not exist", config.ssl)));
+        }
+        if !issued && !config.ssl_key.is_empty() && !Path::new(&config.ssl_key).is_file() {
+            problems.push(("ssl-key", format!("The private key {} does not exist", config.ssl_key)));
+        }
+        if config.listen_tls.is_empty() && !config.ssl_port.is_empty() && !valid_port(&config.ssl_port) {
+            problems.push(("ssl-port", format!("\"ssl-port\" must be between 0 and 65535, not {}", config.ssl_port)));
+        }
+    }
+    for host in config.hosts.iter().filter(|host| !host.ssl_cert.is_empty() || !host.ssl_key.is_empty()) {
+        let name = &host.names[0];
+        if config.ssl.is_empty() {
+            problems.push(("[host]", format!("[host \"{name}\"] has a certificate, but HTTPS is off without a default \"ssl-cert\"")));
+        }
+        if host.ssl_cert.is_empty() || host.ssl_key.is_empty() {
+            problems.push(("[host]", format!("[host \"{name}\"] needs both \"ssl-cert\" and \"ssl-key\"")));
+        }
+        for path in [&host.ssl_cert, &host.ssl_key].into_iter().filter(|path| !path.is_empty() && !Path::new(path).is_file()) {
+            problems.push(("[host]", format!("[host \"{name}\"]: {path} does not exist")));
+        }
+    }
+    if !config.acme_domains.is_empty() {
+        if config.ssl.is_empty() || config.ssl_key.is_empty() {
+            problems.push(("acme-domains", "\"acme-domains\" needs \"ssl-cert\" and \"ssl-key\" to know where to keep the certificate".to_string()));
+        }
+        // HTTP-01 challenges are always fetched over plain HTTP.
+        if config.ssl_only {
+            problems.push(("acme-domains", "\"acme-domains\" needs plain HTTP for its challenges, which \"ssl-only\" turns off".to_string()));
+        }
+        if !config.acme_directory.starts_with("https://") && !config.acme_directory.starts_with("http://") {
+            problems.push(("acme-directory", format!("\"acme-directory\" must be an http:// or https:// URL, not {}", config.acme_directory)));
+        }
+    }
+
+    if !config.rate_limit.is_finite() {
+        problems.push(("rate-limit", format!("\"rate-limit\" must be a finite number, not {}", config.rate_limit)));
+    } else if config.rate_limit < 0.0 {
+        problems.push(("rate-limit", "\"rate-limit\" cannot be negative".to_string()));
+    } else if config.rate_limit > 0.0 {
+        if config.rate_limit_burst.is_nan() || config.rate_limit_burst < 1.0 {
+            problems.push(("rate-limit-burst", "\"rate-limit-burst\" must be at least 1 while rate limiting is enabled".to_string()));
+        } else if rate_limit::full_after(config.rate_limit, config.rate_limit_burst).is_none() {
+            problems.push(("rate-limit", format!("\"rate-limit\" is too low to ever refill a \"rate-limit-burst\" of {}", config.rate_limit_burst)));
+        }
+    }
+    if !config.favicon.as_os_str().is_empty() && !config.favicon.is_file() {
+        problems.push(("favicon", format!("\"favicon\" must be an existing file, not {}", config.favicon.display())));
+    }
+    if !config.well_known_dir.as_os_str().is_empty() && !config.well_known_dir.is_dir() {
+        problems.push(("well-known-dir", format!("\"well-known-dir\" must be an existing directory, not {}", config.well_known_dir.display())));
+    }
+    if !config.spa_fallback.is_empty() && !config.spa_fallback.starts_with('/') {
+        problems.push(("spa-fallback", format!("\"spa-fallback\" must be a path starting with /, not {}", config.spa_fallback)));
+    }
+    for pattern in config.preload.iter().filter(|pattern| !pattern.starts_with('/')) {
+        problems.push(("preload", format!("\"preload\" patterns must be paths starting with /, not {pattern}")));
+    }
+    if !config.preload.is_empty() && config.file_cache_size == 0 {
+        problems.push(("preload", "\"preload\" needs the file cache, but \"file-cache-size\" is 0".to_string()));
+    }
+    if let Err(err) = http_resources::check_header_value("Server", &config.server_header) {
+        problems.push(("server-header", format!("\"server-header\": {err}")));
+    }
+    // A charset is a token, and anything else would end up inside the Content-Type header.
+    let valid_charset = |charset: &str| charset.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:+".contains(&b));
+    if !valid_charset(&config.default_charset) {
+        problems.push(("default-charset", format!("\"default-charset\" is not a valid charset name: {}", config.default_charset)));
+    }
+    for (extension, charset) in config.charsets.iter().filter(|(_, charset)| !valid_charset(charset)) {
+        problems.push(("charset.*", format!("\"charset.{}\" is not a valid charset name: {}", extension, charset)));
+    }
+    if !matches!(config.deny.status, 403 | 404) {
+        problems.push(("deny-status", format!("\"deny-status\" must be 403 or 404, not {}", config.deny.status)));
+    }
+    if !config.templates_dir.as_os_str().is_empty() {
+        if let Err(err) = Templates::load_dir(&config.templates_dir) {
+            problems.push(("templates-dir", format!("The templates could not be loaded: {}", err)));
+        }
+    }
+    for (prefix, dir) in &config.mounts {
+        if !prefix.starts_with('/') {
+            problems.push(("mount.*", format!("Sites are mounted at a path below \"/\", not \"{}\"", prefix)));
+        } else if !dir.is_dir() {
+            problems.push(("mount.*", format!("The directory {} mounted at {} does not exist", dir.display(), prefix)));
+        }
+    }
+    for (prefix, dir) in config.uploads.iter().filter(|(_, dir)| !dir.is_dir()) {
+        problems.push(("upload.*", format!("The upload directory {} for {} does not exist", dir.display(), prefix)));
+    }
+    for (prefix, dir) in config.cgi.iter().filter(|(_, dir)| !dir.is_dir()) {
+        problems.push(("cgi.*", format!("The CGI directory {} for {} does not exist", dir.display(), prefix)));
+    }
+    problems
+}
+
+fn resolve_root_dir(root: &Path) -> PathBuf {
+    let root = match root.strip_prefix("~") {
+        Ok(rest) => match env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+            Some(home) => PathBuf::from(home).join(rest),
+            None => root.to_path_buf(),
+        },
+        Err(_) => root.to_path_buf(),
+    };
+
+    if root.is_absolute() || root.exists() {
+        return root;
+    }
+
+    // Relative roots that don't exist in the working directory are looked up next to the executable,
+    // so the server can be started from anywhere.
+    env::current_exe().ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(&root)))
+        .filter(|candidate| candidate.exists())
+        .unwrap_or(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Entry;
+
+    // Read like a settings.toml file, without one on disk.
+    fn from_toml(source: &str) -> Result<Config, Vec<String>> {
+        let mut layers = Layers::new(Config::default());
+        layers.read("settings.toml", settings::read_toml(source).map_err(|err| vec![err])?);
+        layers.finish()
+    }
+
+    fn invalid(source: &str) -> Vec<(usize, String)> {
+        settings::read_toml(source).unwrap().into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Invalid { line, message } => Some((line, message)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn toml_tables_and_arrays_become_settings() {
+        let config = from_toml(r#"
+port = "9000"
+num-threads = 4
+compression = false
+rate-limit = 2.5
+listen = ["127.0.0.1:9001", "127.0.0.1:9002"]
+cors-origins = ["https://a.example", "https://b.example"]
+mime = { foo = "text/x-foo" }
+
+[proxy]
+"/api" = "http://127.0.0.1:9000/v1"
+
+[header]
+X-Frame-Options = "DENY"
+
+[host."Example.com"]
+root-dir = "/srv/example"
+aliases = ["www.example.com"]
+"#).unwrap_or_else(|errors| panic!("{errors:?}"));
+
+        assert_eq!((config.port.as_str(), config.threads, config.compression, config.rate_limit), ("9000", 4, false, 2.5));
+        assert_eq!(config.listen, ["127.0.0.1:9001", "127.0.0.1:9002"]);
+        assert_eq!(config.cors.origins, ["https://a.example", "https://b.example"]);
+        assert_eq!(config.mime_types, [("foo".to_string(), "text/x-foo".to_string())]);
+        assert_eq!(config.proxies.iter().map(|(prefix, upstream)| (prefix.as_str(), upstream.get_url())).collect::<Vec<_>>(), [("/api", "http://127.0.0.1:9000/v1".to_string())]);
+        assert_eq!(config.default_headers, [("X-Frame-Options".to_string(), "DENY".to_string())]);
+        assert_eq!(config.hosts.len(), 1);
+        assert_eq!(config.hosts[0].names, ["example.com", "www.example.com"]);
+        assert_eq!(config.hosts[0].root_dir, Path::new("/srv/example"));
+    }
+
+    #[test]
+    fn both_formats_read_the_same_settings() {
+        let legacy = Config::default().merge("port = 9000\nlisten = 127.0.0.1:9001, 127.0.0.1:9002\nmime.foo = text/x-foo\n[host \"example.com\"]\nroot-dir = /srv/example").unwrap();
+        let toml = from_toml("port = 9000\nlisten = [\"127.0.0.1:9001\", \"127.0.0.1:9002\"]\n[mime]\nfoo = \"text/x-foo\"\n[host.\"example.com\"]\nroot-dir = \"/srv/example\"").unwrap();
+        assert_eq!((legacy.port, legacy.listen, legacy.mime_types), (toml.port, toml.listen, toml.mime_types));
+        assert_eq!((&legacy.hosts[0].names, &legacy.hosts[0].root_dir), (&toml.hosts[0].names, &toml.hosts[0].root_dir));
+    }
+
+    #[test]
+    fn toml_errors_point_at_their_line() {
+        let syntax = settings::read_toml("port = \"9000\"\nnum-threads = \n").err().expect("The value is missing");
+        assert!(syntax.contains("line 2"), "{syntax}");
+        assert_eq!(from_toml("port = \"9000\"\n\nnum-threads = \"many\"").err(), Some(vec!["settings.toml:3: \"num-threads\" must be a whole number, not \"many\"".to_string()]));
+        assert_eq!(from_toml("[proxy]\n\"/api\" = \"ftp://files\"").err(), Some(vec!["settings.toml:2: Invalid upstream for \"proxy./api\": ftp://files (expected http://host:port)".to_string()]));
+
+        assert_eq!(invalid("port = \"9000\"\nlisten = [[\"127.0.0.1:9001\"]]"), [(2, "\"listen\" cannot hold nested arrays or tables".to_string())]);
+        assert_eq!(invalid("[mime]\nfoo = [{ type = \"text/x-foo\" }]"), [(2, "\"mime.foo\" cannot hold nested arrays or tables".to_string())]);
+        assert_eq!(invalid("port = 1\n[[proxy]]\n\"/api\" = \"http://127.0.0.1:9000\""), [(2, "[[proxy]] is not supported".to_string())]);
+        assert_eq!(settings::read_toml("port = 1\nhost = \"example.com\"").err().as_deref(), Some("line 2: \"host\" must be a table of [host.\"<name>\"] sections"));
+        assert_eq!(settings::read_toml("[host]\n\"example.com\" = 1").err().as_deref(), Some("line 2: [host.\"example.com\"] must be a table"));
+    }
+
+    #[test]
+    fn values_that_do_not_parse_are_reported_with_their_line() {
+        let errors = Config::default().merge(concat!(
+            "compression = yes\nio-backend = fibers\nerror.200 = ok.html\ntrusted-proxies = 10.0.0.0/8, nowhere\n",
+            "header.X-Bad = a\u{1}b\nfastcgi.php = php-fpm\nauth./private = digest:file\nmax-body-size = -1",
+        )).err().expect("The settings are invalid");
+        assert_eq!(errors.len(), 8, "{errors:?}");
+        assert_eq!(errors[..4], [
+            "config:1: \"compression\" must be true or false, not \"yes\"",
+            "config:2: \"io-backend\" must be threads or async, not \"fibers\"",
+            "config:3: \"200\" is not an error status code between 400 and 599",
+            "config:4: \"trusted-proxies\" must list IP addresses or networks like 10.0.0.0/8, not \"nowhere\"",
+        ]);
+        assert!(errors[4].starts_with("config:5: \"header.X-Bad\": "), "{errors:?}");
+        assert_eq!(errors[5..], [
+            "config:6: Invalid FastCGI server for \"fastcgi.php\": php-fpm (expected host:port or unix:<socket path>)",
+            "config:7: Invalid auth rule for \"auth./private\": digest:file (expected basic:<htpasswd file> or bearer:<token>)",
+            "config:8: \"max-body-size\" must be a whole number, not \"-1\"",
+        ]);
+    }
+
+    #[test]
+    fn impossible_settings_are_blamed_on_the_line_that_set_them() {
+        let errors = Config::default().merge(concat!(
+            "port = 70000\nnum-threads = 0\nlisten-tls = nohost\nadmin-listen = 127.0.0.1:99999\ndeny-status = 500\n",
+            "spa-fallback = index.html\npreload = /app.js, app.css\nfile-cache-size = 0\nrate-limit = -1\ndefault-charset = utf 8",
+        )).err().expect("The settings are invalid");
+        assert_eq!(errors, [
+            "config:1: \"port\" must be between 0 and 65535, not 70000",
+            "config:3: \"listen-tls\" needs a host and a port between 0 and 65535, not nohost",
+            "config:4: \"admin-listen\" needs a host and a port between 0 and 65535, not 127.0.0.1:99999",
+            "config:2: \"num-threads\" must be at least 1",
+            "config:3: \"listen-tls\" enables HTTPS, but no \"ssl-cert\" is set",
+            "config:9: \"rate-limit\" cannot be negative",
+            "config:6: \"spa-fallback\" must be a path starting with /, not index.html",
+            "config:7: \"preload\" patterns must be paths starting with /, not app.css",
+            "config:7: \"preload\" needs the file cache, but \"file-cache-size\" is 0",
+            "config:10: \"default-charset\" is not a valid charset name: utf 8",
+            "config:5: \"deny-status\" must be 403 or 404, not 500",
+        ]);
+    }
+
+    #[test]
+    fn files_that_settings_refer_to_have_to_exist() {
+        let missing = std::env::temp_dir().join(format!("backend-web-server-missing-{}", std::process::id()));
+        let missing = missing.display();
+        let errors = Config::default().merge(&format!(
+            "ssl-cert = {missing}/cert.pem\nssl-key = {missing}/key.pem\nfavicon = {missing}/favicon.ico\nmount./docs = {missing}\nupload./in = {missing}\ncgi./cgi-bin = {missing}\n[host \"example.com\"]\nssl-cert = {missing}/example.pem",
+        )).err().expect("The files do not exist");
+        assert_eq!(errors, [
+            format!("config:1: The certificate {missing}/cert.pem does not exist"),
+            format!("config:2: The private key {missing}/key.pem does not exist"),
+            "config: [host \"example.com\"] needs both \"ssl-cert\" and \"ssl-key\"".to_string(),
+            format!("config: [host \"example.com\"]: {missing}/example.pem does not exist"),
+            format!("config:3: \"favicon\" must be an existing file, not {missing}/favicon.ico"),
+            // Problems with one of several settings of a kind are only blamed on the file.
+            format!("config: The directory {missing} mounted at /docs does not exist"),
+            format!("config: The upload directory {missing} for /in does not exist"),
+            format!("config: The CGI directory {missing} for /cgi-bin does not exist"),
+        ]);
+    }
+}
@@ -0,0 +1,348 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+#[cfg(feature = "async")]
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use http_resources::{FileBody, HttpRequest};
+
+// How long a closing connection keeps reading what the client still sends, and how much of it at most.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(2);
+const LINGER_LIMIT: u64 = 256 * 1024;
+
+pub trait Connection: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    // Tells the client that nothing more will be sent, while its side stays open for reading.
+    fn shutdown_write(&mut self) -> io::Result<()>;
+
+    // Returns `None` when the connection cannot hand the file to the kernel, so the caller copies it instead.
+    fn send_file(&mut self, _body: &FileBody) -> Option<io::Result<u64>> {
+        None
+    }
+
+    // Whether input is already buffered above the socket, where waiting for the socket to become readable would miss it.
+    #[cfg(feature = "async")]
+    fn has_pending_input(&mut self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "async")]
+    fn raw_fd(&self) -> RawFd;
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    fn send_file(&mut self, body: &FileBody) -> Option<io::Result<u64>> {
+        Some(sendfile(self, body))
+    }
+
+    #[cfg(feature = "async")]
+    fn raw_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+}
+
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+fn sendfile(stream: &TcpStream, body: &FileBody) -> io::Result<u64> {
+    use std::os::fd::AsRawFd;
+
+    let end = body.get_offset() + body.get_length();
+    let mut offset = body.get_offset() as libc::off_t;
+    let mut sent: u64 = 0;
+    while (offset as u64) < end {
+        // Linux transfers at most 0x7ffff000 bytes per call.
+        let count = (end - offset as u64).min(0x7fff_f000) as usize;
+        // SAFETY: both descriptors stay open for the duration of the call and `offset` points to a live local.
+        let written = unsafe { libc::sendfile(stream.as_raw_fd(), body.get_file().as_raw_fd(), &mut offset, count) };
+        if written < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if written == 0 {
+            break;
+        }
+        sent += written as u64;
+    }
+    Ok(sent)
+}
+
+// Ends a connection after its last response with a FIN, so clients that read up to the end of the stream see it.
+// What the client still sends, such as pipelined requests or an unread body, is then read and dropped for a moment:
+// closing a socket with unread input makes the kernel answer with a reset, which can destroy the response before the
+// client has read it (RFC 9112, section 9.6). A client that half-closed its side already is done at once.
+pub fn close<S: Connection>(mut reader: BufReader<S>) {
+    let stream = reader.get_mut();
+    if stream.flush().and_then(|_| stream.shutdown_write()).is_err() {
+        return;
+    }
+    stream.set_read_timeout(Some(LINGER_TIMEOUT)).unwrap_or(());
+    let mut lingering = Deadline::new(&mut reader, Some(Instant::now() + LINGER_TIMEOUT));
+    io::copy(&mut Read::take(&mut lingering, LINGER_LIMIT), &mut io::sink()).unwrap_or(0);
+}
+
+// Writes a file body that the connection could not hand to the kernel itself. With the mmap feature the bytes go
+// out straight from a mapping of the file; files that cannot be mapped are copied through a buffer instead.
+pub fn copy_file<W: Write + ?Sized>(body: &FileBody, stream: &mut W) -> io::Result<u64> {
+    #[cfg(all(feature = "mmap", unix))]
+    if let Ok(mapping) = crate::mmap::Mapping::new(body.get_file(), body.get_offset(), body.get_length()) {
+        stream.write_all(mapping.as_slice())?;
+        return Ok(body.get_length());
+    }
+    body.copy_to(stream)
+}
+
+pub fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock)
+}
+
+// Socket timeouts only bound a single read, so a client trickling one byte at a time could hold a worker forever;
+// this wrapper additionally fails every read once an overall deadline has passed.
+pub struct Deadline<'a, R> {
+    inner: &'a mut R,
+    deadline: Option<Instant>,
+    expired: bool,
+    consumed: usize,
+}
+
+impl<'a, R: BufRead> Deadline<'a, R> {
+    pub fn new(inner: &'a mut R, deadline: Option<Instant>) -> Deadline<'a, R> {
+        Deadline { inner, deadline, expired: false, consumed: 0 }
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expired
+    }
+
+    pub fn get_consumed(&self) -> usize {
+        self.consumed
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Read for Deadline<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Deadline<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.expired = true;
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        match self.inner.fill_buf() {
+            Ok(buf) => Ok(buf),
+            Err(err) => {
+                self.expired |= is_timeout(&err);
+                Err(err)
+            },
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed += amount;
+        self.inner.consume(amount);
+    }
+}
+
+// Every open connection, for the per-address limit and for the console, the admin endpoint and shutdown to list.
+pub struct ConnectionRegistry {
+    counts: Mutex<HashMap<IpAddr, usize>>,
+    open: Mutex<BTreeMap<u64, Arc<ConnectionInfo>>>,
+    next_id: AtomicU64,
+    total: AtomicUsize,
+}
+
+pub struct ConnectionSlot {
+    registry: &'static ConnectionRegistry,
+    ip: Option<IpAddr>,
+    info: Arc<ConnectionInfo>,
+}
+
+pub struct ConnectionInfo {
+    id: u64,
+    started: Instant,
+    activity: Mutex<Activity>,
+    requests: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+struct Activity {
+    // The socket peer until a request names the client behind a proxy.
+    client: Option<IpAddr>,
+    // "GET /path" while a request is being answered.
+    request: Option<String>,
+}
+
+thread_local! {
+    // The connection this thread is serving, so the request loop and the access log can update its entry.
+    static CURRENT: RefCell<Option<Arc<ConnectionInfo>>> = const { RefCell::new(None) };
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> ConnectionRegistry {
+        ConnectionRegistry {
+            counts: Mutex::new(HashMap::new()),
+            open: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            total: AtomicUsize::new(0),
+        }
+    }
+
+    // Returns `None` when `ip` already holds `max` connections; a `max` of 0 means unlimited.
+    pub fn acquire(&'static self, ip: Option<IpAddr>, max: usize) -> Option<ConnectionSlot> {
+        if !self.count(ip, max) {
+            return None;
+        }
+        self.total.fetch_add(1, Ordering::Relaxed);
+
+        let info = Arc::new(ConnectionInfo {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            started: Instant::now(),
+            activity: Mutex::new(Activity { client: ip, request: None }),
+            requests: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+        });
+        self.open.lock().unwrap_or_else(|e| e.into_inner()).insert(info.id, Arc::clone(&info));
+        Some(ConnectionSlot { registry: self, ip, info })
+    }
+
+    fn count(&self, ip: Option<IpAddr>, max: usize) -> bool {
+        if let Some(ip) = ip {
+            let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+            let count = counts.entry(ip).or_insert(0);
+            if max > 0 && *count >= max {
+                return false;
+            }
+            *count += 1;
+        }
+        true
+    }
+
+    pub fn get_total(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    // Open connections, oldest first.
+    pub fn list(&self) -> Vec<Arc<ConnectionInfo>> {
+        self.open.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+    }
+}
+
+impl ConnectionSlot {
+    // Makes this the connection the current thread reports to, until `leave`.
+    pub fn enter(&self) {
+        CURRENT.with(|current| *current.borrow_mut() = Some(Arc::clone(&self.info)));
+    }
+
+    // For a slot acquired without an address: counts it against `ip` once that is known, like `acquire` would have.
+    pub fn claim(&mut self, ip: Option<IpAddr>, max: usize) -> bool {
+        if self.ip.is_some() {
+            return true;
+        }
+        if !self.registry.count(ip, max) {
+            return false;
+        }
+        self.ip = ip;
+        self.info.activity.lock().unwrap_or_else(|e| e.into_inner()).client = ip;
+        true
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.registry.open.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.info.id);
+        self.registry.total.fetch_sub(1, Ordering::Relaxed);
+        if let Some(ip) = self.ip {
+            let mut counts = self.registry.counts.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(count) = counts.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+impl ConnectionInfo {
+    // One line for the connection table, e.g. "#12 192.0.2.1, open 3s, 2 requests, 431 bytes in, 5120 out: GET /".
+    pub fn describe(&self) -> String {
+        let activity = self.activity.lock().unwrap_or_else(|e| e.into_inner());
+        let client = activity.client.map_or("-".to_string(), |ip| ip.to_string());
+        format!(
+            "#{} {}, open {}s, {} requests, {} bytes in, {} out: {}",
+            self.id,
+            client,
+            self.started.elapsed().as_secs(),
+            self.requests.load(Ordering::Relaxed),
+            self.bytes_in.load(Ordering::Relaxed),
+            self.bytes_out.load(Ordering::Relaxed),
+            activity.request.as_deref().unwrap_or("idle"),
+        )
+    }
+}
+
+fn with_current(update: impl FnOnce(&ConnectionInfo)) {
+    CURRENT.with(|current| {
+        if let Some(info) = current.borrow().as_ref() {
+            update(info);
+        }
+    });
+}
+
+pub fn begin_request(request: &HttpRequest) {
+    with_current(|info| {
+        info.requests.fetch_add(1, Ordering::Relaxed);
+        let mut activity = info.activity.lock().unwrap_or_else(|e| e.into_inner());
+        activity.client = request.client_ip().or(activity.client);
+        activity.request = Some(format!("{} {}", request.get_method().get_name(), request.get_target()));
+    });
+}
+
+pub fn finish_request(bytes_out: usize) {
+    with_current(|info| {
+        info.bytes_out.fetch_add(bytes_out as u64, Ordering::Relaxed);
+        info.activity.lock().unwrap_or_else(|e| e.into_inner()).request = None;
+    });
+}
+
+pub fn add_bytes_in(bytes: usize) {
+    with_current(|info| {
+        info.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    });
+}
+
+// Worker threads are reused, so the connection has to be dropped once it is handed back.
+pub fn leave() {
+    CURRENT.with(|current| *current.borrow_mut() = None);
+}
@@ -0,0 +1,129 @@
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+use log::info;
+use crate::{conf, logging, reload_config, shutdown, FILE_CACHE, STATE};
+use crate::metrics::{Histogram, PERCENTILES};
+
+const HELP: &str = "\
+stop                  Shut the server down
+reload                Reload the config file (also: config-reload)
+status                Uptime, connections, requests and worker usage
+stats                 Responses by status, bytes transferred, latency and queue wait percentiles
+connections           Open connections with their client, age, traffic and current request
+cache-clear           Drop every file from the in-memory cache
+loglevel [<level>]    Show or change the log level until the next reload
+threads [<count>]     Show or change the number of worker threads until the next reload
+help                  Show this list";
+
+// Replies go to stdout as plain `key: value` lines, so the console can be driven by a script through a pipe.
+pub fn run() {
+    let mut input = String::new();
+    loop {
+        input.clear();
+        if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            break;
+        }
+        let mut words = input.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+
+        match command {
+            "stop" => {
+                info!("Stopping the web server...");
+                shutdown::request();
+                break;
+            },
+            "reload" | "config-reload" => {
+                info!("Reloading the config...");
+                reload_config();
+            },
+            "status" => status(),
+            "stats" => stats(),
+            "connections" => connections(),
+            "cache-clear" => {
+                FILE_CACHE.clear();
+                println!("file-cache: cleared");
+            },
+            "loglevel" => match words.next() {
+                Some(level) => match logging::parse_level(level) {
+                    Some(level) => {
+                        log::set_max_level(level);
+                        println!("log-level: {}", level.as_str().to_ascii_lowercase());
+                    },
+                    None => eprintln!("Unknown log level \"{level}\"; use off, error, warn, info, debug or trace."),
+                },
+                None => println!("log-level: {}", log::max_level().as_str().to_ascii_lowercase()),
+            },
+            "threads" => threads(words.next()),
+            "help" => println!("{HELP}"),
+            _ => eprintln!("Unknown command \"{command}\"; type \"help\" for a list."),
+        }
+    }
+}
+
+fn status() {
+    let uptime = STATE.get_uptime().as_secs();
+    println!("uptime: {}h {}m {}s", uptime / 3600, uptime / 60 % 60, uptime % 60);
+    println!("connections: {}, {} turned away, {} over the per-address limit", STATE.connections.get_total(), STATE.get_connection_rejections(), STATE.get_per_ip_rejections());
+    println!("requests: {}", STATE.metrics.get_requests());
+    if let Some(pool) = STATE.get_pool() {
+        println!("workers: {} busy of {}", pool.get_busy_workers(), pool.get_size());
+        println!("queue: {} waiting, peak {}, {} turned away", pool.get_queued_jobs(), pool.get_peak_queued_jobs(), STATE.get_queue_rejections());
+    }
+    if conf().file_cache_size > 0 {
+        println!("file-cache: {} hits, {} misses", FILE_CACHE.get_hits(), FILE_CACHE.get_misses());
+    }
+}
+
+fn stats() {
+    let metrics = &STATE.metrics;
+    println!("requests: {}", metrics.get_requests());
+    let statuses: Vec<String> = metrics.get_status_counts().iter().map(|(status, count)| format!("{status}={count}")).collect();
+    println!("responses: {}", statuses.join(", "));
+    println!("bytes-in: {}", metrics.get_bytes_in());
+    println!("bytes-out: {}", metrics.get_bytes_out());
+    println!("duration: {}", percentiles(metrics.get_duration()));
+    println!("queue-wait: {}", percentiles(metrics.get_queue_wait()));
+}
+
+fn percentiles(histogram: &Histogram) -> String {
+    PERCENTILES.iter()
+        .map(|quantile| {
+            let value = histogram.percentile(*quantile).map_or("-".to_string(), format_duration);
+            format!("p{} {}", quantile * 100.0, value)
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
+
+// Workers beyond the new count stop once they finish their current job and the ones queued before the change.
+fn threads(count: Option<&str>) {
+    let Some(pool) = STATE.get_pool() else {
+        return;
+    };
+    match count.map(usize::from_str) {
+        Some(Ok(count)) if count > 0 => {
+            pool.resize(count);
+            println!("threads: {}", pool.get_size());
+        },
+        Some(_) => eprintln!("The number of threads must be a whole number of at least 1."),
+        None => println!("threads: {} ({} busy)", pool.get_size(), pool.get_busy_workers()),
+    }
+}
+
+fn connections() {
+    for info in STATE.connections.list() {
+        println!("{}", info.describe());
+    }
+    println!("total: {}", STATE.connections.get_total());
+}
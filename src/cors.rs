@@ -0,0 +1,163 @@
+use http_resources::{HttpMethod, HttpProtocols, HttpRequest, HttpResponse, HttpResponseOptions, HttpResponseStatusCode};
+
+#[derive(PartialEq)]
+pub struct CorsPolicy {
+    pub origins: Vec<String>,
+    pub methods: String,
+    pub headers: String,
+    pub max_age: u64,
+    pub credentials: bool,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        CorsPolicy {
+            origins: Vec::new(),
+            methods: "GET, HEAD, POST".to_string(),
+            headers: "Content-Type, Authorization".to_string(),
+            max_age: 600,
+            credentials: false,
+        }
+    }
+}
+
+impl CorsPolicy {
+    pub fn is_enabled(&self) -> bool {
+        !self.origins.is_empty()
+    }
+
+    pub fn is_preflight(&self, request: &HttpRequest) -> bool {
+        self.is_enabled()
+            && request.get_method() == HttpMethod::Options
+            && request.get_header("Origin").is_some()
+            && request.get_header("Access-Control-Request-Method").is_some()
+    }
+
+    pub fn preflight(&self, request: &HttpRequest) -> HttpResponse {
+        let mut response = HttpResponse::new(HttpProtocols::OneOne);
+        response.set_status(HttpResponseStatusCode::NoContent);
+        if self.apply(request, &mut response) {
+            response.append_option(HttpResponseOptions::Custom("Access-Control-Allow-Methods".to_string()), self.methods.as_str());
+            let headers = match self.headers.trim() {
+                "*" => request.get_header("Access-Control-Request-Headers").unwrap_or_default(),
+                headers => headers,
+            };
+            if !headers.is_empty() {
+                response.append_option(HttpResponseOptions::Custom("Access-Control-Allow-Headers".to_string()), headers);
+            }
+            response.append_option(HttpResponseOptions::Custom("Access-Control-Max-Age".to_string()), self.max_age.to_string());
+        }
+        response.add_vary("Access-Control-Request-Method");
+        response.add_vary("Access-Control-Request-Headers");
+        response
+    }
+
+    // Adds the headers shared by preflight and actual responses; returns whether the origin was allowed.
+    pub fn apply(&self, request: &HttpRequest, response: &mut HttpResponse) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let Some(origin) = request.get_header("Origin") else {
+            return false;
+        };
+
+        let any = self.origins.iter().any(|allowed| allowed == "*");
+        if any && !self.credentials {
+            response.append_option(HttpResponseOptions::Custom("Access-Control-Allow-Origin".to_string()), "*");
+            return true;
+        }
+
+        response.add_vary("Origin");
+        if !any && !self.origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)) {
+            return false;
+        }
+        // Browsers reject a wildcard origin on credentialed requests, so the request origin is echoed instead.
+        response.append_option(HttpResponseOptions::Custom("Access-Control-Allow-Origin".to_string()), origin);
+        if self.credentials {
+            response.append_option(HttpResponseOptions::Custom("Access-Control-Allow-Credentials".to_string()), "true");
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(origins: &[&str], credentials: bool) -> CorsPolicy {
+        CorsPolicy { origins: origins.iter().map(|origin| origin.to_string()).collect(), credentials, ..CorsPolicy::default() }
+    }
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        let headers: String = headers.iter().map(|(name, value)| format!("{name}: {value}\r\n")).collect();
+        HttpRequest::parse(&mut format!("{method} /api HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n").as_bytes()).unwrap()
+    }
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response.get_option(&HttpResponseOptions::Custom(name.to_string()))
+    }
+
+    #[test]
+    fn listed_origins_are_echoed_and_others_refused() {
+        let policy = policy(&["https://app.example"], false);
+        let mut allowed = HttpResponse::ok_text("");
+        assert!(policy.apply(&request("GET", &[("Origin", "HTTPS://APP.example")]), &mut allowed));
+        assert_eq!(header(&allowed, "Access-Control-Allow-Origin"), Some("HTTPS://APP.example"));
+        assert_eq!(header(&allowed, "Access-Control-Allow-Credentials"), None);
+
+        let mut refused = HttpResponse::ok_text("");
+        assert!(!policy.apply(&request("GET", &[("Origin", "https://evil.example")]), &mut refused));
+        assert_eq!(header(&refused, "Access-Control-Allow-Origin"), None);
+
+        // Without an Origin, the request is not a cross-origin one and is left alone.
+        let mut same_origin = HttpResponse::ok_text("");
+        assert!(!policy.apply(&request("GET", &[]), &mut same_origin));
+        assert!(same_origin.get_option(&HttpResponseOptions::Vary).is_none());
+        assert!(!CorsPolicy::default().apply(&request("GET", &[("Origin", "https://app.example")]), &mut same_origin));
+    }
+
+    #[test]
+    fn responses_that_depend_on_the_origin_vary_on_it() {
+        // Allowed or not, a listed-origins answer depends on the Origin, so caches have to keep them apart.
+        for origin in ["https://app.example", "https://evil.example"] {
+            let mut response = HttpResponse::ok_text("");
+            response.add_vary("Accept-Encoding");
+            policy(&["https://app.example"], false).apply(&request("GET", &[("Origin", origin)]), &mut response);
+            assert_eq!(response.get_option(&HttpResponseOptions::Vary), Some("Accept-Encoding, Origin"), "{origin}");
+        }
+        // A plain wildcard is the same for everyone.
+        let mut response = HttpResponse::ok_text("");
+        policy(&["*"], false).apply(&request("GET", &[("Origin", "https://app.example")]), &mut response);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("*"));
+        assert!(response.get_option(&HttpResponseOptions::Vary).is_none());
+    }
+
+    #[test]
+    fn credentials_never_go_with_a_wildcard() {
+        let mut response = HttpResponse::ok_text("");
+        assert!(policy(&["*"], true).apply(&request("GET", &[("Origin", "https://app.example")]), &mut response));
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("https://app.example"));
+        assert_eq!(header(&response, "Access-Control-Allow-Credentials"), Some("true"));
+        assert_eq!(response.get_option(&HttpResponseOptions::Vary), Some("Origin"));
+    }
+
+    #[test]
+    fn preflights_are_answered_for_allowed_origins_only() {
+        let policy = CorsPolicy { headers: "*".to_string(), ..policy(&["https://app.example"], false) };
+        let preflight = request("OPTIONS", &[("Origin", "https://app.example"), ("Access-Control-Request-Method", "PUT"), ("Access-Control-Request-Headers", "X-Token")]);
+        assert!(policy.is_preflight(&preflight));
+        assert!(!policy.is_preflight(&request("OPTIONS", &[("Origin", "https://app.example")])));
+        assert!(!CorsPolicy::default().is_preflight(&preflight));
+
+        let response = policy.preflight(&preflight);
+        assert_eq!(response.get_status(), &HttpResponseStatusCode::NoContent);
+        assert_eq!(header(&response, "Access-Control-Allow-Methods"), Some("GET, HEAD, POST"));
+        assert_eq!(header(&response, "Access-Control-Allow-Headers"), Some("X-Token"));
+        assert_eq!(header(&response, "Access-Control-Max-Age"), Some("600"));
+        assert_eq!(response.get_option(&HttpResponseOptions::Vary), Some("Origin, Access-Control-Request-Method, Access-Control-Request-Headers"));
+
+        let refused = policy.preflight(&request("OPTIONS", &[("Origin", "https://evil.example"), ("Access-Control-Request-Method", "PUT")]));
+        assert_eq!(header(&refused, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header(&refused, "Access-Control-Allow-Methods"), None);
+    }
+}
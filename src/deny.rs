@@ -0,0 +1,60 @@
+#[derive(PartialEq)]
+pub struct DenyRules {
+    pub patterns: Vec<String>,
+    // Names that stay reachable even though a pattern matches them, such as ".well-known".
+    pub exceptions: Vec<String>,
+    pub status: u16,
+}
+
+impl Default for DenyRules {
+    fn default() -> Self {
+        DenyRules {
+            patterns: vec![".*".to_string(), "__errors__".to_string(), "*.bak".to_string(), "*.cfg".to_string()],
+            exceptions: Vec::new(),
+            status: 404,
+        }
+    }
+}
+
+impl DenyRules {
+    // A path is denied when any of its segments is, so a pattern for a directory covers everything inside it.
+    pub fn is_denied(&self, path: &str) -> bool {
+        path.split('/').filter(|segment| !segment.is_empty()).any(|segment| self.hides(segment))
+    }
+
+    // Whether a single file or directory name is off limits, which also keeps it out of directory listings.
+    pub fn hides(&self, name: &str) -> bool {
+        self.patterns.iter().any(|pattern| glob_matches(pattern, name))
+            && !self.exceptions.iter().any(|pattern| glob_matches(pattern, name))
+    }
+}
+
+// "*" matches any run of characters and "?" a single one. Case is ignored, since it is on some file systems too.
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let name: Vec<char> = name.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last "*" was, and how much of the name it has taken so far.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                Some((star, taken)) => {
+                    backtrack = Some((star, taken + 1));
+                    p = star + 1;
+                    n = taken + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
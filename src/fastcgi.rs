@@ -0,0 +1,403 @@
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use http_resources::{Handler, HttpRequest, HttpResponse};
+use log::warn;
+use crate::cgi::{self, CgiRoute};
+use crate::proxy::{ProxyError, Relay, Relayed};
+use crate::{error_for_site, Config, ConnectionError};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const VERSION: u8 = 1;
+// Only one request is sent per connection, so its ID never changes.
+const REQUEST_ID: u16 = 1;
+const ROLE_RESPONDER: u16 = 1;
+const MAX_CONTENT: usize = 0xffff;
+
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+
+#[derive(PartialEq)]
+pub enum FastCgiAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FastCgiAddress {
+    // "host:port", or a socket path given as "unix:/run/php/php-fpm.sock" or just "/run/php/php-fpm.sock".
+    pub fn parse(value: &str) -> Option<FastCgiAddress> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix:").or_else(|| value.starts_with('/').then_some(value)) {
+            return (!path.is_empty()).then(|| FastCgiAddress::Unix(PathBuf::from(path)));
+        }
+        let (host, port) = value.rsplit_once(':')?;
+        (!host.is_empty() && port.parse::<u16>().is_ok()).then(|| FastCgiAddress::Tcp(value.to_string()))
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            FastCgiAddress::Tcp(address) => address.clone(),
+            FastCgiAddress::Unix(path) => format!("unix:{}", path.display()),
+        }
+    }
+
+    fn connect(&self, timeout: Duration) -> io::Result<Box<dyn Stream>> {
+        match self {
+            FastCgiAddress::Tcp(address) => {
+                let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses resolved");
+                for addr in address.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                        Ok(stream) => {
+                            stream.set_read_timeout(Some(timeout))?;
+                            stream.set_write_timeout(Some(timeout))?;
+                            return Ok(Box::new(stream));
+                        },
+                        Err(err) => last_error = err,
+                    }
+                }
+                Err(last_error)
+            },
+            #[cfg(unix)]
+            FastCgiAddress::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Ok(Box::new(stream))
+            },
+            #[cfg(not(unix))]
+            FastCgiAddress::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not available on this platform")),
+        }
+    }
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> Stream for S {}
+
+pub struct FastCgiRoute<'a> {
+    script: CgiRoute,
+    address: &'a FastCgiAddress,
+}
+
+impl Handler for FastCgiRoute<'_> {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        fetch(self, request).unwrap_or_else(|_| error_for_site(ConnectionError::BadGateway, Some(request)))
+    }
+}
+
+impl Relay for FastCgiRoute<'_> {
+    fn relay(&self, request: &HttpRequest, client: &mut dyn Write, keep_alive: bool, decorate: &mut dyn FnMut(&mut HttpResponse)) -> Result<Relayed, ProxyError> {
+        forward(self, request, client, keep_alive, decorate)
+    }
+}
+
+// "fastcgi.php = ..." sends requests for .php files in the site to that server, with PATH_INFO as for CGI.
+pub fn route<'a>(config: &'a Config, root: &Path, path: &str) -> Option<FastCgiRoute<'a>> {
+    let timeout = Duration::from_secs(config.cgi_timeout.max(1));
+    config.fastcgi.iter().find_map(|(extension, address)| {
+        let script = cgi::find_in_site(root, path, std::slice::from_ref(extension), timeout)?;
+        Some(FastCgiRoute { script, address })
+    })
+}
+
+pub fn forward<W: Write + ?Sized>(route: &FastCgiRoute, request: &HttpRequest, client: &mut W, keep_alive: bool, decorate: impl FnOnce(&mut HttpResponse)) -> Result<Relayed, ProxyError> {
+    let mut output = BufReader::new(open(route, request)?);
+    cgi::relay(&mut output, request, client, keep_alive, decorate, || true)
+}
+
+pub fn fetch(route: &FastCgiRoute, request: &HttpRequest) -> Result<HttpResponse, ProxyError> {
+    let mut output = BufReader::new(open(route, request)?);
+    cgi::collect(&mut output, request)
+}
+
+// Sends the whole request and returns the application's stdout.
+fn open(route: &FastCgiRoute, request: &HttpRequest) -> Result<Output, ProxyError> {
+    let address = route.address.describe();
+    let mut stream = route.address.connect(route.script.get_timeout()).map_err(|err| {
+        warn!("Unable to reach the FastCGI server {}: {}", address, err);
+        ProxyError::BadGateway
+    })?;
+
+    let mut begin = Vec::with_capacity(8);
+    begin.extend_from_slice(&ROLE_RESPONDER.to_be_bytes());
+    // No flags: the server closes the connection once the request is done.
+    begin.extend_from_slice(&[0; 6]);
+    let mut params = Vec::new();
+    for (name, value) in cgi::environment(&route.script, request) {
+        encode_pair(&mut params, &name, &value);
+    }
+
+    let mut message = Vec::new();
+    write_record(&mut message, BEGIN_REQUEST, &begin);
+    write_stream(&mut message, PARAMS, &params);
+    write_stream(&mut message, STDIN, request.get_body());
+    stream.write_all(&message).and_then(|_| stream.flush()).map_err(|err| {
+        warn!("Unable to send the request to the FastCGI server {}: {}", address, err);
+        ProxyError::BadGateway
+    })?;
+    Ok(Output { stream, address, remaining: 0, padding: 0, ended: false })
+}
+
+fn encode_pair(out: &mut Vec<u8>, name: &str, value: &str) {
+    encode_length(out, name.len());
+    encode_length(out, value.len());
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn encode_length(out: &mut Vec<u8>, length: usize) {
+    if length < 0x80 {
+        out.push(length as u8);
+    } else {
+        out.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+// A stream is split into records of at most 64 KiB and ended by an empty one.
+fn write_stream(out: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    for chunk in data.chunks(MAX_CONTENT) {
+        write_record(out, kind, chunk);
+    }
+    write_record(out, kind, &[]);
+}
+
+fn write_record(out: &mut Vec<u8>, kind: u8, content: &[u8]) {
+    // Content is padded to a multiple of 8 bytes, as servers are encouraged to expect.
+    let padding = (8 - content.len() % 8) % 8;
+    out.extend_from_slice(&[VERSION, kind]);
+    out.extend_from_slice(&REQUEST_ID.to_be_bytes());
+    out.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[padding as u8, 0]);
+    out.extend_from_slice(content);
+    out.extend_from_slice(&[0; 8][..padding]);
+}
+
+// Reads the STDOUT records of the response as one stream, logging STDERR on the way. The stream ends cleanly only
+// with END_REQUEST, so a server that goes away early shows up as an error rather than a short body.
+struct Output {
+    stream: Box<dyn Stream>,
+    address: String,
+    // Left of the current STDOUT record, and the padding after it.
+    remaining: usize,
+    padding: usize,
+    ended: bool,
+}
+
+impl Output {
+    fn skip(&mut self, length: usize) -> io::Result<()> {
+        io::copy(&mut Read::by_ref(&mut self.stream).take(length as u64), &mut io::sink())
+            .and_then(|skipped| if skipped == length as u64 { Ok(()) } else { Err(io::ErrorKind::UnexpectedEof.into()) })
+    }
+}
+
+impl Read for Output {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.ended || buf.is_empty() {
+                return Ok(0);
+            }
+            if self.remaining > 0 {
+                let limit = self.remaining.min(buf.len());
+                let read = self.stream.read(&mut buf[..limit])?;
+                if read == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                self.remaining -= read;
+                return Ok(read);
+            }
+            let padding = std::mem::take(&mut self.padding);
+            self.skip(padding)?;
+
+            let mut header = [0u8; 8];
+            self.stream.read_exact(&mut header)?;
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let padding = header[6] as usize;
+            match header[1] {
+                STDOUT => {
+                    self.remaining = length;
+                    self.padding = padding;
+                },
+                STDERR => {
+                    let mut message = vec![0; length];
+                    self.stream.read_exact(&mut message)?;
+                    self.skip(padding)?;
+                    for line in String::from_utf8_lossy(&message).lines().filter(|line| !line.is_empty()) {
+                        warn!("{}: {}", self.address, line);
+                    }
+                },
+                END_REQUEST => {
+                    let mut body = vec![0; length];
+                    self.stream.read_exact(&mut body)?;
+                    self.skip(padding)?;
+                    // The protocol status is 0 when the request was completed.
+                    if body.get(4).is_some_and(|status| *status != 0) {
+                        return Err(io::Error::other("the FastCGI server rejected the request"));
+                    }
+                    self.ended = true;
+                },
+                _ => self.skip(length + padding)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+    use super::*;
+
+    // Splits a message back into its records, checking the header of each.
+    fn records(mut bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut records = Vec::new();
+        while !bytes.is_empty() {
+            let (header, rest) = bytes.split_at(8);
+            assert_eq!((header[0], u16::from_be_bytes([header[2], header[3]])), (VERSION, REQUEST_ID));
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let padding = header[6] as usize;
+            assert_eq!((length + padding) % 8, 0, "Records are padded to a multiple of 8 bytes");
+            records.push((header[1], rest[..length].to_vec()));
+            bytes = &rest[length + padding..];
+        }
+        records
+    }
+
+    fn decode_length(bytes: &mut &[u8]) -> usize {
+        if bytes[0] < 0x80 {
+            let length = bytes[0] as usize;
+            *bytes = &bytes[1..];
+            return length;
+        }
+        let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & 0x7fff_ffff;
+        *bytes = &bytes[4..];
+        length as usize
+    }
+
+    fn decode_pairs(mut bytes: &[u8]) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        while !bytes.is_empty() {
+            let (name_length, value_length) = (decode_length(&mut bytes), decode_length(&mut bytes));
+            let (name, rest) = bytes.split_at(name_length);
+            let (value, rest) = rest.split_at(value_length);
+            pairs.push((String::from_utf8(name.to_vec()).unwrap(), String::from_utf8(value.to_vec()).unwrap()));
+            bytes = rest;
+        }
+        pairs
+    }
+
+    #[test]
+    fn lengths_take_one_byte_up_to_127_and_four_above() {
+        for (length, encoded) in [(0, vec![0]), (127, vec![0x7f]), (128, vec![0x80, 0, 0, 0x80]), (300, vec![0x80, 0, 0x01, 0x2c]), (70_000, vec![0x80, 0x01, 0x11, 0x70])] {
+            let mut out = Vec::new();
+            encode_length(&mut out, length);
+            assert_eq!(out, encoded, "{length}");
+            assert_eq!(decode_length(&mut out.as_slice()), length);
+        }
+    }
+
+    #[test]
+    fn name_value_pairs_round_trip() {
+        let pairs: Vec<(String, String)> = [(1, 0), (127, 127), (128, 1), (5, 128), (1000, 70_000)].iter().enumerate()
+            .map(|(index, (name, value))| (format!("{index}").repeat(*name), "v".repeat(*value)))
+            .collect();
+        let mut encoded = Vec::new();
+        for (name, value) in &pairs {
+            encode_pair(&mut encoded, name, value);
+        }
+        assert_eq!(decode_pairs(&encoded), pairs);
+    }
+
+    #[test]
+    fn streams_are_split_into_records_and_ended_by_an_empty_one() {
+        let data: Vec<u8> = (0..MAX_CONTENT * 2 + 5).map(|index| index as u8).collect();
+        let mut message = Vec::new();
+        write_stream(&mut message, STDIN, &data);
+        let lengths: Vec<usize> = records(&message).iter().map(|(_, content)| content.len()).collect();
+        assert_eq!(lengths, [MAX_CONTENT, MAX_CONTENT, 5, 0]);
+        let joined: Vec<u8> = records(&message).into_iter().flat_map(|(kind, content)| { assert_eq!(kind, STDIN); content }).collect();
+        assert_eq!(joined, data);
+
+        let mut empty = Vec::new();
+        write_stream(&mut empty, PARAMS, &[]);
+        assert_eq!(records(&empty), [(PARAMS, Vec::new())]);
+    }
+
+    fn output(message: Vec<u8>) -> Output {
+        Output { stream: Box::new(Cursor::new(message)), address: "test".to_string(), remaining: 0, padding: 0, ended: false }
+    }
+
+    #[test]
+    fn output_is_read_from_stdout_records_until_the_request_ends() {
+        let mut message = Vec::new();
+        write_record(&mut message, STDOUT, b"Content-Type: text/plain\r\n\r\nhel");
+        write_record(&mut message, STDERR, b"a warning\n");
+        write_record(&mut message, STDOUT, b"lo");
+        write_record(&mut message, STDOUT, &[]);
+        write_record(&mut message, END_REQUEST, &[0; 8]);
+        let mut read = String::new();
+        output(message.clone()).read_to_string(&mut read).unwrap();
+        assert_eq!(read, "Content-Type: text/plain\r\n\r\nhello");
+
+        // Cut off before END_REQUEST, or refused by the server, the output is an error rather than a short body.
+        let mut truncated = Vec::new();
+        assert!(output(message[..message.len() - 16].to_vec()).read_to_end(&mut truncated).is_err());
+        let mut rejected = message[..message.len() - 8].to_vec();
+        rejected.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0]);
+        assert!(output(rejected).read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn requests_round_trip_through_a_fastcgi_server() {
+        let dir = std::env::temp_dir().join(format!("backend-web-server-fastcgi-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.php"), "").unwrap();
+        let script = cgi::find_in_site(&dir, "/index.php/extra", &["php".to_string()], Duration::from_secs(5));
+        fs::remove_dir_all(&dir).unwrap_or(());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = FastCgiAddress::parse(&listener.local_addr().unwrap().to_string()).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            // The request ends with the empty STDIN record.
+            while !received.ends_with(&[VERSION, STDIN, 0, 1, 0, 0, 0, 0]) {
+                let mut header = [0; 8];
+                stream.read_exact(&mut header).unwrap();
+                let mut content = vec![0; u16::from_be_bytes([header[4], header[5]]) as usize + header[6] as usize];
+                stream.read_exact(&mut content).unwrap();
+                received.extend_from_slice(&header);
+                received.extend_from_slice(&content);
+            }
+            let mut reply = Vec::new();
+            write_stream(&mut reply, STDOUT, b"Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nstored");
+            write_record(&mut reply, END_REQUEST, &[0; 8]);
+            stream.write_all(&reply).unwrap();
+            received
+        });
+
+        let request = HttpRequest::parse(&mut "POST /index.php/extra?a=1 HTTP/1.1\r\nHost: site.example\r\nContent-Length: 4\r\n\r\nbody".as_bytes()).unwrap();
+        let route = FastCgiRoute { script: script.expect("The script was not found"), address: &address };
+        let response = fetch(&route, &request).ok().expect("The FastCGI server did not answer");
+        let received = records(&server.join().unwrap());
+
+        assert_eq!(response.get_status().as_u16(), 201);
+        assert_eq!(response.get_payload(), b"stored");
+        assert_eq!(received[0], (BEGIN_REQUEST, vec![0, 1, 0, 0, 0, 0, 0, 0]));
+        let params: Vec<u8> = received.iter().filter(|(kind, _)| *kind == PARAMS).flat_map(|(_, content)| content.clone()).collect();
+        let params = decode_pairs(&params);
+        for (name, value) in [("REQUEST_METHOD", "POST"), ("SCRIPT_NAME", "/index.php"), ("PATH_INFO", "/extra"), ("QUERY_STRING", "a=1"), ("CONTENT_LENGTH", "4")] {
+            assert!(params.iter().any(|(key, found)| key == name && found == value), "{name}: {params:?}");
+        }
+        let stdin: Vec<&(u8, Vec<u8>)> = received.iter().filter(|(kind, _)| *kind == STDIN).collect();
+        assert_eq!(stdin, [&(STDIN, b"body".to_vec()), &(STDIN, Vec::new())]);
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use crate::compression::Encoding;
+
+pub struct CachedFile {
+    pub content: Vec<u8>,
+    pub modified: Option<SystemTime>,
+    pub etag: String,
+    pub content_type: String,
+    // Compressed copies of the content, worked out ahead of the requests for files that were preloaded.
+    pub variants: Vec<(Encoding, Vec<u8>)>,
+}
+
+impl CachedFile {
+    // The memory the entry takes up, compressed copies included.
+    fn size(&self) -> usize {
+        self.content.len() + self.variants.iter().map(|(_, variant)| variant.len()).sum::<usize>()
+    }
+}
+
+struct Entry {
+    file: Arc<CachedFile>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    files: HashMap<PathBuf, Entry>,
+    recency: BTreeMap<u64, PathBuf>,
+    clock: u64,
+    size: usize,
+}
+
+impl Entries {
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.files.remove(path) {
+            self.recency.remove(&entry.last_used);
+            self.size -= entry.file.size();
+        }
+    }
+
+    fn touch(&mut self, path: &Path) -> Option<Arc<CachedFile>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.files.get_mut(path)?;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = clock;
+        self.recency.insert(clock, path.to_path_buf());
+        Some(Arc::clone(&entry.file))
+    }
+}
+
+pub struct FileCache {
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl FileCache {
+    pub fn new() -> FileCache {
+        FileCache { entries: Mutex::new(Entries::default()), hits: AtomicU64::new(0), misses: AtomicU64::new(0) }
+    }
+
+    // Entries are only returned while the file on disk still has the size and mtime they were read with.
+    pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<Arc<CachedFile>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let fresh = entries.files.get(path).map(|entry| {
+            entry.file.content.len() as u64 == metadata.len() && entry.file.modified == metadata.modified().ok()
+        });
+
+        match fresh {
+            Some(true) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                entries.touch(path)
+            },
+            Some(false) => {
+                entries.remove(path);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+        }
+    }
+
+    pub fn insert(&self, path: &Path, file: CachedFile, max_size: usize) -> Arc<CachedFile> {
+        let file = Arc::new(file);
+        // Anything larger than a quarter of the cache would evict too much to be worth keeping.
+        if file.size() > max_size / 4 {
+            return file;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.remove(path);
+        while entries.size + file.size() > max_size {
+            let Some((_, oldest)) = entries.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = entries.files.remove(&oldest) {
+                entries.size -= entry.file.size();
+            }
+        }
+
+        entries.clock += 1;
+        let clock = entries.clock;
+        entries.size += file.size();
+        entries.recency.insert(clock, path.to_path_buf());
+        entries.files.insert(path.to_path_buf(), Entry { file: Arc::clone(&file), last_used: clock });
+        file
+    }
+
+    #[cfg(feature = "watch")]
+    pub fn remove(&self, path: &Path) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).remove(path);
+    }
+
+    pub fn clear(&self) {
+        *self.entries.lock().unwrap_or_else(|e| e.into_inner()) = Entries::default();
+    }
+
+    pub fn get_hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn get_misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,98 @@
+use std::net::{IpAddr, Ipv6Addr};
+use http_resources::HttpRequest;
+
+// An address, or a network in CIDR notation such as "10.0.0.0/8", whose forwarding headers are believed.
+#[derive(PartialEq)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl TrustedProxy {
+    pub fn parse(value: &str) -> Option<TrustedProxy> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = address.trim().parse().ok()?;
+        let width = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse::<u8>().ok().filter(|prefix| *prefix <= width)?,
+            None => width,
+        };
+        Some(TrustedProxy { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (network, width) = bits(self.network);
+        let (ip, ip_width) = bits(ip.to_canonical());
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        width == ip_width && network & mask == ip & mask
+    }
+
+    pub fn describe(&self) -> String {
+        let width = if self.network.is_ipv4() { 32 } else { 128 };
+        if self.prefix == width { self.network.to_string() } else { format!("{}/{}", self.network, self.prefix) }
+    }
+}
+
+// Addresses as the high bits of a u128, so one mask works for both families.
+fn bits(ip: IpAddr) -> (u128, u8) {
+    match ip {
+        IpAddr::V4(ip) => ((u32::from(ip) as u128) << 96, 32),
+        IpAddr::V6(ip) => (u128::from(ip), 128),
+    }
+}
+
+// The client behind any trusted proxies. Hops are read from the right, where the nearest proxy appended them, and
+// the first one that is not trusted is the client; anything further left could have been sent by the client itself.
+pub fn client_ip(trusted: &[TrustedProxy], peer: Option<IpAddr>, request: &HttpRequest) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|proxy| proxy.contains(ip));
+    let mut client = peer?;
+    if !is_trusted(client) {
+        return Some(client);
+    }
+    for hop in forwarded_for(request).into_iter().rev() {
+        // Hops like "unknown" or obfuscated names cannot be followed any further.
+        let Some(hop) = hop else {
+            break;
+        };
+        client = hop;
+        if !is_trusted(hop) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+// The "for" of every element of the Forwarded headers (RFC 7239), or else the entries of X-Forwarded-For.
+fn forwarded_for(request: &HttpRequest) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| -> Vec<&str> {
+        request.get_headers().iter().filter(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str()).collect()
+    };
+    let forwarded = values("Forwarded");
+    if !forwarded.is_empty() {
+        return forwarded.iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then(|| parse_node(value))
+            }).flatten())
+            .collect();
+    }
+    values("X-Forwarded-For").iter().flat_map(|value| value.split(',')).map(parse_node).collect()
+}
+
+// "192.0.2.1", "192.0.2.1:4711", "2001:db8::1" or "[2001:db8::1]:4711", optionally quoted.
+fn parse_node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        let (address, _) = rest.split_once(']')?;
+        return address.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    value.parse().ok().or_else(|| {
+        let (address, port) = value.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        address.parse().ok()
+    })
+}
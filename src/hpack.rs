@@ -0,0 +1,431 @@
+use std::collections::{HashMap, VecDeque};
+use lazy_static::lazy_static;
+
+// Every table entry is accounted with this overhead on top of its name and value (RFC 7541, section 4.1).
+const ENTRY_OVERHEAD: usize = 32;
+
+// RFC 7541, appendix A.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// RFC 7541, appendix B, indexed by symbol; 256 is EOS.
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28), (0xfffffe5, 28),
+    (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24), (0x3ffffffc, 30), (0xfffffe9, 28),
+    (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28), (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28),
+    (0xfffffef, 28), (0xffffff0, 28), (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28),
+    (0xffffff4, 28), (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10),
+    (0xf9, 8), (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6),
+    (0x0, 5), (0x1, 5), (0x2, 5), (0x19, 6), (0x1a, 6), (0x1b, 6),
+    (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8),
+    (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13), (0x21, 6),
+    (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7),
+    (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7),
+    (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7),
+    (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+    (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5),
+    (0x25, 6), (0x26, 6), (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7),
+    (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7),
+    (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14),
+    (0x1ffd, 13), (0xffffffc, 28), (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20),
+    (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22), (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23),
+    (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23),
+    (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23),
+    (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23),
+    (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24), (0x3fffda, 22), (0x1fffdd, 21),
+    (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22), (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21),
+    (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22),
+    (0x7fffeb, 23), (0x7fffec, 23), (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21),
+    (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23), (0xfffea, 20), (0x3fffe2, 22),
+    (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23), (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23),
+    (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23),
+    (0x3fffe8, 22), (0x1ffffec, 25), (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27),
+    (0x7ffffdf, 27), (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19), (0x1fffe3, 21),
+    (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28), (0x7ffffe3, 27),
+    (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21),
+    (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22), (0x3fffeb, 22),
+    (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27), (0x7ffffe8, 27),
+    (0x7ffffe9, 27), (0x7ffffea, 27), (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27),
+    (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26), (0x3fffffff, 30),
+];
+
+lazy_static! {
+    static ref HUFFMAN_DECODE: HashMap<(u8, u32), u16> = HUFFMAN_CODES.iter()
+        .enumerate()
+        .map(|(symbol, &(code, length))| ((length, code), symbol as u16))
+        .collect();
+}
+
+pub struct Decoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+    limit: usize,
+}
+
+impl Decoder {
+    // `limit` is the table size advertised to the peer; it may shrink the table but never grow it past that.
+    pub fn new(limit: usize) -> Decoder {
+        Decoder { table: VecDeque::new(), size: 0, max_size: limit, limit }
+    }
+
+    // Returns `None` for malformed blocks and once the header list grows past `max_list_size`.
+    pub fn decode(&mut self, block: &[u8], max_list_size: usize) -> Option<Vec<(String, String)>> {
+        let mut headers = Vec::new();
+        let mut list_size = 0;
+        let mut pos = 0;
+        while let Some(&byte) = block.get(pos) {
+            let (name, value) = if byte & 0x80 != 0 {
+                let index = decode_integer(block, &mut pos, 7)?;
+                self.get(index)?
+            } else if byte & 0xe0 == 0x20 {
+                let size = decode_integer(block, &mut pos, 5)?;
+                if size > self.limit {
+                    return None;
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                let indexed = byte & 0x40 != 0;
+                let index = decode_integer(block, &mut pos, if indexed { 6 } else { 4 })?;
+                let name = match index {
+                    0 => decode_string(block, &mut pos)?,
+                    index => self.get(index)?.0,
+                };
+                let value = decode_string(block, &mut pos)?;
+                if indexed {
+                    self.insert(name.clone(), value.clone());
+                }
+                (name, value)
+            };
+
+            list_size += name.len() + value.len() + ENTRY_OVERHEAD;
+            if list_size > max_list_size {
+                return None;
+            }
+            headers.push((name, value));
+        }
+        Some(headers)
+    }
+
+    fn get(&self, index: usize) -> Option<(String, String)> {
+        match index {
+            0 => None,
+            1..=61 => STATIC_TABLE.get(index - 1).map(|(name, value)| (name.to_string(), value.to_string())),
+            _ => self.table.get(index - STATIC_TABLE.len() - 1).cloned(),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // An entry larger than the whole table only empties it.
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    fn evict(&mut self, incoming: usize) {
+        while self.size + incoming > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+// Never uses Huffman coding or the dynamic table, which keeps the encoder stateless.
+pub fn encode(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for &(name, value) in headers {
+        if let Some(index) = STATIC_TABLE.iter().position(|&entry| entry == (name, value)) {
+            encode_integer(&mut out, index + 1, 7, 0x80);
+            continue;
+        }
+        match STATIC_TABLE.iter().position(|&(entry, _)| entry == name) {
+            Some(index) => encode_integer(&mut out, index + 1, 4, 0x00),
+            None => {
+                out.push(0x00);
+                encode_string(&mut out, name);
+            },
+        }
+        encode_string(&mut out, value);
+    }
+    out
+}
+
+fn decode_integer(block: &[u8], pos: &mut usize, prefix: u8) -> Option<usize> {
+    let mask = (1usize << prefix) - 1;
+    let mut value = *block.get(*pos)? as usize & mask;
+    *pos += 1;
+    if value < mask {
+        return Some(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let byte = *block.get(*pos)?;
+        *pos += 1;
+        // Nothing legitimate needs more than four continuation bytes.
+        if shift > 21 {
+            return None;
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+fn decode_string(block: &[u8], pos: &mut usize) -> Option<String> {
+    let huffman = *block.get(*pos)? & 0x80 != 0;
+    let length = decode_integer(block, pos, 7)?;
+    let end = pos.checked_add(length)?;
+    let raw = block.get(*pos..end)?;
+    *pos = end;
+
+    let bytes = if huffman { huffman_decode(raw)? } else { raw.to_vec() };
+    String::from_utf8(bytes).ok()
+}
+
+fn huffman_decode(input: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 8 / 5);
+    let mut code: u32 = 0;
+    let mut length: u8 = 0;
+    for byte in input {
+        for bit in (0..8).rev() {
+            code = (code << 1) | u32::from((byte >> bit) & 1);
+            length += 1;
+            match HUFFMAN_DECODE.get(&(length, code)) {
+                Some(256) => return None,
+                Some(&symbol) => {
+                    out.push(symbol as u8);
+                    code = 0;
+                    length = 0;
+                },
+                None if length >= 30 => return None,
+                None => {},
+            }
+        }
+    }
+    // Only a partial EOS code, i.e. fewer than eight one bits, may pad the end.
+    (length < 8 && code == (1 << length) - 1).then_some(out)
+}
+
+fn encode_integer(out: &mut Vec<u8>, value: usize, prefix: u8, flags: u8) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | mask as u8);
+    let mut value = value - mask;
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn encode_string(out: &mut Vec<u8>, value: &str) {
+    encode_integer(out, value.len(), 7, 0x00);
+    out.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    fn headers(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    // A header block in hex, what it decodes to and the size of the dynamic table after it.
+    type Example<'a> = (&'a str, &'a [(&'a str, &'a str)], usize);
+
+    // Each block is decoded in turn on one connection, and the dynamic table checked after it.
+    fn decode_all(decoder: &mut Decoder, blocks: &[Example]) {
+        for (n, &(block, expected, table_size)) in blocks.iter().enumerate() {
+            assert_eq!(decoder.decode(&hex(block), usize::MAX), Some(headers(expected)), "block {}", n + 1);
+            assert_eq!(decoder.size, table_size, "block {}", n + 1);
+        }
+    }
+
+    #[test]
+    fn static_table_matches_the_rfc() {
+        assert_eq!(STATIC_TABLE[14], ("accept-charset", ""));
+        assert_eq!(Decoder::new(4096).decode(&[0x8f], usize::MAX), Some(headers(&[("accept-charset", "")])));
+        assert_eq!(encode(&[("accept-charset", "utf-8")]), hex("0f00 05 7574662d38"));
+    }
+
+    // RFC 7541, appendix C.2.
+    #[test]
+    fn decodes_single_representations() {
+        let mut decoder = Decoder::new(4096);
+        decode_all(&mut decoder, &[
+            ("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572", &[("custom-key", "custom-header")], 55),
+        ]);
+        let mut decoder = Decoder::new(4096);
+        decode_all(&mut decoder, &[
+            ("040c 2f73 616d 706c 652f 7061 7468", &[(":path", "/sample/path")], 0),
+            ("1008 7061 7373 776f 7264 0673 6563 7265 74", &[("password", "secret")], 0),
+            ("82", &[(":method", "GET")], 0),
+        ]);
+    }
+
+    // RFC 7541, appendices C.3 and C.4: the same requests, without and with Huffman coding.
+    #[test]
+    fn decodes_requests() {
+        let first = [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")];
+        let second = [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"), ("cache-control", "no-cache")];
+        let third = [(":method", "GET"), (":scheme", "https"), (":path", "/index.html"), (":authority", "www.example.com"), ("custom-key", "custom-value")];
+
+        decode_all(&mut Decoder::new(4096), &[
+            ("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d", &first, 57),
+            ("8286 84be 5808 6e6f 2d63 6163 6865", &second, 110),
+            ("8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65", &third, 164),
+        ]);
+        decode_all(&mut Decoder::new(4096), &[
+            ("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff", &first, 57),
+            ("8286 84be 5886 a8eb 1064 9cbf", &second, 110),
+            ("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf", &third, 164),
+        ]);
+    }
+
+    // RFC 7541, appendices C.5 and C.6: a table of 256 bytes, so that every response evicts older entries.
+    #[test]
+    fn decodes_responses_and_evicts_entries() {
+        let first = [(":status", "302"), ("cache-control", "private"), ("date", "Mon, 21 Oct 2013 20:13:21 GMT"), ("location", "https://www.example.com")];
+        let second = [(":status", "307"), ("cache-control", "private"), ("date", "Mon, 21 Oct 2013 20:13:21 GMT"), ("location", "https://www.example.com")];
+        let third = [
+            (":status", "200"),
+            ("cache-control", "private"),
+            ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+            ("location", "https://www.example.com"),
+            ("content-encoding", "gzip"),
+            ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"),
+        ];
+        let final_table = headers(&[
+            ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"),
+            ("content-encoding", "gzip"),
+            ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+        ]);
+
+        let mut decoder = Decoder::new(256);
+        decode_all(&mut decoder, &[
+            ("4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70 6c65 2e63 6f6d", &first, 222),
+            ("4803 3330 37c1 c0bf", &second, 222),
+            ("88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d 54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049 5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e 3d31", &third, 215),
+        ]);
+        assert_eq!(Vec::from(decoder.table.clone()), final_table);
+
+        let mut decoder = Decoder::new(256);
+        decode_all(&mut decoder, &[
+            ("4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3", &first, 222),
+            ("4883 640e ffc1 c0bf", &second, 222),
+            ("88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f 9587 3160 65c0 03ed 4ee5 b106 3d50 07", &third, 215),
+        ]);
+        assert_eq!(Vec::from(decoder.table.clone()), final_table);
+    }
+
+    #[test]
+    fn table_size_updates_and_limits_are_enforced() {
+        let mut decoder = Decoder::new(4096);
+        decoder.decode(&hex("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572"), usize::MAX).unwrap();
+        // Shrinking the table to nothing evicts everything in it, so the entry can no longer be referred to.
+        assert_eq!(decoder.decode(&[0x20], usize::MAX), Some(Vec::new()));
+        assert_eq!(decoder.size, 0);
+        assert_eq!(decoder.decode(&[0xbe], usize::MAX), None);
+        // Nor can it grow past what was advertised.
+        assert_eq!(decoder.decode(&hex("3fe2 1f"), usize::MAX), None);
+
+        assert_eq!(Decoder::new(4096).decode(&[0x82, 0x84], 70), None);
+        assert_eq!(Decoder::new(4096).decode(&[0x80], usize::MAX), None);
+        assert_eq!(Decoder::new(4096).decode(&hex("410f 7777"), usize::MAX), None);
+        // A padding of eight or more bits, or one that is not all ones, is malformed.
+        assert_eq!(Decoder::new(4096).decode(&hex("4182 f1ff"), usize::MAX), None);
+        assert_eq!(Decoder::new(4096).decode(&hex("4181 f0"), usize::MAX), None);
+    }
+
+    #[test]
+    fn encoded_headers_decode_back() {
+        let list = [(":status", "200"), ("content-type", "text/html"), ("x-custom", "value"), (":status", "404")];
+        assert_eq!(Decoder::new(4096).decode(&encode(&list), usize::MAX), Some(headers(&list)));
+    }
+}
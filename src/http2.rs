@@ -0,0 +1,733 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufReader, Read, Write};
+use std::time::Duration;
+use http_resources::{HttpError, HttpMethod, HttpProtocols, HttpRequest, HttpRequestLimits, HttpResponse, HttpResponseOptions};
+use crate::connection::Connection;
+use crate::hpack::{self, Decoder};
+use crate::shutdown;
+
+const PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+const HTTP_1_1_REQUIRED: u32 = 0xd;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const HEADER_TABLE_SIZE: usize = 4096;
+const MAX_CONCURRENT_STREAMS: usize = 100;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+
+// Connection-specific headers are forbidden in HTTP/2 and make a response malformed.
+const CONNECTION_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+enum Http2Error {
+    Io,
+    Connection(u32),
+    StreamReset,
+}
+
+impl From<io::Error> for Http2Error {
+    fn from(_: io::Error) -> Self {
+        Http2Error::Io
+    }
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn data(&self) -> Result<&[u8], Http2Error> {
+        if self.flags & PADDED == 0 {
+            return Ok(&self.payload);
+        }
+        let padding = *self.payload.first().ok_or(Http2Error::Connection(PROTOCOL_ERROR))? as usize;
+        self.payload.len().checked_sub(padding)
+            .and_then(|end| self.payload.get(1..end))
+            .ok_or(Http2Error::Connection(PROTOCOL_ERROR))
+    }
+}
+
+struct Stream {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    error: Option<HttpError>,
+    window: i64,
+    remote_closed: bool,
+}
+
+struct HeaderBlock {
+    stream: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+}
+
+struct Http2Connection<S: Connection> {
+    reader: BufReader<S>,
+    decoder: Decoder,
+    limits: HttpRequestLimits,
+    streams: HashMap<u32, Stream>,
+    ready: VecDeque<u32>,
+    pending: Option<HeaderBlock>,
+    send_window: i64,
+    initial_window: i64,
+    max_frame_size: usize,
+    last_stream: u32,
+    going_away: bool,
+}
+
+// Serves a connection that negotiated HTTP/2. Streams are received concurrently and answered one at a time
+// through `handler`, in the order they complete.
+pub fn serve<S: Connection>(stream: S, limits: HttpRequestLimits, idle_timeout: Duration, mut handler: impl FnMut(Result<HttpRequest, HttpError>) -> HttpResponse) {
+    let mut connection = Http2Connection::new(stream, limits);
+    let code = match connection.run(idle_timeout, &mut handler) {
+        Err(Http2Error::Connection(code)) => code,
+        _ => NO_ERROR,
+    };
+    connection.go_away(code).unwrap_or(());
+    crate::connection::close(connection.reader);
+}
+
+impl<S: Connection> Http2Connection<S> {
+    fn new(stream: S, limits: HttpRequestLimits) -> Http2Connection<S> {
+        Http2Connection {
+            reader: BufReader::new(stream),
+            decoder: Decoder::new(HEADER_TABLE_SIZE),
+            limits,
+            streams: HashMap::new(),
+            ready: VecDeque::new(),
+            pending: None,
+            send_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: DEFAULT_FRAME_SIZE,
+            last_stream: 0,
+            going_away: false,
+        }
+    }
+
+    fn run(&mut self, idle_timeout: Duration, handler: &mut impl FnMut(Result<HttpRequest, HttpError>) -> HttpResponse) -> Result<(), Http2Error> {
+        let mut preface = [0; PREFACE.len()];
+        self.reader.read_exact(&mut preface)?;
+        if &preface != PREFACE {
+            return Err(Http2Error::Connection(PROTOCOL_ERROR));
+        }
+        self.write_settings()?;
+        self.reader.get_ref().set_read_timeout(Some(idle_timeout))?;
+
+        loop {
+            while let Some(id) = self.ready.pop_front() {
+                let Some(stream) = self.streams.get_mut(&id) else {
+                    continue;
+                };
+                let request = build_request(stream);
+                let response = handler(request);
+                // Responses are sent whole here, so an event stream would never get past its headers. Clients retry
+                // the request over HTTP/1.1 instead.
+                let result = if response.is_event_stream() { self.reset(id, HTTP_1_1_REQUIRED) } else { self.respond(id, &response) };
+                match result {
+                    Ok(()) | Err(Http2Error::StreamReset) => {},
+                    Err(err) => return Err(err),
+                }
+                self.streams.remove(&id);
+            }
+
+            if shutdown::is_requested() || (self.going_away && self.streams.is_empty()) {
+                return Ok(());
+            }
+            let frame = self.read_frame()?;
+            self.process(frame)?;
+        }
+    }
+
+    fn process(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if self.pending.as_ref().is_some_and(|pending| frame.kind != CONTINUATION || frame.stream != pending.stream) {
+            return Err(Http2Error::Connection(PROTOCOL_ERROR));
+        }
+
+        match frame.kind {
+            DATA => self.on_data(frame),
+            HEADERS => self.on_headers(frame),
+            PRIORITY => Ok(()),
+            RST_STREAM => {
+                if frame.stream == 0 || frame.payload.len() != 4 {
+                    return Err(Http2Error::Connection(PROTOCOL_ERROR));
+                }
+                self.streams.remove(&frame.stream);
+                Ok(())
+            },
+            SETTINGS => self.on_settings(frame),
+            PUSH_PROMISE => Err(Http2Error::Connection(PROTOCOL_ERROR)),
+            PING => {
+                if frame.stream != 0 || frame.payload.len() != 8 {
+                    return Err(Http2Error::Connection(FRAME_SIZE_ERROR));
+                }
+                if frame.flags & ACK == 0 {
+                    self.write_frame(PING, ACK, 0, &frame.payload)?;
+                }
+                Ok(())
+            },
+            GOAWAY => {
+                self.going_away = true;
+                Ok(())
+            },
+            WINDOW_UPDATE => self.on_window_update(frame),
+            CONTINUATION => self.on_continuation(frame),
+            // Unknown frame types must be ignored.
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if frame.stream == 0 {
+            return Err(Http2Error::Connection(PROTOCOL_ERROR));
+        }
+        let data = frame.data()?;
+        let end_stream = frame.flags & END_STREAM != 0;
+
+        // Received data is credited back right away; the body size limit is what bounds memory.
+        let consumed = frame.payload.len() as u32;
+        if consumed > 0 {
+            self.write_window_update(0, consumed)?;
+        }
+
+        let max_body_size = self.limits.max_body_size;
+        match self.streams.get_mut(&frame.stream) {
+            Some(stream) if !stream.remote_closed => {
+                if stream.error.is_none() {
+                    if stream.body.len() + data.len() > max_body_size {
+                        stream.error = Some(HttpError::BodyTooLarge);
+                        stream.body = Vec::new();
+                    } else {
+                        stream.body.extend_from_slice(data);
+                    }
+                }
+                if end_stream {
+                    stream.remote_closed = true;
+                    self.ready.push_back(frame.stream);
+                } else if consumed > 0 {
+                    self.write_window_update(frame.stream, consumed)?;
+                }
+                Ok(())
+            },
+            _ if frame.stream > self.last_stream => Err(Http2Error::Connection(PROTOCOL_ERROR)),
+            _ => self.reset(frame.stream, STREAM_CLOSED),
+        }
+    }
+
+    fn on_headers(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if frame.stream == 0 || frame.stream.is_multiple_of(2) {
+            return Err(Http2Error::Connection(PROTOCOL_ERROR));
+        }
+        let mut block = frame.data()?;
+        if frame.flags & PRIORITY_FLAG != 0 {
+            block = block.get(5..).ok_or(Http2Error::Connection(FRAME_SIZE_ERROR))?;
+        }
+
+        let block = HeaderBlock { stream: frame.stream, block: block.to_vec(), end_stream: frame.flags & END_STREAM != 0 };
+        if frame.flags & END_HEADERS != 0 {
+            self.on_header_block(block)
+        } else {
+            self.pending = Some(block);
+            Ok(())
+        }
+    }
+
+    fn on_continuation(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        let mut pending = self.pending.take().ok_or(Http2Error::Connection(PROTOCOL_ERROR))?;
+        pending.block.extend_from_slice(&frame.payload);
+        if pending.block.len() > self.max_header_list_size() {
+            return Err(Http2Error::Connection(ENHANCE_YOUR_CALM));
+        }
+
+        if frame.flags & END_HEADERS != 0 {
+            self.on_header_block(pending)
+        } else {
+            self.pending = Some(pending);
+            Ok(())
+        }
+    }
+
+    fn on_header_block(&mut self, block: HeaderBlock) -> Result<(), Http2Error> {
+        // Every block has to be decoded, even for refused streams, to keep the compression state in sync.
+        let headers = self.decoder.decode(&block.block, self.max_header_list_size()).ok_or(Http2Error::Connection(COMPRESSION_ERROR))?;
+
+        match self.streams.get_mut(&block.stream) {
+            // Trailers; nothing downstream uses them, but they do end the request.
+            Some(stream) if !stream.remote_closed => {
+                if !block.end_stream {
+                    return Err(Http2Error::Connection(PROTOCOL_ERROR));
+                }
+                stream.remote_closed = true;
+                self.ready.push_back(block.stream);
+                Ok(())
+            },
+            Some(_) => self.reset(block.stream, STREAM_CLOSED),
+            None if block.stream <= self.last_stream => self.reset(block.stream, STREAM_CLOSED),
+            None => {
+                self.last_stream = block.stream;
+                if self.going_away || self.streams.len() >= MAX_CONCURRENT_STREAMS {
+                    return self.reset(block.stream, REFUSED_STREAM);
+                }
+
+                let error = (headers.len() > self.limits.max_headers).then_some(HttpError::HeadersTooLarge);
+                self.streams.insert(block.stream, Stream { headers, body: Vec::new(), error, window: self.initial_window, remote_closed: block.end_stream });
+                if block.end_stream {
+                    self.ready.push_back(block.stream);
+                }
+                Ok(())
+            },
+        }
+    }
+
+    fn on_settings(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if frame.stream != 0 {
+            return Err(Http2Error::Connection(PROTOCOL_ERROR));
+        }
+        if frame.flags & ACK != 0 {
+            return if frame.payload.is_empty() { Ok(()) } else { Err(Http2Error::Connection(FRAME_SIZE_ERROR)) };
+        }
+        if !frame.payload.len().is_multiple_of(6) {
+            return Err(Http2Error::Connection(FRAME_SIZE_ERROR));
+        }
+
+        for setting in frame.payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err(Http2Error::Connection(FLOW_CONTROL_ERROR));
+                    }
+                    let delta = value as i64 - self.initial_window;
+                    self.streams.values_mut().for_each(|stream| stream.window += delta);
+                    self.initial_window = value as i64;
+                },
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_FRAME_SIZE as u32..=0xff_ffff).contains(&value) {
+                        return Err(Http2Error::Connection(PROTOCOL_ERROR));
+                    }
+                    self.max_frame_size = value as usize;
+                },
+                // The encoder never uses the dynamic table, so the peer's table size does not matter.
+                _ => {},
+            }
+        }
+        self.write_frame(SETTINGS, ACK, 0, &[])?;
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if frame.payload.len() != 4 {
+            return Err(Http2Error::Connection(FRAME_SIZE_ERROR));
+        }
+        let increment = (u32::from_be_bytes([frame.payload[0], frame.payload[1], frame.payload[2], frame.payload[3]]) & 0x7fff_ffff) as i64;
+        if increment == 0 {
+            return Err(Http2Error::Connection(PROTOCOL_ERROR));
+        }
+
+        let window = match frame.stream {
+            0 => &mut self.send_window,
+            id => match self.streams.get_mut(&id) {
+                Some(stream) => &mut stream.window,
+                None => return Ok(()),
+            },
+        };
+        *window += increment;
+        if *window > MAX_WINDOW {
+            return Err(Http2Error::Connection(FLOW_CONTROL_ERROR));
+        }
+        Ok(())
+    }
+
+    fn respond(&mut self, id: u32, response: &HttpResponse) -> Result<(), Http2Error> {
+        let mut headers = vec![(":status".to_string(), response.get_status().as_u16().to_string())];
+        // The same framing rules as HTTP/1.1: a body gets its real length, HEAD keeps the announced one.
+        let length = response.has_body().then(|| response.get_body_length());
+        for (name, value) in response.get_headers() {
+            let name = name.to_ascii_lowercase();
+            let framing = name == "content-length" && (length.is_some() || !response.get_status().allows_body());
+            if !CONNECTION_HEADERS.contains(&name.as_str()) && !framing {
+                headers.push((name, value.to_string()));
+            }
+        }
+        if let Some(length) = length {
+            headers.push(("content-length".to_string(), length.to_string()));
+        }
+        if response.get_option(&HttpResponseOptions::Date).is_none() {
+            headers.push(("date".to_string(), http_resources::http_date_now()));
+        }
+        let fields: Vec<(&str, &str)> = headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        let block = hpack::encode(&fields);
+
+        let has_body = length.is_some_and(|length| length > 0);
+        self.write_headers(id, &block, !has_body)?;
+        if has_body {
+            match response.get_file_body() {
+                Some(body) => {
+                    let mut writer = DataWriter { connection: self, stream: id, error: None };
+                    if crate::connection::copy_file(body, &mut writer).is_err() {
+                        return Err(writer.error.unwrap_or(Http2Error::Io));
+                    }
+                },
+                None => self.send_data(id, response.get_payload())?,
+            }
+            self.write_frame(DATA, END_STREAM, id, &[])?;
+        }
+        self.reader.get_mut().flush()?;
+        Ok(())
+    }
+
+    fn send_data(&mut self, id: u32, mut data: &[u8]) -> Result<(), Http2Error> {
+        while !data.is_empty() {
+            let window = self.wait_for_window(id)?;
+            let size = data.len().min(self.max_frame_size).min(window as usize);
+            self.write_frame(DATA, 0, id, &data[..size])?;
+            self.send_window -= size as i64;
+            if let Some(stream) = self.streams.get_mut(&id) {
+                stream.window -= size as i64;
+            }
+            data = &data[size..];
+        }
+        Ok(())
+    }
+
+    // Keeps processing incoming frames until the peer lets more data through for `id`.
+    fn wait_for_window(&mut self, id: u32) -> Result<i64, Http2Error> {
+        loop {
+            let stream_window = self.streams.get(&id).ok_or(Http2Error::StreamReset)?.window;
+            let window = stream_window.min(self.send_window);
+            if window > 0 {
+                return Ok(window);
+            }
+            self.reader.get_mut().flush()?;
+            let frame = self.read_frame()?;
+            self.process(frame)?;
+        }
+    }
+
+    fn max_header_list_size(&self) -> usize {
+        self.limits.max_header_bytes + self.limits.max_headers * 32
+    }
+
+    fn read_frame(&mut self) -> Result<Frame, Http2Error> {
+        let mut header = [0; 9];
+        self.reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if length > DEFAULT_FRAME_SIZE {
+            return Err(Http2Error::Connection(FRAME_SIZE_ERROR));
+        }
+
+        let mut payload = vec![0; length];
+        self.reader.read_exact(&mut payload)?;
+        Ok(Frame {
+            kind: header[3],
+            flags: header[4],
+            stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+            payload,
+        })
+    }
+
+    fn write_frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.reader.get_mut().write_all(&frame)
+    }
+
+    fn write_headers(&mut self, id: u32, block: &[u8], end_stream: bool) -> io::Result<()> {
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            if chunks.peek().is_none() {
+                return self.write_frame(kind, flags | END_HEADERS, id, chunk);
+            }
+            self.write_frame(kind, flags, id, chunk)?;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+    }
+
+    fn write_settings(&mut self) -> io::Result<()> {
+        let mut payload = Vec::new();
+        for (id, value) in [
+            (SETTINGS_HEADER_TABLE_SIZE, HEADER_TABLE_SIZE),
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, self.max_header_list_size()),
+        ] {
+            payload.extend_from_slice(&id.to_be_bytes());
+            payload.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        self.write_frame(SETTINGS, 0, 0, &payload)
+    }
+
+    fn write_window_update(&mut self, stream: u32, increment: u32) -> io::Result<()> {
+        self.write_frame(WINDOW_UPDATE, 0, stream, &increment.to_be_bytes())
+    }
+
+    fn reset(&mut self, stream: u32, code: u32) -> Result<(), Http2Error> {
+        self.write_frame(RST_STREAM, 0, stream, &code.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn go_away(&mut self, code: u32) -> io::Result<()> {
+        let mut payload = self.last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        self.write_frame(GOAWAY, 0, 0, &payload)?;
+        self.reader.get_mut().flush()
+    }
+}
+
+fn build_request(stream: &mut Stream) -> Result<HttpRequest, HttpError> {
+    if let Some(error) = stream.error.take() {
+        return Err(error);
+    }
+
+    let mut method = None;
+    let mut target = None;
+    let mut headers = Vec::new();
+    for (name, value) in std::mem::take(&mut stream.headers) {
+        match name.as_str() {
+            ":method" => method = HttpMethod::from_name(&value),
+            ":path" => target = Some(value),
+            ":authority" => headers.push(("Host".to_string(), value)),
+            ":scheme" => {},
+            _ if name.starts_with(':') || name.bytes().any(|byte| byte.is_ascii_uppercase()) => return Err(HttpError::ParseError),
+            _ => headers.push((name, value)),
+        }
+    }
+
+    let (Some(method), Some(target)) = (method, target) else {
+        return Err(HttpError::ParseError);
+    };
+    HttpRequest::from_parts(method, &target, HttpProtocols::Two, headers, std::mem::take(&mut stream.body))
+}
+
+// Lets file bodies be copied straight into flow-controlled DATA frames.
+struct DataWriter<'a, S: Connection> {
+    connection: &'a mut Http2Connection<S>,
+    stream: u32,
+    error: Option<Http2Error>,
+}
+
+impl<S: Connection> Write for DataWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.connection.send_data(self.stream, buf) {
+            Ok(()) => Ok(buf.len()),
+            Err(err) => {
+                self.error = Some(err);
+                Err(io::Error::other("HTTP/2 stream closed"))
+            },
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.connection.reader.get_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    #[cfg(feature = "async")]
+    use std::os::fd::RawFd;
+    use std::rc::Rc;
+
+    // A connection that reads what the client would have sent and keeps what the server writes.
+    struct Pipe {
+        input: io::Cursor<Vec<u8>>,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Pipe {
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn shutdown_write(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        #[cfg(feature = "async")]
+        fn raw_fd(&self) -> RawFd {
+            -1
+        }
+    }
+
+    fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn pipe(input: Vec<u8>) -> (Pipe, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        (Pipe { input: io::Cursor::new(input), output: Rc::clone(&output) }, output)
+    }
+
+    // Serves the preface and `frames`, and returns the requests handed over and the frames sent back.
+    fn exchange(frames: &[Vec<u8>]) -> (Vec<Result<HttpRequest, HttpError>>, Vec<Frame>) {
+        let mut input = PREFACE.to_vec();
+        input.extend(frame(SETTINGS, 0, 0, &[]));
+        frames.iter().for_each(|frame| input.extend_from_slice(frame));
+        let (stream, output) = pipe(input);
+
+        let mut requests = Vec::new();
+        serve(stream, HttpRequestLimits::default(), Duration::from_secs(1), |request| {
+            requests.push(request);
+            HttpResponse::ok_text("answered")
+        });
+        let (stream, _) = pipe(output.take());
+        let mut connection = Http2Connection::new(stream, HttpRequestLimits::default());
+        let mut sent = Vec::new();
+        while let Ok(frame) = connection.read_frame() {
+            sent.push(frame);
+        }
+        (requests, sent)
+    }
+
+    fn go_away_code(sent: &[Frame]) -> Option<u32> {
+        let last = sent.last().filter(|frame| frame.kind == GOAWAY)?;
+        Some(u32::from_be_bytes(last.payload[4..8].try_into().unwrap()))
+    }
+
+    #[test]
+    fn reads_frame_headers() {
+        let mut input = frame(PING, ACK, 0x8000_0000, b"12345678");
+        input.extend(frame(0xfa, 0xff, 7, &[]));
+        let (stream, _) = pipe(input);
+        let mut connection = Http2Connection::new(stream, HttpRequestLimits::default());
+
+        // The reserved bit of the stream identifier is ignored.
+        let ping = connection.read_frame().ok().unwrap();
+        assert_eq!((ping.kind, ping.flags, ping.stream, ping.payload.as_slice()), (PING, ACK, 0, &b"12345678"[..]));
+        let unknown = connection.read_frame().ok().unwrap();
+        assert_eq!((unknown.kind, unknown.flags, unknown.stream, unknown.payload.len()), (0xfa, 0xff, 7, 0));
+        assert!(matches!(connection.read_frame(), Err(Http2Error::Io)));
+
+        let (stream, _) = pipe(frame(DATA, 0, 1, &vec![0; DEFAULT_FRAME_SIZE + 1]));
+        let mut connection = Http2Connection::new(stream, HttpRequestLimits::default());
+        assert!(matches!(connection.read_frame(), Err(Http2Error::Connection(FRAME_SIZE_ERROR))));
+    }
+
+    #[test]
+    fn strips_padding() {
+        let padded = |payload: &[u8]| Frame { kind: DATA, flags: PADDED, stream: 1, payload: payload.to_vec() };
+        assert_eq!(padded(b"\x02hi\0\0").data().ok(), Some(&b"hi"[..]));
+        assert_eq!(padded(b"\x00hi").data().ok(), Some(&b"hi"[..]));
+        assert!(padded(b"\x03hi").data().is_err());
+        assert!(padded(b"").data().is_err());
+        assert_eq!(Frame { kind: DATA, flags: 0, stream: 1, payload: b"\x02hi".to_vec() }.data().ok(), Some(&b"\x02hi"[..]));
+    }
+
+    #[test]
+    fn assembles_requests_from_headers_continuation_and_data() {
+        let block = hpack::encode(&[(":method", "POST"), (":scheme", "https"), (":path", "/form?a=1"), (":authority", "example.com"), ("content-type", "text/plain")]);
+        let (first, rest) = block.split_at(block.len() / 2);
+        // Padded, with a priority, and split across a CONTINUATION frame.
+        let mut headers = vec![2, 0, 0, 0, 0, 16];
+        headers.extend_from_slice(first);
+        headers.extend_from_slice(&[0, 0]);
+
+        let (requests, sent) = exchange(&[
+            frame(HEADERS, PADDED | PRIORITY_FLAG, 1, &headers),
+            frame(CONTINUATION, END_HEADERS, 1, rest),
+            frame(DATA, 0, 1, b"hello "),
+            frame(DATA, PADDED | END_STREAM, 1, b"\x01world\0"),
+        ]);
+
+        let [Ok(request)] = requests.as_slice() else {
+            panic!("{requests:?}");
+        };
+        assert_eq!((request.get_method(), request.get_path(), request.get_query()), (HttpMethod::Post, "/form", Some("a=1")));
+        assert_eq!(request.get_header("Host"), Some("example.com"));
+        assert_eq!(request.get_header("content-type"), Some("text/plain"));
+        assert_eq!(request.get_body(), b"hello world");
+
+        assert!(sent.iter().any(|frame| frame.kind == SETTINGS && frame.flags == ACK));
+        let response = sent.iter().find(|frame| frame.kind == HEADERS && frame.stream == 1).expect("No response headers");
+        let fields = Decoder::new(HEADER_TABLE_SIZE).decode(&response.payload, usize::MAX).unwrap();
+        assert_eq!(fields[0], (":status".to_string(), "200".to_string()));
+        let body: Vec<u8> = sent.iter().filter(|frame| frame.kind == DATA && frame.stream == 1).flat_map(|frame| frame.payload.clone()).collect();
+        assert_eq!(body, b"answered");
+        assert_eq!(go_away_code(&sent), Some(NO_ERROR));
+    }
+
+    #[test]
+    fn answers_malformed_frames_with_connection_errors() {
+        let block = hpack::encode(&[(":method", "GET"), (":path", "/")]);
+        for (frames, code) in [
+            (vec![frame(DATA, END_STREAM, 0, b"x")], PROTOCOL_ERROR),
+            (vec![frame(HEADERS, END_STREAM | END_HEADERS, 2, &block)], PROTOCOL_ERROR),
+            (vec![frame(HEADERS, END_STREAM, 1, &block), frame(PING, 0, 0, b"12345678")], PROTOCOL_ERROR),
+            (vec![frame(CONTINUATION, END_HEADERS, 1, &block)], PROTOCOL_ERROR),
+            (vec![frame(PING, 0, 0, b"1234")], FRAME_SIZE_ERROR),
+            (vec![frame(SETTINGS, 0, 0, &[0, 4, 0, 0])], FRAME_SIZE_ERROR),
+            (vec![frame(SETTINGS, 0, 0, &[0, 4, 0x80, 0, 0, 0])], FLOW_CONTROL_ERROR),
+            (vec![frame(WINDOW_UPDATE, 0, 0, &[0, 0, 0, 0])], PROTOCOL_ERROR),
+            (vec![frame(PUSH_PROMISE, END_HEADERS, 1, &[0, 0, 0, 2])], PROTOCOL_ERROR),
+            (vec![frame(HEADERS, END_STREAM | END_HEADERS, 1, &[0xff, 0xff])], COMPRESSION_ERROR),
+        ] {
+            let (requests, sent) = exchange(&frames);
+            assert!(requests.is_empty());
+            assert_eq!(go_away_code(&sent), Some(code), "{:?}", frames);
+        }
+    }
+}
@@ -0,0 +1,1730 @@
+#[cfg(feature = "acme")]
+mod acme;
+mod access_log;
+mod admin;
+mod auth;
+mod autoindex;
+mod cache_control;
+mod cgi;
+mod cli;
+mod compression;
+pub mod config;
+mod connection;
+mod console;
+mod cors;
+mod deny;
+mod fastcgi;
+mod file_cache;
+mod forwarded;
+#[cfg(feature = "http2")]
+mod hpack;
+#[cfg(feature = "http2")]
+mod http2;
+mod listener;
+mod logging;
+mod metrics;
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+mod negotiation;
+mod preload;
+mod proxy;
+mod proxy_protocol;
+mod rate_limit;
+#[cfg(feature = "async")]
+mod reactor;
+mod redirect;
+mod request_id;
+mod routes;
+mod server;
+mod settings;
+mod shutdown;
+mod sse;
+mod state;
+mod throttle;
+#[cfg(feature = "tls")]
+mod tls;
+mod upload;
+#[cfg(feature = "watch")]
+mod watch;
+
+use std::{env, fmt, fs, io, thread};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+#[cfg(feature = "async")]
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thread_helper::ThreadPool;
+use config::{host_matches, Config, Site};
+use connection::{Connection, ConnectionSlot, Deadline};
+use file_cache::{CachedFile, FileCache};
+use proxy::{ProxyError, Relay, Relayed};
+use rate_limit::RateLimiter;
+use throttle::Throttled;
+use state::ServerState;
+use upload::UploadRoute;
+#[cfg(feature = "async")]
+use reactor::Reactor;
+use access_log::{AccessLogEntry, AccessLogger};
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use http_resources::{multipart_byteranges, normalize_path, percent_encode, ByteRange, HttpError, HttpPathError, HttpProtocols, HttpRangeError, HttpRequestLimits, HttpResponseOptions, HttpResponseStatusCode, Templates};
+use crate::ConnectionError::InternalServerErr;
+
+pub use cgi::Cgi;
+pub use http_resources::{Handler, HttpMethod, HttpRequest, HttpResponse};
+pub use proxy::Proxy;
+pub use server::{Server, ShutdownHandle};
+
+lazy_static!{
+    static ref ARGS: cli::Args = cli::Args::parse(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}\n\n{}", cli::USAGE);
+        std::process::exit(2);
+    });
+
+    static ref ACCESS_LOG: Option<AccessLogger> = match conf().access_log.as_str() {
+        "" => None,
+        path => AccessLogger::open(Path::new(path), &conf().access_log_format, conf().access_log_max_size, conf().access_log_rotation),
+    };
+
+    static ref FILE_CACHE: FileCache = FileCache::new();
+
+    static ref STATE: ServerState = ServerState::new();
+
+    static ref RATE_LIMITER: RateLimiter = RateLimiter::new();
+
+    // Replaced by `Server::run` before anything is served.
+    static ref CONF: RwLock<Arc<Config>> = RwLock::new(Arc::new(Config::default()));
+}
+
+// Only set when the async backend is enabled.
+#[cfg(feature = "async")]
+static REACTOR: OnceLock<Reactor> = OnceLock::new();
+
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+const STATIC_FILE_METHODS: [HttpMethod; 3] = [HttpMethod::Get, HttpMethod::Head, HttpMethod::Options];
+
+enum ConnectionError {
+    TCPReadFailed,
+    Forbidden,
+    SourceNotFound,
+    PayloadTooLarge,
+    ExpectationFailed,
+    // What went wrong, which only "dev-mode" shows to the client.
+    InternalServerErr(String),
+    BadGateway,
+    Unauthorized(String),
+    TooManyRequests(u64),
+    ServiceUnavailable(u64),
+    RequestTimeout,
+    UriTooLong,
+    HeadersTooLarge,
+    VersionNotSupported,
+    NotImplemented,
+    MisdirectedRequest,
+    NotAcceptable,
+}
+
+// For bodies read after the head, such as uploads: what the body's framing got wrong.
+impl From<HttpError> for ConnectionError {
+    fn from(err: HttpError) -> ConnectionError {
+        match err {
+            HttpError::BodyTooLarge => ConnectionError::PayloadTooLarge,
+            HttpError::HeadersTooLarge => ConnectionError::HeadersTooLarge,
+            HttpError::UnsupportedTransferEncoding => ConnectionError::NotImplemented,
+            _ => ConnectionError::TCPReadFailed,
+        }
+    }
+}
+
+impl ConnectionError {
+    // A 500 for an I/O error, with what was being done or the file it was done to.
+    fn internal(context: impl fmt::Display) -> impl FnOnce(io::Error) -> ConnectionError {
+        move |err| InternalServerErr(format!("{context}: {err}"))
+    }
+
+    fn into_response(self, root: &Path, request: Option<&HttpRequest>) -> HttpResponse {
+        let status = self.get_status();
+        let (content_type, body) = match &self {
+            InternalServerErr(cause) if conf().dev_mode => {
+                let target = request.map_or_else(|| "-".to_string(), |request| format!("{} {}", request.get_method().get_name(), request.get_path()));
+                ("text/plain; charset=utf-8", format!("{status}\n\n{target}\n{cause}\n").into_bytes())
+            },
+            _ => match error_template(&status).or_else(|| error_page(root, status.as_u16())) {
+                Some(page) => ("text/html; charset=utf-8", page.into_bytes()),
+                None => ("text/plain; charset=utf-8", status.to_string().into_bytes()),
+            },
+        };
+        let builder = match self {
+            ConnectionError::Unauthorized(challenge) => HttpResponse::builder().header(HttpResponseOptions::WwwAuthenticate, challenge),
+            ConnectionError::TooManyRequests(retry_after) | ConnectionError::ServiceUnavailable(retry_after) => {
+                HttpResponse::builder().header(HttpResponseOptions::RetryAfter, retry_after.to_string())
+            },
+            ConnectionError::NotAcceptable => HttpResponse::builder().header(HttpResponseOptions::Vary, "Accept"),
+            _ => HttpResponse::builder(),
+        };
+        builder.status(status)
+            .content_type(content_type)
+            .header(HttpResponseOptions::Connection, "close")
+            .body(body)
+            .build()
+    }
+
+    fn get_status(&self) -> HttpResponseStatusCode {
+        match self {
+            ConnectionError::TCPReadFailed => HttpResponseStatusCode::BadRequest,
+            ConnectionError::Forbidden => HttpResponseStatusCode::Forbidden,
+            ConnectionError::SourceNotFound => HttpResponseStatusCode::NotFound,
+            ConnectionError::PayloadTooLarge => HttpResponseStatusCode::PayloadTooLarge,
+            ConnectionError::ExpectationFailed => HttpResponseStatusCode::ExpectationFailed,
+            InternalServerErr(_) => HttpResponseStatusCode::InternalServerError,
+            ConnectionError::BadGateway => HttpResponseStatusCode::BadGateway,
+            ConnectionError::Unauthorized(_) => HttpResponseStatusCode::Unauthorized,
+            ConnectionError::TooManyRequests(_) => HttpResponseStatusCode::TooManyRequests,
+            ConnectionError::ServiceUnavailable(_) => HttpResponseStatusCode::ServiceUnavailable,
+            ConnectionError::RequestTimeout => HttpResponseStatusCode::RequestTimeout,
+            ConnectionError::UriTooLong => HttpResponseStatusCode::UriTooLong,
+            ConnectionError::HeadersTooLarge => HttpResponseStatusCode::RequestHeaderFieldsTooLarge,
+            ConnectionError::VersionNotSupported => HttpResponseStatusCode::HttpVersionNotSupported,
+            ConnectionError::NotImplemented => HttpResponseStatusCode::NotImplemented,
+            ConnectionError::MisdirectedRequest => HttpResponseStatusCode::MisdirectedRequest,
+            ConnectionError::NotAcceptable => HttpResponseStatusCode::NotAcceptable,
+        }
+    }
+}
+
+// Pages are looked up in the site's root first and fall back to the default root, so virtual hosts can share them.
+fn error_page(root: &Path, code: u16) -> Option<String> {
+    let config = conf();
+    let (_, page) = config.error_pages.iter().find(|(status, _)| *status == code)?;
+    fs::read_to_string(root.join(page)).or_else(|_| fs::read_to_string(config.root_dir.join(page))).ok()
+}
+
+// An "errors/<code>" template, or "errors/default" for any status, wins over the error page files.
+fn error_template(status: &HttpResponseStatusCode) -> Option<String> {
+    let templates = Templates::installed()?;
+    let code = status.as_u16().to_string();
+    let name = [format!("errors/{code}"), "errors/default".to_string()].into_iter().find(|name| templates.contains(name))?;
+    let context = HashMap::from([
+        ("status", code),
+        ("reason", status.get_reason().to_string()),
+        ("request_id", request_id::current().unwrap_or_default()),
+    ]);
+    templates.render(&name, &context)
+        .inspect_err(|err| error!("Unable to render the error page {}: {}", name, err))
+        .ok()
+}
+
+// Keeps the templates already installed when the new ones do not load.
+fn load_templates(config: &Config) {
+    if config.templates_dir.as_os_str().is_empty() {
+        Templates::new().install();
+        return;
+    }
+    match Templates::load_dir(&config.templates_dir) {
+        Ok(templates) => {
+            debug!("Loaded {} templates from {}.", templates.get_names().len(), config.templates_dir.display());
+            templates.install();
+        },
+        Err(err) => error!("Unable to load the templates: {}", err),
+    }
+}
+
+fn create_error_pages(config: &Config) {
+    for (code, page) in &config.error_pages {
+        let path = config.root_dir.join(page);
+        if path.exists() {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).unwrap_or(());
+        }
+        fs::write(&path, format!("<!DOCTYPE html><html><body><h1>{code}</h1></body></html>"))
+            .unwrap_or_else(|err| warn!("Unable to create the default error page {}: {}", path.display(), err));
+    }
+}
+
+// HTTP/1.1 needs exactly one well-formed Host header (RFC 9112, section 3.2). With allowed-hosts set, it must also
+// name this server or one of its virtual hosts, which stops DNS rebinding and links built from a forged Host.
+fn check_host(config: &Config, request: &HttpRequest) -> Result<(), ConnectionError> {
+    let mut hosts = request.get_headers().iter().filter(|(name, _)| name.eq_ignore_ascii_case("Host")).map(|(_, value)| value.trim());
+    let host = match (hosts.next(), hosts.next()) {
+        (Some(host), None) => host,
+        (None, _) if *request.get_protocol() != HttpProtocols::OneOne => return Ok(()),
+        _ => return Err(ConnectionError::TCPReadFailed),
+    };
+    if !is_valid_host(host) {
+        return Err(ConnectionError::TCPReadFailed);
+    }
+    if config.allowed_hosts.is_empty() {
+        return Ok(());
+    }
+    let name = strip_port(host).to_ascii_lowercase();
+    let mut names = config.allowed_hosts.iter().chain(config.hosts.iter().flat_map(|vhost| &vhost.names));
+    if names.any(|pattern| host_matches(pattern, &name)) { Ok(()) } else { Err(ConnectionError::MisdirectedRequest) }
+}
+
+// Whether to answer "100 Continue" before reading the body of a request that waits for it (RFC 9110, section
+// 10.1.1). A body that is too large is refused before the client sends it. HTTP/1.0 clients do not know the
+// mechanism, so their Expect header is ignored. Nor is a body asked for that could not be read.
+fn expect_continue(request: &HttpRequest, max_body: u64) -> Result<bool, ConnectionError> {
+    let Some(expect) = request.get_header("Expect").filter(|_| *request.get_protocol() == HttpProtocols::OneOne) else {
+        return Ok(false);
+    };
+    if !expect.trim().eq_ignore_ascii_case("100-continue") {
+        return Err(ConnectionError::ExpectationFailed);
+    }
+    if request.headers().contains("Transfer-Encoding") && !request.is_chunked() {
+        return Err(ConnectionError::NotImplemented);
+    }
+    let length = request.content_length();
+    if length.is_some_and(|length| length > max_body) {
+        return Err(ConnectionError::PayloadTooLarge);
+    }
+    Ok(request.is_chunked() || length.is_some_and(|length| length > 0))
+}
+
+// A name or IPv4 address, or an IPv6 address in brackets, with an optional port. An empty Host is allowed, as sent
+// for targets without an authority.
+fn is_valid_host(host: &str) -> bool {
+    let (name, port) = match host.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((address, port)) if !address.is_empty() && address.chars().all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.') => (None, port),
+            _ => return false,
+        },
+        None => match host.rsplit_once(':') {
+            Some((name, _)) => (Some(name), &host[name.len()..]),
+            None => (Some(host), ""),
+        },
+    };
+    let valid_port = port.is_empty() || port.strip_prefix(':').is_some_and(|port| !port.is_empty() && port.len() <= 5 && port.bytes().all(|b| b.is_ascii_digit()));
+    // A port needs a name in front of it.
+    let valid_name = name.is_none_or(|name| (!name.is_empty() || port.is_empty()) && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_'));
+    valid_port && valid_name
+}
+
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.split_once(']').map_or(host, |(address, _)| &address[1..]);
+    }
+    host.rsplit_once(':').map_or(host, |(name, _)| name)
+}
+
+// Reads the configuration the command line asks for. --help, --version and --validate-config are answered here, and
+// an invalid configuration ends the process; `None` means there is nothing left to do.
+pub fn config_from_args() -> Option<Config> {
+    if ARGS.help {
+        println!("{}", cli::USAGE);
+        return None;
+    }
+    if ARGS.version {
+        println!("backend_web_server {}", env!("CARGO_PKG_VERSION"));
+        return None;
+    }
+
+    logging::init();
+    if ARGS.validate_config {
+        std::process::exit(validate_config());
+    }
+    match parse_config() {
+        Ok(config) => Some(config),
+        Err(errors) => {
+            for error in errors {
+                error!("{}", error);
+            }
+            error!("Refusing to start until the configuration is fixed.");
+            std::process::exit(1);
+        },
+    }
+}
+
+fn accept_http(listener: TcpListener, pool: Arc<ThreadPool>) {
+    for stream in listener.incoming() {
+        if shutdown::is_requested() {
+            break;
+        }
+        let mut stream = match stream {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        stream.set_write_timeout(Some(Duration::from_secs(conf().write_timeout.max(1)))).unwrap_or(());
+
+        let peer = stream.peer_addr().ok().map(|addr| addr.ip());
+        if !wait_for_connections() {
+            STATE.count_connection_rejection();
+            send_error(&mut stream, ConnectionError::ServiceUnavailable(1), peer, None, Instant::now());
+            continue;
+        }
+        if !wait_for_queue(&pool) {
+            STATE.count_queue_rejection();
+            send_error(&mut stream, ConnectionError::ServiceUnavailable(1), peer, None, Instant::now());
+            continue;
+        }
+        let Some(mut slot) = STATE.connections.acquire(limited_address(peer), conf().max_connections_per_ip) else {
+            refuse_over_address_limit(peer);
+            send_error(&mut stream, ConnectionError::ServiceUnavailable(1), peer, None, Instant::now());
+            continue;
+        };
+
+        #[cfg(feature = "async")]
+        if let Some(reactor) = REACTOR.get() {
+            reactor.park(stream, Duration::from_secs(conf().header_timeout.max(1)), move |mut stream, readable| {
+                if !readable {
+                    send_error(&mut stream, ConnectionError::RequestTimeout, peer, None, Instant::now());
+                } else if let Some(peer) = read_proxy_header(&mut stream, peer) {
+                    if claim_client_slot(&mut slot, peer) {
+                        serve_connection(stream, slot, peer, "http", 0);
+                    } else {
+                        send_error(&mut stream, ConnectionError::ServiceUnavailable(1), peer, None, Instant::now());
+                    }
+                }
+            });
+            continue;
+        }
+
+        pool.execute(metrics::queued(move || {
+            if let Some(peer) = read_proxy_header(&mut stream, peer) {
+                if claim_client_slot(&mut slot, peer) {
+                    serve_connection(stream, slot, peer, "http", 0);
+                } else {
+                    send_error(&mut stream, ConnectionError::ServiceUnavailable(1), peer, None, Instant::now());
+                }
+            }
+        }));
+    }
+}
+
+#[cfg(feature = "tls")]
+fn start_tls_listeners(config: &Config, pool: &Arc<ThreadPool>, listeners: Vec<(String, TcpListener)>) -> io::Result<Vec<thread::JoinHandle<()>>> {
+    if config.ssl.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    #[cfg(feature = "acme")]
+    if !config.acme_domains.is_empty() {
+        acme::prepare(config);
+    }
+    let tls_config = tls::load_server_config(config).ok_or_else(|| io::Error::other("Unable to load the TLS certificates"))?;
+    tls::watch_certificates();
+
+    if listeners.is_empty() {
+        warn!("\"ssl-cert\" is set, but neither \"ssl-port\" nor \"listen-tls\" is; HTTPS is disabled.");
+    }
+    Ok(listeners.into_iter().map(|(address, listener)| {
+        info!("Successfully started! Listening for HTTPS on: {}...", bound_address(&listener, &address));
+        if config.dev_mode {
+            print_local_url(&listener, "https");
+        }
+        let tls_config = Arc::clone(&tls_config);
+        let pool = Arc::clone(pool);
+        thread::spawn(move || accept_tls(listener, tls_config, pool))
+    }).collect())
+}
+
+#[cfg(feature = "tls")]
+fn accept_tls(listener: TcpListener, tls_config: Arc<rustls::ServerConfig>, pool: Arc<ThreadPool>) {
+    for stream in listener.incoming() {
+        if shutdown::is_requested() {
+            break;
+        }
+        let stream = match stream {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        stream.set_write_timeout(Some(Duration::from_secs(conf().write_timeout.max(1)))).unwrap_or(());
+
+        let peer = stream.peer_addr().ok().map(|addr| addr.ip());
+        // There is no way to answer before the handshake, which would itself need a worker.
+        if !wait_for_connections() {
+            STATE.count_connection_rejection();
+            continue;
+        }
+        if !wait_for_queue(&pool) {
+            STATE.count_queue_rejection();
+            continue;
+        }
+        let Some(mut slot) = STATE.connections.acquire(limited_address(peer), conf().max_connections_per_ip) else {
+            refuse_over_address_limit(peer);
+            continue;
+        };
+
+        let tls_config = Arc::clone(&tls_config);
+        let serve = move |mut stream| {
+            let Some(peer) = read_proxy_header(&mut stream, peer) else {
+                return;
+            };
+            if !claim_client_slot(&mut slot, peer) {
+                return;
+            }
+            if let Some(stream) = tls::accept(&tls_config, stream) {
+                #[cfg(feature = "http2")]
+                let mut stream = stream;
+                #[cfg(feature = "http2")]
+                if tls::negotiated_http2(&mut stream, Duration::from_secs(conf().header_timeout.max(1))) {
+                    slot.enter();
+                    serve_http2(stream, peer);
+                    return;
+                }
+                serve_connection(stream, slot, peer, "https", 0);
+            }
+        };
+
+        // A handshake that never starts is dropped without a response.
+        #[cfg(feature = "async")]
+        if let Some(reactor) = REACTOR.get() {
+            reactor.park(stream, Duration::from_secs(conf().header_timeout.max(1)), move |stream, readable| {
+                if readable {
+                    serve(stream);
+                }
+            });
+            continue;
+        }
+
+        pool.execute(metrics::queued(move || serve(stream)));
+    }
+}
+
+fn bind(address: &str) -> io::Result<TcpListener> {
+    let listener = listener::bind(address).map_err(|err| io::Error::new(err.kind(), format!("Unable to bind to {address}: {err}")))?;
+    if let Ok(addr) = listener.local_addr() {
+        shutdown::register_listener(addr);
+    }
+    Ok(listener)
+}
+
+// The port actually picked when the config asked for port 0.
+fn bound_address(listener: &TcpListener, address: &str) -> String {
+    listener.local_addr().map_or_else(|_| address.to_string(), |addr| addr.to_string())
+}
+
+// On a line of its own, where terminals make it a link. Wildcard and loopback addresses are opened as localhost.
+fn print_local_url(listener: &TcpListener, scheme: &str) {
+    let Ok(address) = listener.local_addr() else {
+        return;
+    };
+    let host = match address.ip() {
+        ip if ip.is_unspecified() || ip.is_loopback() => "localhost".to_string(),
+        IpAddr::V6(ip) => format!("[{ip}]"),
+        ip => ip.to_string(),
+    };
+    println!("  {scheme}://{host}:{}/", address.port());
+}
+
+// Returns false when the job queue is full and the connection should be turned away. With "queue-full = wait" it
+// instead holds the accept loop until a worker catches up, leaving further connections in the listen backlog.
+fn wait_for_queue(pool: &ThreadPool) -> bool {
+    loop {
+        let config = conf();
+        if !queue_is_full(pool, config.queue_limit) || shutdown::is_requested() {
+            return true;
+        }
+        if !config.queue_full_wait {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Returns false when "max-connections" are open and the connection should be turned away. With
+// "connections-full = wait" it instead holds the accept loop until one closes, like "queue-full = wait".
+fn wait_for_connections() -> bool {
+    loop {
+        let config = conf();
+        if !connections_are_full(config.max_connections) || shutdown::is_requested() {
+            return true;
+        }
+        if !config.connections_full_wait {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Like the queue, reaching the limit is logged once, and again once connections are accepted again.
+fn connections_are_full(limit: usize) -> bool {
+    let open = STATE.connections.get_total();
+    let full = limit > 0 && open >= limit;
+    if full != STATE.set_connections_full(full) {
+        if full {
+            warn!("The connection limit is reached: {} of {} connections open.", open, limit);
+        } else {
+            info!("Connections have closed; accepting new ones again.");
+        }
+    }
+    full
+}
+
+// Overload is logged when the queue fills up and when it drains again, not for every connection in between.
+fn queue_is_full(pool: &ThreadPool, limit: usize) -> bool {
+    let full = pool.is_full(limit);
+    if full != STATE.set_queue_full(full) {
+        if full {
+            warn!("The job queue is full: {} connections waiting, {} of {} workers busy.", pool.get_queued_jobs(), pool.get_busy_workers(), pool.get_size());
+        } else {
+            info!("The job queue has drained; accepting connections again.");
+        }
+    }
+    full
+}
+
+fn request_limits(config: &Config) -> HttpRequestLimits {
+    HttpRequestLimits {
+        max_body_size: config.max_body_size,
+        max_request_line: config.max_request_line,
+        max_header_size: config.max_header_size,
+        max_header_bytes: config.max_header_bytes,
+        max_headers: config.max_headers,
+    }
+}
+
+#[cfg(feature = "async")]
+fn start_reactor(config: &Config, pool: &Arc<ThreadPool>) -> Option<tokio::runtime::Runtime> {
+    match config.io_backend.as_str() {
+        "async" => {},
+        "threads" => return None,
+        other => {
+            warn!("Unknown io-backend \"{}\"; using threads.", other);
+            return None;
+        },
+    }
+    match Reactor::start(pool) {
+        Ok((reactor, runtime)) => {
+            REACTOR.set(reactor).unwrap_or(());
+            info!("Using the async backend for idle connections.");
+            Some(runtime)
+        },
+        Err(err) => {
+            error!("Unable to start the async backend, using threads instead: {}", err);
+            None
+        },
+    }
+}
+
+// With proxy-protocol on, every connection starts with the load balancer's PROXY header, which names the client in
+// place of the balancer's own address. Connections without a valid header are closed.
+fn read_proxy_header(stream: &mut TcpStream, peer: Option<IpAddr>) -> Option<Option<IpAddr>> {
+    let config = conf();
+    if !config.proxy_protocol {
+        return Some(peer);
+    }
+    stream.set_read_timeout(Some(Duration::from_secs(config.header_timeout.max(1)))).unwrap_or(());
+    match proxy_protocol::read_header(stream) {
+        Ok(client) => Some(client.or(peer)),
+        Err(err) => {
+            debug!("Closing a connection from {:?} without a valid PROXY protocol header: {}", peer, err);
+            None
+        },
+    }
+}
+
+// Behind a load balancer every connection comes from the balancer's address, so the per-address limit waits for the
+// PROXY header to name the client; see `claim_client_slot`.
+fn limited_address(peer: Option<IpAddr>) -> Option<IpAddr> {
+    if conf().proxy_protocol { None } else { peer }
+}
+
+fn claim_client_slot(slot: &mut ConnectionSlot, client: Option<IpAddr>) -> bool {
+    if slot.claim(client, conf().max_connections_per_ip) {
+        return true;
+    }
+    refuse_over_address_limit(client);
+    false
+}
+
+fn refuse_over_address_limit(client: Option<IpAddr>) {
+    STATE.count_per_ip_rejection();
+    info!("Refusing a connection from {:?}: too many open connections from this address.", client);
+}
+
+// `served` counts the requests answered on this connection before it was parked, if it was.
+fn serve_connection<S: Connection + Send + 'static>(stream: S, slot: ConnectionSlot, peer: Option<IpAddr>, scheme: &'static str, mut served: usize) {
+    let config = conf();
+    let mut buf_reader = BufReader::new(Throttled::new(stream, config.bandwidth_limit, config.bandwidth_limit_total));
+    let limits = request_limits(&config);
+    drop(config);
+    slot.enter();
+
+    loop {
+        let timeouts = conf();
+
+        // Idle keep-alive connections are closed quietly; a new connection that never sends anything gets a 408.
+        let idle_timeout = if served == 0 { timeouts.header_timeout } else { timeouts.keep_alive_timeout };
+        buf_reader.get_ref().set_read_timeout(Some(Duration::from_secs(idle_timeout.max(1)))).unwrap_or(());
+        match buf_reader.fill_buf() {
+            Ok([]) => break,
+            Ok(_) => request_id::begin(),
+            Err(err) => {
+                if served == 0 && connection::is_timeout(&err) {
+                    send_error(buf_reader.get_mut(), ConnectionError::RequestTimeout, peer, None, Instant::now());
+                }
+                break;
+            }
+        }
+
+        buf_reader.get_ref().set_read_timeout(Some(Duration::from_secs(timeouts.read_timeout.max(1)))).unwrap_or(());
+        let mut reader = Deadline::new(&mut buf_reader, Some(Instant::now() + Duration::from_secs(timeouts.header_timeout.max(1))));
+        let mut expectation = Ok(false);
+        let parsed = HttpRequest::parse_head(&mut reader, &limits).and_then(|mut request| {
+            reader.set_deadline(None);
+            // Uploads are streamed to disk after authentication, so their body stays on the connection for now.
+            if upload::is_upload(&timeouts.uploads, &request) {
+                return Ok(request);
+            }
+            // Uploads are authenticated before their body is read anyway; everything else is checked here too, so a
+            // body is not sent only to be refused.
+            expectation = expect_continue(&request, limits.max_body_size as u64).and_then(|send_continue| {
+                match auth::find(&timeouts.auth, &normalize_path(request.get_path()).unwrap_or_default()) {
+                    Some(rule) if send_continue && !rule.authorize(&request) => Err(ConnectionError::Unauthorized(rule.challenge())),
+                    _ => Ok(send_continue),
+                }
+            });
+            match expectation {
+                Ok(true) => reader.get_mut().get_mut().write_all(CONTINUE).map_err(|_| HttpError::ReadFailed)?,
+                Ok(false) => {},
+                Err(_) => return Ok(request),
+            }
+            request.read_body(&mut reader, &limits).map(|_| request)
+        });
+        let timed_out = reader.is_expired();
+        STATE.metrics.add_bytes_in(reader.get_consumed());
+        connection::add_bytes_in(reader.get_consumed());
+
+        let mut request = match parsed {
+            Ok(request) => request,
+            Err(_) if timed_out => {
+                send_error(buf_reader.get_mut(), ConnectionError::RequestTimeout, peer, None, Instant::now());
+                break;
+            }
+            Err(HttpError::ConnectionClosed) | Err(HttpError::ReadFailed) => break,
+            Err(HttpError::ParseError) | Err(HttpError::Incomplete) | Err(HttpError::InvalidHeaderName(_)) | Err(HttpError::InvalidHeaderValue(_)) => {
+                send_error(buf_reader.get_mut(), ConnectionError::TCPReadFailed, peer, None, Instant::now());
+                break;
+            }
+            Err(HttpError::UnsupportedVersion) => {
+                send_error(buf_reader.get_mut(), ConnectionError::VersionNotSupported, peer, None, Instant::now());
+                break;
+            }
+            Err(HttpError::UnsupportedTransferEncoding) => {
+                send_error(buf_reader.get_mut(), ConnectionError::NotImplemented, peer, None, Instant::now());
+                break;
+            }
+            Err(HttpError::BodyTooLarge) => {
+                send_error(buf_reader.get_mut(), ConnectionError::PayloadTooLarge, peer, None, Instant::now());
+                break;
+            }
+            Err(HttpError::UriTooLong) => {
+                send_error(buf_reader.get_mut(), ConnectionError::UriTooLong, peer, None, Instant::now());
+                break;
+            }
+            Err(HttpError::HeadersTooLarge) => {
+                send_error(buf_reader.get_mut(), ConnectionError::HeadersTooLarge, peer, None, Instant::now());
+                break;
+            }
+        };
+        request_id::adopt(&mut request);
+        let started = Instant::now();
+        // The body was never sent, so nothing after it can be read from this connection.
+        if let Err(err) = expectation {
+            send_error(buf_reader.get_mut(), err, peer, Some(&request), started);
+            break;
+        }
+        let config = conf();
+        served += 1;
+
+        let mut keep_alive = request.wants_keep_alive() && served < config.keep_alive_max && !shutdown::is_requested();
+        let unread_body = upload::is_upload(&timeouts.uploads, &request);
+        let response = match dispatch(&config, &mut request, peer, scheme) {
+            Dispatch::Relay(handler) => {
+                let relayed = handler.relay(&request, buf_reader.get_mut(), keep_alive, &mut |response| decorate(&config, &request, response, scheme));
+                keep_alive = finish_relay(relayed, buf_reader.get_mut(), peer, &request, started);
+                None
+            },
+            Dispatch::Upload(route) if !unread_body => Some(upload::receive(route, &request, &mut request.get_body(), config.max_upload_size, &limits)),
+            Dispatch::Upload(route) => match expect_continue(&request, config.max_upload_size) {
+                Ok(send_continue) => {
+                    if send_continue {
+                        buf_reader.get_mut().write_all(CONTINUE).unwrap_or(());
+                    }
+                    let mut body = Deadline::new(&mut buf_reader, None);
+                    let received = upload::receive(route, &request, &mut body, config.max_upload_size, &limits);
+                    STATE.metrics.add_bytes_in(body.get_consumed());
+                    connection::add_bytes_in(body.get_consumed());
+                    Some(received)
+                },
+                // Refused before the client sent the body; the connection is closed after the answer.
+                Err(err) => Some(Err(err)),
+            },
+            Dispatch::Respond(response) => {
+                // A redirect or a failed login for an upload leaves its body unread, so the connection cannot be reused.
+                if unread_body {
+                    keep_alive = false;
+                }
+                Some(response)
+            },
+        };
+        match response {
+            Some(Ok(mut response)) if response.is_event_stream() => {
+                decorate(&config, &request, &mut response, scheme);
+                let streamed = sse::stream(&mut response, buf_reader.get_mut(), keep_alive, Duration::from_secs(config.sse_keep_alive.max(1)));
+                keep_alive = finish_relay(Ok(streamed), buf_reader.get_mut(), peer, &request, started);
+            },
+            Some(Ok(mut response)) => {
+                decorate(&config, &request, &mut response, scheme);
+                if keep_alive {
+                    response.append_option(HttpResponseOptions::Connection, "keep-alive");
+                    response.append_option(HttpResponseOptions::KeepAlive, format!("timeout={}, max={}", config.keep_alive_timeout, config.keep_alive_max - served));
+                } else {
+                    response.append_option(HttpResponseOptions::Connection, "close");
+                }
+                if let Err(err) = send_response(buf_reader.get_mut(), &response) {
+                    debug!("Failed to send a response to {:?}: {}", peer, err);
+                    keep_alive = false;
+                }
+                let bytes = if response.has_body() { response.get_body_length() } else { 0 };
+                log_access(peer, Some(&request), response.get_status().as_u16(), bytes, started);
+            },
+            Some(Err(e)) => {
+                send_error(buf_reader.get_mut(), e, peer, Some(&request), started);
+                keep_alive = false;
+            },
+            None => {},
+        }
+        buf_reader.get_mut().flush().unwrap_or(());
+
+        if !keep_alive {
+            break;
+        }
+
+        // Unless the next request is already buffered, wait for it on the reactor rather than on this thread.
+        #[cfg(feature = "async")]
+        if buf_reader.buffer().is_empty() && !buf_reader.get_mut().has_pending_input() {
+            if let Some(reactor) = REACTOR.get() {
+                request_id::clear();
+                connection::leave();
+                reactor.park(buf_reader.into_inner().into_inner(), Duration::from_secs(config.keep_alive_timeout.max(1)), move |stream, readable| {
+                    if readable {
+                        serve_connection(stream, slot, peer, scheme, served);
+                    }
+                });
+                return;
+            }
+        }
+    }
+    request_id::clear();
+    connection::leave();
+    connection::close(buf_reader);
+    drop(slot);
+}
+
+// Logs a response that was streamed straight to the client, or answers with a 502 if nothing was sent yet. Returns
+// whether the connection can be kept open.
+fn finish_relay<W: Write>(relayed: Result<Relayed, ProxyError>, stream: &mut W, peer: Option<IpAddr>, request: &HttpRequest, started: Instant) -> bool {
+    match relayed {
+        Ok(relayed) => {
+            log_access(peer, Some(request), relayed.status, relayed.bytes, started);
+            relayed.keep_alive
+        },
+        Err(ProxyError::BadGateway) => {
+            send_error(stream, ConnectionError::BadGateway, peer, Some(request), started);
+            false
+        },
+        Err(ProxyError::Aborted) => false,
+    }
+}
+
+#[cfg(feature = "http2")]
+fn serve_http2<S: Connection>(stream: S, peer: Option<IpAddr>) {
+    let config = conf();
+    let limits = request_limits(&config);
+    let idle_timeout = Duration::from_secs(config.keep_alive_timeout.max(1));
+    let stream = Throttled::new(stream, config.bandwidth_limit, config.bandwidth_limit_total);
+    drop(config);
+
+    http2::serve(stream, limits, idle_timeout, |request| {
+        let started = Instant::now();
+        request_id::begin();
+        let mut request = match request {
+            Ok(request) => request,
+            Err(HttpError::BodyTooLarge) => return error_response(ConnectionError::PayloadTooLarge, peer, None, started),
+            Err(HttpError::HeadersTooLarge) => return error_response(ConnectionError::HeadersTooLarge, peer, None, started),
+            Err(_) => return error_response(ConnectionError::TCPReadFailed, peer, None, started),
+        };
+        request_id::adopt(&mut request);
+        // Frames are decoded by the HTTP/2 layer, so only the body is counted here.
+        STATE.metrics.add_bytes_in(request.get_body().len());
+        connection::add_bytes_in(request.get_body().len());
+
+        let config = conf();
+        let response = match dispatch(&config, &mut request, peer, "https") {
+            Dispatch::Relay(handler) => Ok(handler.handle(&request)),
+            // HTTP/2 bodies arrive whole, within max-body-size, so here the upload is only copied to disk.
+            Dispatch::Upload(route) => upload::receive(route, &request, &mut request.get_body(), config.max_upload_size, &request_limits(&config)),
+            Dispatch::Respond(response) => response,
+        };
+        match response {
+            Ok(mut response) => {
+                decorate(&config, &request, &mut response, "https");
+                let bytes = if response.has_body() { response.get_body_length() } else { 0 };
+                log_access(peer, Some(&request), response.get_status().as_u16(), bytes, started);
+                response
+            },
+            Err(e) => error_response(e, peer, Some(&request), started),
+        }
+    });
+    request_id::clear();
+    connection::leave();
+}
+
+enum Dispatch<'a> {
+    Respond(Result<HttpResponse, ConnectionError>),
+    // A proxy or a script, the same handlers that embedders can route to themselves.
+    Relay(Box<dyn Relay + 'a>),
+    Upload(UploadRoute<'a>),
+}
+
+// Decides how a parsed request is answered, independent of the protocol it arrived over.
+fn dispatch<'a>(config: &'a Config, request: &mut HttpRequest, peer: Option<IpAddr>, scheme: &str) -> Dispatch<'a> {
+    request.set_peer_ip(peer);
+    request.set_secure(scheme == "https");
+    request.set_client_ip(forwarded::client_ip(&config.trusted_proxies, peer, request));
+    connection::begin_request(request);
+    if let Err(err) = check_host(config, request) {
+        return Dispatch::Respond(Err(err));
+    }
+    if let Some(ip) = request.client_ip().filter(|_| config.rate_limit > 0.0) {
+        if let Err(wait) = RATE_LIMITER.check(ip, config.rate_limit, config.rate_limit_burst.max(1.0)) {
+            return Dispatch::Respond(Err(ConnectionError::TooManyRequests(wait.as_secs_f64().ceil() as u64)));
+        }
+    }
+
+    if request.get_target() == "*" {
+        return Dispatch::Respond(Ok(server_options(config)));
+    }
+
+    // Preflights never carry credentials, so they are answered before authentication.
+    let preflight = config.cors.is_preflight(request);
+    let path = normalize_path(request.get_path()).unwrap_or_default();
+    // Validation requests have to get past redirects and authentication meant for visitors.
+    #[cfg(feature = "acme")]
+    if let Some(response) = acme::answer_challenge(&path) {
+        return Dispatch::Respond(Ok(response));
+    }
+    if let Some((rule, rest)) = redirect::find(&config.redirects, &path).filter(|_| !preflight) {
+        return Dispatch::Respond(Ok(rule.respond(rest, request.get_query())));
+    }
+    if let Some(rule) = auth::find(&config.auth, &path).filter(|rule| !preflight && !rule.authorize(request)) {
+        return Dispatch::Respond(Err(ConnectionError::Unauthorized(rule.challenge())));
+    }
+    // Checked before anything can read or write a file, so hidden files stay hidden from scripts and uploads too.
+    // /.well-known/ itself is always reachable; what is inside it is not exempt.
+    if config.deny.is_denied(path.strip_prefix("/.well-known/").unwrap_or(&path)) {
+        return Dispatch::Respond(Err(if config.deny.status == 403 { ConnectionError::Forbidden } else { ConnectionError::SourceNotFound }));
+    }
+    if let Some(route) = upload::route(&config.uploads, request, &path) {
+        return Dispatch::Upload(route);
+    }
+    if let Some(route) = proxy::route(&config.proxies, &path).filter(|_| !preflight) {
+        return Dispatch::Relay(Box::new(route));
+    }
+    if !preflight && (!config.cgi.is_empty() || !config.cgi_extensions.is_empty()) {
+        if let Some(route) = cgi::route(config, config.site_for(request.host()).root_dir, &path) {
+            return Dispatch::Relay(Box::new(route));
+        }
+    }
+    if !preflight && !config.fastcgi.is_empty() {
+        if let Some(route) = fastcgi::route(config, config.site_for(request.host()).root_dir, &path) {
+            return Dispatch::Relay(Box::new(route));
+        }
+    }
+
+    if preflight {
+        return Dispatch::Respond(Ok(config.cors.preflight(request)));
+    }
+    Dispatch::Respond(match server::router().handle(request) {
+        Some(response) => Ok(response),
+        None => handle_connection(request),
+    })
+}
+
+// "OPTIONS *" asks about the server as a whole: every method some resource on it answers to. Whatever sits behind a
+// proxy or a script may answer to anything.
+fn server_options(config: &Config) -> HttpResponse {
+    let mut allowed = STATIC_FILE_METHODS.to_vec();
+    allowed.extend(server::router().methods());
+    if !config.uploads.is_empty() {
+        allowed.extend([HttpMethod::Put, HttpMethod::Post]);
+    }
+    if !config.proxies.is_empty() || !config.cgi.is_empty() || !config.cgi_extensions.is_empty() || !config.fastcgi.is_empty() {
+        allowed.extend(HttpMethod::ALL);
+    }
+    let names: Vec<&str> = HttpMethod::ALL.iter().filter(|method| allowed.contains(method)).map(|method| method.get_name()).collect();
+
+    let mut response = HttpResponse::new(HttpProtocols::OneOne);
+    response.set_status(HttpResponseStatusCode::NoContent);
+    response.append_option(HttpResponseOptions::Allow, names.join(", "));
+    response
+}
+
+fn send_response<S: Connection>(stream: &mut S, response: &HttpResponse) -> io::Result<usize> {
+    match response.get_file_body().filter(|_| response.has_body()) {
+        Some(body) => {
+            let header = response.get_framed_header();
+            stream.write_all(header.as_bytes())?;
+            stream.flush()?;
+            let sent = match stream.send_file(body) {
+                Some(sent) => sent?,
+                None => connection::copy_file(body, stream)?,
+            };
+            Ok(header.len() + sent as usize)
+        },
+        None => response.send(stream),
+    }
+}
+
+fn decorate(config: &Config, request: &HttpRequest, response: &mut HttpResponse, scheme: &str) {
+    response.set_protocol(request.get_protocol().response_version());
+    if let Some(id) = request_id::current() {
+        response.append_option(HttpResponseOptions::RequestId, id);
+    }
+    config.cors.apply(request, response);
+    standard_headers(config, response);
+
+    // In "dev-mode" nothing is cached, so every change to the site shows on the next request.
+    if config.dev_mode {
+        for option in [HttpResponseOptions::ETag, HttpResponseOptions::LastModified, HttpResponseOptions::Expires, HttpResponseOptions::CacheControl] {
+            response.remove_option(&option);
+        }
+        response.append_option(HttpResponseOptions::CacheControl, "no-store");
+    }
+
+    if config.security_headers {
+        for (name, value) in &config.security_header_values {
+            // Browsers ignore HSTS received over plain HTTP, so it is only sent on HTTPS responses.
+            if value.is_empty() || (name.eq_ignore_ascii_case("Strict-Transport-Security") && scheme != "https") {
+                continue;
+            }
+            let option = HttpResponseOptions::from_name(name);
+            if response.get_option(&option).is_none() {
+                response.append_option(option, value.as_str());
+            }
+        }
+    }
+}
+
+// The Server banner and the headers every response gets from the config, where the response does not set them itself.
+fn standard_headers(config: &Config, response: &mut HttpResponse) {
+    if !config.server_header.is_empty() && response.get_option(&HttpResponseOptions::Server).is_none() {
+        response.append_option(HttpResponseOptions::Server, config.server_header.as_str());
+    }
+    for (name, value) in &config.default_headers {
+        let option = HttpResponseOptions::from_name(name);
+        if response.get_option(&option).is_none() {
+            response.append_option(option, value.as_str());
+        }
+    }
+}
+
+fn send_error<W: Write>(stream: &mut W, error: ConnectionError, peer: Option<IpAddr>, request: Option<&HttpRequest>, started: Instant) {
+    let response = error_response(error, peer, request, started);
+    if let Err(err) = response.send(stream) {
+        debug!("Failed to send an error response to {:?}: {}", peer, err);
+    }
+}
+
+// The page for `error` from the site the request was for.
+fn error_for_site(error: ConnectionError, request: Option<&HttpRequest>) -> HttpResponse {
+    let config = conf();
+    let path = request.and_then(|request| normalize_path(request.get_path()).ok()).unwrap_or_default();
+    let site = config.mount_for(&path).unwrap_or_else(|| config.site_for(request.and_then(HttpRequest::host)));
+    if let InternalServerErr(cause) = &error {
+        warn!("Failed to answer {}: {}", request.map_or("a request", HttpRequest::get_path), cause);
+    }
+    error.into_response(site.root_dir, request)
+}
+
+fn error_response(error: ConnectionError, peer: Option<IpAddr>, request: Option<&HttpRequest>, started: Instant) -> HttpResponse {
+    let config = conf();
+    let mut response = error_for_site(error, request);
+    standard_headers(&config, &mut response);
+    if let Some(id) = request_id::current() {
+        response.append_option(HttpResponseOptions::RequestId, id);
+    }
+    if let Some(request) = request {
+        response.set_protocol(request.get_protocol().response_version());
+    }
+    response.set_body_suppressed(request.is_some_and(|request| request.get_method() == HttpMethod::Head));
+
+    let bytes = if response.has_body() { response.get_body_length() } else { 0 };
+    log_access(peer, request, response.get_status().as_u16(), bytes, started);
+    response
+}
+
+fn log_access(peer: Option<IpAddr>, request: Option<&HttpRequest>, status: u16, bytes: usize, started: Instant) {
+    STATE.metrics.record(status, bytes, started.elapsed());
+    if conf().dev_mode {
+        let target = request.map_or_else(|| "-".to_string(), |request| format!("{} {}", request.get_method().get_name(), request.get_path()));
+        println!("{target} {status} {bytes} bytes in {:.2?}", started.elapsed());
+    }
+    connection::finish_request(bytes);
+    if let Some(logger) = ACCESS_LOG.as_ref() {
+        logger.log(&AccessLogEntry {
+            time: SystemTime::now(),
+            client: request.and_then(HttpRequest::client_ip).or(peer),
+            request,
+            status,
+            bytes,
+            duration: started.elapsed(),
+            request_id: request_id::current(),
+        });
+    }
+}
+
+// The site's files, which answer whatever no route or backend takes. On a route of its own, it serves the request's
+// path all the same.
+pub struct StaticFiles;
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        handle_connection(request).unwrap_or_else(|err| error_for_site(err, Some(request)))
+    }
+}
+
+fn handle_connection(request: &HttpRequest) -> Result<HttpResponse, ConnectionError> {
+    let config = conf();
+    let mut response: HttpResponse = HttpResponse::new(HttpProtocols::OneOne);
+    response.set_body_suppressed(request.get_method() == HttpMethod::Head);
+
+    if !STATIC_FILE_METHODS.contains(&request.get_method()) {
+        response.set_status(HttpResponseStatusCode::MethodNotAllowed);
+        response.append_option(HttpResponseOptions::Allow, STATIC_FILE_METHODS.map(|m| m.get_name().to_string()).join(", "));
+        response.append_option(HttpResponseOptions::ContentLength, "0");
+        return Ok(response);
+    }
+
+    let mut path: String = normalize_path(request.get_path()).map_err(|err| match err {
+        HttpPathError::Malformed => ConnectionError::TCPReadFailed,
+        HttpPathError::Traversal => ConnectionError::Forbidden,
+    })?;
+    if request.get_method() == HttpMethod::Options {
+        let mut allowed = STATIC_FILE_METHODS.to_vec();
+        if upload::find(&config.uploads, &path).is_some() {
+            allowed.extend([HttpMethod::Put, HttpMethod::Post]);
+        }
+        response.set_status(HttpResponseStatusCode::NoContent);
+        response.append_option(HttpResponseOptions::Allow, allowed.iter().map(|method| method.get_name()).collect::<Vec<&str>>().join(", "));
+        return Ok(response);
+    }
+    let site = config.mount_for(&path).unwrap_or_else(|| config.site_for(request.host()));
+    if !site.prefix.is_empty() {
+        if path == site.prefix {
+            return Ok(canonical_redirect(request, &format!("{path}/")));
+        }
+        path = path[site.prefix.len()..].to_string();
+    }
+    // Client-side routes have no extension; a missing script or image should still be a 404.
+    let spa_route = !config.spa_fallback.is_empty() && Path::new(path.as_str()).extension().is_none();
+
+    let resolved = match path.strip_prefix("/.well-known/").filter(|_| site.prefix.is_empty()) {
+        // ACME challenge tokens and security.txt are served under their exact names, without any .html rewriting.
+        Some(name) if config.well_known_dir.as_os_str().is_empty() => resolve_file(&site.root_dir.join(".well-known"), name),
+        Some(name) => resolve_file(&config.well_known_dir, name),
+        None => {
+            if let Some(canonical) = canonical_html_path(&config.html_extension, &site, &path) {
+                return Ok(canonical_redirect(request, &format!("{}{canonical}", site.prefix)));
+            }
+
+            if path != "/" {
+                if let Some(dir) = resolve_file(site.root_dir, &path).ok().filter(|resolved| resolved.is_dir()) {
+                    // Relative links in the page would otherwise resolve against the parent directory.
+                    if !path.ends_with('/') {
+                        return Ok(canonical_redirect(request, &format!("{}{path}/", site.prefix)));
+                    }
+                    if dir.join("index.html").is_file() {
+                        path = format!("{}/index.html", path.trim_end_matches('/'));
+                    } else if config.autoindex {
+                        return directory_listing(&config, response, request, &format!("{}{path}", site.prefix), &dir);
+                    } else {
+                        return Err(ConnectionError::SourceNotFound);
+                    }
+                }
+            }
+
+            if Path::new(path.as_str()).extension().is_none() {
+                if path == "/" {
+                    path = "/".to_owned() + site.home_name;
+                }
+                path = negotiate_representation(&config, &site, request, &mut response, &path)?;
+            }
+            let resolved = resolve_file(site.root_dir, &path);
+            if spa_route && matches!(resolved, Err(ConnectionError::SourceNotFound)) {
+                path = config.spa_fallback.clone();
+                resolve_file(site.root_dir, &path)
+            } else {
+                resolved
+            }
+        },
+    };
+    let mut resolved = match resolved {
+        // Browsers ask every site for one, so a site without it gets the configured icon or an empty answer they
+        // remember for a day, rather than a 404 in the logs on every visit.
+        Err(ConnectionError::SourceNotFound) if path == "/favicon.ico" => {
+            if config.favicon.as_os_str().is_empty() {
+                response.set_status(HttpResponseStatusCode::NoContent);
+                response.append_option(HttpResponseOptions::CacheControl, "max-age=86400");
+                return Ok(response);
+            }
+            fs::canonicalize(&config.favicon).ok().ok_or(ConnectionError::SourceNotFound)?
+        },
+        resolved => resolved?,
+    };
+    let extension = Path::new(path.as_str()).extension().and_then(|ext| ext.to_str());
+
+    let mut metadata = fs::metadata(&resolved).ok().filter(|metadata| metadata.is_file()).ok_or(ConnectionError::SourceNotFound)?;
+    let mut encoding = None;
+    if config.precompressed {
+        let sidecars = precompressed_files(&resolved, &metadata);
+        if !sidecars.is_empty() {
+            response.add_vary("Accept-Encoding");
+        }
+        let available: Vec<&str> = sidecars.iter().map(|(name, _, _)| *name).collect();
+        let chosen = request.get_header("Accept-Encoding").and_then(|accept| compression::choose(accept, &available));
+        if let Some((name, sidecar, sidecar_metadata)) = sidecars.into_iter().find(|(name, _, _)| Some(*name) == chosen) {
+            (resolved, metadata, encoding) = (sidecar, sidecar_metadata, Some(name));
+        }
+    }
+    // The cache keeps a precompressed file under its own name and type, so it only stands in for the original here.
+    let served_extension = if encoding.is_some() { resolved.extension().and_then(|ext| ext.to_str()) } else { extension };
+    let cached = if config.file_cache_size > 0 { FILE_CACHE.get(&resolved, &metadata) } else { None };
+    let (etag, modified, content_type) = match &cached {
+        Some(file) => (file.etag.clone(), file.modified, file.content_type.clone()),
+        None => (make_etag(metadata.modified().ok(), metadata.len()), metadata.modified().ok(), config.mime.content_type(served_extension)),
+    };
+
+    response.append_option(HttpResponseOptions::ContentType, if encoding.is_some() { config.mime.content_type(extension) } else { content_type.clone() });
+    if let Some(encoding) = encoding {
+        response.append_option(HttpResponseOptions::ContentEncoding, encoding);
+    }
+    // Rules see the whole path, so they can tell mounted sites apart.
+    if let Some(rule) = cache_control::find(&config.cache_rules, &format!("{}{path}", site.prefix), extension, config.mime.lookup(extension)) {
+        response.append_option(HttpResponseOptions::CacheControl, rule.get_directives());
+    }
+    response.append_option(HttpResponseOptions::ETag, etag.as_str());
+    if let Some(modified) = modified {
+        response.append_option(HttpResponseOptions::LastModified, httpdate::fmt_http_date(modified));
+    }
+
+    if !config.dev_mode && is_not_modified(request, &etag, modified) {
+        response.set_status(HttpResponseStatusCode::NotModified);
+        response.remove_option(&HttpResponseOptions::ContentType);
+        return Ok(response);
+    }
+
+    if cached.is_none() && config.stream_threshold > 0 && metadata.len() > config.stream_threshold {
+        return stream_file(request, response, &resolved, metadata.len(), &etag, modified);
+    }
+
+    let file = match cached {
+        Some(file) => file,
+        None => cache_file(&config, &resolved, &metadata, served_extension, false).map_err(ConnectionError::internal(resolved.display()))?,
+    };
+    let mut content: Vec<u8> = file.content.clone();
+
+    response.append_option(HttpResponseOptions::AcceptRanges, "bytes");
+    let mut ranged = false;
+    match requested_ranges(request, &file.etag, modified, content.len()) {
+        Some(Ok(ranges)) => {
+            response.set_status(HttpResponseStatusCode::PartialContent);
+            content = match ranges[..] {
+                [range] => {
+                    response.append_option(HttpResponseOptions::ContentRange, range.content_range(content.len()));
+                    content[range.start..=range.end].to_vec()
+                },
+                _ => {
+                    let parts: Vec<(ByteRange, &[u8])> = ranges.iter().map(|range| (*range, &content[range.start..=range.end])).collect();
+                    multipart_ranges(&mut response, &parts, content.len())
+                },
+            };
+            ranged = true;
+        },
+        Some(Err(_)) => {
+            response.set_status(HttpResponseStatusCode::RangeNotSatisfiable);
+            response.append_option(HttpResponseOptions::ContentRange, format!("bytes */{}", content.len()));
+            content = Vec::new();
+            ranged = true;
+        },
+        None => {},
+    }
+
+    // Pages in "dev-mode" listen for reloads, so the compressed copies of the file no longer match what is sent.
+    let live_reload = config.dev_mode && !ranged && encoding.is_none() && content_type.starts_with("text/html");
+    #[cfg(feature = "watch")]
+    if live_reload {
+        content = watch::inject_script(content);
+    }
+    if config.compression && !ranged && encoding.is_none() {
+        content = compress_content(request, &mut response, content, if live_reload { &[] } else { &file.variants });
+    }
+
+    response.append_option(HttpResponseOptions::ContentLength, content.len().to_string());
+    response.append_payload(content);
+
+    Ok(response)
+}
+
+// Reads a file and keeps it in the file cache, if there is one, with what every response for it needs: its ETag, its
+// type and, with `compress` set, its compressed copies.
+fn cache_file(config: &Config, resolved: &Path, metadata: &fs::Metadata, extension: Option<&str>, compress: bool) -> io::Result<Arc<CachedFile>> {
+    let content = fs::read(resolved)?;
+    let content_type = config.mime.content_type(extension);
+    let variants = if compress && config.compression && compression::is_compressible(&content_type) && content.len() >= config.compression_min_size {
+        compression::Encoding::PREFERENCE.iter().filter_map(|encoding| Some((*encoding, encoding.compress(&content)?))).collect()
+    } else {
+        Vec::new()
+    };
+    let modified = metadata.modified().ok();
+    let file = CachedFile { content, modified, etag: make_etag(modified, metadata.len()), content_type, variants };
+    Ok(if config.file_cache_size > 0 { FILE_CACHE.insert(resolved, file, config.file_cache_size) } else { Arc::new(file) })
+}
+
+// With "html-extension = strip", existing pages are addressed without .html (and index pages by their directory);
+// with "add", always with it.
+// "style.css.br" and "style.css.gz" next to the file, as far as they are at least as new as it is. A stale copy
+// would otherwise keep serving an old build.
+fn precompressed_files(resolved: &Path, metadata: &fs::Metadata) -> Vec<(&'static str, PathBuf, fs::Metadata)> {
+    let modified = metadata.modified().ok();
+    [("br", "br"), ("gzip", "gz")].into_iter()
+        .filter_map(|(encoding, suffix)| {
+            let mut name = resolved.file_name()?.to_os_string();
+            name.push(format!(".{suffix}"));
+            let sidecar = resolved.with_file_name(name);
+            let sidecar_metadata = fs::metadata(&sidecar).ok().filter(|sidecar| sidecar.is_file())?;
+            if sidecar_metadata.modified().ok() < modified {
+                return None;
+            }
+            Some((encoding, sidecar, sidecar_metadata))
+        })
+        .collect()
+}
+
+// "/page" may exist as page.html, page.json, page.md and so on, and the Accept header picks one of them. A page that
+// only exists as HTML is served as it always was.
+fn negotiate_representation(config: &Config, site: &Site, request: &HttpRequest, response: &mut HttpResponse, path: &str) -> Result<String, ConnectionError> {
+    let (dir, stem) = path.rsplit_once('/').unwrap_or(("", path));
+    let names: Vec<String> = negotiation::representations(&site.root_dir.join(dir.trim_start_matches('/')), stem).into_iter()
+        .filter(|name| !config.deny.is_denied(&format!("{dir}/{name}")))
+        .collect();
+    if names.is_empty() || names == [format!("{stem}.html")] {
+        return Ok(format!("{path}.html"));
+    }
+
+    response.add_vary("Accept");
+    let types: Vec<&str> = names.iter().map(|name| config.mime.lookup(Path::new(name).extension().and_then(|ext| ext.to_str()))).collect();
+    let chosen = negotiation::choose(request.get_header("Accept").unwrap_or("*/*"), &types).ok_or(ConnectionError::NotAcceptable)?;
+    Ok(format!("{dir}/{}", names[chosen]))
+}
+
+fn canonical_html_path(mode: &str, site: &Site, path: &str) -> Option<String> {
+    let exists = |path: &str| resolve_file(site.root_dir, path).is_ok_and(|resolved| resolved.is_file());
+    match mode {
+        "strip" => {
+            let stem = path.strip_suffix(".html").filter(|_| exists(path))?;
+            Some(match stem.strip_suffix("index") {
+                Some(dir) if dir.ends_with('/') => dir.to_string(),
+                _ if stem == format!("/{}", site.home_name) => "/".to_string(),
+                _ => stem.to_string(),
+            })
+        },
+        "add" if path != "/" && !path.ends_with('/') && Path::new(path).extension().is_none() => {
+            let with_extension = format!("{path}.html");
+            exists(&with_extension).then_some(with_extension)
+        },
+        _ => None,
+    }
+}
+
+fn canonical_redirect(request: &HttpRequest, path: &str) -> HttpResponse {
+    let location = match request.get_query() {
+        Some(query) => format!("{}?{}", percent_encode(path), query),
+        None => percent_encode(path),
+    };
+    HttpResponse::redirect(HttpResponseStatusCode::MovedPermanently, location)
+}
+
+fn stream_file(request: &HttpRequest, mut response: HttpResponse, resolved: &Path, length: u64, etag: &str, modified: Option<SystemTime>) -> Result<HttpResponse, ConnectionError> {
+    let mut file = File::open(resolved).map_err(ConnectionError::internal(resolved.display()))?;
+
+    response.append_option(HttpResponseOptions::AcceptRanges, "bytes");
+    let (offset, body_length) = match requested_ranges(request, etag, modified, length as usize) {
+        Some(Ok(ranges)) if ranges.len() == 1 => {
+            response.set_status(HttpResponseStatusCode::PartialContent);
+            response.append_option(HttpResponseOptions::ContentRange, ranges[0].content_range(length as usize));
+            (ranges[0].start as u64, ranges[0].get_length() as u64)
+        },
+        // Several ranges are read into memory as far as they would have been for a small file; more than that is not
+        // worth holding, and the whole file is sent instead.
+        Some(Ok(ranges)) if ranges.iter().map(ByteRange::get_length).sum::<usize>() as u64 <= conf().stream_threshold => {
+            response.set_status(HttpResponseStatusCode::PartialContent);
+            let body = multipart_file_ranges(&mut response, &mut file, &ranges, length)?;
+            response.append_option(HttpResponseOptions::ContentLength, body.len().to_string());
+            response.append_payload(body);
+            return Ok(response);
+        },
+        Some(Err(_)) => {
+            response.set_status(HttpResponseStatusCode::RangeNotSatisfiable);
+            response.append_option(HttpResponseOptions::ContentRange, format!("bytes */{length}"));
+            response.append_option(HttpResponseOptions::ContentLength, "0");
+            return Ok(response);
+        },
+        _ => (0, length),
+    };
+
+    response.append_option(HttpResponseOptions::ContentLength, body_length.to_string());
+    response.set_file_body(file, offset, body_length);
+    Ok(response)
+}
+
+// With the mmap feature the parts are cut straight out of a mapping of the file; otherwise, or when the file cannot be
+// mapped, each is read into a buffer first.
+fn multipart_file_ranges(response: &mut HttpResponse, file: &mut File, ranges: &[ByteRange], length: u64) -> Result<Vec<u8>, ConnectionError> {
+    #[cfg(all(feature = "mmap", unix))]
+    if let Ok(mapping) = mmap::Mapping::new(file, 0, length) {
+        let data = mapping.as_slice();
+        let parts: Vec<(ByteRange, &[u8])> = ranges.iter().map(|range| (*range, &data[range.start..=range.end])).collect();
+        return Ok(multipart_ranges(response, &parts, length as usize));
+    }
+
+    let mut parts = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let mut data = vec![0; range.get_length()];
+        file.seek(SeekFrom::Start(range.start as u64)).map_err(ConnectionError::internal("Seeking to a range"))?;
+        file.read_exact(&mut data).map_err(ConnectionError::internal("Reading a range"))?;
+        parts.push((*range, data));
+    }
+    let parts: Vec<(ByteRange, &[u8])> = parts.iter().map(|(range, data)| (*range, data.as_slice())).collect();
+    Ok(multipart_ranges(response, &parts, length as usize))
+}
+
+fn directory_listing(config: &Config, mut response: HttpResponse, request: &HttpRequest, path: &str, dir: &Path) -> Result<HttpResponse, ConnectionError> {
+    let (content_type, listing) = if request.get_header("Accept").is_some_and(autoindex::wants_json) {
+        ("application/json", autoindex::render_json(dir, &config.deny))
+    } else {
+        ("text/html; charset=utf-8", autoindex::render_html(path, dir, &config.deny))
+    };
+    let listing = listing.map_err(ConnectionError::internal(dir.display()))?;
+
+    response.append_option(HttpResponseOptions::ContentType, content_type);
+    response.append_option(HttpResponseOptions::ContentLength, listing.len().to_string());
+    response.add_vary("Accept");
+    response.append_payload(listing.into_bytes());
+    Ok(response)
+}
+
+fn resolve_file(root_dir: &Path, path: &str) -> Result<PathBuf, ConnectionError> {
+    let root = fs::canonicalize(root_dir).map_err(ConnectionError::internal(root_dir.display()))?;
+    let resolved = fs::canonicalize(root_dir.join(path.trim_start_matches('/'))).ok().ok_or(ConnectionError::SourceNotFound)?;
+    if !resolved.starts_with(&root) {
+        return Err(ConnectionError::Forbidden);
+    }
+    Ok(resolved)
+}
+
+fn make_etag(modified: Option<SystemTime>, length: u64) -> String {
+    let mtime = modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs());
+    format!("\"{mtime:x}-{length:x}\"")
+}
+
+// The ranges to answer a Range request with, or None when the whole file is to be sent: there was no Range header, it
+// could not be understood, or If-Range names another version of the file than this one.
+fn requested_ranges(request: &HttpRequest, etag: &str, modified: Option<SystemTime>, length: usize) -> Option<Result<Vec<ByteRange>, HttpRangeError>> {
+    let range = request.get_header("Range")?;
+    if request.get_header("If-Range").is_some_and(|validator| !if_range_matches(validator, etag, modified)) {
+        return None;
+    }
+    match ByteRange::parse_all(range, length) {
+        Err(HttpRangeError::Invalid) => None,
+        ranges => Some(ranges),
+    }
+}
+
+// Only a strong match counts, since the parts have to fit the bytes the client already has: weak tags never match, and
+// a date only when it is exactly the file's.
+fn if_range_matches(validator: &str, etag: &str, modified: Option<SystemTime>) -> bool {
+    let validator = validator.trim();
+    if validator.starts_with('"') || validator.starts_with("W/") {
+        return validator == etag;
+    }
+    match (httpdate::parse_http_date(validator).ok(), modified) {
+        (Some(date), Some(modified)) => {
+            let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+            seconds(date) == seconds(modified)
+        },
+        _ => false,
+    }
+}
+
+// Turns the response into a multipart/byteranges one; the parts keep the type the file itself has.
+fn multipart_ranges(response: &mut HttpResponse, parts: &[(ByteRange, &[u8])], length: usize) -> Vec<u8> {
+    let boundary = request_id::generate();
+    let content_type = response.get_option(&HttpResponseOptions::ContentType).unwrap_or("application/octet-stream").to_string();
+    response.append_option(HttpResponseOptions::ContentType, format!("multipart/byteranges; boundary={boundary}"));
+    multipart_byteranges(parts, length, &content_type, &boundary)
+}
+
+fn is_not_modified(request: &HttpRequest, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = request.get_header("If-None-Match") {
+        return if_none_match.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+
+    match (request.get_header("If-Modified-Since").and_then(|date| httpdate::parse_http_date(date).ok()), modified) {
+        (Some(since), Some(modified)) => {
+            let modified = modified.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+            let since = since.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+            modified <= since
+        },
+        _ => false,
+    }
+}
+
+// `variants` are copies compressed ahead of time, used instead of compressing again where one fits.
+fn compress_content(request: &HttpRequest, response: &mut HttpResponse, content: Vec<u8>, variants: &[(compression::Encoding, Vec<u8>)]) -> Vec<u8> {
+    let config = conf();
+    let compressible = response.get_option(&HttpResponseOptions::ContentType).is_some_and(compression::is_compressible);
+    if !compressible {
+        return content;
+    }
+    response.add_vary("Accept-Encoding");
+
+    if content.len() < config.compression_min_size {
+        return content;
+    }
+
+    let encoding = match request.get_header("Accept-Encoding").and_then(compression::negotiate) {
+        Some(encoding) => encoding,
+        None => return content,
+    };
+
+    let compressed = match variants.iter().find(|(variant, _)| *variant == encoding) {
+        Some((_, compressed)) => Some(compressed.clone()),
+        None => encoding.compress(&content),
+    };
+    match compressed {
+        Some(compressed) => {
+            response.append_option(HttpResponseOptions::ContentEncoding, encoding.get_name());
+            if let Some(etag) = response.get_option(&HttpResponseOptions::ETag).filter(|etag| !etag.starts_with("W/")) {
+                response.append_option(HttpResponseOptions::ETag, format!("W/{etag}"));
+            }
+            compressed
+        },
+        None => content,
+    }
+}
+
+// The config file named on the command line, with the command line's values over it.
+pub fn parse_config() -> Result<Config, Vec<String>> {
+    Config::load(&ARGS.get_config_path(), |config| ARGS.apply(config))
+}
+
+fn conf() -> Arc<Config> {
+    Arc::clone(&CONF.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+fn apply_logging(config: &Config) {
+    logging::configure(config.log_level, (!config.error_log.is_empty()).then(|| Path::new(config.error_log.as_str()))).unwrap_or_else(|err| {
+        error!("Unable to open the error log \"{}\": {}", config.error_log, err);
+    });
+}
+
+fn reload_config() {
+    let new = match server::reload() {
+        Some(Ok(config)) => config,
+        None => {
+            warn!("The server was given its configuration directly, so there is nothing to reload it from.");
+            return;
+        },
+        Some(Err(errors)) => {
+            for error in errors {
+                error!("{}", error);
+            }
+            error!("Keeping the current configuration.");
+            return;
+        }
+    };
+    let old = conf();
+
+    let mut applied: Vec<&str> = Vec::new();
+    let mut restart: Vec<&str> = Vec::new();
+    macro_rules! compare {
+        ($list:ident, $($field:ident => $key:literal),+ $(,)?) => {
+            $(if old.$field != new.$field { $list.push($key); })+
+        };
+    }
+    compare!(applied,
+        home_name => "home-name",
+        root_dir => "root-dir",
+        hosts => "[host]",
+        threads => "num-threads",
+        allowed_hosts => "allowed-hosts",
+        trusted_proxies => "trusted-proxies",
+        proxy_protocol => "proxy-protocol",
+        error_pages => "error.*",
+        proxies => "proxy.*",
+        uploads => "upload.*",
+        mounts => "mount.*",
+        cgi => "cgi.*",
+        cgi_extensions => "cgi-extensions",
+        cgi_timeout => "cgi-timeout",
+        fastcgi => "fastcgi.*",
+        sse_keep_alive => "sse-keep-alive",
+        auth => "auth.*",
+        redirects => "redirect.*",
+        cache_rules => "cache.*",
+        html_extension => "html-extension",
+        deny => "deny*",
+        spa_fallback => "spa-fallback",
+        favicon => "favicon",
+        well_known_dir => "well-known-dir",
+        templates_dir => "templates-dir",
+        rate_limit => "rate-limit",
+        rate_limit_burst => "rate-limit-burst",
+        bandwidth_limit => "bandwidth-limit",
+        bandwidth_limit_total => "bandwidth-limit-total",
+        cors => "cors-*",
+        security_headers => "security-headers",
+        security_header_values => "security.*",
+        server_header => "server-header",
+        default_headers => "header.*",
+        keep_alive_timeout => "keep-alive-timeout",
+        read_timeout => "read-timeout",
+        write_timeout => "write-timeout",
+        header_timeout => "header-timeout",
+        max_connections_per_ip => "max-connections-per-ip",
+        queue_limit => "queue-limit",
+        queue_full_wait => "queue-full",
+        max_connections => "max-connections",
+        connections_full_wait => "connections-full",
+        keep_alive_max => "keep-alive-max",
+        max_body_size => "max-body-size",
+        max_upload_size => "max-upload-size",
+        max_request_line => "max-request-line",
+        max_header_size => "max-header-size",
+        max_header_bytes => "max-header-bytes",
+        max_headers => "max-headers",
+        compression => "compression",
+        compression_min_size => "compression-min-size",
+        precompressed => "precompressed",
+        file_cache_size => "file-cache-size",
+        preload => "preload",
+        stream_threshold => "stream-threshold",
+        dev_mode => "dev-mode",
+        mime_types => "mime.*",
+        charsets => "charset.*",
+        default_charset => "default-charset",
+        autoindex => "autoindex",
+        log_level => "log-level",
+        error_log => "error-log",
+        shutdown_timeout => "shutdown-timeout",
+    );
+    compare!(restart,
+        ip => "ip",
+        port => "port",
+        listen => "listen",
+        listen_tls => "listen-tls",
+        admin_listen => "admin-listen",
+        io_backend => "io-backend",
+        ssl => "ssl-cert",
+        ssl_key => "ssl-key",
+        ssl_port => "ssl-port",
+        ssl_only => "ssl-only",
+        acme_domains => "acme-domains",
+        acme_email => "acme-email",
+        acme_directory => "acme-directory",
+        acme_dir => "acme-dir",
+        acme_renew_days => "acme-renew-days",
+        access_log => "access-log",
+        access_log_format => "access-log-format",
+        access_log_max_size => "access-log-max-size",
+        access_log_rotation => "access-log-rotate",
+        interactive => "interactive",
+    );
+
+    // Settings that need a restart keep their running values so the live config stays truthful.
+    let merged = Config {
+        ip: old.ip.clone(),
+        port: old.port.clone(),
+        listen: old.listen.clone(),
+        listen_tls: old.listen_tls.clone(),
+        admin_listen: old.admin_listen.clone(),
+        io_backend: old.io_backend.clone(),
+        ssl: old.ssl.clone(),
+        ssl_key: old.ssl_key.clone(),
+        ssl_port: old.ssl_port.clone(),
+        ssl_only: old.ssl_only,
+        acme_domains: old.acme_domains.clone(),
+        acme_email: old.acme_email.clone(),
+        acme_directory: old.acme_directory.clone(),
+        acme_dir: old.acme_dir.clone(),
+        acme_renew_days: old.acme_renew_days,
+        access_log: old.access_log.clone(),
+        access_log_format: old.access_log_format.clone(),
+        access_log_max_size: old.access_log_max_size,
+        access_log_rotation: old.access_log_rotation,
+        interactive: old.interactive,
+        ..new
+    };
+    apply_logging(&merged);
+    // Also undoes a size set from the console.
+    if let Some(pool) = STATE.get_pool().filter(|pool| pool.get_size() != merged.threads) {
+        pool.resize(merged.threads);
+    }
+    // Templates are read again on every reload, so edits to them apply even when the config did not change.
+    load_templates(&merged);
+    // The same goes for certificates, which may have been renewed in place.
+    #[cfg(feature = "tls")]
+    tls::reload_certificates(&merged);
+    *CONF.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(merged);
+    // Cached entries carry content types and roots from the old config.
+    FILE_CACHE.clear();
+    preload::run(&conf());
+    // The roots may have moved.
+    #[cfg(feature = "watch")]
+    watch::start(&conf());
+
+    if applied.is_empty() && restart.is_empty() {
+        info!("Config reloaded; nothing changed.");
+    }
+    if !applied.is_empty() {
+        info!("Applied: {}", applied.join(", "));
+    }
+    if !restart.is_empty() {
+        warn!("Changed but require a restart to take effect: {}", restart.join(", "));
+    }
+}
+
+// Returns the process exit code.
+fn validate_config() -> i32 {
+    let path = ARGS.get_config_path();
+    match parse_config() {
+        Ok(config) => {
+            if !config.root_dir.is_dir() {
+                warn!("The root directory {} does not exist.", config.root_dir.display());
+            }
+            info!("{} is valid.", path.display());
+            0
+        },
+        Err(errors) => {
+            for error in &errors {
+                error!("{}", error);
+            }
+            error!("{} is not valid.", path.display());
+            1
+        },
+    }
+}
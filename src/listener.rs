@@ -0,0 +1,31 @@
+use std::io;
+use std::net::{TcpListener, ToSocketAddrs};
+use socket2::{Domain, Socket, Type};
+
+const BACKLOG: i32 = 1024;
+
+// Bare IPv6 addresses need brackets before a port can be appended.
+pub fn join_host_port(host: &str, port: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+// IPv6 sockets are made v6-only, so "[::]:80" and "0.0.0.0:80" can be listened on side by side.
+pub fn bind(address: &str) -> io::Result<TcpListener> {
+    let addr = address.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the address did not resolve"))?;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // Matches std's TcpListener, so a restarted server can bind while old connections sit in TIME_WAIT.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
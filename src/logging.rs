@@ -0,0 +1,84 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use crate::access_log::civil_time;
+use crate::request_id;
+
+static LOGGER: ServerLogger = ServerLogger { file: Mutex::new(None) };
+
+struct ServerLogger {
+    file: Mutex<Option<File>>,
+}
+
+impl Log for ServerLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let (year, month, day, hour, minute, second) = civil_time(SystemTime::now());
+        // Lines written while a request is being handled name it, to match the X-Request-Id header and access log.
+        let request = request_id::current().map_or_else(String::new, |id| format!("[{id}] "));
+        let line = format!(
+            "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z [{}] {}{}",
+            record.level(),
+            request,
+            record.args()
+        );
+
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("{line}"),
+            _ => println!("{line}"),
+        }
+
+        if let Some(file) = self.file.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            writeln!(file, "{line}").unwrap_or(());
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            file.flush().unwrap_or(());
+        }
+    }
+}
+
+pub fn init() {
+    log::set_logger(&LOGGER).unwrap_or(());
+    log::set_max_level(LevelFilter::Info);
+}
+
+pub fn configure(level: LevelFilter, error_log: Option<&Path>) -> io::Result<()> {
+    log::set_max_level(level);
+
+    let file = match error_log {
+        Some(path) => {
+            if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            Some(OpenOptions::new().create(true).append(true).open(path)?)
+        },
+        None => None,
+    };
+    *LOGGER.file.lock().unwrap_or_else(|e| e.into_inner()) = file;
+    Ok(())
+}
+
+pub fn parse_level(value: &str) -> Option<LevelFilter> {
+    match value.to_ascii_lowercase().as_str() {
+        "off" => Some(LevelFilter::Off),
+        "error" => Some(LevelFilter::Error),
+        "warn" | "warning" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
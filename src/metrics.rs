@@ -0,0 +1,162 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::STATE;
+
+// Upper bounds in seconds. Percentiles are interpolated within a bucket, so they are only as precise as these are.
+const BUCKETS: [f64; 14] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+pub const PERCENTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+pub struct Histogram {
+    // One more than there are bounds, for +Inf.
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(BUCKETS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn get_count(&self) -> u64 {
+        self.counts.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+    }
+
+    // `None` until something has been recorded. Values past the last bound are reported as that bound.
+    pub fn percentile(&self, quantile: f64) -> Option<Duration> {
+        let counts: Vec<u64> = self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = (quantile * total as f64).max(1.0);
+        let mut below = 0;
+        for (index, count) in counts.iter().enumerate() {
+            if (below + count) as f64 >= rank {
+                let Some(upper) = BUCKETS.get(index) else {
+                    break;
+                };
+                let lower = if index == 0 { 0.0 } else { BUCKETS[index - 1] };
+                let within = (rank - below as f64) / *count as f64;
+                return Some(Duration::from_secs_f64(lower + (upper - lower) * within));
+            }
+            below += count;
+        }
+        Some(Duration::from_secs_f64(BUCKETS[BUCKETS.len() - 1]))
+    }
+
+    fn write_prometheus(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram").unwrap_or(());
+        let mut cumulative = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            match BUCKETS.get(index) {
+                Some(bound) => writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}"),
+                None => writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {cumulative}"),
+            }.unwrap_or(());
+        }
+        writeln!(out, "{name}_sum {}", self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0).unwrap_or(());
+        writeln!(out, "{name}_count {cumulative}").unwrap_or(());
+
+        // Precomputed for dashboards that do not run histogram_quantile.
+        writeln!(out, "# HELP {name}_quantile Estimated from the buckets above.\n# TYPE {name}_quantile gauge").unwrap_or(());
+        for quantile in PERCENTILES {
+            let value = self.percentile(quantile).map_or(0.0, |duration| duration.as_secs_f64());
+            writeln!(out, "{name}_quantile{{quantile=\"{quantile}\"}} {value}").unwrap_or(());
+        }
+    }
+}
+
+pub struct Metrics {
+    // Indexed by status code - 100.
+    statuses: [AtomicU64; 500],
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    duration: Histogram,
+    queue_wait: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            statuses: std::array::from_fn(|_| AtomicU64::new(0)),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            duration: Histogram::new(),
+            queue_wait: Histogram::new(),
+        }
+    }
+
+    pub fn record(&self, status: u16, bytes: usize, duration: Duration) {
+        if let Some(count) = status.checked_sub(100).and_then(|index| self.statuses.get(index as usize)) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.duration.record(duration);
+    }
+
+    // Everything read off a connection, including requests that never got an answer.
+    pub fn add_bytes_in(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn get_requests(&self) -> u64 {
+        self.duration.get_count()
+    }
+
+    pub fn get_bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn get_bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    pub fn get_duration(&self) -> &Histogram {
+        &self.duration
+    }
+
+    pub fn get_queue_wait(&self) -> &Histogram {
+        &self.queue_wait
+    }
+
+    // Status codes that have been answered at least once, in order.
+    pub fn get_status_counts(&self) -> Vec<(u16, u64)> {
+        self.statuses.iter().enumerate()
+            .map(|(index, count)| (index as u16 + 100, count.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    pub fn write_prometheus(&self, out: &mut String) {
+        writeln!(out, "# HELP webserver_requests_total Requests answered.\n# TYPE webserver_requests_total counter\nwebserver_requests_total {}", self.get_requests()).unwrap_or(());
+        out.push_str("# HELP webserver_responses_total Responses sent, by status code.\n# TYPE webserver_responses_total counter\n");
+        for (status, count) in self.get_status_counts() {
+            writeln!(out, "webserver_responses_total{{status=\"{status}\"}} {count}").unwrap_or(());
+        }
+        writeln!(out, "# HELP webserver_received_bytes_total Bytes of requests read.\n# TYPE webserver_received_bytes_total counter\nwebserver_received_bytes_total {}", self.get_bytes_in()).unwrap_or(());
+        writeln!(out, "# HELP webserver_sent_bytes_total Bytes of response bodies sent.\n# TYPE webserver_sent_bytes_total counter\nwebserver_sent_bytes_total {}", self.get_bytes_out()).unwrap_or(());
+
+        self.duration.write_prometheus(out, "webserver_request_duration_seconds", "Time from reading a request to sending its response.");
+        self.queue_wait.write_prometheus(out, "webserver_queue_wait_seconds", "Time a connection waited for a free worker.");
+    }
+}
+
+// Wraps a job for the thread pool so the time it spends in the queue is recorded once a worker picks it up.
+pub fn queued(job: impl FnOnce() + Send + 'static) -> impl FnOnce() + Send + 'static {
+    let submitted = Instant::now();
+    move || {
+        STATE.metrics.queue_wait.record(submitted.elapsed());
+        job();
+    }
+}
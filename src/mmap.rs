@@ -0,0 +1,61 @@
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::ptr;
+use std::slice;
+
+// A read-only view of part of a file, backed by the page cache itself, so serving it copies nothing into buffers of
+// our own. A file that shrinks while it is mapped makes reading the missing pages fail with SIGBUS; deployments that
+// overwrite files in place instead of replacing them should leave the feature off.
+pub struct Mapping {
+    address: *mut libc::c_void,
+    length: usize,
+    // Mappings start on a page boundary, which may be before the first byte asked for.
+    skip: usize,
+}
+
+// The pages are only ever read, and unmapped once by the single owner.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    // Fails for empty ranges, ranges beyond the end of the file and files the system will not map, such as those on
+    // some network filesystems; callers read the file instead.
+    pub fn new(file: &File, offset: u64, length: u64) -> io::Result<Mapping> {
+        // SAFETY: sysconf has no preconditions.
+        let page = u64::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok().filter(|page| *page > 0).unwrap_or(4096);
+        let start = offset - offset % page;
+        let skip = (offset - start) as usize;
+        let mapped = usize::try_from(length).ok()
+            .filter(|length| *length > 0)
+            .and_then(|length| length.checked_add(skip))
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        let size = file.metadata()?.len();
+        if offset.checked_add(length).is_none_or(|end| end > size) {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        // SAFETY: a fresh shared read-only mapping of an open descriptor; the kernel validates the range.
+        let address = unsafe { libc::mmap(ptr::null_mut(), mapped, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), start as libc::off_t) };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // Only a hint for read-ahead, so a failure changes nothing.
+        // SAFETY: the range was just mapped.
+        unsafe { libc::madvise(address, mapped, libc::MADV_SEQUENTIAL) };
+        Ok(Mapping { address, length: mapped, skip })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: the mapping is `length` readable bytes and lives as long as `self`.
+        let mapped = unsafe { slice::from_raw_parts(self.address as *const u8, self.length) };
+        &mapped[self.skip..]
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: unmaps exactly what `new` mapped, once.
+        unsafe { libc::munmap(self.address, self.length) };
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs;
+use std::path::Path;
+
+// How much the client wants `media_type`, going by the most specific range in the Accept header that covers it.
+pub fn quality(accept: &str, media_type: &str) -> f32 {
+    let wanted = media_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    accept.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(|s| s.trim());
+            let range = parts.next()?.to_ascii_lowercase();
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(1.0, |q| q.parse::<f32>().unwrap_or(0.0));
+            let matches = range == wanted || range == "*/*" || range.strip_suffix("/*").is_some_and(|kind| wanted.starts_with(&format!("{kind}/")));
+            // The most specific range decides.
+            matches.then_some((range.matches('*').count(), quality))
+        })
+        .min_by(|a, b| a.0.cmp(&b.0))
+        .map_or(0.0, |(_, quality)| quality)
+}
+
+// The index of the media type the client wants most, or None when it accepts none of them. Earlier types win ties.
+pub fn choose(accept: &str, available: &[&str]) -> Option<usize> {
+    available.iter()
+        .map(|media_type| quality(accept, media_type))
+        .enumerate()
+        .filter(|(_, quality)| *quality > 0.0)
+        .fold(None, |best: Option<(usize, f32)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(candidate),
+        })
+        .map(|(index, _)| index)
+}
+
+// The names of the files "<stem>.<extension>" in `dir`, HTML first and the rest by name. Precompressed copies such as
+// "page.html.gz" are not representations of their own.
+pub fn representations(dir: &Path, stem: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok().filter(|_| entry.path().is_file()))
+        .filter(|name| {
+            let extension = name.strip_prefix(stem).and_then(|rest| rest.strip_prefix('.'));
+            extension.is_some_and(|extension| !extension.is_empty() && !extension.contains('.') && !matches!(extension, "gz" | "br"))
+        })
+        .collect();
+    names.sort_by_key(|name| (!name.ends_with(".html"), name.clone()));
+    names
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use log::{debug, info, warn};
+use crate::deny::glob_matches;
+use crate::{cache_file, precompressed_files, Config};
+
+// Reads the files that "preload" names into the file cache, compressed copies included, so the first requests after
+// a start or a reload are answered from memory. Files that would be streamed are left on disk.
+pub fn run(config: &Config) {
+    if config.preload.is_empty() || config.file_cache_size == 0 {
+        return;
+    }
+    let started = Instant::now();
+    let (mut files, mut bytes) = (0, 0);
+    for (prefix, root) in roots(config) {
+        let mut found = Vec::new();
+        collect(config, &root, &prefix, "", &mut found);
+        for resolved in found {
+            let (loaded, size) = load(config, &resolved);
+            files += loaded;
+            bytes += size;
+        }
+    }
+    info!("Preloaded {} files ({} bytes) into the file cache in {:.2?}.", files, bytes, started.elapsed());
+}
+
+// The site roots with the URL prefix they are mounted at, canonical like the paths requests look files up by.
+pub fn roots(config: &Config) -> Vec<(String, PathBuf)> {
+    let roots: BTreeSet<(&str, &PathBuf)> = std::iter::once(&config.root_dir)
+        .chain(config.hosts.iter().map(|vhost| &vhost.root_dir))
+        .map(|root| ("", root))
+        .chain(config.mounts.iter().map(|(prefix, root)| (prefix.as_str(), root)))
+        .collect();
+    roots.into_iter().filter_map(|(prefix, root)| Some((prefix.to_string(), fs::canonicalize(root).ok()?))).collect()
+}
+
+// Returns how many files were cached, counting precompressed copies, and their size.
+pub fn load(config: &Config, resolved: &Path) -> (usize, usize) {
+    let Ok(metadata) = fs::metadata(resolved) else {
+        return (0, 0);
+    };
+    if config.stream_threshold > 0 && metadata.len() > config.stream_threshold {
+        debug!("Not preloading {}, which is streamed from disk.", resolved.display());
+        return (0, 0);
+    }
+    // Precompressed copies next to the file are what clients that accept them are served.
+    let sidecars = if config.precompressed { precompressed_files(resolved, &metadata) } else { Vec::new() };
+    let extension = resolved.extension().and_then(|ext| ext.to_str());
+    let loaded = std::iter::once(cache_file(config, resolved, &metadata, extension, true))
+        .chain(sidecars.iter().map(|(_, sidecar, sidecar_metadata)| {
+            cache_file(config, sidecar, sidecar_metadata, sidecar.extension().and_then(|ext| ext.to_str()), false)
+        }));
+    let (mut files, mut bytes) = (0, 0);
+    for file in loaded {
+        match file {
+            Ok(file) => {
+                files += 1;
+                bytes += file.content.len();
+            },
+            Err(err) => warn!("Unable to preload {}: {}", resolved.display(), err),
+        }
+    }
+    (files, bytes)
+}
+
+// The files below `dir` (a path like "/assets" under the canonical `root`, mounted at `prefix`) that a pattern
+// matches, by their canonical path as requests look them up. Directories no pattern can reach and hidden names are
+// skipped.
+fn collect(config: &Config, root: &Path, prefix: &str, dir: &str, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(root.join(dir.trim_start_matches('/'))) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if config.deny.hides(&name) {
+            continue;
+        }
+        let path = format!("{dir}/{name}");
+        let url = format!("{prefix}{path}");
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() && config.preload.iter().any(|pattern| could_contain(pattern, &url)) => collect(config, root, prefix, &path, found),
+            Ok(_) if entry.path().is_file() && config.preload.iter().any(|pattern| glob_matches(pattern, &url)) => {
+                if let Some(resolved) = fs::canonicalize(entry.path()).ok().filter(|resolved| resolved.starts_with(root)) {
+                    found.push(resolved);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+// Whether anything below the directory `dir` can match `pattern`: the part of the pattern before its first wildcard
+// has to lead into the directory, or end inside it.
+fn could_contain(pattern: &str, dir: &str) -> bool {
+    let literal = pattern[..pattern.find(['*', '?']).unwrap_or(pattern.len())].to_ascii_lowercase();
+    let dir = format!("{}/", dir.to_ascii_lowercase());
+    literal.starts_with(&dir) || dir.starts_with(&literal)
+}
@@ -0,0 +1,497 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use http_resources::{percent_encode, Handler, HttpMethod, HttpProtocols, HttpRequest, HttpResponse, HttpResponseOptions, HttpResponseStatusCode};
+use log::warn;
+use crate::{error_for_site, ConnectionError};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+const HOP_BY_HOP: [&str; 8] = ["connection", "keep-alive", "proxy-connection", "proxy-authorization", "te", "trailer", "transfer-encoding", "upgrade"];
+
+#[derive(PartialEq)]
+pub struct Upstream {
+    host: String,
+    port: u16,
+    base: String,
+}
+
+impl Upstream {
+    pub fn parse(url: &str) -> Option<Upstream> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, base) = match rest.find('/') {
+            Some(index) => (&rest[..index], rest[index..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, port) = bracketed.split_once(']')?;
+                (host, port.strip_prefix(':').map_or(Some(80), |port| port.parse().ok())?)
+            },
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, port.parse().ok()?),
+                None => (authority, 80),
+            },
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Upstream { host: host.to_string(), port, base: base.to_string() })
+    }
+
+    pub fn get_url(&self) -> String {
+        format!("http://{}{}", self.authority(), self.base)
+    }
+
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == 80 { host } else { format!("{host}:{}", self.port) }
+    }
+}
+
+// Forwards requests to an upstream server like "proxy.*" does, for routes of their own. The request's whole path is
+// appended to the upstream's, and the response is buffered before it is answered with.
+pub struct Proxy {
+    upstream: Upstream,
+}
+
+impl Proxy {
+    // `url` is written like a "proxy.*" value: http://host:port, optionally followed by a path.
+    pub fn new(url: &str) -> Option<Proxy> {
+        Upstream::parse(url).map(|upstream| Proxy { upstream })
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        ProxyRoute { upstream: &self.upstream, rest: request.get_path().to_string() }.handle(request)
+    }
+}
+
+// A handler whose response comes from an upstream or a script. HTTP/1.1 connections have it relayed to the client as
+// it arrives; `handle` collects it first, for everything else.
+pub trait Relay: Handler {
+    fn relay(&self, request: &HttpRequest, client: &mut dyn Write, keep_alive: bool, decorate: &mut dyn FnMut(&mut HttpResponse)) -> Result<Relayed, ProxyError>;
+}
+
+pub enum ProxyError {
+    BadGateway,
+    Aborted,
+}
+
+pub struct Relayed {
+    pub status: u16,
+    pub bytes: usize,
+    pub keep_alive: bool,
+}
+
+enum UpstreamBody {
+    None,
+    Chunked,
+    Length(u64),
+    UntilClose,
+}
+
+pub struct ProxyRoute<'a> {
+    upstream: &'a Upstream,
+    rest: String,
+}
+
+impl Handler for ProxyRoute<'_> {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        fetch(self, request).unwrap_or_else(|_| error_for_site(ConnectionError::BadGateway, Some(request)))
+    }
+}
+
+impl Relay for ProxyRoute<'_> {
+    fn relay(&self, request: &HttpRequest, client: &mut dyn Write, keep_alive: bool, decorate: &mut dyn FnMut(&mut HttpResponse)) -> Result<Relayed, ProxyError> {
+        forward(self, request, client, keep_alive, decorate)
+    }
+}
+
+pub fn route<'a>(proxies: &'a [(String, Upstream)], path: &str) -> Option<ProxyRoute<'a>> {
+    proxies.iter()
+        .filter_map(|(prefix, upstream)| {
+            let prefix = prefix.trim_end_matches('/');
+            let rest = path.strip_prefix(prefix).filter(|rest| rest.is_empty() || rest.starts_with('/'))?;
+            Some((prefix.len(), ProxyRoute { upstream, rest: rest.to_string() }))
+        })
+        .max_by_key(|(length, _)| *length)
+        .map(|(_, route)| route)
+}
+
+pub fn forward<W: Write + ?Sized>(route: &ProxyRoute, request: &HttpRequest, client: &mut W, keep_alive: bool, decorate: impl FnOnce(&mut HttpResponse)) -> Result<Relayed, ProxyError> {
+    let mut upstream = open(route, request)?;
+    // Clients older than HTTP/1.1 get chunked bodies decoded, delimited by closing the connection.
+    let framed = request.get_protocol().response_version().supports_chunked();
+    let (mut response, body) = read_head(&mut upstream, request, framed)?;
+    let keep_alive = keep_alive && match body {
+        UpstreamBody::None | UpstreamBody::Length(_) => true,
+        UpstreamBody::Chunked => framed,
+        UpstreamBody::UntilClose => false,
+    };
+
+    response.append_option(HttpResponseOptions::Connection, if keep_alive { "keep-alive" } else { "close" });
+    decorate(&mut response);
+    client.write_all(response.get_header().as_bytes()).map_err(|_| ProxyError::Aborted)?;
+    let bytes = copy_body(&mut upstream, body, client, framed)?;
+
+    Ok(Relayed { status: response.get_status().as_u16(), bytes, keep_alive })
+}
+
+// Buffers the whole upstream response, for connections that cannot pass HTTP/1.1 framing through.
+pub fn fetch(route: &ProxyRoute, request: &HttpRequest) -> Result<HttpResponse, ProxyError> {
+    let mut upstream = open(route, request)?;
+    let (mut response, body) = read_head(&mut upstream, request, false)?;
+    if !matches!(body, UpstreamBody::None) {
+        let mut payload = Vec::new();
+        copy_body(&mut upstream, body, &mut payload, false).map_err(|_| ProxyError::BadGateway)?;
+        response.append_option(HttpResponseOptions::ContentLength, payload.len().to_string());
+        response.append_payload(payload);
+    }
+    Ok(response)
+}
+
+// The client's address is added to X-Forwarded-For, and X-Forwarded-Proto tells the scheme it used.
+fn open(route: &ProxyRoute, request: &HttpRequest) -> Result<BufReader<TcpStream>, ProxyError> {
+    let upstream = route.upstream;
+    let mut stream = connect(upstream).map_err(|err| {
+        warn!("Unable to reach upstream {}: {}", upstream.authority(), err);
+        ProxyError::BadGateway
+    })?;
+
+    let mut target = format!("{}{}", upstream.base, percent_encode(&route.rest));
+    if !target.starts_with('/') {
+        target.insert(0, '/');
+    }
+    if let Some(query) = request.get_query() {
+        target.push('?');
+        target.push_str(query);
+    }
+
+    let mut head = format!("{} {target} HTTP/1.1\r\nHost: {}\r\n", request.get_method().get_name(), upstream.authority());
+    let mut forwarded_for = None;
+    // Headers named in Connection are meant for this hop only, like the standard ones.
+    let connection = request.headers().get_list("Connection");
+    for (name, value) in request.get_headers() {
+        let lower = name.to_ascii_lowercase();
+        match lower.as_str() {
+            "host" | "content-length" => {},
+            "x-forwarded-for" => forwarded_for = Some(value.clone()),
+            "x-forwarded-proto" | "x-forwarded-host" => {},
+            _ if HOP_BY_HOP.contains(&lower.as_str()) || connection.iter().any(|listed| listed.eq_ignore_ascii_case(name)) => {},
+            _ => head.push_str(&format!("{name}: {value}\r\n")),
+        }
+    }
+    if let Some(peer) = request.peer_ip() {
+        let forwarded_for = forwarded_for.map_or_else(|| peer.to_string(), |previous| format!("{previous}, {peer}"));
+        head.push_str(&format!("X-Forwarded-For: {forwarded_for}\r\n"));
+    }
+    head.push_str(&format!("X-Forwarded-Proto: {}\r\n", request.scheme()));
+    if let Some(host) = request.get_header("Host") {
+        head.push_str(&format!("X-Forwarded-Host: {host}\r\n"));
+    }
+    if !request.get_body().is_empty() || request.headers().contains("Content-Length") {
+        head.push_str(&format!("Content-Length: {}\r\n", request.get_body().len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    stream.write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(request.get_body()))
+        .and_then(|_| stream.flush())
+        .map_err(|err| {
+            warn!("Unable to send the request to upstream {}: {}", upstream.authority(), err);
+            ProxyError::BadGateway
+        })?;
+
+    Ok(BufReader::new(stream))
+}
+
+fn connect(upstream: &Upstream) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses resolved");
+    for addr in (upstream.host.as_str(), upstream.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+                stream.set_write_timeout(Some(UPSTREAM_TIMEOUT))?;
+                return Ok(stream);
+            },
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+fn read_head<R: BufRead>(upstream: &mut R, request: &HttpRequest, framed: bool) -> Result<(HttpResponse, UpstreamBody), ProxyError> {
+    let status_line = read_line(upstream).ok_or(ProxyError::BadGateway)?;
+    let mut parts = status_line.splitn(2, ' ');
+    let version = parts.next().unwrap_or_default();
+    let status_text = parts.next().unwrap_or_default();
+    let status: u16 = status_text.get(..3).and_then(|code| code.parse().ok()).ok_or(ProxyError::BadGateway)?;
+    if !version.starts_with("HTTP/") {
+        return Err(ProxyError::BadGateway);
+    }
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    loop {
+        let line = read_line(upstream).ok_or(ProxyError::BadGateway)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let header = |name: &str| headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str());
+    let chunked = header("Transfer-Encoding").is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    let length = header("Content-Length").and_then(|value| value.parse::<u64>().ok());
+    let body = if request.get_method() == HttpMethod::Head || status < 200 || status == 204 || status == 304 {
+        UpstreamBody::None
+    } else if chunked {
+        UpstreamBody::Chunked
+    } else if let Some(length) = length {
+        UpstreamBody::Length(length)
+    } else {
+        UpstreamBody::UntilClose
+    };
+
+    let connection: Vec<&str> = headers.iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|(_, value)| value.split(',').map(str::trim))
+        .collect();
+    // Transfer-Encoding overrides Content-Length, and a length that does not parse frames nothing, so neither is
+    // passed on next to the framing the body is actually sent with.
+    let keep_length = length.is_some() && !chunked;
+    let reason = status_text.get(3..).unwrap_or_default().trim();
+    let mut response = HttpResponse::new(HttpProtocols::OneOne);
+    response.set_protocol(request.get_protocol().response_version());
+    response.set_status(HttpResponseStatusCode::Custom(status, reason.to_string()));
+    for (name, value) in &headers {
+        let lower = name.to_ascii_lowercase();
+        if lower == "transfer-encoding" && matches!(body, UpstreamBody::Chunked) && framed {
+            response.append_option(HttpResponseOptions::TransferEncoding, "chunked");
+        } else if lower == "content-length" && !keep_length {
+            continue;
+        } else if !HOP_BY_HOP.contains(&lower.as_str()) && !connection.iter().any(|listed| listed.eq_ignore_ascii_case(name)) {
+            let option = HttpResponseOptions::from_name(name);
+            match response.get_option(&option).filter(|_| !option.is_repeatable()) {
+                Some(existing) => {
+                    let merged = format!("{existing}, {value}");
+                    response.append_option(option, merged);
+                },
+                None => response.add_option(option, value.as_str()),
+            }
+        }
+    }
+    Ok((response, body))
+}
+
+fn copy_body<R: BufRead, W: Write + ?Sized>(upstream: &mut R, body: UpstreamBody, client: &mut W, framed: bool) -> Result<usize, ProxyError> {
+    match body {
+        UpstreamBody::None => Ok(0),
+        UpstreamBody::Chunked => relay_chunked(upstream, client, framed),
+        UpstreamBody::Length(length) => {
+            let copied = io::copy(&mut upstream.by_ref().take(length), client).map_err(|_| ProxyError::Aborted)?;
+            if copied != length {
+                return Err(ProxyError::Aborted);
+            }
+            Ok(copied as usize)
+        },
+        UpstreamBody::UntilClose => io::copy(upstream, client).map(|copied| copied as usize).map_err(|_| ProxyError::Aborted),
+    }
+}
+
+// With `framed` unset the chunk data is passed on without its framing.
+fn relay_chunked<R: BufRead, W: Write + ?Sized>(upstream: &mut R, client: &mut W, framed: bool) -> Result<usize, ProxyError> {
+    let mut total = 0;
+    loop {
+        let size_line = read_line(upstream).ok_or(ProxyError::Aborted)?;
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap_or_default().trim(), 16).map_err(|_| ProxyError::Aborted)?;
+        if framed {
+            client.write_all(format!("{size_line}\r\n").as_bytes()).map_err(|_| ProxyError::Aborted)?;
+        }
+
+        if size == 0 {
+            loop {
+                let trailer = read_line(upstream).ok_or(ProxyError::Aborted)?;
+                if framed {
+                    client.write_all(format!("{trailer}\r\n").as_bytes()).map_err(|_| ProxyError::Aborted)?;
+                }
+                if trailer.is_empty() {
+                    return Ok(total);
+                }
+            }
+        }
+
+        let copied = io::copy(&mut upstream.by_ref().take(size as u64), client).map_err(|_| ProxyError::Aborted)?;
+        if copied != size as u64 || read_line(upstream).is_none_or(|line| !line.is_empty()) {
+            return Err(ProxyError::Aborted);
+        }
+        if framed {
+            client.write_all(b"\r\n").map_err(|_| ProxyError::Aborted)?;
+        }
+        total += size;
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Option<String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, TcpListener};
+    use std::thread;
+    use super::*;
+
+    // Answers a single request with `response`, and hands back the request as it arrived.
+    fn serve_once(response: &'static str) -> (Upstream, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = Upstream::parse(&format!("http://{}/app", listener.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut received = String::new();
+            let mut length = 0;
+            while let Some(line) = read_line(&mut reader).filter(|line| !line.is_empty()) {
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    length = value.parse().unwrap();
+                }
+                received.push_str(&line);
+                received.push_str("\r\n");
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            received.push_str("\r\n");
+            received.push_str(&String::from_utf8(body).unwrap());
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            received
+        });
+        (upstream, server)
+    }
+
+    fn request(head: &str, body: &str) -> HttpRequest {
+        HttpRequest::parse(&mut format!("{head}\r\n\r\n{body}").as_bytes()).unwrap()
+    }
+
+    fn header_names(head: &str) -> Vec<String> {
+        head.lines().skip(1).filter_map(|line| line.split_once(':')).map(|(name, _)| name.to_ascii_lowercase()).collect()
+    }
+
+    #[test]
+    fn requests_are_rewritten_for_the_upstream() {
+        let (upstream, server) = serve_once("HTTP/1.1 204 No Content\r\n\r\n");
+        let mut request = request(concat!(
+            "POST /api/a%20b?x=1 HTTP/1.1\r\nHost: site.example\r\nContent-Length: 4\r\nConnection: keep-alive, X-Secret\r\n",
+            "Keep-Alive: timeout=5\r\nTE: trailers\r\nUpgrade: websocket\r\nProxy-Authorization: Basic YTpi\r\nX-Secret: hidden\r\n",
+            "X-Forwarded-For: 203.0.113.7\r\nX-Forwarded-Proto: http\r\nX-Forwarded-Host: spoofed.example\r\nX-Custom: kept",
+        ), "ping");
+        request.set_peer_ip(Some(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 2))));
+        request.set_secure(true);
+
+        let route = ProxyRoute { upstream: &upstream, rest: "/a b".to_string() };
+        let response = route.handle(&request);
+        let received = server.join().unwrap();
+
+        assert_eq!(response.get_status().as_u16(), 204);
+        assert!(received.starts_with(&format!("POST /app/a%20b?x=1 HTTP/1.1\r\nHost: {}\r\n", upstream.authority())), "{received}");
+        assert!(received.ends_with("\r\n\r\nping"), "{received}");
+        let names = header_names(&received);
+        for hop_by_hop in ["keep-alive", "te", "upgrade", "proxy-authorization", "x-secret"] {
+            assert!(!names.iter().any(|name| name == hop_by_hop), "{hop_by_hop} was forwarded: {received}");
+        }
+        for (name, value) in [("X-Forwarded-For", "203.0.113.7, 198.51.100.2"), ("X-Forwarded-Proto", "https"), ("X-Forwarded-Host", "site.example"), ("Connection", "close"), ("X-Custom", "kept"), ("Content-Length", "4")] {
+            assert_eq!(names.iter().filter(|other| **other == name.to_ascii_lowercase()).count(), 1, "{name}: {received}");
+            assert!(received.contains(&format!("\r\n{name}: {value}\r\n")), "{name}: {received}");
+        }
+    }
+
+    #[test]
+    fn forwarded_for_is_dropped_without_a_peer_to_append() {
+        let (upstream, server) = serve_once("HTTP/1.1 204 No Content\r\n\r\n");
+        let request = request("GET /api HTTP/1.1\r\nHost: site.example\r\nX-Forwarded-For: 203.0.113.7", "");
+        ProxyRoute { upstream: &upstream, rest: String::new() }.handle(&request);
+        let received = server.join().unwrap();
+        assert!(received.starts_with("GET /app HTTP/1.1\r\n"), "{received}");
+        assert!(!received.contains("X-Forwarded-For"), "{received}");
+        assert!(received.contains("\r\nX-Forwarded-Proto: http\r\n"), "{received}");
+    }
+
+    #[test]
+    fn responses_are_relayed_without_their_hop_by_hop_headers() {
+        const RESPONSE: &str = concat!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close, X-Internal\r\nX-Internal: 1\r\nKeep-Alive: timeout=5\r\nProxy-Connection: close\r\n",
+            "Upgrade: h2c\r\nTransfer-Encoding: chunked\r\nX-Upstream: yes\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        );
+        let (upstream, server) = serve_once(RESPONSE);
+        let route = ProxyRoute { upstream: &upstream, rest: String::new() };
+        let mut relayed = Vec::new();
+        let Ok(result) = forward(&route, &request("GET / HTTP/1.1\r\nHost: site.example", ""), &mut relayed, true, |response| response.add_option(HttpResponseOptions::Custom("X-Decorated".to_string()), "yes")) else {
+            panic!("The response was not relayed");
+        };
+        server.join().unwrap();
+        let relayed = String::from_utf8(relayed).unwrap();
+        let (head, body) = relayed.split_once("\r\n\r\n").unwrap();
+
+        assert_eq!((result.status, result.bytes, result.keep_alive), (200, 11, true));
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        let mut names = header_names(head);
+        names.sort();
+        assert_eq!(names, ["connection", "content-type", "date", "transfer-encoding", "x-decorated", "x-upstream"], "{head}");
+        assert!(head.contains("\r\nConnection: keep-alive") && head.contains("\r\nTransfer-Encoding: chunked"), "{head}");
+        assert_eq!(body, "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+
+        // Buffered, the chunked body is decoded and given a length instead.
+        let (upstream, server) = serve_once(RESPONSE);
+        let Ok(fetched) = fetch(&ProxyRoute { upstream: &upstream, rest: String::new() }, &request("GET / HTTP/1.1\r\nHost: site.example", "")) else {
+            panic!("The response was not fetched");
+        };
+        server.join().unwrap();
+        assert_eq!(fetched.get_payload(), b"hello world");
+        assert_eq!(fetched.get_option(&HttpResponseOptions::ContentLength), Some("11"));
+        assert_eq!(fetched.get_option(&HttpResponseOptions::TransferEncoding), None);
+        assert_eq!(fetched.get_option(&HttpResponseOptions::Connection), None);
+        assert_eq!(fetched.get_option(&HttpResponseOptions::Custom("X-Upstream".to_string())), Some("yes"));
+
+        // Transfer-Encoding overrides a Content-Length sent with it, which must not reach the client next to it.
+        const BOTH: &str = "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        for (version, expected) in [("HTTP/1.1", "\r\n\r\n5\r\nhello\r\n0\r\n\r\n"), ("HTTP/1.0", "\r\n\r\nhello")] {
+            let (upstream, server) = serve_once(BOTH);
+            let route = ProxyRoute { upstream: &upstream, rest: String::new() };
+            let mut relayed = Vec::new();
+            let Ok(result) = forward(&route, &request(&format!("GET / {version}\r\nHost: site.example"), ""), &mut relayed, true, |_| {}) else {
+                panic!("The response was not relayed");
+            };
+            server.join().unwrap();
+            let relayed = String::from_utf8(relayed).unwrap();
+            assert!(!relayed.to_ascii_lowercase().contains("content-length"), "{relayed}");
+            assert!(relayed.ends_with(expected), "{relayed}");
+            assert_eq!((result.bytes, result.keep_alive), (5, version == "HTTP/1.1"));
+        }
+
+        // A length that does not parse leaves the body delimited by the upstream closing, so it is dropped too.
+        let (upstream, server) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 3x\r\n\r\nhello");
+        let mut relayed = Vec::new();
+        let Ok(result) = forward(&ProxyRoute { upstream: &upstream, rest: String::new() }, &request("GET / HTTP/1.1\r\nHost: site.example", ""), &mut relayed, true, |_| {}) else {
+            panic!("The response was not relayed");
+        };
+        server.join().unwrap();
+        let relayed = String::from_utf8(relayed).unwrap();
+        assert!(!relayed.to_ascii_lowercase().contains("content-length") && relayed.ends_with("\r\n\r\nhello"), "{relayed}");
+        assert!(!result.keep_alive);
+    }
+
+    #[test]
+    fn the_longest_prefix_picks_the_upstream() {
+        let proxies = [("/api".to_string(), Upstream::parse("http://one").unwrap()), ("/api/v2/".to_string(), Upstream::parse("http://two:8080/base/").unwrap())];
+        let route = route(&proxies, "/api/v2/users").unwrap();
+        assert_eq!((route.upstream.get_url().as_str(), route.rest.as_str()), ("http://two:8080/base", "/users"));
+        assert_eq!(super::route(&proxies, "/api").map(|route| route.upstream.get_url()), Some("http://one".to_string()));
+        assert!(super::route(&proxies, "/apiary").is_none());
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// The longest header version 1 allows, line ending included.
+const MAX_V1_LENGTH: usize = 107;
+
+// Reads the PROXY protocol header (version 1 or 2) a load balancer such as HAProxy sends before the connection's own
+// bytes, and returns the client's address. Health checks and other connections the balancer makes for itself
+// ("UNKNOWN" or LOCAL) name no client. Nothing past the header is read, so it can come before a TLS handshake.
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<Option<IpAddr>> {
+    // Both versions are longer than this, even "PROXY UNKNOWN\r\n".
+    let mut start = [0u8; 12];
+    reader.read_exact(&mut start)?;
+    if start == SIGNATURE {
+        read_v2(reader)
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(reader, &start)
+    } else {
+        Err(invalid("the connection does not start with a PROXY protocol header"))
+    }
+}
+
+// "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n"
+fn read_v1<R: Read>(reader: &mut R, start: &[u8]) -> io::Result<Option<IpAddr>> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= MAX_V1_LENGTH {
+            return Err(invalid("the PROXY protocol header is too long"));
+        }
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("the PROXY protocol header is not text"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    let source = match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => return Ok(None),
+        ["PROXY", "TCP4", source, _, _, _] => source.parse::<Ipv4Addr>().ok().map(IpAddr::V4),
+        ["PROXY", "TCP6", source, _, _, _] => source.parse::<Ipv6Addr>().ok().map(IpAddr::V6),
+        _ => None,
+    };
+    source.map(Some).ok_or_else(|| invalid("malformed PROXY protocol header"))
+}
+
+// The signature is followed by the version and command, the address family, the length of the rest and then the
+// addresses, source first.
+fn read_v2<R: Read>(reader: &mut R) -> io::Result<Option<IpAddr>> {
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header)?;
+    if header[0] >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    let mut addresses = vec![0u8; u16::from_be_bytes([header[2], header[3]]) as usize];
+    reader.read_exact(&mut addresses)?;
+    match header[0] & 0x0f {
+        // LOCAL
+        0 => return Ok(None),
+        // PROXY
+        1 => {},
+        _ => return Err(invalid("unsupported PROXY protocol command")),
+    }
+    match header[1] >> 4 {
+        // AF_INET
+        1 => <[u8; 4]>::try_from(addresses.get(..4).unwrap_or_default())
+            .map(|octets| Some(IpAddr::from(octets)))
+            .map_err(|_| invalid("truncated PROXY protocol addresses")),
+        // AF_INET6
+        2 => <[u8; 16]>::try_from(addresses.get(..16).unwrap_or_default())
+            .map(|octets| Some(IpAddr::from(octets)))
+            .map_err(|_| invalid("truncated PROXY protocol addresses")),
+        // Unix sockets and unspecified families.
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
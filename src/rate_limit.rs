@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SHARDS: usize = 16;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+// Longer waits are reported as this; a client that slow to be let back in only needs to know it is not soon.
+const MAX_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Shard {
+    buckets: HashMap<IpAddr, Bucket>,
+    last_sweep: Instant,
+}
+
+pub struct RateLimiter {
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter {
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard { buckets: HashMap::new(), last_sweep: Instant::now() })).collect(),
+        }
+    }
+
+    // Takes a token for `ip`, or returns how long the client has to wait for the next one.
+    pub fn check(&self, ip: IpAddr, rate: f64, burst: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut shard = self.shard(ip).lock().unwrap_or_else(|e| e.into_inner());
+
+        // A bucket that has been idle long enough to refill completely is the same as a fresh one, so drop it.
+        if now.duration_since(shard.last_sweep) >= SWEEP_INTERVAL {
+            let full_after = full_after(rate, burst).unwrap_or(Duration::MAX);
+            shard.buckets.retain(|_, bucket| now.duration_since(bucket.updated) < full_after);
+            shard.last_sweep = now;
+        }
+
+        let bucket = shard.buckets.entry(ip).or_insert(Bucket { tokens: burst, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::try_from_secs_f64((1.0 - bucket.tokens) / rate).map_or(MAX_WAIT, |wait| wait.min(MAX_WAIT)))
+        }
+    }
+
+    fn shard(&self, ip: IpAddr) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        ip.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+}
+
+// How long an empty bucket takes to fill up, if that fits in a `Duration` at all.
+pub fn full_after(rate: f64, burst: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(burst / rate).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    // Moves the client's bucket into the past instead of waiting for it to refill.
+    fn rewind(limiter: &RateLimiter, ip: IpAddr, by: Duration) {
+        let mut shard = limiter.shard(ip).lock().unwrap();
+        let bucket = shard.buckets.get_mut(&ip).unwrap();
+        bucket.updated -= by;
+    }
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_then_runs_out() {
+        let limiter = RateLimiter::new();
+        for _ in 0..3 {
+            assert_eq!(limiter.check(CLIENT, 2.0, 3.0), Ok(()));
+        }
+        let wait = limiter.check(CLIENT, 2.0, 3.0).unwrap_err();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500), "{wait:?}");
+        // Every client has a bucket of its own.
+        assert_eq!(limiter.check(OTHER, 2.0, 3.0), Ok(()));
+    }
+
+    #[test]
+    fn buckets_refill_at_the_rate_up_to_the_burst() {
+        let limiter = RateLimiter::new();
+        for _ in 0..3 {
+            limiter.check(CLIENT, 2.0, 3.0).unwrap();
+        }
+        rewind(&limiter, CLIENT, Duration::from_secs(1));
+        assert_eq!(limiter.check(CLIENT, 2.0, 3.0), Ok(()));
+        assert_eq!(limiter.check(CLIENT, 2.0, 3.0), Ok(()));
+        assert!(limiter.check(CLIENT, 2.0, 3.0).is_err());
+
+        // However long the client stays away, it only ever gets a full bucket back.
+        rewind(&limiter, CLIENT, Duration::from_secs(10));
+        for _ in 0..3 {
+            assert_eq!(limiter.check(CLIENT, 2.0, 3.0), Ok(()));
+        }
+        assert!(limiter.check(CLIENT, 2.0, 3.0).is_err());
+    }
+
+    #[test]
+    fn tiny_rates_do_not_overflow_the_wait() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter.check(CLIENT, 1e-300, 1.0), Ok(()));
+        assert_eq!(limiter.check(CLIENT, 1e-300, 1.0), Err(MAX_WAIT));
+
+        // Sweeping idle buckets has to work out when they are full, too.
+        limiter.shard(CLIENT).lock().unwrap().last_sweep -= SWEEP_INTERVAL;
+        assert_eq!(limiter.check(CLIENT, 1e-300, 1.0), Err(MAX_WAIT));
+        assert_eq!(full_after(1e-300, 1.0), None);
+        assert_eq!(full_after(2.0, 3.0), Some(Duration::from_millis(1500)));
+    }
+}
@@ -0,0 +1,54 @@
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use thread_helper::ThreadPool;
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tokio::runtime::{Builder, Handle, Runtime};
+use crate::connection::Connection;
+use crate::metrics;
+
+// Only borrows the descriptor; the connection that owns it is kept alive alongside the registration.
+struct Descriptor(RawFd);
+
+impl AsRawFd for Descriptor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+// Holds connections that are waiting for their next request, so they do not occupy a worker thread while idle.
+pub struct Reactor {
+    handle: Handle,
+    pool: Weak<ThreadPool>,
+}
+
+impl Reactor {
+    // The runtime is returned separately so it can be shut down before the thread pool is.
+    pub fn start(pool: &Arc<ThreadPool>) -> io::Result<(Reactor, Runtime)> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("reactor")
+            .enable_io()
+            .enable_time()
+            .build()?;
+        Ok((Reactor { handle: runtime.handle().clone(), pool: Arc::downgrade(pool) }, runtime))
+    }
+
+    // Runs `resume` on the thread pool once `connection` is readable, or with `false` once `timeout` has passed.
+    pub fn park<C: Connection + Send + 'static>(&self, connection: C, timeout: Duration, resume: impl FnOnce(C, bool) + Send + 'static) {
+        let pool = Weak::clone(&self.pool);
+        let fd = connection.raw_fd();
+        self.handle.spawn(async move {
+            let readable = match AsyncFd::with_interest(Descriptor(fd), Interest::READABLE) {
+                Ok(registration) => matches!(tokio::time::timeout(timeout, registration.readable()).await, Ok(Ok(_))),
+                // Let a worker wait on it the blocking way instead.
+                Err(_) => true,
+            };
+            if let Some(pool) = pool.upgrade() {
+                pool.execute(metrics::queued(move || resume(connection, readable)));
+            }
+        });
+    }
+}
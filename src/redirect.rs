@@ -0,0 +1,53 @@
+use http_resources::{percent_encode, HttpResponse, HttpResponseStatusCode};
+
+#[derive(PartialEq)]
+pub struct RedirectRule {
+    prefix: String,
+    target: String,
+    status: HttpResponseStatusCode,
+}
+
+impl RedirectRule {
+    // `value` is the new location, optionally followed by the status in brackets: "/new [301]". Without one the
+    // redirect is temporary (302).
+    pub fn parse(prefix: &str, value: &str) -> Option<RedirectRule> {
+        let (target, status) = match value.trim().strip_suffix(']').and_then(|rest| rest.rsplit_once('[')) {
+            Some((target, code)) => (target.trim(), code.trim().parse().ok().and_then(HttpResponseStatusCode::from_u16)?),
+            None => (value.trim(), HttpResponseStatusCode::Found),
+        };
+        if target.is_empty() || !matches!(status.as_u16(), 301 | 302 | 307 | 308) {
+            return None;
+        }
+        Some(RedirectRule { prefix: prefix.trim_end_matches('/').to_string(), target: target.trim_end_matches('/').to_string(), status })
+    }
+
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} [{}]", self.target, self.status.as_u16())
+    }
+
+    // Whatever followed the prefix, and the query string, are carried over to the new location.
+    pub fn respond(&self, rest: &str, query: Option<&str>) -> HttpResponse {
+        let mut location = format!("{}{}", self.target, percent_encode(rest));
+        if location.is_empty() {
+            location.push('/');
+        }
+        if let Some(query) = query {
+            location = format!("{location}?{query}");
+        }
+        HttpResponse::redirect(self.status.clone(), location)
+    }
+}
+
+// The longest matching prefix wins, as with proxies and auth rules.
+pub fn find<'a, 'p>(rules: &'a [RedirectRule], path: &'p str) -> Option<(&'a RedirectRule, &'p str)> {
+    rules.iter()
+        .filter_map(|rule| {
+            let rest = path.strip_prefix(rule.prefix.as_str()).filter(|rest| rest.is_empty() || rest.starts_with('/'))?;
+            Some((rule, rest))
+        })
+        .max_by_key(|(rule, _)| rule.prefix.len())
+}
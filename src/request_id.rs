@@ -0,0 +1,61 @@
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use http_resources::HttpRequest;
+
+const HEADER: &str = "X-Request-Id";
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // The request this thread is working on, so log lines written while handling it can name it.
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// A ULID: 48 bits of milliseconds and 80 random bits, as 26 Crockford base32 characters that sort by time.
+pub fn generate() -> String {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis() as u64);
+    let state = RandomState::new();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let random = ((state.hash_one(count) as u128) << 16) | (state.hash_one(!count) as u128 & 0xffff);
+    let value = ((millis as u128 & 0xffff_ffff_ffff) << 80) | random;
+
+    (0..26).rev().map(|index| CROCKFORD[(value >> (index * 5)) as usize & 31] as char).collect()
+}
+
+// Starts a new request on this thread with a fresh ID, before it has been read, so that errors from reading it
+// can already be correlated.
+pub fn begin() {
+    CURRENT.with(|current| *current.borrow_mut() = Some(generate()));
+}
+
+// A sensible ID sent by the client or a proxy in front of us is kept; otherwise the one from `begin` is written
+// into the request, so it also reaches upstream servers.
+pub fn adopt(request: &mut HttpRequest) {
+    match request.get_header(HEADER).filter(|id| is_valid(id)) {
+        Some(id) => {
+            let id = id.to_string();
+            CURRENT.with(|current| *current.borrow_mut() = Some(id));
+        },
+        None => {
+            let id = CURRENT.with(|current| current.borrow_mut().get_or_insert_with(generate).clone());
+            request.set_header(HEADER, id);
+        },
+    }
+}
+
+pub fn current() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+// Worker threads are reused, so the ID has to be dropped once the connection is handed back.
+pub fn clear() {
+    CURRENT.with(|current| *current.borrow_mut() = None);
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
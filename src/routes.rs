@@ -0,0 +1,8 @@
+use http_resources::{HttpResponse, Router};
+
+// Dynamic handlers are registered here; anything unmatched falls through to the static file server.
+pub fn register(router: &mut Router) {
+    router.get("/api/health", |_| HttpResponse::ok_text("ok"));
+    #[cfg(feature = "watch")]
+    router.get(crate::watch::RELOAD_PATH, crate::watch::subscribe);
+}
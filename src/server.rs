@@ -0,0 +1,263 @@
+use std::{fs, io, thread};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use http_resources::{Handler, HttpMethod, HttpRequest, HttpResponse, Router};
+use log::{info, warn};
+use thread_helper::ThreadPool;
+#[cfg(feature = "acme")]
+use crate::acme;
+use crate::config::Config;
+#[cfg(feature = "watch")]
+use crate::watch;
+use crate::{admin, console, logging, preload, routes, shutdown, CONF, FILE_CACHE, STATE};
+use crate::{accept_http, apply_logging, bind, bound_address, conf, create_error_pages, load_templates, print_local_url};
+#[cfg(feature = "async")]
+use crate::start_reactor;
+#[cfg(feature = "tls")]
+use crate::start_tls_listeners;
+
+type Loader = Box<dyn Fn() -> Result<Config, Vec<String>> + Send + Sync>;
+
+// Both are handed over by `Server::run`.
+static ROUTER: OnceLock<Router> = OnceLock::new();
+static LOADER: OnceLock<Loader> = OnceLock::new();
+
+// The web server, for binaries that embed it. Requests are answered through state shared by the whole process, so
+// only one server can run in it, once.
+pub struct Server {
+    config: Config,
+    router: Router,
+    loader: Option<Loader>,
+    listeners: Option<Listeners>,
+}
+
+// Each with the address it was configured as, for the log.
+struct Listeners {
+    http: Vec<(String, TcpListener)>,
+    tls: Vec<(String, TcpListener)>,
+    admin: Option<(String, TcpListener)>,
+}
+
+impl Listeners {
+    fn bind(config: &Config) -> io::Result<Listeners> {
+        let bind_all = |addresses: Vec<String>| addresses.into_iter()
+            .map(|address| bind(&address).map(|listener| (address, listener)))
+            .collect::<io::Result<Vec<_>>>();
+        #[cfg(feature = "tls")]
+        let tls = if config.ssl.is_empty() { Vec::new() } else { bind_all(config.tls_addresses())? };
+        #[cfg(not(feature = "tls"))]
+        let tls = Vec::new();
+        // With "ssl-only", plain HTTP is only left out once HTTPS is actually being served.
+        let http = if !config.ssl_only || tls.is_empty() { bind_all(config.http_addresses())? } else { Vec::new() };
+        // Health checks and metrics are served on a listener of their own.
+        let admin = match config.admin_listen.as_str() {
+            "" => None,
+            address => Some((address.to_string(), bind(address)?)),
+        };
+        Ok(Listeners { http, tls, admin })
+    }
+
+    fn addresses(&self) -> Vec<SocketAddr> {
+        self.http.iter().chain(&self.tls).chain(&self.admin).filter_map(|(_, listener)| listener.local_addr().ok()).collect()
+    }
+}
+
+// Stops a running server from any thread, as "stop" on the console does.
+#[derive(Clone)]
+pub struct ShutdownHandle(());
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        shutdown::request();
+    }
+}
+
+impl Server {
+    pub fn new(config: Config) -> Server {
+        let mut router = Router::new();
+        routes::register(&mut router);
+        Server { config, router, loader: None, listeners: None }
+    }
+
+    // Answers requests matching `pattern` with `handler`, ahead of the static files, like the routes in routes.rs.
+    pub fn route<F>(&mut self, method: HttpMethod, pattern: &str, handler: F) -> &mut Server
+    where
+        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.router.route(method, pattern, handler);
+        self
+    }
+
+    // Like `route`, for any handler: `StaticFiles`, `Proxy`, `Cgi` or one of the embedder's own.
+    pub fn handler<H: Handler + 'static>(&mut self, method: HttpMethod, pattern: &str, handler: H) -> &mut Server {
+        self.router.handler(method, pattern, handler);
+        self
+    }
+
+    // Where "reload" on the console reads the configuration from again. Without it there is nothing to reload.
+    pub fn reload_from(mut self, loader: impl Fn() -> Result<Config, Vec<String>> + Send + Sync + 'static) -> Server {
+        self.loader = Some(Box::new(loader));
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(())
+    }
+
+    // Binds every address the configuration listens on, so that a port in use is reported before anything runs.
+    // Returns the addresses bound: plain HTTP, then HTTPS, then the admin endpoint, with the ports picked for port 0.
+    // `run` binds them itself when this was not called.
+    pub fn bind(&mut self) -> io::Result<Vec<SocketAddr>> {
+        let listeners = match self.listeners.take() {
+            Some(listeners) => listeners,
+            None => Listeners::bind(&self.config)?,
+        };
+        let addresses = listeners.addresses();
+        self.listeners = Some(listeners);
+        Ok(addresses)
+    }
+
+    // Serves until the server is shut down, by a signal, the console or a `ShutdownHandle`, and the open connections
+    // have finished or timed out. Fails without serving anything when an address cannot be bound or the
+    // certificates cannot be loaded.
+    pub fn run(self) -> io::Result<()> {
+        logging::init();
+        let listeners = match self.listeners {
+            Some(listeners) => listeners,
+            None => Listeners::bind(&self.config)?,
+        };
+        if ROUTER.set(self.router).is_err() {
+            return Err(io::Error::other("A server has already run in this process, and there can only be one"));
+        }
+        if let Some(loader) = self.loader {
+            LOADER.get_or_init(|| loader);
+        }
+        *CONF.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(self.config);
+
+        info!("Starting web server...");
+
+        let config = conf();
+        apply_logging(&config);
+
+        create_error_pages(&config);
+        load_templates(&config);
+        preload::run(&config);
+        #[cfg(feature = "watch")]
+        watch::start(&config);
+        if config.dev_mode {
+            let root = fs::canonicalize(&config.root_dir).unwrap_or_else(|_| config.root_dir.clone());
+            println!("Serving {} in dev mode, which shows error details to clients and turns off caching. Never use it in production.", root.display());
+        }
+        #[cfg(not(feature = "watch"))]
+        if config.dev_mode {
+            warn!("\"dev-mode\" is set, but this build cannot watch the site for changes. Rebuild with \"--features watch\" to reload pages as they change.");
+        }
+
+        shutdown::install_signal_handler();
+
+        let pool = Arc::new(ThreadPool::new(config.threads));
+        STATE.set_pool(&pool);
+        #[cfg(feature = "async")]
+        let reactor_runtime = start_reactor(&config, &pool);
+        #[cfg(not(feature = "async"))]
+        if config.io_backend == "async" {
+            warn!("\"io-backend\" is set to async, but this build does not include it. Rebuild with \"--features async\"; using threads instead.");
+        }
+
+        #[cfg(feature = "tls")]
+        let mut threads = start_tls_listeners(&config, &pool, listeners.tls)?;
+        #[cfg(not(feature = "tls"))]
+        let mut threads: Vec<thread::JoinHandle<()>> = Vec::new();
+        #[cfg(not(feature = "tls"))]
+        if !config.ssl.is_empty() {
+            warn!("\"ssl-cert\" is set, but this build does not include TLS support. Rebuild with \"--features tls\" to enable HTTPS.");
+        }
+        #[cfg(not(feature = "acme"))]
+        if !config.acme_domains.is_empty() {
+            warn!("\"acme-domains\" is set, but this build does not include ACME support. Rebuild with \"--features acme\" to obtain certificates.");
+        }
+
+        // Without a console, the server is stopped with SIGINT or SIGTERM.
+        if config.interactive {
+            thread::spawn(console::run);
+        }
+
+        for (address, listener) in listeners.http {
+            info!("Successfully started! Listening on: {}...", bound_address(&listener, &address));
+            if config.dev_mode {
+                print_local_url(&listener, "http");
+            }
+            let pool = Arc::clone(&pool);
+            threads.push(thread::spawn(move || accept_http(listener, pool)));
+        }
+
+        // Health checks and metrics are served off the pool.
+        if let Some((address, listener)) = listeners.admin {
+            info!("Admin endpoint listening on: {}...", bound_address(&listener, &address));
+            threads.push(thread::spawn(move || admin::serve(listener)));
+        }
+
+        // Challenges are answered over plain HTTP, so certificates are only requested once that is being served.
+        #[cfg(feature = "acme")]
+        if !config.acme_domains.is_empty() && !config.ssl.is_empty() {
+            acme::start(&config);
+        }
+
+        for thread in threads {
+            thread.join().expect("Listener thread panicked");
+        }
+
+        // Parked connections are simply closed; the reactor has to be gone before the pool can be shut down.
+        #[cfg(feature = "async")]
+        if let Some(runtime) = reactor_runtime {
+            runtime.shutdown_timeout(Duration::from_secs(1));
+        }
+
+        let mut pool = Arc::try_unwrap(pool).ok().expect("Listener threads still hold the thread pool");
+        let shutdown_timeout = conf().shutdown_timeout;
+        let open = STATE.connections.list();
+        if !open.is_empty() {
+            info!("Waiting up to {} seconds for {} active connections to finish...", shutdown_timeout, open.len());
+            for info in open {
+                info!("  {}", info.describe());
+            }
+        }
+        if pool.shutdown(Duration::from_secs(shutdown_timeout)) {
+            info!("All connections closed.");
+        } else {
+            warn!("Timed out waiting for active connections; closing them forcefully:");
+            for info in STATE.connections.list() {
+                warn!("  {}", info.describe());
+            }
+        }
+
+        info!("Job queue: peak depth {}, {} connections turned away.", pool.get_peak_queued_jobs(), STATE.get_queue_rejections());
+        if STATE.get_connection_rejections() > 0 {
+            info!("Connection limit: {} connections turned away.", STATE.get_connection_rejections());
+        }
+        if STATE.get_per_ip_rejections() > 0 {
+            info!("Per-address connection limit: {} connections turned away.", STATE.get_per_ip_rejections());
+        }
+        if conf().file_cache_size > 0 {
+            info!("File cache: {} hits, {} misses.", FILE_CACHE.get_hits(), FILE_CACHE.get_misses());
+        }
+
+        // Stopping from the console leaves the window open until it has been read.
+        if conf().interactive && !shutdown::is_from_signal() {
+            println!("Press enter to continue...");
+            let mut temp = String::new();
+            io::stdin().read_line(&mut temp).unwrap_or(0);
+        }
+        Ok(())
+    }
+}
+
+pub fn router() -> &'static Router {
+    ROUTER.get_or_init(Router::new)
+}
+
+// The configuration read again for a reload, if the server was told where from.
+pub fn reload() -> Option<Result<Config, Vec<String>>> {
+    LOADER.get().map(|load| load())
+}
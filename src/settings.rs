@@ -0,0 +1,122 @@
+use toml_edit::{Document, Item, Table, Value};
+
+// Both file formats are read into the same flat list, so every key is interpreted in one place.
+pub enum Entry {
+    // Settings after this belong to the named virtual host, or to the top level again for `None`.
+    Section { host: Option<String> },
+    Setting { line: usize, key: String, value: String },
+    Invalid { line: usize, message: String },
+}
+
+// The legacy format: `key = value` lines and `[host "name"]` sections.
+pub fn read_legacy(source: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            match section.trim().strip_prefix("host").map(|name| name.trim().trim_matches('\"')) {
+                Some(name) if !name.is_empty() => entries.push(Entry::Section { host: Some(name.to_string()) }),
+                _ => {
+                    entries.push(Entry::Invalid { line: line_number, message: format!("Unknown section: {line}") });
+                    entries.push(Entry::Section { host: None });
+                },
+            }
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((key, value)) => entries.push(Entry::Setting { line: line_number, key: key.trim().to_string(), value: value.trim().to_string() }),
+            None => entries.push(Entry::Invalid { line: line_number, message: format!("Invalid line: {line}") }),
+        }
+    }
+    entries
+}
+
+// Tables are flattened into the legacy dotted keys, so `[proxy]` with `"/api" = "..."` becomes `proxy./api`, and
+// arrays become comma separated lists. Virtual hosts are written as `[host."example.com"]` tables.
+pub fn read_toml(source: &str) -> Result<Vec<Entry>, String> {
+    let document = Document::parse(source).map_err(|err| err.to_string().trim_end().to_string())?;
+    let root = document.as_table();
+    let lines = LineIndex::new(source);
+
+    let mut entries = Vec::new();
+    for (key, item) in root.iter().filter(|(key, _)| *key != "host") {
+        flatten(&lines, key, item, &mut entries);
+    }
+
+    // Hosts go last: everything after a host section is read as part of that host.
+    if let Some(hosts) = root.get("host") {
+        let Some(hosts) = hosts.as_table_like() else {
+            return Err(format!("line {}: \"host\" must be a table of [host.\"<name>\"] sections", lines.line_of(hosts.span())));
+        };
+        for (name, item) in hosts.iter() {
+            let Some(host) = item.as_table_like() else {
+                return Err(format!("line {}: [host.\"{}\"] must be a table", lines.line_of(item.span()), name));
+            };
+            entries.push(Entry::Section { host: Some(name.to_string()) });
+            for (key, item) in host.iter() {
+                flatten(&lines, key, item, &mut entries);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn flatten(lines: &LineIndex, key: &str, item: &Item, entries: &mut Vec<Entry>) {
+    let line = lines.line_of(item.span());
+    match item {
+        Item::None => {},
+        Item::Value(Value::InlineTable(table)) => {
+            for (name, value) in table.iter() {
+                flatten(lines, &format!("{key}.{name}"), &Item::Value(value.clone()), entries);
+            }
+        },
+        Item::Value(value) => match scalar(value) {
+            Some(value) => entries.push(Entry::Setting { line, key: key.to_string(), value }),
+            None => entries.push(Entry::Invalid { line, message: format!("\"{key}\" cannot hold nested arrays or tables") }),
+        },
+        Item::Table(table) => flatten_table(lines, key, table, entries),
+        Item::ArrayOfTables(_) => entries.push(Entry::Invalid { line, message: format!("[[{key}]] is not supported") }),
+    }
+}
+
+fn flatten_table(lines: &LineIndex, prefix: &str, table: &Table, entries: &mut Vec<Entry>) {
+    for (key, item) in table.iter() {
+        flatten(lines, &format!("{prefix}.{key}"), item, entries);
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Float(f) => Some(f.value().to_string()),
+        Value::Boolean(b) => Some(b.value().to_string()),
+        Value::Datetime(d) => Some(d.value().to_string()),
+        Value::Array(array) => array.iter().map(|value| match value {
+            Value::Array(_) | Value::InlineTable(_) => None,
+            value => scalar(value),
+        }).collect::<Option<Vec<String>>>().map(|values| values.join(", ")),
+        Value::InlineTable(_) => None,
+    }
+}
+
+struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(source: &str) -> LineIndex {
+        LineIndex { starts: std::iter::once(0).chain(source.match_indices('\n').map(|(index, _)| index + 1)).collect() }
+    }
+
+    // Line numbers start at 1; 0 means the position is unknown.
+    fn line_of(&self, span: Option<std::ops::Range<usize>>) -> usize {
+        span.map_or(0, |span| self.starts.partition_point(|start| *start <= span.start))
+    }
+}
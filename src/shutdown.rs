@@ -0,0 +1,47 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use log::{info, warn};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static FROM_SIGNAL: AtomicBool = AtomicBool::new(false);
+static LISTENERS: Mutex<Vec<SocketAddr>> = Mutex::new(Vec::new());
+
+pub fn install_signal_handler() {
+    ctrlc::set_handler(|| {
+        info!("Received a termination signal, shutting down...");
+        FROM_SIGNAL.store(true, Ordering::SeqCst);
+        request();
+    }).unwrap_or_else(|err| warn!("Unable to install the termination signal handler: {}", err));
+}
+
+pub fn register_listener(addr: SocketAddr) {
+    LISTENERS.lock().unwrap_or_else(|e| e.into_inner()).push(addr);
+}
+
+pub fn request() {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    // Accept loops block in `accept`, so poke each listener with a throwaway connection to let them notice the flag.
+    for addr in LISTENERS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let mut addr = *addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        TcpStream::connect_timeout(&addr, Duration::from_secs(1)).map(|_| ()).unwrap_or(());
+    }
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+pub fn is_from_signal() -> bool {
+    FROM_SIGNAL.load(Ordering::SeqCst)
+}
@@ -0,0 +1,52 @@
+use std::io::Write;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+use http_resources::{HttpResponse, HttpResponseOptions};
+use log::debug;
+use crate::proxy::Relayed;
+use crate::shutdown;
+
+const KEEP_ALIVE: &[u8] = b": keep-alive\n\n";
+// How often a quiet stream looks for a shutdown.
+const POLL: Duration = Duration::from_secs(1);
+
+// Writes the events of a response made by `HttpResponse::event_stream` as they are sent, with a comment after every
+// `interval` without one so proxies keep the connection open and a client that went away is noticed. The stream
+// ends when the handler drops its sender, the client disconnects or the server shuts down.
+pub fn stream<W: Write>(response: &mut HttpResponse, client: &mut W, keep_alive: bool, interval: Duration) -> Relayed {
+    let events = response.take_event_stream();
+    // Without chunked framing, only closing the connection can mark the end of the stream.
+    let keep_alive = keep_alive && response.get_protocol().supports_chunked();
+    response.append_option(HttpResponseOptions::Connection, if keep_alive { "keep-alive" } else { "close" });
+    let status = response.get_status().as_u16();
+    let gone = |err| {
+        debug!("The event stream client went away: {}", err);
+        Relayed { status, bytes: 0, keep_alive: false }
+    };
+
+    let mut body = match response.send_chunked(client).and_then(|mut body| body.flush().map(|_| body)) {
+        Ok(body) => body,
+        Err(err) => return gone(err),
+    };
+    let mut bytes = 0;
+    let mut last_write = Instant::now();
+    if let Some(events) = events.filter(|_| !response.is_body_suppressed()) {
+        while !shutdown::is_requested() {
+            let frame = match events.recv_timeout(POLL.min(interval)) {
+                Ok(frame) => frame,
+                Err(RecvTimeoutError::Timeout) if last_write.elapsed() < interval => continue,
+                Err(RecvTimeoutError::Timeout) => KEEP_ALIVE.to_vec(),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if let Err(err) = body.write_chunk(&frame).and_then(|_| body.flush()) {
+                return Relayed { bytes, ..gone(err) };
+            }
+            bytes += frame.len();
+            last_write = Instant::now();
+        }
+    }
+    match body.finish() {
+        Ok(()) => Relayed { status, bytes, keep_alive },
+        Err(err) => Relayed { bytes, ..gone(err) },
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::{Arc, OnceLock, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use thread_helper::ThreadPool;
+use crate::connection::ConnectionRegistry;
+use crate::metrics::Metrics;
+
+// What the listeners, workers and console share about the running server.
+pub struct ServerState {
+    started: Instant,
+    queue_full: AtomicBool,
+    queue_rejected: AtomicU64,
+    connections_full: AtomicBool,
+    connections_rejected: AtomicU64,
+    per_ip_rejected: AtomicU64,
+    pub connections: ConnectionRegistry,
+    pub metrics: Metrics,
+    // Weak, so holding the state never keeps the pool from shutting down.
+    pool: OnceLock<Weak<ThreadPool>>,
+}
+
+impl ServerState {
+    pub fn new() -> ServerState {
+        ServerState {
+            started: Instant::now(),
+            queue_full: AtomicBool::new(false),
+            queue_rejected: AtomicU64::new(0),
+            connections_full: AtomicBool::new(false),
+            connections_rejected: AtomicU64::new(0),
+            per_ip_rejected: AtomicU64::new(0),
+            connections: ConnectionRegistry::new(),
+            metrics: Metrics::new(),
+            pool: OnceLock::new(),
+        }
+    }
+
+    pub fn set_pool(&self, pool: &Arc<ThreadPool>) {
+        self.pool.set(Arc::downgrade(pool)).unwrap_or(());
+    }
+
+    pub fn get_pool(&self) -> Option<Arc<ThreadPool>> {
+        self.pool.get().and_then(Weak::upgrade)
+    }
+
+    pub fn get_uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // Returns the previous value, so callers can tell when the queue has just filled up or drained.
+    pub fn is_queue_full(&self) -> bool {
+        self.queue_full.load(Ordering::Relaxed)
+    }
+
+    pub fn set_queue_full(&self, full: bool) -> bool {
+        self.queue_full.swap(full, Ordering::Relaxed)
+    }
+
+    pub fn count_queue_rejection(&self) {
+        self.queue_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_queue_rejections(&self) -> u64 {
+        self.queue_rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn is_connections_full(&self) -> bool {
+        self.connections_full.load(Ordering::Relaxed)
+    }
+
+    // Returns the previous value, like `set_queue_full`.
+    pub fn set_connections_full(&self, full: bool) -> bool {
+        self.connections_full.swap(full, Ordering::Relaxed)
+    }
+
+    pub fn count_connection_rejection(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_connection_rejections(&self) -> u64 {
+        self.connections_rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn count_per_ip_rejection(&self) {
+        self.per_ip_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_per_ip_rejections(&self) -> u64 {
+        self.per_ip_rejected.load(Ordering::Relaxed)
+    }
+}
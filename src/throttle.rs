@@ -0,0 +1,123 @@
+use std::io::{self, Read, Write};
+#[cfg(feature = "async")]
+use std::os::fd::RawFd;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use http_resources::FileBody;
+use crate::connection::Connection;
+
+// Writes are split up so a throttled connection sends steadily instead of in bursts a second apart.
+const SLICE: usize = 16 * 1024;
+
+// Shared by every connection, for "bandwidth-limit-total".
+static TOTAL: Mutex<Option<Bucket>> = Mutex::new(None);
+
+// Up to a second's worth of bytes can be sent at once; sending more runs into debt that is slept off.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Bucket {
+        Bucket { tokens: rate as f64, updated: Instant::now() }
+    }
+
+    // Takes `bytes` and returns how long to wait before sending more.
+    fn take(&mut self, bytes: usize, rate: u64) -> Duration {
+        let now = Instant::now();
+        let rate = rate as f64;
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * rate).min(rate) - bytes as f64;
+        self.updated = now;
+        if self.tokens < 0.0 { Duration::from_secs_f64(-self.tokens / rate) } else { Duration::ZERO }
+    }
+}
+
+// A connection whose responses are held to "bandwidth-limit" bytes per second, and together with all others to
+// "bandwidth-limit-total". Without either it only passes everything through.
+pub struct Throttled<S> {
+    inner: S,
+    rate: u64,
+    total_rate: u64,
+    bucket: Bucket,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, rate: u64, total_rate: u64) -> Throttled<S> {
+        Throttled { inner, rate, total_rate, bucket: Bucket::new(rate) }
+    }
+
+    // For parking the connection between requests; a connection picked up again is throttled afresh.
+    #[cfg(feature = "async")]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn is_limited(&self) -> bool {
+        self.rate > 0 || self.total_rate > 0
+    }
+
+    fn wait(&mut self, bytes: usize) {
+        let mut wait = Duration::ZERO;
+        if self.rate > 0 {
+            wait = self.bucket.take(bytes, self.rate);
+        }
+        if self.total_rate > 0 {
+            let mut total = TOTAL.lock().unwrap_or_else(|e| e.into_inner());
+            wait = wait.max(total.get_or_insert_with(|| Bucket::new(self.total_rate)).take(bytes, self.total_rate));
+        }
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+impl<S: Read> Read for Throttled<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for Throttled<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.is_limited() {
+            return self.inner.write(buf);
+        }
+        let written = self.inner.write(&buf[..buf.len().min(SLICE)])?;
+        self.wait(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Connection> Connection for Throttled<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.inner.shutdown_write()
+    }
+
+    // The kernel would send the file as fast as it can, so throttled files go through `write` instead.
+    fn send_file(&mut self, body: &FileBody) -> Option<io::Result<u64>> {
+        if self.is_limited() {
+            return None;
+        }
+        self.inner.send_file(body)
+    }
+
+    #[cfg(feature = "async")]
+    fn has_pending_input(&mut self) -> bool {
+        self.inner.has_pending_input()
+    }
+
+    #[cfg(feature = "async")]
+    fn raw_fd(&self) -> RawFd {
+        self.inner.raw_fd()
+    }
+}
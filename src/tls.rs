@@ -0,0 +1,198 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(feature = "async")]
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use log::{error, info};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use crate::connection::Connection;
+use crate::{conf, host_matches, shutdown, Config};
+
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+static CERTIFICATES: RwLock<Certificates> = RwLock::new(Certificates { default: None, by_name: Vec::new(), stamps: Vec::new() });
+
+// The listener's own certificate, those of virtual hosts by each of their names, and the modification times of the
+// files they were last read from.
+struct Certificates {
+    default: Option<Arc<CertifiedKey>>,
+    by_name: Vec<(String, Arc<CertifiedKey>)>,
+    stamps: Vec<Option<SystemTime>>,
+}
+
+// Hands each handshake whichever certificates were installed last, so renewed ones are used without a restart.
+#[derive(Debug)]
+struct CurrentCertificates;
+
+impl ResolvesServerCert for CurrentCertificates {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certificates = CERTIFICATES.read().unwrap_or_else(|e| e.into_inner());
+        // Clients that name no server (SNI), such as those connecting to an IP address, get the default one.
+        let name = client_hello.server_name().map(str::to_ascii_lowercase);
+        let matched = name.and_then(|name| certificates.by_name.iter().find(|(pattern, _)| host_matches(pattern, &name)).map(|(_, key)| Arc::clone(key)));
+        matched.or_else(|| certificates.default.clone())
+    }
+}
+
+impl Connection for TlsStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    // The close_notify alert goes first, so the client can tell the end of the stream from a truncation.
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()?;
+        self.sock.shutdown(Shutdown::Write)
+    }
+
+    // Records that were already read off the socket may still hold undelivered plaintext.
+    #[cfg(feature = "async")]
+    fn has_pending_input(&mut self) -> bool {
+        self.conn.process_new_packets().is_ok_and(|state| state.plaintext_bytes_to_read() > 0)
+    }
+
+    #[cfg(feature = "async")]
+    fn raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
+
+pub fn load_server_config(config: &Config) -> Option<Arc<ServerConfig>> {
+    if !install_certificates(config) {
+        return None;
+    }
+
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map(|builder| builder.with_no_client_auth().with_cert_resolver(Arc::new(CurrentCertificates)))
+        .map_err(|err| error!("Unable to build the TLS configuration: {}", err))
+        .ok()?;
+    #[cfg(feature = "http2")]
+    let config = {
+        let mut config = config;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        config
+    };
+
+    Some(Arc::new(config))
+}
+
+// Reads the certificates of the listener and of every virtual host that has one, and swaps them in together. If
+// any of them cannot be loaded, those in use are kept. Connections that are already open keep the one they started
+// with.
+pub fn install_certificates(config: &Config) -> bool {
+    // Taken first, so a file replaced while it is read counts as changed again.
+    let stamps = file_stamps(config);
+    let loaded = load_certified_key(&config.ssl, &config.ssl_key).and_then(|default| {
+        let mut by_name = Vec::new();
+        for host in config.hosts.iter().filter(|host| !host.ssl_cert.is_empty()) {
+            let key = load_certified_key(&host.ssl_cert, &host.ssl_key)?;
+            by_name.extend(host.names.iter().map(|name| (name.clone(), Arc::clone(&key))));
+        }
+        Some((default, by_name))
+    });
+
+    let mut certificates = CERTIFICATES.write().unwrap_or_else(|e| e.into_inner());
+    // A broken file is not retried until it changes again.
+    certificates.stamps = stamps;
+    let Some((default, by_name)) = loaded else {
+        return false;
+    };
+    certificates.default = Some(default);
+    certificates.by_name = by_name;
+    true
+}
+
+// Reads the certificates again, as on a config reload. Does nothing unless HTTPS is being served.
+pub fn reload_certificates(config: &Config) {
+    if CERTIFICATES.read().unwrap_or_else(|e| e.into_inner()).default.is_none() {
+        return;
+    }
+    if install_certificates(config) {
+        info!("Reloaded the TLS certificates.");
+    } else {
+        error!("Unable to reload the TLS certificates; keeping the ones in use.");
+    }
+}
+
+// Reloads the certificates whenever one of their files changes, e.g. after an external ACME client renewed them.
+pub fn watch_certificates() {
+    thread::spawn(|| while !shutdown::is_requested() {
+        thread::sleep(WATCH_INTERVAL);
+        let config = conf();
+        if file_stamps(&config) != CERTIFICATES.read().unwrap_or_else(|e| e.into_inner()).stamps {
+            reload_certificates(&config);
+        }
+    });
+}
+
+fn file_stamps(config: &Config) -> Vec<Option<SystemTime>> {
+    let hosts = config.hosts.iter().flat_map(|host| [&host.ssl_cert, &host.ssl_key]);
+    [&config.ssl, &config.ssl_key].into_iter().chain(hosts)
+        .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Option<Arc<CertifiedKey>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+    CertifiedKey::from_der(certs, key, &rustls::crypto::ring::default_provider())
+        .map(Arc::new)
+        .map_err(|err| error!("Unable to use the certificate {} with the key {}: {}", cert_path, key_path, err))
+        .ok()
+}
+
+pub fn accept(config: &Arc<ServerConfig>, stream: TcpStream) -> Option<TlsStream> {
+    let connection = ServerConnection::new(Arc::clone(config)).ok()?;
+    Some(StreamOwned::new(connection, stream))
+}
+
+// Completes the handshake up front, since the negotiated protocol decides how the connection is served.
+#[cfg(feature = "http2")]
+pub fn negotiated_http2(stream: &mut TlsStream, timeout: Duration) -> bool {
+    stream.sock.set_read_timeout(Some(timeout)).unwrap_or(());
+    while stream.conn.is_handshaking() {
+        if stream.conn.complete_io(&mut stream.sock).is_err() {
+            return false;
+        }
+    }
+    stream.conn.alpn_protocol() == Some(b"h2")
+}
+
+fn load_certs(path: &str) -> Option<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).map_err(|err| error!("Unable to open certificate file {}: {}", path, err)).ok()?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| error!("Unable to read certificate file {}: {}", path, err))
+        .ok()?;
+
+    if certs.is_empty() {
+        error!("No certificates found in {}", path);
+        return None;
+    }
+    Some(certs)
+}
+
+fn load_key(path: &str) -> Option<PrivateKeyDer<'static>> {
+    let file = File::open(path).map_err(|err| error!("Unable to open private key file {}: {}", path, err)).ok()?;
+    match rustls_pemfile::private_key(&mut BufReader::new(file)) {
+        Ok(Some(key)) => Some(key),
+        Ok(None) => {
+            error!("No private key found in {}", path);
+            None
+        }
+        Err(err) => {
+            error!("Unable to read private key file {}: {}", path, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::{self, File};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http_resources::{normalize_path, percent_encode, read_chunked, HttpMethod, HttpRequest, HttpRequestLimits, HttpResponse, HttpResponseOptions, HttpResponseStatusCode};
+use log::{debug, info};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use crate::{request_id, ConnectionError};
+
+pub struct UploadRoute<'a> {
+    dir: &'a Path,
+    prefix: &'a str,
+    rest: String,
+}
+
+// PUT and POST below a configured prefix; every other method falls through to the static files.
+pub fn route<'a>(uploads: &'a [(String, PathBuf)], request: &HttpRequest, path: &str) -> Option<UploadRoute<'a>> {
+    if !matches!(request.get_method(), HttpMethod::Put | HttpMethod::Post) {
+        return None;
+    }
+    find(uploads, path)
+}
+
+// The upload prefix `path` lies below, whatever the method.
+pub fn find<'a>(uploads: &'a [(String, PathBuf)], path: &str) -> Option<UploadRoute<'a>> {
+    uploads.iter()
+        .filter_map(|(prefix, dir)| {
+            let prefix = prefix.trim_end_matches('/');
+            let rest = path.strip_prefix(prefix).filter(|rest| rest.is_empty() || rest.starts_with('/'))?;
+            Some(UploadRoute { dir, prefix, rest: rest.to_string() })
+        })
+        .max_by_key(|route| route.prefix.len())
+}
+
+// Whether the body of this request is left on the connection for `receive` instead of being read up front.
+pub fn is_upload(uploads: &[(String, PathBuf)], request: &HttpRequest) -> bool {
+    !uploads.is_empty() && route(uploads, request, &normalize_path(request.get_path()).unwrap_or_default()).is_some()
+}
+
+// Streams the body into a temporary file next to its destination, which replaces the destination only once the
+// whole body has arrived and matches any digest the client sent. POSTing to a directory picks a fresh name.
+pub fn receive<R: BufRead>(route: UploadRoute, request: &HttpRequest, body: &mut R, max_size: u64, limits: &HttpRequestLimits) -> Result<HttpResponse, ConnectionError> {
+    let mut name = route.rest.trim_start_matches('/').to_string();
+    if name.is_empty() || name.ends_with('/') {
+        if request.get_method() != HttpMethod::Post {
+            return Err(ConnectionError::Forbidden);
+        }
+        name += &request_id::generate();
+    }
+    let destination = route.dir.join(&name);
+    if destination.is_dir() {
+        return Err(ConnectionError::Forbidden);
+    }
+    let parent = destination.parent().ok_or(ConnectionError::Forbidden)?;
+    fs::create_dir_all(parent).map_err(ConnectionError::internal(parent.display()))?;
+
+    let file_name = destination.file_name().and_then(|name| name.to_str()).unwrap_or("upload");
+    let temp = parent.join(format!(".{}.{}.part", file_name, request_id::generate()));
+    let result = File::create(&temp).map_err(ConnectionError::internal(temp.display()))
+        .and_then(|mut file| write_body(request, body, &mut file, max_size, limits));
+    let received = match result {
+        Ok(received) => received,
+        Err(err) => {
+            fs::remove_file(&temp).unwrap_or(());
+            return Err(err);
+        },
+    };
+
+    let fields = request.get_headers().iter().chain(received.trailers.iter());
+    if let Some(name) = fields.filter_map(|(name, value)| received.digests.mismatch(name, value)).next() {
+        debug!("Rejected an upload to {}: the {} does not match the body", destination.display(), name);
+        fs::remove_file(&temp).unwrap_or(());
+        return Err(ConnectionError::TCPReadFailed);
+    }
+
+    let existed = destination.exists();
+    if let Err(err) = fs::rename(&temp, &destination) {
+        fs::remove_file(&temp).unwrap_or(());
+        return Err(ConnectionError::internal(destination.display())(err));
+    }
+    info!("Stored {} bytes at {}", received.length, destination.display());
+
+    if existed {
+        return Ok(HttpResponse::no_content());
+    }
+    Ok(HttpResponse::builder()
+        .status(HttpResponseStatusCode::Created)
+        .header(HttpResponseOptions::Location, percent_encode(&format!("{}/{}", route.prefix, name)))
+        .body("")
+        .build())
+}
+
+struct Received {
+    length: u64,
+    // Only chunked bodies have trailers.
+    trailers: Vec<(String, String)>,
+    digests: Digests,
+}
+
+fn write_body<R: BufRead>(request: &HttpRequest, body: &mut R, file: &mut File, max_size: u64, limits: &HttpRequestLimits) -> Result<Received, ConnectionError> {
+    let mut digests = Digests { md5: Md5::new(), sha256: Sha256::new() };
+    let mut written: u64 = 0;
+    let mut write = |data: &[u8]| -> Result<(), ConnectionError> {
+        written += data.len() as u64;
+        if written > max_size {
+            return Err(ConnectionError::PayloadTooLarge);
+        }
+        digests.md5.update(data);
+        digests.sha256.update(data);
+        file.write_all(data).map_err(ConnectionError::internal("Writing the upload"))
+    };
+
+    if request.headers().contains("Transfer-Encoding") && !request.is_chunked() {
+        return Err(ConnectionError::NotImplemented);
+    }
+    let trailers = if request.is_chunked() {
+        read_chunked(body, limits, &mut write)?
+    } else {
+        let length: u64 = request.content_length().ok_or(ConnectionError::TCPReadFailed)?;
+        if length > max_size {
+            return Err(ConnectionError::PayloadTooLarge);
+        }
+        copy_exact(body, length, &mut write)?;
+        Vec::new()
+    };
+    file.flush().map_err(ConnectionError::internal("Writing the upload"))?;
+    Ok(Received { length: written, trailers, digests })
+}
+
+fn copy_exact<R: BufRead>(body: &mut R, mut remaining: u64, write: &mut impl FnMut(&[u8]) -> Result<(), ConnectionError>) -> Result<(), ConnectionError> {
+    while remaining > 0 {
+        let available = body.fill_buf().ok().filter(|data| !data.is_empty()).ok_or(ConnectionError::TCPReadFailed)?;
+        let take = available.len().min(remaining as usize);
+        write(&available[..take])?;
+        body.consume(take);
+        remaining -= take as u64;
+    }
+    Ok(())
+}
+
+struct Digests {
+    md5: Md5,
+    sha256: Sha256,
+}
+
+impl Digests {
+    // Understands Content-MD5, Digest (RFC 3230) and Content-Digest (RFC 9530), as headers or trailers. Returns the
+    // field name when it names a value that differs from what was received; unknown algorithms are ignored.
+    fn mismatch<'a>(&self, name: &'a str, value: &str) -> Option<&'a str> {
+        let claims: Vec<(String, &str)> = match name.to_ascii_lowercase().as_str() {
+            "content-md5" => vec![("md5".to_string(), value.trim())],
+            "digest" | "content-digest" => value.split(',')
+                .filter_map(|claim| claim.split_once('='))
+                .map(|(algorithm, encoded)| (algorithm.trim().to_ascii_lowercase(), encoded.trim().trim_matches(':')))
+                .collect(),
+            _ => return None,
+        };
+        claims.iter().any(|(algorithm, encoded)| {
+            let actual = match algorithm.as_str() {
+                "md5" => self.md5.clone().finalize().to_vec(),
+                "sha-256" => self.sha256.clone().finalize().to_vec(),
+                _ => return false,
+            };
+            STANDARD.decode(encoded).ok() != Some(actual)
+        }).then_some(name)
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use http_resources::{Event, EventSender, HttpRequest, HttpResponse};
+use log::{debug, info, warn};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::deny::glob_matches;
+use crate::{conf, preload, Config, FILE_CACHE};
+
+// Editors save a file in several steps; the changes of one save are handled together.
+const SETTLE: Duration = Duration::from_millis(100);
+pub const RELOAD_PATH: &str = "/__dev__/reload";
+
+// Replaced on every reload, which stops the previous watcher and its thread.
+static WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+// The pages open in "dev-mode", waiting for a reload event.
+static CLIENTS: Mutex<Vec<EventSender>> = Mutex::new(Vec::new());
+
+// Watches the site roots for changes, which drop the files from the file cache (so their ETags are worked out again),
+// load preloaded files again and, in "dev-mode", reload the open pages.
+pub fn start(config: &Config) {
+    let mut current = WATCHER.lock().unwrap_or_else(|e| e.into_inner());
+    *current = None;
+    if config.file_cache_size == 0 && !config.dev_mode {
+        return;
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(sender) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            warn!("Unable to watch the site for changes: {}", err);
+            return;
+        },
+    };
+    let roots = preload::roots(config);
+    for (_, root) in &roots {
+        if let Err(err) = watcher.watch(root, RecursiveMode::Recursive) {
+            warn!("Unable to watch {} for changes: {}", root.display(), err);
+        }
+    }
+    thread::spawn(move || settle(receiver));
+    *current = Some(watcher);
+    info!("Watching {} site roots for changes.", roots.len());
+}
+
+// Answers the event stream that pages served in "dev-mode" listen to.
+pub fn subscribe(_request: &HttpRequest) -> HttpResponse {
+    if !conf().dev_mode {
+        return HttpResponse::not_found();
+    }
+    let (response, sender) = HttpResponse::event_stream();
+    CLIENTS.lock().unwrap_or_else(|e| e.into_inner()).push(sender);
+    response
+}
+
+// Adds the script that listens for reload events before the closing body tag of a page, or at its end.
+pub fn inject_script(mut content: Vec<u8>) -> Vec<u8> {
+    let at = content.windows(7).rposition(|window| window.eq_ignore_ascii_case(b"</body>")).unwrap_or(content.len());
+    let script = format!("<script>new EventSource(\"{RELOAD_PATH}\").addEventListener(\"reload\", () => location.reload());</script>");
+    content.splice(at..at, script.into_bytes());
+    content
+}
+
+// Gathers the paths that changed until the site has been quiet for a moment, then handles them. Ends when the watcher
+// is replaced.
+fn settle(events: Receiver<notify::Result<notify::Event>>) {
+    while let Ok(first) = events.recv() {
+        let mut changed = BTreeSet::new();
+        gather(&mut changed, first);
+        let deadline = Instant::now() + SETTLE;
+        while let Ok(event) = events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            gather(&mut changed, event);
+        }
+        if !changed.is_empty() {
+            apply(&changed);
+        }
+    }
+}
+
+fn gather(changed: &mut BTreeSet<PathBuf>, event: notify::Result<notify::Event>) {
+    match event {
+        // Reading a file, which the server itself does, changes nothing.
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => {},
+        Ok(event) => changed.extend(event.paths),
+        Err(err) => warn!("Watching the site for changes failed: {}", err),
+    }
+}
+
+fn apply(changed: &BTreeSet<PathBuf>) {
+    let config = conf();
+    for path in changed {
+        debug!("{} changed.", path.display());
+        FILE_CACHE.remove(path);
+        if path.is_file() && is_preloaded(&config, path) {
+            preload::load(&config, path);
+        }
+    }
+
+    if config.dev_mode {
+        let event = Event::new("reload").event("reload");
+        let mut clients = CLIENTS.lock().unwrap_or_else(|e| e.into_inner());
+        // Pages that were closed are noticed here.
+        clients.retain(|client| client.send(&event).is_ok());
+        if !clients.is_empty() {
+            info!("Reloading {} pages after {} files changed.", clients.len(), changed.len());
+        }
+    }
+}
+
+// Whether "preload" names the file at `resolved` under one of the site roots.
+fn is_preloaded(config: &Config, resolved: &Path) -> bool {
+    if config.preload.is_empty() || config.file_cache_size == 0 {
+        return false;
+    }
+    preload::roots(config).iter().any(|(prefix, root)| {
+        let Ok(relative) = resolved.strip_prefix(root) else {
+            return false;
+        };
+        let segments: Vec<&str> = relative.iter().filter_map(|segment| segment.to_str()).collect();
+        let path = format!("{prefix}/{}", segments.join("/"));
+        !config.deny.is_denied(&path) && config.preload.iter().any(|pattern| glob_matches(pattern, &path))
+    })
+}
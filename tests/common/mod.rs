@@ -0,0 +1,263 @@
+// Boots the real server binary against a throwaway site, for end-to-end tests over plain sockets.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A temporary directory with a website/ root and a settings.cfg, removed again when the site is dropped. The server
+// runs inside it, so nothing it writes ends up in the developer's checkout.
+pub struct TestSite {
+    dir: PathBuf,
+    config: Vec<String>,
+}
+
+impl TestSite {
+    pub fn new() -> TestSite {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.subsec_nanos());
+        let dir = std::env::temp_dir().join(format!("backend-web-server-test-{}-{}-{nanos:x}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(dir.join("website")).unwrap();
+        TestSite { dir, config: Vec::new() }
+    }
+
+    // Writes a file below the website root, creating its directories.
+    pub fn file(self, path: &str, contents: impl AsRef<[u8]>) -> TestSite {
+        let path = self.dir.join("website").join(path.trim_start_matches('/'));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        self
+    }
+
+    // Adds a line to settings.cfg, after the defaults below.
+    pub fn config(mut self, line: &str) -> TestSite {
+        self.config.push(line.to_string());
+        self
+    }
+
+    pub fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn start(self) -> TestServer {
+        let mut settings = String::from("ip = \"127.0.0.1\"\nport = \"0\"\nroot-dir = \"website\"\nnum-threads = 4\ninteractive = false\nlog-level = \"info\"\n");
+        for line in &self.config {
+            settings.push_str(line);
+            settings.push('\n');
+        }
+        fs::write(self.dir.join("settings.cfg"), settings).unwrap();
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_backend_web_server"))
+            .arg("--daemon")
+            .current_dir(&self.dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Unable to start the server binary");
+
+        // The log names the address once it is bound; everything after that is drained so the server never blocks
+        // on a full pipe, and kept for tests that look at what the server printed.
+        let (found, address) = mpsc::channel();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let stderr = child.stderr.take().unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let printed = Arc::clone(&output);
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if let Some(bound) = line.split("Listening on: ").nth(1) {
+                    found.send(bound.trim_end_matches("...").parse::<SocketAddr>().ok()).unwrap_or(());
+                }
+                printed.lock().unwrap().push(line);
+            }
+        });
+        let errors = thread::spawn(move || {
+            let mut errors = String::new();
+            BufReader::new(stderr).read_to_string(&mut errors).unwrap_or(0);
+            errors
+        });
+
+        match address.recv_timeout(STARTUP_TIMEOUT) {
+            Ok(Some(address)) => TestServer { site: self, child, address, output },
+            _ => {
+                child.kill().unwrap_or(());
+                child.wait().unwrap_or_else(|err| panic!("The server did not start: {err}"));
+                panic!("The server did not start:\n{}", errors.join().unwrap_or_default());
+            },
+        }
+    }
+}
+
+impl Drop for TestSite {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).unwrap_or(());
+    }
+}
+
+pub struct TestServer {
+    // Dropped after the server has been stopped, see below.
+    site: TestSite,
+    child: Child,
+    address: SocketAddr,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get_site(&self) -> &TestSite {
+        &self.site
+    }
+
+    pub fn connect(&self) -> Client {
+        Client::connect(self.address)
+    }
+
+    // Waits a moment for a line of the server's standard output that contains `text`.
+    pub fn printed(&self, text: &str) -> bool {
+        let started = std::time::Instant::now();
+        while started.elapsed() < IO_TIMEOUT {
+            if self.output.lock().unwrap().iter().any(|line| line.contains(text)) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    // A single GET on a fresh connection.
+    pub fn get(&self, path: &str) -> Response {
+        self.connect().request("GET", path, &[("Connection", "close")])
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.child.kill().unwrap_or(());
+        self.child.wait().unwrap_or_else(|err| panic!("Unable to stop the server: {err}"));
+    }
+}
+
+// Just enough HTTP/1.1 to send requests and read Content-Length or chunked responses off one connection.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(address: SocketAddr) -> Client {
+        let stream = TcpStream::connect_timeout(&address, IO_TIMEOUT).unwrap();
+        stream.set_read_timeout(Some(IO_TIMEOUT)).unwrap();
+        stream.set_write_timeout(Some(IO_TIMEOUT)).unwrap();
+        Client { reader: BufReader::new(stream) }
+    }
+
+    pub fn request(&mut self, method: &str, path: &str, headers: &[(&str, &str)]) -> Response {
+        let mut raw = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n");
+        for (name, value) in headers {
+            raw.push_str(&format!("{name}: {value}\r\n"));
+        }
+        raw.push_str("\r\n");
+        self.send_raw(raw.as_bytes());
+        self.read_response(method == "HEAD")
+    }
+
+    pub fn send_raw(&mut self, bytes: &[u8]) {
+        self.reader.get_mut().write_all(bytes).unwrap();
+    }
+
+    pub fn read_response(&mut self, head_only: bool) -> Response {
+        let status_line = self.read_line();
+        let status = status_line.split(' ').nth(1).and_then(|status| status.parse().ok())
+            .unwrap_or_else(|| panic!("Not a status line: {status_line:?}"));
+
+        let mut headers = Vec::new();
+        loop {
+            let line = self.read_line();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap_or_else(|| panic!("Not a header: {line:?}"));
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        let mut response = Response { status, headers, body: Vec::new() };
+
+        if head_only || status < 200 || status == 204 || status == 304 {
+            return response;
+        }
+        if response.header("Transfer-Encoding").is_some_and(|value| value.eq_ignore_ascii_case("chunked")) {
+            loop {
+                let chunk = self.read_chunk();
+                if chunk.is_empty() {
+                    while !self.read_line().is_empty() {}
+                    break;
+                }
+                response.body.extend(chunk);
+            }
+        } else if let Some(length) = response.header("Content-Length") {
+            response.body = vec![0; length.parse().unwrap()];
+            self.reader.read_exact(&mut response.body).unwrap();
+        } else {
+            self.reader.read_to_end(&mut response.body).unwrap();
+        }
+        response
+    }
+
+    // The next chunk of a chunked body, for responses such as event streams that are read as they arrive. The last
+    // chunk is empty.
+    pub fn read_chunk(&mut self) -> Vec<u8> {
+        let size = usize::from_str_radix(self.read_line().split(';').next().unwrap().trim(), 16).unwrap();
+        let mut chunk = vec![0; size];
+        self.reader.read_exact(&mut chunk).unwrap();
+        if size > 0 {
+            self.read_line();
+        }
+        chunk
+    }
+
+    // Half-closes the connection: the server sees the end of the input, and responses can still be read.
+    pub fn finish_sending(&mut self) {
+        self.reader.get_ref().shutdown(Shutdown::Write).unwrap();
+    }
+
+    // Waits for the server to close its side; more data or running into the read timeout means it did not.
+    pub fn is_closed(&mut self) -> bool {
+        match self.reader.fill_buf() {
+            Ok(buf) => buf.is_empty(),
+            Err(err) => !matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        }
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line.trim_end_matches(['\r', '\n']).to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
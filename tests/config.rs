@@ -0,0 +1,70 @@
+// The configuration as a library: built from strings and files, and layered, without starting a server.
+
+use std::fs;
+use std::str::FromStr;
+use backend_web_server::config::Config;
+
+#[test]
+fn parses_settings_over_the_defaults() {
+    let config = Config::from_str("port = \"9000\"\ncompression = false\nmime.foo = \"text/x-foo\"").unwrap_or_else(|errors| panic!("{errors:?}"));
+    assert_eq!(config.port, "9000");
+    assert!(!config.compression);
+    assert_eq!(config.mime_types, vec![("foo".to_string(), "text/x-foo".to_string())]);
+    assert_eq!(config.threads, Config::default().threads);
+    assert_eq!(config.home_name, "home");
+}
+
+#[test]
+fn collects_every_invalid_value() {
+    let errors = Config::from_str("num-threads = many\nport = \"8080\"\nrate-limit = -1").err().expect("The settings are invalid");
+    assert_eq!(errors.len(), 2, "{errors:?}");
+    assert!(errors[0].starts_with("config:1: \"num-threads\" must be a whole number"), "{errors:?}");
+    assert!(errors[1].starts_with("config:3: "), "{errors:?}");
+}
+
+#[test]
+fn merges_settings_on_top() {
+    let base = Config::from_str("port = \"9000\"\nlisten = \"127.0.0.1:9001\"\nmime.foo = \"text/x-foo\"\nautoindex = true").unwrap();
+    let merged = base.merge("port = \"9100\"\nlisten = \"127.0.0.1:9002\"\nmime.bar = \"text/x-bar\"").unwrap_or_else(|errors| panic!("{errors:?}"));
+    assert_eq!(merged.port, "9100");
+    assert_eq!(merged.listen, vec!["127.0.0.1:9001", "127.0.0.1:9002"]);
+    assert_eq!(merged.mime_types.len(), 2);
+    assert!(merged.autoindex);
+
+    // Problems are checked against the merged settings.
+    let errors = Config::default().merge("ssl-only = true").err().expect("HTTPS needs a certificate");
+    assert!(errors[0].contains("no \"ssl-cert\" is set"), "{errors:?}");
+}
+
+#[test]
+fn reads_files_in_either_format() {
+    let dir = std::env::temp_dir().join(format!("backend-web-server-config-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("settings.toml"), "port = \"9000\"\nnum-threads = 4\n\n[mime]\nfoo = \"text/x-foo\"\n").unwrap();
+    fs::write(dir.join("settings.cfg"), "port = \"9000\"\nnum-threads = 0\n").unwrap();
+
+    let toml = Config::from_file(&dir.join("settings.toml"));
+    let legacy = Config::from_file(&dir.join("settings.cfg"));
+    let missing = Config::from_file(&dir.join("missing.cfg"));
+    fs::remove_dir_all(&dir).unwrap_or(());
+
+    let toml = toml.unwrap_or_else(|errors| panic!("{errors:?}"));
+    assert_eq!((toml.port.as_str(), toml.threads), ("9000", 4));
+    assert_eq!(toml.mime_types, vec![("foo".to_string(), "text/x-foo".to_string())]);
+    let errors = legacy.err().expect("num-threads must be at least 1");
+    assert_eq!(errors, vec![format!("{}:2: \"num-threads\" must be at least 1", dir.join("settings.cfg").display())]);
+    assert!(missing.err().expect("The file does not exist")[0].starts_with("Unable to open configuration file"));
+}
+
+#[test]
+fn refuses_rate_limits_that_cannot_be_counted() {
+    for (settings, expected) in [
+        ("rate-limit = 1e-300", "config:1: \"rate-limit\" is too low to ever refill a \"rate-limit-burst\" of 20"),
+        ("rate-limit = inf", "config:1: \"rate-limit\" must be a finite number, not inf"),
+        ("rate-limit = 5\nrate-limit-burst = NaN", "config:2: \"rate-limit-burst\" must be at least 1 while rate limiting is enabled"),
+    ] {
+        let errors = Config::from_str(settings).err().expect(settings);
+        assert_eq!(errors, vec![expected.to_string()]);
+    }
+    assert!(Config::from_str("rate-limit = 0.001\nrate-limit-burst = 5").is_ok());
+}
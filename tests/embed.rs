@@ -0,0 +1,99 @@
+// The server run inside this test binary, as another program would embed it. Only one server can run per process,
+// so everything is checked in a single test.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use backend_web_server::config::Config;
+use backend_web_server::{Cgi, Handler, HttpMethod, HttpRequest, HttpResponse, Proxy, Server, StaticFiles};
+
+struct Counter {
+    start: u32,
+}
+
+impl Handler for Counter {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        HttpResponse::ok_text(format!("{}", self.start + request.get_param("by").and_then(|by| by.parse::<u32>().ok()).unwrap_or(0)))
+    }
+}
+
+fn get(port: u16, path: &str) -> Option<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    Some(response)
+}
+
+#[test]
+fn runs_embedded_with_its_own_handlers_until_shut_down() {
+    let dir = std::env::temp_dir().join(format!("backend-web-server-embed-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("notes.txt"), "static file").unwrap();
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs/guide.txt"), "the guide").unwrap();
+    let scripts = dir.join("bin");
+    fs::create_dir_all(scripts.join("scripts")).unwrap();
+    let script = scripts.join("scripts/env.sh");
+    fs::write(&script, "#!/bin/sh\nprintf 'Content-Type: text/plain\\r\\nX-Script: yes\\r\\n\\r\\n%s %s %s' \"$REQUEST_SCHEME\" \"$PATH_INFO\" \"$QUERY_STRING\"\n").unwrap();
+    #[cfg(unix)]
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let config = Config::from_str(&format!("port = \"0\"\nroot-dir = \"{}\"\nnum-threads = 2\ninteractive = false\nlog-level = \"warn\"", dir.display()))
+        .unwrap_or_else(|errors| panic!("{errors:?}"));
+    let mut server = Server::new(config);
+    // Bound before running, to learn the port picked, which the proxied routes need.
+    let port = server.bind().unwrap()[0].port();
+    server.route(HttpMethod::Get, "/hello/:name", |request| HttpResponse::ok_text(format!("hello {}", request.get_param("name").unwrap_or("?"))));
+    server.handler(HttpMethod::Get, "/count/:by", Counter { start: 40 });
+    // The request's path goes after the upstream's, so this ends up at /hello/proxied.
+    server.handler(HttpMethod::Get, "/proxied", Proxy::new(&format!("http://127.0.0.1:{port}/hello")).unwrap());
+    server.route(HttpMethod::Get, "/echo/*rest", |request| HttpResponse::ok_text(request.get_header("X-Forwarded-Proto").unwrap_or("none").to_string()));
+    server.handler(HttpMethod::Get, "/forwarded", Proxy::new(&format!("http://127.0.0.1:{port}/echo")).unwrap());
+    server.handler(HttpMethod::Get, "/docs/*rest", StaticFiles);
+    server.handler(HttpMethod::Get, "/scripts/*rest", Cgi::new(&scripts));
+    let handle = server.shutdown_handle();
+    let (stopped, finished) = mpsc::channel();
+    thread::spawn(move || {
+        stopped.send(server.run()).unwrap_or(());
+    });
+
+    let started = Instant::now();
+    let mut hello = get(port, "/hello/embedder");
+    while hello.is_none() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(20));
+        hello = get(port, "/hello/embedder");
+    }
+    let hello = hello.expect("The server did not start");
+    let file = get(port, "/notes.txt").unwrap_or_default();
+    let counted = get(port, "/count/2").unwrap_or_default();
+    let proxied = get(port, "/proxied").unwrap_or_default();
+    let forwarded = get(port, "/forwarded").unwrap_or_default();
+    let guide = get(port, "/docs/guide.txt").unwrap_or_default();
+    let missing = get(port, "/docs/missing.txt").unwrap_or_default();
+    let script = get(port, "/scripts/env.sh/extra?a=1").unwrap_or_default();
+    let no_script = get(port, "/scripts/none.sh").unwrap_or_default();
+
+    handle.shutdown();
+    let result = finished.recv_timeout(Duration::from_secs(10));
+    fs::remove_dir_all(&dir).unwrap_or(());
+
+    assert!(hello.starts_with("HTTP/1.1 200"), "{hello}");
+    assert!(hello.ends_with("hello embedder"), "{hello}");
+    assert!(file.ends_with("static file"), "{file}");
+    assert!(counted.ends_with("\r\n\r\n42"), "{counted}");
+    assert!(proxied.starts_with("HTTP/1.1 200") && proxied.ends_with("hello proxied"), "{proxied}");
+    assert!(forwarded.ends_with("\r\n\r\nhttp"), "{forwarded}");
+    assert!(guide.starts_with("HTTP/1.1 200") && guide.contains("Content-Type: text/plain") && guide.ends_with("the guide"), "{guide}");
+    assert!(missing.starts_with("HTTP/1.1 404"), "{missing}");
+    assert!(script.starts_with("HTTP/1.1 200") && script.contains("X-Script: yes"), "{script}");
+    assert!(script.ends_with("\r\n\r\nhttp /extra a=1"), "{script}");
+    assert!(no_script.starts_with("HTTP/1.1 404"), "{no_script}");
+    assert!(matches!(result, Ok(Ok(()))), "The server did not stop cleanly: {result:?}");
+}
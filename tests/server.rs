@@ -0,0 +1,597 @@
+mod common;
+
+use common::TestSite;
+
+#[test]
+fn serves_routes_and_static_files() {
+    let server = TestSite::new()
+        .file("home.html", "<h1>home</h1>")
+        .file("docs/guide.html", "<h1>guide</h1>")
+        .start();
+
+    let health = server.get("/api/health");
+    assert_eq!(health.status, 200);
+    assert_eq!(health.text(), "ok");
+
+    let home = server.get("/");
+    assert_eq!(home.status, 200);
+    assert_eq!(home.text(), "<h1>home</h1>");
+
+    let guide = server.get("/docs/guide.html");
+    assert_eq!(guide.status, 200);
+    assert_eq!(guide.text(), "<h1>guide</h1>");
+}
+
+#[test]
+fn answers_errors_with_the_configured_pages() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .file("__errors__/404.html", "<p>nothing here</p>")
+        .start();
+
+    let missing = server.get("/missing.html");
+    assert_eq!(missing.status, 404);
+    assert_eq!(missing.text(), "<p>nothing here</p>");
+
+    // The error pages themselves are hidden, and nothing outside the root is reachable.
+    assert_eq!(server.get("/__errors__/404.html").status, 404);
+    assert_eq!(server.get("/../settings.cfg").status, 403);
+}
+
+#[test]
+fn sends_content_types_by_extension() {
+    let server = TestSite::new()
+        .file("style.css", "body {}")
+        .file("app.js", "run()")
+        .file("data.custom", "{}")
+        .file("blob.unknownext", "?")
+        .file("notes.md", "# notes")
+        .file("table.csv", "a,b")
+        .config("mime.custom = application/x-custom")
+        .config("charset.md = \"\"")
+        .config("charset.csv = \"iso-8859-1\"")
+        .start();
+
+    assert_eq!(server.get("/style.css").header("Content-Type"), Some("text/css; charset=utf-8"));
+    assert_eq!(server.get("/app.js").header("Content-Type"), Some("application/javascript; charset=utf-8"));
+    assert_eq!(server.get("/data.custom").header("Content-Type"), Some("application/x-custom"));
+    assert_eq!(server.get("/blob.unknownext").header("Content-Type"), Some("application/octet-stream"));
+    assert_eq!(server.get("/notes.md").header("Content-Type"), Some("text/markdown"));
+    assert_eq!(server.get("/table.csv").header("Content-Type"), Some("text/csv; charset=iso-8859-1"));
+    assert_eq!(server.get("/api/health").header("Content-Type"), Some("text/plain; charset=utf-8"));
+}
+
+#[test]
+fn keeps_connections_alive() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .file("a.txt", "first")
+        .config("keep-alive-max = 3")
+        .start();
+
+    let mut client = server.connect();
+    let first = client.request("GET", "/a.txt", &[]);
+    assert_eq!(first.text(), "first");
+    assert_eq!(first.header("Connection"), Some("keep-alive"));
+    let second = client.request("HEAD", "/a.txt", &[]);
+    assert_eq!(second.status, 200);
+    assert_eq!(second.header("Content-Length"), Some("5"));
+
+    // The last request allowed on the connection closes it.
+    let third = client.request("GET", "/a.txt", &[]);
+    assert_eq!(third.header("Connection"), Some("close"));
+    assert!(client.is_closed());
+
+    let mut client = server.connect();
+    let closing = client.request("GET", "/a.txt", &[("Connection", "close")]);
+    assert_eq!(closing.header("Connection"), Some("close"));
+    assert!(client.is_closed());
+}
+
+#[test]
+fn answers_favicon_and_well_known_requests() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .file(".well-known/acme-challenge/Xk3d9_token", "Xk3d9_token.key")
+        .file(".well-known/security.txt", "Contact: mailto:security@example.com")
+        .start();
+
+    let favicon = server.get("/favicon.ico");
+    assert_eq!(favicon.status, 204);
+    assert_eq!(favicon.header("Cache-Control"), Some("max-age=86400"));
+
+    // Challenge tokens have no extension and must not be rewritten to .html.
+    let token = server.get("/.well-known/acme-challenge/Xk3d9_token");
+    assert_eq!(token.status, 200);
+    assert_eq!(token.text(), "Xk3d9_token.key");
+    assert_eq!(server.get("/.well-known/security.txt").status, 200);
+    assert_eq!(server.get("/.well-known/acme-challenge/missing").status, 404);
+}
+
+#[test]
+fn answers_expect_continue_before_the_body() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .config("max-body-size = 16")
+        .config("auth./private = bearer:secret")
+        .start();
+
+    // The go-ahead comes first, then the answer to the whole request, and the connection stays usable.
+    let mut client = server.connect();
+    client.send_raw(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n");
+    assert_eq!(client.read_response(false).status, 100);
+    client.send_raw(b"hello");
+    assert_ne!(client.read_response(false).status, 100);
+    assert_eq!(client.request("GET", "/", &[]).text(), "home");
+
+    // Chunked bodies are waited for in the same way, and read to the end before the next request.
+    client.send_raw(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nExpect: 100-continue\r\n\r\n");
+    assert_eq!(client.read_response(false).status, 100);
+    client.send_raw(b"5\r\nhello\r\n0\r\n\r\n");
+    assert_ne!(client.read_response(false).status, 100);
+    assert_eq!(client.request("GET", "/", &[]).text(), "home");
+
+    // Bodies that would be refused are refused before they are sent.
+    let mut client = server.connect();
+    client.send_raw(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1000\r\nExpect: 100-continue\r\n\r\n");
+    assert_eq!(client.read_response(false).status, 413);
+    assert!(client.is_closed());
+
+    let mut client = server.connect();
+    client.send_raw(b"POST /private/x HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n");
+    let unauthorized = client.read_response(false);
+    assert_eq!(unauthorized.status, 401);
+    assert!(unauthorized.header("WWW-Authenticate").is_some_and(|challenge| challenge.starts_with("Bearer")));
+
+    let mut client = server.connect();
+    client.send_raw(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: something-else\r\n\r\n");
+    assert_eq!(client.read_response(false).status, 417);
+}
+
+#[test]
+fn answers_malformed_requests_with_bad_request() {
+    let server = TestSite::new().file("home.html", "home").start();
+
+    for raw in [&b"GET\r\n\r\n"[..], b"GET / HTTP/1.1 extra\r\n\r\n", b"GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n", b"GET /\xff HTTP/1.1\r\n\r\n"] {
+        let mut client = server.connect();
+        client.send_raw(raw);
+        assert_eq!(client.read_response(false).status, 400, "{:?}", String::from_utf8_lossy(raw));
+        assert!(client.is_closed());
+    }
+}
+
+#[test]
+fn refuses_requests_whose_body_could_be_read_two_ways() {
+    let server = TestSite::new().file("home.html", "home").file("s.txt", "secret").start();
+
+    // Each would hide a second request in what the server might take for a body, or the other way round. Only the
+    // refusal comes back, and nothing after it is read.
+    for raw in [
+        &b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /s.txt HTTP/1.1\r\nHost: localhost\r\n\r\n"[..],
+        b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nContent-Length: 31\r\n\r\nGET /s.txt HTTP/1.1\r\nHost: a\r\n\r\n",
+        b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0, 31\r\n\r\nGET /s.txt HTTP/1.1\r\nHost: a\r\n\r\n",
+        b"POST / HTTP/1.0\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /s.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    ] {
+        let mut client = server.connect();
+        client.send_raw(raw);
+        assert_eq!(client.read_response(false).status, 400, "{:?}", String::from_utf8_lossy(raw));
+        assert!(client.is_closed(), "{:?}", String::from_utf8_lossy(raw));
+    }
+
+    let mut client = server.connect();
+    client.send_raw(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\nGET /s.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(client.read_response(false).status, 501);
+    assert!(client.is_closed());
+
+    // Nor is a body asked for that could not be read.
+    let mut client = server.connect();
+    client.send_raw(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\nExpect: 100-continue\r\n\r\n");
+    assert_eq!(client.read_response(false).status, 501);
+    assert!(client.is_closed());
+
+    // A chunked body is read to its last chunk, so the request after it is the one that comes next.
+    let mut client = server.connect();
+    client.send_raw(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert_ne!(client.read_response(false).status, 400);
+    let next = client.request("GET", "/", &[]);
+    assert_eq!((next.status, next.text().as_str()), (200, "home"));
+}
+
+#[test]
+fn answers_if_range_and_multiple_ranges() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .file("data.txt", "0123456789")
+        .file("big.txt", "abcdefghijklmnopqrstuvwxyz")
+        .config("stream-threshold = 16")
+        .start();
+
+    let full = server.get("/data.txt");
+    let etag = full.header("ETag").unwrap().to_string();
+    let modified = full.header("Last-Modified").unwrap().to_string();
+
+    let mut client = server.connect();
+    let single = client.request("GET", "/data.txt", &[("Range", "bytes=2-4"), ("If-Range", &etag)]);
+    assert_eq!(single.status, 206);
+    assert_eq!(single.text(), "234");
+    assert_eq!(client.request("GET", "/data.txt", &[("Range", "bytes=2-4"), ("If-Range", &modified)]).status, 206);
+
+    // A client holding another version of the file gets all of this one.
+    let changed = client.request("GET", "/data.txt", &[("Range", "bytes=2-4"), ("If-Range", "\"other\"")]);
+    assert_eq!(changed.status, 200);
+    assert_eq!(changed.text(), "0123456789");
+    assert_eq!(client.request("GET", "/data.txt", &[("Range", "bytes=2-4"), ("If-Range", &format!("W/{etag}"))]).status, 200);
+
+    // The same from memory and from a file too large to be held there.
+    for (path, first, last) in [("/data.txt", "0-1/10\r\n\r\n01", "8-9/10\r\n\r\n89"), ("/big.txt", "0-1/26\r\n\r\nab", "24-25/26\r\n\r\nyz")] {
+        let multi = client.request("GET", path, &[("Range", "bytes=0-1,-2")]);
+        assert_eq!(multi.status, 206);
+        let boundary = multi.header("Content-Type").and_then(|value| value.strip_prefix("multipart/byteranges; boundary=")).unwrap();
+        let body = multi.text();
+        let parts: Vec<&str> = body.split(&format!("--{boundary}")).collect();
+        assert_eq!(parts.len(), 4, "{body}");
+        assert!(parts[1].contains("Content-Type: text/plain; charset=utf-8\r\n") && parts[1].ends_with(&format!("{first}\r\n")), "{body}");
+        assert!(parts[2].ends_with(&format!("{last}\r\n")), "{body}");
+        assert_eq!(parts[3], "--\r\n");
+    }
+}
+
+#[test]
+fn answers_options_with_the_allowed_methods() {
+    let server = TestSite::new().file("home.html", "home").start();
+    let mut client = server.connect();
+
+    let everything = client.request("OPTIONS", "*", &[]);
+    assert_eq!(everything.status, 204);
+    assert_eq!(everything.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(everything.header("Content-Length"), None);
+
+    let file = client.request("OPTIONS", "/home.html", &[]);
+    assert_eq!(file.status, 204);
+    assert_eq!(file.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(client.request("OPTIONS", "/api/health", &[]).header("Allow"), Some("GET, HEAD, OPTIONS"));
+
+    let refused = client.request("DELETE", "/home.html", &[]);
+    assert_eq!(refused.status, 405);
+    assert_eq!(refused.header("Allow"), Some("GET, HEAD, OPTIONS"));
+}
+
+#[test]
+fn negotiates_extensionless_paths_on_accept() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .file("about.html", "<p>about</p>")
+        .file("page.html", "<p>page</p>")
+        .file("page.json", "{\"page\":true}")
+        .file("page.md", "# page")
+        .file("data.json", "[]")
+        .start();
+    let mut client = server.connect();
+    let varies = |response: &common::Response| response.header("Vary").is_some_and(|vary| vary.split(", ").any(|field| field == "Accept"));
+
+    let html = client.request("GET", "/page", &[("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")]);
+    assert_eq!(html.text(), "<p>page</p>");
+    assert!(varies(&html));
+    assert_eq!(client.request("GET", "/page", &[("Accept", "application/json")]).text(), "{\"page\":true}");
+    assert_eq!(client.request("GET", "/page", &[("Accept", "text/markdown, text/html;q=0.5")]).text(), "# page");
+    assert_eq!(client.request("GET", "/page", &[]).text(), "<p>page</p>");
+
+    let refused = client.request("GET", "/page", &[("Accept", "image/png")]);
+    assert_eq!(refused.status, 406);
+    assert!(varies(&refused));
+
+    // Pages that only exist as HTML, and a single other representation, are served as before.
+    let mut client = server.connect();
+    let about = client.request("GET", "/about", &[("Accept", "application/json")]);
+    assert_eq!(about.text(), "<p>about</p>");
+    assert!(!varies(&about));
+    assert_eq!(client.request("GET", "/data", &[("Accept", "application/json")]).text(), "[]");
+}
+
+#[test]
+fn sends_date_server_and_default_headers() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .config("header.X-Custom = \"custom value\"")
+        .start();
+
+    let home = server.get("/");
+    let date = httpdate::parse_http_date(home.header("Date").unwrap()).unwrap();
+    assert!(std::time::SystemTime::now().duration_since(date).unwrap() < std::time::Duration::from_secs(5));
+    assert_eq!(home.header("Server"), Some("backend_web_server"));
+    assert_eq!(home.header("X-Custom"), Some("custom value"));
+
+    // Error responses get them as well.
+    let missing = server.get("/missing.html");
+    assert_eq!(missing.status, 404);
+    assert!(missing.header("Date").is_some());
+    assert_eq!(missing.header("Server"), Some("backend_web_server"));
+    assert_eq!(missing.header("X-Custom"), Some("custom value"));
+
+    let server = TestSite::new().file("home.html", "home").config("server-header = \"\"").start();
+    let home = server.get("/");
+    assert_eq!(home.header("Server"), None);
+    assert!(home.header("Date").is_some());
+}
+
+#[test]
+fn closes_connections_after_the_last_response() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .file("a.txt", "first")
+        .config("max-body-size = 16")
+        .start();
+
+    // A client that stops sending still gets answers to everything it sent, then the end of the stream.
+    let mut client = server.connect();
+    client.send_raw(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    client.finish_sending();
+    assert_eq!(client.read_response(false).text(), "first");
+    assert_eq!(client.read_response(false).text(), "home");
+    assert!(client.is_closed());
+
+    // Requests after the one that closes the connection are dropped without resetting it under the response.
+    let mut client = server.connect();
+    client.send_raw(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let closing = client.read_response(false);
+    assert_eq!(closing.text(), "first");
+    assert_eq!(closing.header("Connection"), Some("close"));
+    assert!(client.is_closed());
+
+    // The same for a body that is refused without being read.
+    let mut client = server.connect();
+    let mut raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100000\r\n\r\n".to_vec();
+    raw.resize(raw.len() + 100_000, b'x');
+    client.send_raw(&raw);
+    let refused = client.read_response(false);
+    assert_eq!(refused.status, 413);
+    assert_eq!(refused.header("Connection"), Some("close"));
+    assert!(client.is_closed());
+}
+
+#[test]
+fn streams_large_files_and_their_ranges() {
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .file("large.bin", &data)
+        .config("stream-threshold = 1024")
+        .start();
+    let mut client = server.connect();
+
+    assert_eq!(client.request("GET", "/large.bin", &[]).body, data);
+    // Ranges that start inside a page, the last byte, and several at once.
+    let ranged = client.request("GET", "/large.bin", &[("Range", "bytes=5000-9099")]);
+    assert_eq!(ranged.status, 206);
+    assert_eq!(ranged.body, &data[5000..9100]);
+    assert_eq!(client.request("GET", "/large.bin", &[("Range", "bytes=-1")]).body, &data[19_999..]);
+    let multi = client.request("GET", "/large.bin", &[("Range", "bytes=4097-4100,19990-")]);
+    assert_eq!(multi.status, 206);
+    let body = multi.body;
+    let contains = |part: &[u8]| body.windows(part.len()).any(|window| window == part);
+    assert!(contains(&data[4097..4101]) && contains(&data[19_990..]));
+}
+
+#[test]
+// A watcher would notice the files being rewritten.
+#[cfg(not(feature = "watch"))]
+fn preloads_files_into_the_cache() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .file("assets/app.js", "let version = 1;")
+        .file("assets/deep/style.css", "body { color: red }")
+        .file("other.js", "let version = 1;")
+        .config("preload = \"/assets/*\"")
+        .start();
+
+    // Files rewritten behind the cache's back, with their size and time unchanged, are only seen where nothing was
+    // preloaded.
+    let root = server.get_site().get_dir().join("website");
+    for (path, contents) in [("assets/app.js", "let version = 2;"), ("assets/deep/style.css", "body { color: 0x0 }"), ("other.js", "let version = 2;")] {
+        let modified = std::fs::metadata(root.join(path)).unwrap().modified().unwrap();
+        std::fs::write(root.join(path), contents).unwrap();
+        std::fs::File::options().write(true).open(root.join(path)).unwrap().set_modified(modified).unwrap();
+    }
+    assert_eq!(server.get("/assets/app.js").text(), "let version = 1;");
+    assert_eq!(server.get("/assets/deep/style.css").text(), "body { color: red }");
+    assert_eq!(server.get("/other.js").text(), "let version = 2;");
+}
+
+#[test]
+fn shows_errors_and_turns_off_caching_in_dev_mode() {
+    let server = TestSite::new()
+        .file("notes.txt", "home")
+        .file("uploads/blocked", "not a directory")
+        .config("upload./files = \"website/uploads\"")
+        .config("dev-mode = true")
+        .start();
+    let port = server.get_address().port();
+    assert!(server.printed(&format!("  http://localhost:{port}/")));
+
+    let home = server.get("/notes.txt");
+    assert_eq!(home.header("Cache-Control"), Some("no-store"));
+    assert_eq!(home.header("ETag"), None);
+    assert_eq!(home.header("Last-Modified"), None);
+    assert!(server.printed("GET /notes.txt 200 4 bytes in "));
+
+    let failed = server.connect().request("PUT", "/files/blocked/a.txt", &[("Content-Length", "0"), ("Connection", "close")]);
+    assert_eq!(failed.status, 500);
+    let text = failed.text();
+    assert!(text.starts_with("500 Internal Server Error\n\nPUT /files/blocked/a.txt\n"), "{text}");
+    assert!(text.contains("blocked"), "{text}");
+
+    // Without it, the cause stays in the log.
+    let server = TestSite::new()
+        .file("uploads/blocked", "not a directory")
+        .config("upload./files = \"website/uploads\"")
+        .start();
+    let failed = server.connect().request("PUT", "/files/blocked/a.txt", &[("Content-Length", "0"), ("Connection", "close")]);
+    assert_eq!(failed.status, 500);
+    assert!(!failed.text().contains("blocked"));
+}
+
+#[test]
+fn reads_chunked_uploads_with_the_request_limits() {
+    let server = TestSite::new()
+        .file("uploads/.keep", "")
+        .config("upload./files = \"website/uploads\"")
+        .config("max-header-bytes = 256")
+        .start();
+    let upload = |path: &str, body: &str| {
+        let mut client = server.connect();
+        client.send_raw(format!("PUT {path} HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{body}").as_bytes());
+        client.read_response(false)
+    };
+
+    assert_eq!(upload("/files/a.txt", "3\r\nabc\r\n2\r\nde\r\n0\r\nX-Note: done\r\n\r\n").status, 201);
+    assert_eq!(std::fs::read_to_string(server.get_site().get_dir().join("website/uploads/a.txt")).unwrap(), "abcde");
+
+    assert_eq!(upload("/files/b.txt", "+5\r\nhello\r\n0\r\n\r\n").status, 400);
+    let trailer = format!("X-Long: {}\r\n", "x".repeat(300));
+    assert_eq!(upload("/files/c.txt", &format!("5\r\nhello\r\n0\r\n{trailer}\r\n")).status, 431);
+    for name in ["b.txt", "c.txt"] {
+        assert!(!server.get_site().get_dir().join("website/uploads").join(name).exists(), "{name}");
+    }
+}
+
+#[test]
+#[cfg(feature = "watch")]
+fn reloads_pages_in_dev_mode_when_files_change() {
+    let server = TestSite::new()
+        .file("index.html", "<html><BODY>old</BODY></html>")
+        .config("dev-mode = true")
+        .start();
+
+    let page = server.get("/index.html").text();
+    assert!(page.starts_with("<html><BODY>old<script>"), "{page}");
+    assert!(page.ends_with("location.reload());</script></BODY></html>"), "{page}");
+
+    let mut events = server.connect();
+    events.send_raw(b"GET /__dev__/reload HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let head = events.read_response(true);
+    assert_eq!(head.status, 200);
+    assert!(head.header("Content-Type").is_some_and(|value| value.starts_with("text/event-stream")));
+
+    // The same size and time, which only the watcher tells apart from the cached copy.
+    let file = server.get_site().get_dir().join("website/index.html");
+    let modified = std::fs::metadata(&file).unwrap().modified().unwrap();
+    std::fs::write(&file, "<html><BODY>new</BODY></html>").unwrap();
+    std::fs::File::options().write(true).open(&file).unwrap().set_modified(modified).unwrap();
+
+    assert_eq!(String::from_utf8(events.read_chunk()).unwrap(), "event: reload\ndata: reload\n\n");
+    assert!(server.get("/index.html").text().starts_with("<html><BODY>new<script>"));
+}
+
+#[test]
+fn serves_mounted_sites_below_their_prefix() {
+    let server = TestSite::new()
+        .file("home.html", "main home")
+        .file("__errors__/404.html", "main 404")
+        .file("../docs-site/index.html", "docs home")
+        .file("../docs-site/guide/intro.html", "docs intro")
+        .file("../docs-site/__errors__/404.html", "docs 404")
+        .config("mount./docs = docs-site")
+        .config("cache.docs = /docs/* no-cache")
+        .start();
+
+    let bare = server.get("/docs");
+    assert_eq!(bare.status, 301);
+    assert_eq!(bare.header("Location"), Some("/docs/"));
+    assert_eq!(server.get("/docs/").text(), "docs home");
+    assert_eq!(server.get("/").text(), "main home");
+
+    let intro = server.get("/docs/guide/intro.html");
+    assert_eq!(intro.text(), "docs intro");
+    assert_eq!(intro.header("Cache-Control"), Some("no-cache"));
+    assert_eq!(server.get("/docs/guide").header("Location"), Some("/docs/guide/"));
+
+    // Each site answers with its own error pages, and only whole path segments are mounted.
+    assert_eq!(server.get("/docs/missing.html").text(), "docs 404");
+    assert_eq!(server.get("/docsx/index.html").text(), "main 404");
+    assert_eq!(server.get("/index.html").text(), "main 404");
+}
+
+#[test]
+fn throttles_responses_to_the_bandwidth_limit() {
+    let large: Vec<u8> = (0..150_000).map(|i| (i % 251) as u8).collect();
+    let server = TestSite::new()
+        .file("large.bin", &large)
+        .config("bandwidth-limit = 50000")
+        .start();
+
+    // A second's worth goes out at once, the rest at the limit.
+    let started = std::time::Instant::now();
+    let response = server.get("/large.bin");
+    let elapsed = started.elapsed();
+    assert_eq!(response.body, large);
+    assert!(elapsed >= std::time::Duration::from_millis(1800), "took {elapsed:?}");
+    assert!(elapsed < std::time::Duration::from_secs(4), "took {elapsed:?}");
+}
+
+#[test]
+fn turns_connections_away_above_the_per_address_limit() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .config("max-connections-per-ip = 1")
+        .start();
+
+    let mut first = server.connect();
+    assert_eq!(first.request("GET", "/home.html", &[]).status, 200);
+    let refused = server.get("/home.html");
+    assert_eq!(refused.status, 503);
+    assert_eq!(refused.header("Retry-After"), Some("1"));
+    assert!(server.printed("too many open connections from this address"));
+
+    drop(first);
+    let started = std::time::Instant::now();
+    while server.get("/home.html").status != 200 {
+        assert!(started.elapsed() < std::time::Duration::from_secs(5), "the closed connection was never released");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn applies_the_per_address_limit_to_clients_behind_a_proxy() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .config("proxy-protocol = true")
+        .config("max-connections-per-ip = 1")
+        .start();
+    let connect = |client: &str| {
+        let mut connection = server.connect();
+        connection.send_raw(format!("PROXY TCP4 {client} 192.0.2.100 50000 80\r\nGET /home.html HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes());
+        connection
+    };
+
+    // Every connection comes from the balancer's address, but only clients are held to the limit.
+    let mut first = connect("192.0.2.1");
+    assert_eq!(first.read_response(false).status, 200);
+    let mut second = connect("192.0.2.2");
+    assert_eq!(second.read_response(false).status, 200);
+
+    let refused = connect("192.0.2.1").read_response(false);
+    assert_eq!(refused.status, 503);
+    assert_eq!(refused.header("Retry-After"), Some("1"));
+}
+
+#[test]
+fn turns_connections_away_above_the_limit() {
+    let server = TestSite::new()
+        .file("home.html", "home")
+        .config("max-connections = 1")
+        .start();
+
+    // An idle keep-alive connection counts too.
+    let mut first = server.connect();
+    assert_eq!(first.request("GET", "/home.html", &[]).status, 200);
+    let refused = server.get("/home.html");
+    assert_eq!(refused.status, 503);
+    assert_eq!(refused.header("Retry-After"), Some("1"));
+
+    drop(first);
+    let started = std::time::Instant::now();
+    while server.get("/home.html").status != 200 {
+        assert!(started.elapsed() < std::time::Duration::from_secs(5), "the closed connection was never released");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+}
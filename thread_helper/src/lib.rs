@@ -1,10 +1,13 @@
+use log::debug;
 use std::{
     sync::{
+        atomic::{AtomicUsize, Ordering},
         mpsc,
         Arc,
         Mutex
     },
     thread,
+    time::{Duration, Instant},
 };
 
 struct Worker {
@@ -13,18 +16,25 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>, stats: Arc<PoolStats>) -> Worker {
         let thread = thread::spawn(move || loop {
             let message = receiver.lock().unwrap().recv();
 
             match message {
-                Ok(job) => {
-                    println!("[Worker {id}] Processing request...");
+                Ok(Message::Job(job)) => {
+                    debug!("[Worker {id}] Processing request...");
 
+                    stats.busy.fetch_add(1, Ordering::Relaxed);
+                    stats.queued.fetch_sub(1, Ordering::Relaxed);
                     job();
+                    stats.busy.fetch_sub(1, Ordering::Relaxed);
+                }
+                Ok(Message::Retire) => {
+                    debug!("[Worker {id}] Retired; Shutting down...");
+                    break;
                 }
                 Err(_) => {
-                    println!("[Worker {id}] Disconnected; Shutting down...");
+                    debug!("[Worker {id}] Disconnected; Shutting down...");
                     break;
                 }
             }
@@ -36,9 +46,27 @@ impl Worker {
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+enum Message {
+    Job(Job),
+    // Queued like a job, so the worker that takes it has finished everything sent before.
+    Retire,
+}
+
+#[derive(Default)]
+struct PoolStats {
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+    peak_queued: AtomicUsize,
+}
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    workers: Mutex<Vec<Worker>>,
+    sender: Option<mpsc::Sender<Message>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+    stats: Arc<PoolStats>,
+    // The number of workers asked for; retiring ones may still be finishing their jobs.
+    size: AtomicUsize,
+    next_id: AtomicUsize,
 }
 
 impl ThreadPool {
@@ -48,27 +76,93 @@ impl ThreadPool {
         let (sender, receiver) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(receiver));
+        let stats = Arc::new(PoolStats::default());
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&stats)));
         }
 
-        ThreadPool { workers, sender: Some(sender) }
+        ThreadPool {
+            workers: Mutex::new(workers),
+            sender: Some(sender),
+            receiver,
+            stats,
+            size: AtomicUsize::new(size),
+            next_id: AtomicUsize::new(size),
+        }
     }
 
     pub fn execute<F>(&self, f: F) where F: FnOnce() + Send + 'static, {
         let job = Box::new(f);
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        let queued = self.stats.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.stats.peak_queued.fetch_max(queued, Ordering::Relaxed);
+        self.sender.as_ref().unwrap().send(Message::Job(job)).unwrap();
     }
-}
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+    // New workers start right away. Shrinking queues one retirement per worker too many, so workers only leave once
+    // the jobs already waiting have been picked up, and never in the middle of one.
+    pub fn resize(&self, size: usize) {
+        assert!(size > 0);
+        let Some(sender) = self.sender.as_ref() else {
+            return;
+        };
+
+        let mut workers = self.workers.lock().unwrap();
+        // Retired workers have nothing left to join for.
+        workers.retain(|worker| worker.thread.as_ref().is_some_and(|thread| !thread.is_finished()));
+        let current = self.size.swap(size, Ordering::Relaxed);
+        for _ in current..size {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            workers.push(Worker::new(id, Arc::clone(&self.receiver), Arc::clone(&self.stats)));
+        }
+        for _ in size..current {
+            sender.send(Message::Retire).unwrap();
+        }
+    }
+
+    // Whether `limit` jobs are already waiting for a worker; a `limit` of 0 means unbounded.
+    pub fn is_full(&self, limit: usize) -> bool {
+        limit > 0 && self.get_queued_jobs() >= limit
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    // Jobs waiting for a free worker, not counting the ones being run.
+    pub fn get_queued_jobs(&self) -> usize {
+        self.stats.queued.load(Ordering::Relaxed)
+    }
+
+    pub fn get_peak_queued_jobs(&self) -> usize {
+        self.stats.peak_queued.load(Ordering::Relaxed)
+    }
+
+    pub fn get_busy_workers(&self) -> usize {
+        self.stats.busy.load(Ordering::Relaxed)
+    }
+
+    pub fn shutdown(&mut self, timeout: Duration) -> bool {
+        drop(self.sender.take());
+
+        let deadline = Instant::now() + timeout;
+        while self.workers.get_mut().unwrap_or_else(|e| e.into_inner()).iter().any(|worker| worker.thread.as_ref().is_some_and(|thread| !thread.is_finished())) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(25));
+        }
+
+        self.join_workers();
+        true
+    }
+
+    fn join_workers(&mut self) {
+        for worker in self.workers.get_mut().unwrap_or_else(|e| e.into_inner()) {
+            debug!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
                 thread.join().unwrap();
@@ -77,9 +171,76 @@ impl Drop for ThreadPool {
     }
 }
 
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        self.join_workers();
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
     }
+
+    #[test]
+    fn tracks_queued_and_busy_jobs() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        });
+        running.recv().unwrap();
+        assert_eq!(pool.get_busy_workers(), 1);
+        assert_eq!(pool.get_queued_jobs(), 0);
+
+        assert!(!pool.is_full(2));
+        pool.execute(|| {});
+        pool.execute(|| {});
+        assert!(pool.is_full(2));
+        assert!(!pool.is_full(0));
+        pool.execute(|| {});
+        assert_eq!(pool.get_queued_jobs(), 3);
+        assert_eq!(pool.get_peak_queued_jobs(), 3);
+
+        release.send(()).unwrap();
+        let mut pool = pool;
+        assert!(pool.shutdown(Duration::from_secs(5)));
+        assert_eq!(pool.get_queued_jobs(), 0);
+        assert_eq!(pool.get_busy_workers(), 0);
+    }
+
+    #[test]
+    fn resizes_while_running() {
+        let pool = ThreadPool::new(1);
+        pool.resize(3);
+        assert_eq!(pool.get_size(), 3);
+
+        // All three jobs can only finish together if three workers run them at once.
+        let barrier = Arc::new(std::sync::Barrier::new(3));
+        let (done, finished) = mpsc::channel();
+        for _ in 0..3 {
+            let (barrier, done) = (Arc::clone(&barrier), done.clone());
+            pool.execute(move || {
+                barrier.wait();
+                done.send(()).unwrap();
+            });
+        }
+        for _ in 0..3 {
+            finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+
+        pool.resize(1);
+        assert_eq!(pool.get_size(), 1);
+        pool.execute(move || done.send(()).unwrap());
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let mut pool = pool;
+        assert!(pool.shutdown(Duration::from_secs(5)));
+    }
 }